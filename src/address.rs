@@ -0,0 +1,68 @@
+//! Parsing of daemon addresses.
+//!
+//! Besides the usual `host:port` form, Spread tools conventionally write
+//! daemon addresses as `port@host`. Both forms accept IPv6 literals, with
+//! or without brackets in the `port@host` form (`4803@::1`, `4803@[::1]`)
+//! and bracketed in the `host:port` form (`[::1]:4803`).
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::vec;
+use {Error, DEFAULT_SPREAD_PORT};
+
+/// A daemon host name or IP literal together with a port.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DaemonAddress {
+    pub host: String,
+    pub port: u16
+}
+
+impl DaemonAddress {
+    /// Parse a daemon address in `port@host`, `host:port`, `[v6]:port` or
+    /// bare `host` form. A bare host uses the default Spread port.
+    pub fn parse(spec: &str) -> Result<DaemonAddress, Error> {
+        let spec = spec.trim();
+        let (host, port) = if let Some(at) = spec.find('@') {
+            (&spec[at + 1..], Some(&spec[..at]))
+        } else if spec.starts_with("[") {
+            match spec.find(']') {
+                Some(close) if close + 1 == spec.len() => (spec, None),
+                Some(close) if spec[close + 1..].starts_with(":") =>
+                    (&spec[..close + 1], Some(&spec[close + 2..])),
+                _ => return Err(invalid_address(spec))
+            }
+        } else {
+            match spec.rfind(':') {
+                // More than one colon without brackets: a bare IPv6 literal.
+                Some(_) if spec.matches(':').count() > 1 => (spec, None),
+                Some(colon) => (&spec[..colon], Some(&spec[colon + 1..])),
+                None => (spec, None)
+            }
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid_address(spec));
+        }
+        let port = match port {
+            Some(port) => match port.parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => return Err(invalid_address(spec))
+            },
+            None => DEFAULT_SPREAD_PORT as u16
+        };
+        Ok(DaemonAddress { host: host.to_string(), port: port })
+    }
+}
+
+impl ToSocketAddrs for DaemonAddress {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<vec::IntoIter<SocketAddr>> {
+        (self.host.as_str(), self.port).to_socket_addrs()
+    }
+}
+
+fn invalid_address(spec: &str) -> Error {
+    Error::InvalidInput(format!("Malformed daemon address: {}", spec))
+}
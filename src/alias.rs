@@ -0,0 +1,55 @@
+//! Translation between logical group names used by an application and the
+//! physical group names used on the wire.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use sync::RwLock;
+
+/// A shared, updatable map from logical to physical group names.
+///
+/// Clones share the same map, so a handle kept by a configuration watcher
+/// can swap in new aliases while clients that were given another clone are
+/// running; the change applies to the client's next join, leave, multicast
+/// or receive. Groups without an alias are used as-is.
+#[derive(Clone)]
+pub struct GroupAliases {
+    aliases: Arc<RwLock<HashMap<String, String>>>
+}
+
+impl GroupAliases {
+    pub fn new() -> GroupAliases {
+        GroupAliases { aliases: Arc::new(RwLock::new(HashMap::new())) }
+    }
+
+    /// Send and join `physical` wherever the application uses `logical`.
+    pub fn set(&self, logical: &str, physical: &str) {
+        self.aliases.write().unwrap().insert(logical.to_string(), physical.to_string());
+    }
+
+    pub fn remove(&self, logical: &str) {
+        self.aliases.write().unwrap().remove(logical);
+    }
+
+    /// Replace every alias at once, e.g. after reloading configuration.
+    pub fn replace_all(&self, aliases: HashMap<String, String>) {
+        *self.aliases.write().unwrap() = aliases;
+    }
+
+    /// The physical name of `logical`.
+    pub fn to_physical(&self, logical: &str) -> String {
+        match self.aliases.read().unwrap().get(logical) {
+            Some(physical) => physical.clone(),
+            None => logical.to_string()
+        }
+    }
+
+    /// The logical name of `physical`. If several logical names map to the
+    /// same physical group, which one is returned is unspecified.
+    pub fn to_logical(&self, physical: &str) -> String {
+        let aliases = self.aliases.read().unwrap();
+        match aliases.iter().find(|&(_, p)| p.as_str() == physical) {
+            Some((logical, _)) => logical.clone(),
+            None => physical.to_string()
+        }
+    }
+}
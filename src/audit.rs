@@ -0,0 +1,156 @@
+//! A record of a session's group memberships.
+//!
+//! An `AuditLog` attached with `SpreadClient::set_audit_log` records every
+//! join and leave the client makes, every change `resync` applies, and
+//! every membership message it receives, so the question "when did this
+//! client join that group" can be answered after the fact. Entries are
+//! kept in memory and can also be written to a file as they are recorded,
+//! one tab-separated line per entry (tabs shown as `\t`):
+//!
+//! ```text
+//! 2015-02-01T12:00:00Z\tjoin\torders\trequested
+//! 2015-02-01T12:00:05Z\tmembership\torders\tnetwork\t#a#d1,#b#d2
+//! ```
+
+use std::io::Write;
+use time::{self, Timespec};
+use Error;
+
+/// What happened to a group.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuditAction {
+    /// The client joined, or found itself in, the group.
+    Join,
+    /// The client left, or found itself out of, the group.
+    Leave,
+    /// A membership message reported the group's members.
+    Membership(Vec<String>)
+}
+
+/// Why it happened.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AuditCause {
+    /// The application called `join` or `leave`.
+    Requested,
+    /// `resync` reconciled the client's groups with membership messages.
+    Resync,
+    /// The client re-joined the group after reconnecting.
+    Reconnect,
+    /// A member joined.
+    MemberJoined,
+    /// A member left.
+    MemberLeft,
+    /// A member disconnected.
+    MemberDisconnected,
+    /// The daemons' network partitioned or merged.
+    Network,
+    /// A membership message whose cause isn't known.
+    Unknown
+}
+
+impl AuditCause {
+    /// The cause of a membership message, from its service type.
+    pub fn from_service_type(service_type: u32) -> AuditCause {
+        if service_type & 0x100 != 0 {
+            AuditCause::MemberJoined
+        } else if service_type & 0x200 != 0 {
+            AuditCause::MemberLeft
+        } else if service_type & 0x400 != 0 {
+            AuditCause::MemberDisconnected
+        } else if service_type & 0x800 != 0 {
+            AuditCause::Network
+        } else {
+            AuditCause::Unknown
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            AuditCause::Requested => "requested",
+            AuditCause::Resync => "resync",
+            AuditCause::Reconnect => "reconnect",
+            AuditCause::MemberJoined => "join",
+            AuditCause::MemberLeft => "leave",
+            AuditCause::MemberDisconnected => "disconnect",
+            AuditCause::Network => "network",
+            AuditCause::Unknown => "unknown"
+        }
+    }
+}
+
+/// One recorded change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: Timespec,
+    pub group: String,
+    pub action: AuditAction,
+    pub cause: AuditCause
+}
+
+impl AuditEntry {
+    /// Render the entry as a tab-separated line, including the trailing
+    /// newline.
+    pub fn to_line(&self) -> String {
+        let timestamp = format!("{}", time::at_utc(self.timestamp).rfc3339());
+        match self.action {
+            AuditAction::Join =>
+                format!("{}\tjoin\t{}\t{}\n", timestamp, self.group, self.cause.name()),
+            AuditAction::Leave =>
+                format!("{}\tleave\t{}\t{}\n", timestamp, self.group, self.cause.name()),
+            AuditAction::Membership(ref members) =>
+                format!("{}\tmembership\t{}\t{}\t{}\n", timestamp, self.group, self.cause.name(),
+                        members.join(","))
+        }
+    }
+}
+
+/// The entries recorded for a session, oldest first.
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+    export: Option<Box<dyn Write + Send>>
+}
+
+impl AuditLog {
+    pub fn new() -> AuditLog {
+        AuditLog { entries: Vec::new(), export: None }
+    }
+
+    /// Also write every entry to `writer` as it is recorded.
+    pub fn export_to(mut self, writer: Box<dyn Write + Send>) -> AuditLog {
+        self.export = Some(writer);
+        self
+    }
+
+    /// Add an entry, writing it to the export writer if there is one.
+    pub fn record(&mut self, entry: AuditEntry) -> Result<(), Error> {
+        let result = match self.export {
+            Some(ref mut writer) => writer.write_all(entry.to_line().as_bytes()),
+            None => Ok(())
+        };
+        self.entries.push(entry);
+        result.map_err(Error::from)
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        self.entries.as_slice()
+    }
+
+    /// The entries about `group`, oldest first.
+    pub fn for_group(&self, group: &str) -> Vec<&AuditEntry> {
+        self.entries.iter().filter(|entry| entry.group.as_str() == group).collect()
+    }
+
+    /// When the client most recently joined `group`, if ever.
+    pub fn last_joined(&self, group: &str) -> Option<Timespec> {
+        self.entries.iter().rev()
+            .find(|entry| entry.group.as_str() == group && entry.action == AuditAction::Join)
+            .map(|entry| entry.timestamp)
+    }
+
+    /// When the client most recently left `group`, if ever.
+    pub fn last_left(&self, group: &str) -> Option<Timespec> {
+        self.entries.iter().rev()
+            .find(|entry| entry.group.as_str() == group && entry.action == AuditAction::Leave)
+            .map(|entry| entry.timestamp)
+    }
+}
@@ -0,0 +1,151 @@
+//! Recovering missed sequence ranges by asking their sender to re-send them.
+//!
+//! Senders enable sequence stamping and a bounded send history on their
+//! client (`set_sequencing`, `set_send_history`) and pass every message
+//! received on a shared control group to `handle_request`. Receivers that
+//! detect a gap (see the `resequence` module) call `request` to ask the
+//! sender for the missing range, which is then re-sent with its original
+//! sequence numbers.
+
+use std::collections::VecDeque;
+use envelope::{decode_u64, encode_u64};
+use {Error, Level, SpreadClient, SpreadMessage};
+
+static REQUEST_PREFIX: &'static [u8] = b"\x00spread-backfill:";
+
+/// A request for `sender` to re-send sequence numbers `first..last`
+/// (inclusive).
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackfillRequest {
+    pub sender: String,
+    pub first: u64,
+    pub last: u64
+}
+
+impl BackfillRequest {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = REQUEST_PREFIX.to_vec();
+        out.push(self.sender.len() as u8);
+        out.extend_from_slice(self.sender.as_bytes());
+        out.extend_from_slice(encode_u64(self.first).as_slice());
+        out.extend_from_slice(encode_u64(self.last).as_slice());
+        out
+    }
+
+    /// Decode a request, returning `None` if `data` is not one.
+    pub fn decode(data: &[u8]) -> Option<BackfillRequest> {
+        if !data.starts_with(REQUEST_PREFIX) || data.len() < REQUEST_PREFIX.len() + 1 {
+            return None;
+        }
+        let mut offset = REQUEST_PREFIX.len();
+        let sender_len = data[offset] as usize;
+        offset += 1;
+        if data.len() != offset + sender_len + 16 {
+            return None;
+        }
+        let sender = match String::from_utf8(data[offset..offset + sender_len].to_vec()) {
+            Ok(sender) => sender,
+            Err(_) => return None
+        };
+        offset += sender_len;
+        let first = decode_u64(&data[offset..offset + 8]);
+        let last = decode_u64(&data[offset + 8..offset + 16]);
+        match (first, last) {
+            (Some(first), Some(last)) => Some(BackfillRequest { sender: sender, first: first, last: last }),
+            _ => None
+        }
+    }
+}
+
+/// Ask `sender` to re-send the range `first..last` by multicasting a
+/// request to `control_group`.
+pub fn request(
+    client: &mut SpreadClient,
+    control_group: &str,
+    sender: &str,
+    first: u64,
+    last: u64
+) -> Result<(), Error> {
+    let request = BackfillRequest {
+        sender: sender.trim_end_matches('\0').to_string(),
+        first: first,
+        last: last
+    };
+    client_log!(client, Level::Debug,
+                "Requesting backfill of {}..{} from \"{}\"", first, last, request.sender);
+    client.multicast([control_group].as_slice(), request.encode().as_slice())
+}
+
+/// If `message` is a backfill request addressed to `client`, re-send the
+/// requested range from its history and return how many messages were
+/// re-sent. Returns `Ok(None)` for any other message.
+pub fn handle_request(client: &mut SpreadClient, message: &SpreadMessage) -> Result<Option<usize>, Error> {
+    let request = match BackfillRequest::decode(message.data.as_slice()) {
+        Some(request) => request,
+        None => return Ok(None)
+    };
+    if request.sender != client.private_name {
+        return Ok(None);
+    }
+    client_log!(client, Level::Debug, "Handling backfill request for {}..{} from \"{}\"",
+                request.first, request.last, message.sender);
+    client.resend_range(request.first, request.last).map(Some)
+}
+
+// Bounded history of sequenced messages sent by a client.
+pub struct SendHistory {
+    capacity: usize,
+    entries: VecDeque<(u64, Vec<String>, Vec<u8>)>,
+    bytes: usize
+}
+
+// Bytes of group names and payload held for one entry.
+fn entry_bytes(groups: &[String], enveloped: &[u8]) -> usize {
+    groups.iter().fold(enveloped.len(), |acc, group| acc + group.len())
+}
+
+impl SendHistory {
+    pub fn new(capacity: usize) -> SendHistory {
+        SendHistory { capacity: capacity, entries: VecDeque::new(), bytes: 0 }
+    }
+
+    pub fn record(&mut self, sequence: u64, groups: &[&str], enveloped: &[u8]) {
+        while self.entries.len() >= self.capacity {
+            self.evict_oldest();
+        }
+        let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+        self.bytes += entry_bytes(groups.as_slice(), enveloped);
+        self.entries.push_back((sequence, groups, enveloped.to_vec()));
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, groups, enveloped)) = self.entries.pop_front() {
+            self.bytes -= entry_bytes(groups.as_slice(), enveloped.as_slice());
+        }
+    }
+
+    // Bytes of group names and payloads held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Evict the oldest entries until at most `max_bytes` are held,
+    // returning how many were evicted.
+    pub fn trim_to(&mut self, max_bytes: usize) -> usize {
+        let mut evicted = 0;
+        while self.bytes > max_bytes {
+            self.evict_oldest();
+            evicted += 1;
+        }
+        evicted
+    }
+
+    // The retained (groups, enveloped payload) pairs in `first..last`, in
+    // sequence order.
+    pub fn range(&self, first: u64, last: u64) -> Vec<(Vec<String>, Vec<u8>)> {
+        self.entries.iter()
+            .filter(|&&(sequence, _, _)| sequence >= first && sequence <= last)
+            .map(|(_, groups, enveloped)| (groups.clone(), enveloped.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,38 @@
+//! Messages sent together with `SpreadClient::send_batch`.
+
+use fanout::validate_group_name;
+
+/// A message waiting to be multicast.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OutboundMessage {
+    pub groups: Vec<String>,
+    pub data: Vec<u8>
+}
+
+impl OutboundMessage {
+    pub fn new(groups: &[&str], data: &[u8]) -> OutboundMessage {
+        OutboundMessage { groups: groups.iter().map(|g| g.to_string()).collect(), data: data.to_vec() }
+    }
+}
+
+// Check that a message addressed to the `groups` named on the wire, with
+// a payload of `size` bytes, can be sent within a client's `max_groups`
+// and `max_message_size`, returning the reason if not.
+pub fn validate(groups: &[String], size: usize, max_groups: usize, max_message_size: usize)
+                -> Result<(), String> {
+    if groups.is_empty() {
+        return Err("no groups".to_string());
+    }
+    if groups.len() > max_groups {
+        return Err(format!("{} groups, maximum {}", groups.len(), max_groups));
+    }
+    for group in groups.iter() {
+        validate_group_name(group.as_str())
+        .map_err(|reason| format!("group \"{}\": {}", group, reason))?;
+    }
+    if size > max_message_size {
+        return Err(format!("{} bytes, maximum {}", size, max_message_size));
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! Comparing this crate with the C client library, libspread.
+//!
+//! Only available when the crate is built with the `libspread` feature,
+//! which links against `libspread`. The same workload is run through a
+//! `SpreadClient` pair and through a pair of libspread mailboxes connected
+//! to the same daemon, so the results differ only in the client library.
+//! The `spread-bench` binary runs both and prints a comparison.
+
+use std::ffi::CString;
+use std::io;
+use time::precise_time_ns;
+use stats::Histogram;
+use {Error, SpreadClient, SpreadErrorCode};
+
+/// The messages to send in one run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Workload {
+    pub group: String,
+    pub messages: usize,
+    pub payload_size: usize
+}
+
+/// One side of a benchmark: sends to and receives from the workload's
+/// group.
+pub trait BenchClient {
+    fn join(&mut self, group: &str) -> Result<(), Error>;
+    fn send(&mut self, group: &str, data: &[u8]) -> Result<(), Error>;
+    /// Block until the next data message and return its payload length.
+    fn receive(&mut self) -> Result<usize, Error>;
+}
+
+impl BenchClient for SpreadClient {
+    fn join(&mut self, group: &str) -> Result<(), Error> {
+        SpreadClient::join(self, group)
+    }
+
+    fn send(&mut self, group: &str, data: &[u8]) -> Result<(), Error> {
+        self.multicast([group].as_slice(), data)
+    }
+
+    fn receive(&mut self) -> Result<usize, Error> {
+        loop {
+            let message = SpreadClient::receive(self)?;
+            if !message.is_membership() {
+                return Ok(message.data.len());
+            }
+        }
+    }
+}
+
+/// The outcome of running a workload.
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub messages: usize,
+    pub elapsed_ns: u64,
+    /// Time from sending each message to receiving it, in microseconds.
+    pub latency_us: Histogram
+}
+
+impl BenchResult {
+    /// Messages delivered per second.
+    pub fn throughput(&self) -> f64 {
+        self.messages as f64 * 1e9 / self.elapsed_ns as f64
+    }
+}
+
+/// Send every message of `workload` from `sender` and wait for `receiver`
+/// to receive it before sending the next, timing each round trip.
+pub fn run(sender: &mut dyn BenchClient, receiver: &mut dyn BenchClient, workload: &Workload)
+           -> Result<BenchResult, Error> {
+    receiver.join(workload.group.as_str())?;
+    let payload = vec![0x5a; workload.payload_size];
+    let mut latency_us = Histogram::new();
+    let started = precise_time_ns();
+    for _ in 0..workload.messages {
+        let sent = precise_time_ns();
+        sender.send(workload.group.as_str(), payload.as_slice())?;
+        receiver.receive()?;
+        latency_us.record((precise_time_ns() - sent) / 1000);
+    }
+    Ok(BenchResult {
+        messages: workload.messages,
+        elapsed_ns: precise_time_ns() - started,
+        latency_us: latency_us
+    })
+}
+
+// Declarations from libspread's sp.h.
+static RELIABLE_MESS: i32 = 0x00000002;
+static MAX_GROUP_NAME: usize = 32;
+
+#[link(name = "spread")]
+extern "C" {
+    fn SP_connect(spread_name: *const i8, private_name: *const i8, priority: i32,
+                  group_membership: i32, mbox: *mut i32, private_group: *mut i8) -> i32;
+    fn SP_disconnect(mbox: i32) -> i32;
+    fn SP_join(mbox: i32, group: *const i8) -> i32;
+    fn SP_multicast(mbox: i32, service_type: i32, group: *const i8, mess_type: i16,
+                    mess_len: i32, mess: *const i8) -> i32;
+    fn SP_receive(mbox: i32, service_type: *mut i32, sender: *mut i8, max_groups: i32,
+                  num_groups: *mut i32, groups: *mut i8, mess_type: *mut i16,
+                  endian_mismatch: *mut i32, max_mess_len: i32, mess: *mut i8) -> i32;
+}
+
+// libspread returns the same error codes as the daemon.
+fn libspread_error(desc: &'static str, code: i32) -> Error {
+    match SpreadErrorCode::from_code(code) {
+        Some(code) => Error::DaemonError(code),
+        None => Error::from(io::Error::other(format!("{}: libspread error {}", desc, code)))
+    }
+}
+
+fn c_string(s: &str) -> Result<CString, Error> {
+    CString::new(s).map_err(|_| Error::InvalidInput(format!("String contains a NUL byte: {:?}", s)))
+}
+
+/// A session opened through libspread.
+pub struct LibSpreadClient {
+    mbox: i32,
+    buffer: Vec<u8>,
+    groups: Vec<u8>
+}
+
+impl LibSpreadClient {
+    /// Connect to the daemon named `daemon` (e.g. `4803@localhost`) as
+    /// `private_name`, receiving messages of up to `max_message_len` bytes.
+    pub fn connect(daemon: &str, private_name: &str, max_message_len: usize)
+                   -> Result<LibSpreadClient, Error> {
+        let daemon = c_string(daemon)?;
+        let private_name = c_string(private_name)?;
+        let mut mbox = 0;
+        let mut private_group = [0i8; 32];
+        let code = unsafe {
+            SP_connect(daemon.as_ptr(), private_name.as_ptr(), 0, 0, &mut mbox,
+                       private_group.as_mut_ptr())
+        };
+        if code < 0 {
+            return Err(libspread_error("libspread connect failed", code));
+        }
+        Ok(LibSpreadClient {
+            mbox: mbox,
+            buffer: vec![0; max_message_len],
+            groups: vec![0; MAX_GROUP_NAME * 100]
+        })
+    }
+}
+
+impl BenchClient for LibSpreadClient {
+    fn join(&mut self, group: &str) -> Result<(), Error> {
+        let group = c_string(group)?;
+        match unsafe { SP_join(self.mbox, group.as_ptr()) } {
+            code if code < 0 => Err(libspread_error("libspread join failed", code)),
+            _ => Ok(())
+        }
+    }
+
+    fn send(&mut self, group: &str, data: &[u8]) -> Result<(), Error> {
+        let group = c_string(group)?;
+        let code = unsafe {
+            SP_multicast(self.mbox, RELIABLE_MESS, group.as_ptr(), 0, data.len() as i32,
+                         data.as_ptr() as *const i8)
+        };
+        if code < 0 { Err(libspread_error("libspread multicast failed", code)) } else { Ok(()) }
+    }
+
+    fn receive(&mut self) -> Result<usize, Error> {
+        let mut service_type = 0;
+        let mut sender = [0i8; 32];
+        let mut num_groups = 0;
+        let mut mess_type = 0;
+        let mut endian_mismatch = 0;
+        let code = unsafe {
+            SP_receive(self.mbox, &mut service_type, sender.as_mut_ptr(),
+                       (self.groups.len() / MAX_GROUP_NAME) as i32, &mut num_groups,
+                       self.groups.as_mut_ptr() as *mut i8, &mut mess_type, &mut endian_mismatch,
+                       self.buffer.len() as i32, self.buffer.as_mut_ptr() as *mut i8)
+        };
+        if code < 0 { Err(libspread_error("libspread receive failed", code)) } else { Ok(code as usize) }
+    }
+}
+
+impl Drop for LibSpreadClient {
+    fn drop(&mut self) {
+        unsafe { SP_disconnect(self.mbox); }
+    }
+}
@@ -0,0 +1,99 @@
+//! Throughput and latency comparison against libspread.
+//!
+//! Runs the same workload through a pair of `SpreadClient`s and a pair of
+//! libspread mailboxes connected to the same daemon, then prints both
+//! results and the ratio between them. Requires the `libspread` feature:
+//!
+//! ```text
+//! cargo run --features libspread --bin spread-bench -- \
+//!     [--daemon <addr>] [--messages <n>] [--size <bytes>]
+//! ```
+
+extern crate spread;
+
+use std::env;
+use std::process;
+
+#[cfg(feature = "libspread")]
+use spread::{ConnectOptions, DaemonAddress};
+#[cfg(feature = "libspread")]
+use spread::bench::{self, BenchResult, LibSpreadClient, Workload};
+
+struct Config {
+    daemon: String,
+    messages: usize,
+    size: usize
+}
+
+impl Config {
+    fn parse(args: Vec<String>) -> Result<Config, String> {
+        let mut config = Config { daemon: "4803@localhost".to_string(), messages: 10000, size: 100 };
+        let mut args = args.into_iter().skip(1);
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or(format!("missing value for {}", flag))?;
+            let number = || value.parse::<usize>().map_err(|_| format!("invalid number for {}: {}", flag, value));
+            match flag.as_str() {
+                "--daemon" => config.daemon = value.clone(),
+                "--messages" => config.messages = number()?,
+                "--size" => config.size = number()?,
+                _ => return Err(format!("unknown option {}", flag))
+            }
+        }
+        if config.messages == 0 {
+            return Err("--messages must be positive".to_string());
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(feature = "libspread")]
+fn print_result(name: &str, result: &BenchResult) {
+    println!("{:<10} {:>10.0} msg/s  p50={}us p99={}us", name, result.throughput(),
+             result.latency_us.quantile(0.5), result.latency_us.quantile(0.99));
+}
+
+#[cfg(feature = "libspread")]
+fn compare(config: Config) -> Result<(), String> {
+    let workload = Workload {
+        group: "spread-bench".to_string(),
+        messages: config.messages,
+        payload_size: config.size
+    };
+    let address = DaemonAddress::parse(config.daemon.as_str()).map_err(|e| format!("{}", e))?;
+    let connect = |name: &str| {
+        ConnectOptions::new(name).membership_messages(false).connect(address.clone())
+            .map_err(|error| format!("{} failed to connect: {}", name, error))
+    };
+    let mut sender = connect("benchrs")?;
+    let mut receiver = connect("benchrr")?;
+    let native = bench::run(&mut sender, &mut receiver, &workload)
+                     .map_err(|error| format!("spread.rs run failed: {}", error))?;
+
+    let libspread_connect = |name: &str| {
+        LibSpreadClient::connect(config.daemon.as_str(), name, config.size)
+            .map_err(|error| format!("{} failed to connect: {}", name, error))
+    };
+    let mut sender = libspread_connect("benchcs")?;
+    let mut receiver = libspread_connect("benchcr")?;
+    let c = bench::run(&mut sender, &mut receiver, &workload)
+                .map_err(|error| format!("libspread run failed: {}", error))?;
+
+    println!("{} messages of {} bytes", config.messages, config.size);
+    print_result("spread.rs", &native);
+    print_result("libspread", &c);
+    println!("relative throughput: {:.2}x", native.throughput() / c.throughput());
+    Ok(())
+}
+
+#[cfg(not(feature = "libspread"))]
+fn compare(_: Config) -> Result<(), String> {
+    Err("built without the libspread feature".to_string())
+}
+
+fn main() {
+    let result = Config::parse(env::args().collect()).and_then(compare);
+    if let Err(reason) = result {
+        println!("spread-bench: {}", reason);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,249 @@
+//! Long-running stability test against a live Spread daemon.
+//!
+//! Runs several client instances for a configurable time. Each instance has
+//! a sending session that multicasts sequenced messages at a fixed rate and
+//! a receiving session that churns through joins and leaves while checking
+//! the order of everything it receives. A report of message counts, error
+//! counts, ordering violations and buffered memory is printed periodically
+//! and at the end; the exit status is non-zero if any errors or violations
+//! were seen.
+//!
+//! Usage:
+//!
+//! ```text
+//! spread-soak [--daemon <addr>] [--clients <n>] [--duration <secs>]
+//!             [--rate <msgs/sec>] [--churn-groups <n>] [--report <secs>]
+//! ```
+
+extern crate spread;
+extern crate time;
+
+use spread::{ConnectOptions, DaemonAddress, SpreadClient};
+use spread::envelope::Envelope;
+use std::collections::HashMap;
+use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration as StdDuration;
+use time::Duration;
+
+// Every message is sent to this group, which every receiver stays in, so
+// each receiver should see every sender's sequence without gaps.
+static ALL_GROUP: &str = "soak-all";
+
+struct Config {
+    daemon: String,
+    clients: usize,
+    duration_secs: u64,
+    rate: u64,
+    churn_groups: usize,
+    report_secs: u64
+}
+
+impl Config {
+    fn parse(args: Vec<String>) -> Result<Config, String> {
+        let mut config = Config {
+            daemon: "4803@localhost".to_string(),
+            clients: 3,
+            duration_secs: 3600,
+            rate: 50,
+            churn_groups: 4,
+            report_secs: 60
+        };
+        let mut args = args.into_iter().skip(1);
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or(format!("missing value for {}", flag))?;
+            let number = || value.parse::<u64>().map_err(|_| format!("invalid number for {}: {}", flag, value));
+            match flag.as_str() {
+                "--daemon" => config.daemon = value.clone(),
+                "--clients" => config.clients = number()? as usize,
+                "--duration" => config.duration_secs = number()?,
+                "--rate" => config.rate = number()?,
+                "--churn-groups" => config.churn_groups = number()? as usize,
+                "--report" => config.report_secs = number()?,
+                _ => return Err(format!("unknown option {}", flag))
+            }
+        }
+        if config.clients == 0 || config.rate == 0 || config.churn_groups == 0 {
+            return Err("--clients, --rate and --churn-groups must be positive".to_string());
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Report {
+    sent: u64,
+    received: u64,
+    send_errors: u64,
+    receive_errors: u64,
+    joins: u64,
+    leaves: u64,
+    // Messages received with a sequence number at or below one already
+    // received from the same sender.
+    reordered: u64,
+    // Sequence numbers skipped over.
+    lost: u64,
+    max_buffered_bytes: u64
+}
+
+impl Report {
+    fn print(&self, elapsed_secs: u64) {
+        println!("[{}s] sent={} received={} send_errors={} receive_errors={} joins={} leaves={} \
+                  reordered={} lost={} max_buffered_bytes={}",
+                 elapsed_secs, self.sent, self.received, self.send_errors, self.receive_errors,
+                 self.joins, self.leaves, self.reordered, self.lost, self.max_buffered_bytes);
+    }
+
+    fn is_clean(&self) -> bool {
+        self.send_errors == 0 && self.receive_errors == 0 && self.reordered == 0 && self.lost == 0
+    }
+}
+
+fn now_secs() -> u64 {
+    time::get_time().sec as u64
+}
+
+fn connect(config: &Config, name: String, membership: bool) -> Result<SpreadClient, String> {
+    let address = DaemonAddress::parse(config.daemon.as_str()).map_err(|e| format!("{}", e))?;
+    ConnectOptions::new(name.as_str())
+        .membership_messages(membership)
+        .join(ALL_GROUP)
+        .connect(address)
+        .map_err(|error| format!("{} failed to connect: {}", name, error))
+}
+
+fn run_sender(mut client: SpreadClient, config: Arc<Config>, deadline: u64, report: Arc<Mutex<Report>>) {
+    client.set_sequencing(true);
+    let interval = Duration::microseconds(1000000 / config.rate as i64);
+    let mut n = 0;
+    while now_secs() < deadline {
+        let churn = format!("soak-churn-{}", n % config.churn_groups);
+        let payload = format!("soak message {}", n);
+        let result = client.multicast([ALL_GROUP, churn.as_str()].as_slice(), payload.as_bytes());
+        {
+            let mut report = report.lock().unwrap();
+            match result {
+                Ok(()) => report.sent += 1,
+                Err(error) => {
+                    report.send_errors += 1;
+                    println!("send error from {}: {}", client.private_name, error);
+                }
+            }
+            let buffered = client.stats().buffered_bytes;
+            if buffered > report.max_buffered_bytes {
+                report.max_buffered_bytes = buffered;
+            }
+        }
+        n += 1;
+        thread::sleep(interval.to_std().unwrap());
+    }
+    let _ = client.disconnect();
+}
+
+fn run_receiver(mut client: SpreadClient, config: Arc<Config>, deadline: u64, report: Arc<Mutex<Report>>) {
+    let mut last_sequence: HashMap<String, u64> = HashMap::new();
+    let mut joined: Option<String> = None;
+    let mut n = 0;
+    while now_secs() < deadline {
+        // Move to a different churn group every hundred messages.
+        if n % 100 == 0 {
+            let next = format!("soak-churn-{}", (n / 100) % config.churn_groups);
+            let mut report = report.lock().unwrap();
+            if let Some(previous) = joined.take() {
+                match client.leave(previous.as_str()) {
+                    Ok(()) => report.leaves += 1,
+                    Err(_) => report.receive_errors += 1
+                }
+            }
+            match client.join(next.as_str()) {
+                Ok(()) => {
+                    report.joins += 1;
+                    joined = Some(next);
+                },
+                Err(_) => report.receive_errors += 1
+            }
+        }
+        n += 1;
+
+        let message = match client.receive() {
+            Ok(message) => message,
+            Err(error) => {
+                println!("receive error on {}: {}", client.private_name, error);
+                report.lock().unwrap().receive_errors += 1;
+                break;
+            }
+        };
+        if message.is_membership() {
+            continue;
+        }
+        let sequence = match Envelope::decode(message.data()).and_then(|e| e.sequence()) {
+            Some(sequence) => sequence,
+            None => continue
+        };
+        let mut report = report.lock().unwrap();
+        report.received += 1;
+        let sender = message.sender().to_string();
+        match last_sequence.get(&sender).copied() {
+            Some(last) if sequence <= last => report.reordered += 1,
+            Some(last) => report.lost += sequence - last - 1,
+            None => ()
+        }
+        if last_sequence.get(&sender).is_none_or(|last| sequence > *last) {
+            last_sequence.insert(sender, sequence);
+        }
+        let buffered = client.stats().buffered_bytes;
+        if buffered > report.max_buffered_bytes {
+            report.max_buffered_bytes = buffered;
+        }
+    }
+    let _ = client.disconnect();
+}
+
+fn main() {
+    let config = match Config::parse(env::args().collect()) {
+        Ok(config) => Arc::new(config),
+        Err(reason) => {
+            println!("spread-soak: {}", reason);
+            process::exit(2);
+        }
+    };
+    let started = now_secs();
+    let deadline = started + config.duration_secs;
+    let report = Arc::new(Mutex::new(Report::default()));
+
+    // Receivers block in receive() once the senders stop, so only the
+    // senders are waited for; receivers end with the process.
+    let mut senders = Vec::new();
+    for i in 0..config.clients {
+        let receiver = connect(&config, format!("soakr{}", i), true);
+        let sender = connect(&config, format!("soaks{}", i), false);
+        let (receiver, sender) = match (receiver, sender) {
+            (Ok(receiver), Ok(sender)) => (receiver, sender),
+            (Err(reason), _) | (_, Err(reason)) => {
+                println!("spread-soak: {}", reason);
+                process::exit(1);
+            }
+        };
+        let (r_config, r_report) = (config.clone(), report.clone());
+        thread::spawn(move || run_receiver(receiver, r_config, deadline, r_report));
+        let (s_config, s_report) = (config.clone(), report.clone());
+        senders.push(thread::spawn(move || run_sender(sender, s_config, deadline, s_report)));
+    }
+
+    while now_secs() < deadline {
+        thread::sleep(StdDuration::from_secs(config.report_secs));
+        report.lock().unwrap().print(now_secs() - started);
+    }
+    for sender in senders.into_iter() {
+        let _ = sender.join();
+    }
+
+    let report = report.lock().unwrap().clone();
+    println!("final report:");
+    report.print(now_secs() - started);
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
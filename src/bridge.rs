@@ -0,0 +1,220 @@
+//! Forwarding between Spread groups and external message buses.
+//!
+//! Implement `Bridge` for an endpoint (a Kafka producer, an MQTT client, ...)
+//! and hand it to `Pump::spawn`, which runs one thread per direction and takes
+//! care of batching, retrying with backoff, and shutdown. To fan groups out
+//! to consumers within the process instead, see the `channels` module,
+//! available with the `tokio` feature.
+
+use std::sync::Arc;
+use sync::{AtomicBool, Mutex, Ordering};
+use sync::thread::JoinHandle;
+use time::Duration;
+use threads::ThreadOptions;
+use {Clock, Level, SpreadClient, SpreadMessage};
+
+/// An external endpoint that messages are forwarded to and from.
+pub trait Bridge: Send {
+    /// Deliver a batch of messages received from Spread to the endpoint.
+    /// On error the whole batch is retried after a backoff.
+    fn deliver(&mut self, batch: &[SpreadMessage]) -> Result<(), String>;
+
+    /// Fetch up to `max` payloads from the endpoint to be multicast to
+    /// Spread. Returning an empty batch means nothing is ready yet.
+    fn fetch(&mut self, max: usize) -> Result<Vec<Vec<u8>>, String>;
+}
+
+/// Tuning for a `Pump`.
+#[derive(Clone, Debug)]
+pub struct PumpConfig {
+    /// Groups whose traffic is delivered to the bridge.
+    pub inbound_groups: Vec<String>,
+    /// Groups that payloads fetched from the bridge are multicast to.
+    pub outbound_groups: Vec<String>,
+    /// Largest batch passed to `deliver` or requested from `fetch`.
+    pub batch_size: usize,
+    /// Delay before the first retry after an error, doubled on each
+    /// consecutive failure up to `max_backoff_ms`.
+    pub initial_backoff_ms: i64,
+    pub max_backoff_ms: i64,
+    /// Delay between `fetch` calls that return nothing.
+    pub idle_poll_ms: i64,
+    /// Options for the two forwarding threads, named `pump-inbound` and
+    /// `pump-outbound`.
+    pub threads: ThreadOptions
+}
+
+impl PumpConfig {
+    pub fn new(inbound_groups: &[&str], outbound_groups: &[&str]) -> PumpConfig {
+        PumpConfig {
+            inbound_groups: inbound_groups.iter().map(|g| g.to_string()).collect(),
+            outbound_groups: outbound_groups.iter().map(|g| g.to_string()).collect(),
+            batch_size: 64,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 10000,
+            idle_poll_ms: 50,
+            threads: ThreadOptions::new()
+        }
+    }
+}
+
+/// A running bidirectional pump.
+pub struct Pump {
+    shutdown: Arc<AtomicBool>,
+    inbound: JoinHandle<()>,
+    outbound: JoinHandle<()>
+}
+
+impl Pump {
+    /// Start forwarding. `inbound_client` joins the inbound groups and feeds
+    /// the bridge; `outbound_client` multicasts what the bridge produces.
+    /// Two sessions are used so that neither direction blocks the other.
+    pub fn spawn<B: Bridge + 'static>(
+        mut inbound_client: SpreadClient,
+        mut outbound_client: SpreadClient,
+        bridge: B,
+        config: PumpConfig
+    ) -> Pump {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let bridge = Arc::new(Mutex::new(bridge));
+
+        let inbound = {
+            let shutdown = shutdown.clone();
+            let bridge = bridge.clone();
+            let config = config.clone();
+            config.threads.clone().spawn("pump-inbound", move || {
+                pump_inbound(&mut inbound_client, &*bridge, &config, &shutdown);
+                let _ = inbound_client.disconnect();
+            })
+        };
+        let outbound = {
+            let shutdown = shutdown.clone();
+            config.threads.clone().spawn("pump-outbound", move || {
+                pump_outbound(&mut outbound_client, &*bridge, &config, &shutdown);
+                let _ = outbound_client.disconnect();
+            })
+        };
+
+        Pump { shutdown: shutdown, inbound: inbound, outbound: outbound }
+    }
+
+    /// Ask both directions to stop and wait for them. The inbound direction
+    /// notices the request after its next received message.
+    pub fn shutdown(self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.outbound.join();
+        let _ = self.inbound.join();
+    }
+}
+
+// Exponential backoff state shared by both directions.
+struct Backoff {
+    next_ms: i64,
+    initial_ms: i64,
+    max_ms: i64
+}
+
+impl Backoff {
+    fn new(config: &PumpConfig) -> Backoff {
+        Backoff {
+            next_ms: config.initial_backoff_ms,
+            initial_ms: config.initial_backoff_ms,
+            max_ms: config.max_backoff_ms
+        }
+    }
+
+    fn wait(&mut self, clock: &dyn Clock) {
+        clock.sleep(Duration::milliseconds(self.next_ms));
+        self.next_ms = if self.next_ms * 2 > self.max_ms { self.max_ms } else { self.next_ms * 2 };
+    }
+
+    fn reset(&mut self) {
+        self.next_ms = self.initial_ms;
+    }
+}
+
+fn pump_inbound<B: Bridge>(
+    client: &mut SpreadClient,
+    bridge: &Mutex<B>,
+    config: &PumpConfig,
+    shutdown: &AtomicBool
+) {
+    for group in config.inbound_groups.iter() {
+        if let Err(error) = client.join(group.as_str()) {
+            client_log!(client, Level::Error, "Bridge failed to join group \"{}\": {}", group, error);
+            return;
+        }
+    }
+
+    let mut backoff = Backoff::new(config);
+    let mut pending: Vec<SpreadMessage> = Vec::new();
+    while !shutdown.load(Ordering::SeqCst) {
+        // Only block for new traffic when nothing is awaiting redelivery.
+        if pending.is_empty() {
+            match client.receive() {
+                Ok(message) => pending.push(message),
+                Err(error) => {
+                    client_log!(client, Level::Warn, "Bridge receive failed: {}", error);
+                    backoff.wait(client.clock());
+                    continue;
+                }
+            }
+        }
+
+        let result = bridge.lock().unwrap().deliver(pending.as_slice());
+        match result {
+            Ok(()) => {
+                pending.clear();
+                backoff.reset();
+            },
+            Err(error) => {
+                client_log!(client, Level::Warn,
+                            "Bridge delivery of {} message(s) failed: {}", pending.len(), error);
+                backoff.wait(client.clock());
+            }
+        }
+    }
+}
+
+fn pump_outbound<B: Bridge>(
+    client: &mut SpreadClient,
+    bridge: &Mutex<B>,
+    config: &PumpConfig,
+    shutdown: &AtomicBool
+) {
+    let groups: Vec<&str> = config.outbound_groups.iter().map(|g| g.as_str()).collect();
+    let mut backoff = Backoff::new(config);
+    let mut pending: Vec<Vec<u8>> = Vec::new();
+    while !shutdown.load(Ordering::SeqCst) {
+        if pending.is_empty() {
+            let fetched = bridge.lock().unwrap().fetch(config.batch_size);
+            match fetched {
+                Ok(batch) => pending = batch,
+                Err(error) => {
+                    client_log!(client, Level::Warn, "Bridge fetch failed: {}", error);
+                    backoff.wait(client.clock());
+                    continue;
+                }
+            }
+            if pending.is_empty() {
+                client.clock().sleep(Duration::milliseconds(config.idle_poll_ms));
+                continue;
+            }
+        }
+
+        // Send in order, keeping anything unsent for the next attempt.
+        while !pending.is_empty() {
+            match client.multicast(groups.as_slice(), pending[0].as_slice()) {
+                Ok(()) => {
+                    pending.remove(0);
+                    backoff.reset();
+                },
+                Err(error) => {
+                    client_log!(client, Level::Warn, "Bridge multicast failed: {}", error);
+                    backoff.wait(client.clock());
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Advertising which envelope features a sender understands.
+//!
+//! Enveloped messages may carry a capabilities field: a bit set of the
+//! envelope features the sending client can handle. Receivers record the
+//! latest advertisement of each sender in a `CapabilityTable`, and a client
+//! about to use a feature can check that every current member of the target
+//! group advertised it, falling back to plain messages otherwise. This lets
+//! fleets running different versions of this crate interoperate during a
+//! rolling upgrade.
+
+use std::collections::HashMap;
+use envelope::{decode_u64, encode_u64, Envelope, TAG_CAPABILITIES};
+use SpreadMessage;
+
+/// Sequence stamping and resequencing (see the `resequence` module).
+pub static CAP_SEQUENCING: u64 = 0x01;
+/// Message IDs shared by redundant copies (see the `redundant` module).
+pub static CAP_MESSAGE_ID: u64 = 0x02;
+/// Relay path and origin fields (see the `relay` module).
+pub static CAP_RELAY: u64 = 0x04;
+/// Fragmentation of large payloads.
+pub static CAP_FRAGMENTATION: u64 = 0x08;
+/// Compressed payloads (`FLAG_COMPRESSED`).
+pub static CAP_COMPRESSION: u64 = 0x10;
+/// Encrypted payloads (`FLAG_ENCRYPTED`).
+pub static CAP_ENCRYPTION: u64 = 0x20;
+/// Per-session unique message IDs (`TAG_UNIQUE_ID`).
+pub static CAP_UNIQUE_ID: u64 = 0x40;
+
+/// The capabilities of this version of the crate.
+pub fn local_capabilities() -> u64 {
+    CAP_SEQUENCING | CAP_MESSAGE_ID | CAP_RELAY | CAP_UNIQUE_ID
+}
+
+/// Advertise `capabilities` in `envelope`.
+pub fn advertise(envelope: &mut Envelope, capabilities: u64) {
+    envelope.set_field(TAG_CAPABILITIES, encode_u64(capabilities).as_slice());
+}
+
+/// The capabilities advertised in `envelope`, if any.
+pub fn advertised(envelope: &Envelope) -> Option<u64> {
+    envelope.field(TAG_CAPABILITIES).and_then(decode_u64)
+}
+
+/// The most recent capabilities advertised by each sender.
+pub struct CapabilityTable {
+    senders: HashMap<String, u64>
+}
+
+impl CapabilityTable {
+    pub fn new() -> CapabilityTable {
+        CapabilityTable { senders: HashMap::new() }
+    }
+
+    /// Record the advertisement carried by `message`, if any.
+    pub fn observe(&mut self, message: &SpreadMessage) {
+        if let Some(capabilities) = Envelope::decode(message.data.as_slice()).as_ref().and_then(advertised) {
+            let sender = message.sender.as_str().trim_end_matches('\0').to_string();
+            self.senders.insert(sender, capabilities);
+        }
+    }
+
+    /// The capabilities last advertised by `sender`.
+    pub fn capabilities(&self, sender: &str) -> Option<u64> {
+        self.senders.get(sender).copied()
+    }
+
+    /// The capabilities shared by all of `members`. Members that never
+    /// advertised are assumed to support nothing.
+    pub fn common(&self, members: &[String]) -> u64 {
+        members.iter().fold(!0, |acc, member| acc & self.capabilities(member.as_str()).unwrap_or(0))
+    }
+
+    /// Returns true if every one of `members` advertised `capability`.
+    pub fn all_support(&self, members: &[String], capability: u64) -> bool {
+        self.common(members) & capability == capability
+    }
+
+    /// Forget `sender`, e.g. once it has left.
+    pub fn remove(&mut self, sender: &str) {
+        self.senders.remove(sender);
+    }
+}
@@ -0,0 +1,73 @@
+//! Opt-in wire-level capture of the frames exchanged with a daemon.
+
+use std::io::Write;
+use logging::{Level, LogSink};
+use util::hex_dump;
+
+/// The direction in which a captured frame travelled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound
+}
+
+/// Where captured frames are written.
+pub enum CaptureSink {
+    /// Emit each frame through the `log` crate at debug level.
+    Log,
+    /// Write each frame to an arbitrary writer, such as a file.
+    Writer(Box<dyn Write + Send>)
+}
+
+/// Configuration for capturing every frame sent or received by a client as
+/// an annotated hex dump.
+pub struct Capture {
+    sink: CaptureSink,
+    redact_payloads: bool
+}
+
+impl Capture {
+    /// Capture frames to the debug log.
+    pub fn to_log() -> Capture {
+        Capture { sink: CaptureSink::Log, redact_payloads: false }
+    }
+
+    /// Capture frames to `writer`.
+    pub fn to_writer(writer: Box<dyn Write + Send>) -> Capture {
+        Capture { sink: CaptureSink::Writer(writer), redact_payloads: false }
+    }
+
+    /// If true, payload bytes are replaced by their length in the dump.
+    pub fn redact_payloads(mut self, redact: bool) -> Capture {
+        self.redact_payloads = redact;
+        self
+    }
+
+    // Record a frame made up of a protocol header (including any group
+    // names) followed by an application payload.
+    pub fn record(&mut self, direction: Direction, header: &[u8], payload: &[u8], logger: &dyn LogSink) {
+        let arrow = match direction {
+            Direction::Inbound => "<<",
+            Direction::Outbound => ">>"
+        };
+        let mut dump = format!("{} {:?} frame: {} header bytes, {} payload bytes\n",
+                               arrow, direction, header.len(), payload.len());
+        dump.push_str("header:\n");
+        dump.push_str(hex_dump(header).as_str());
+        if self.redact_payloads {
+            dump.push_str(format!("payload: <{} bytes redacted>\n", payload.len()).as_str());
+        } else if !payload.is_empty() {
+            dump.push_str("payload:\n");
+            dump.push_str(hex_dump(payload).as_str());
+        }
+
+        match self.sink {
+            CaptureSink::Log => logger.log(Level::Debug, dump.as_str()),
+            CaptureSink::Writer(ref mut writer) => {
+                if let Err(error) = writer.write_all(dump.as_bytes()) {
+                    logger.log(Level::Warn, format!("Failed to write captured frame: {}", error).as_str());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Mirroring Spread groups into tokio channels.
+//!
+//! A `ChannelBridge` joins a set of groups on a session of its own and
+//! republishes every data message received on each of them to that group's
+//! `tokio::sync::broadcast` channel, for any number of async consumers, and
+//! to its `watch` channel, which holds only the latest message. A message
+//! addressed to several bridged groups goes to each of their channels. A
+//! consumer that falls more than the configured capacity behind misses the
+//! oldest messages and is told how many with `RecvError::Lagged`.
+//!
+//! Given a second session with `with_outbound`, the bridge also runs the
+//! other way, multicasting every payload sent on a broadcast channel to a
+//! set of groups. Both directions run on threads of their own, so neither
+//! the sessions nor the consumers need a runtime.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use sync::{AtomicBool, Ordering};
+use sync::thread::JoinHandle;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use threads::ThreadOptions;
+use {Error, Level, SpreadClient, SpreadMessage};
+
+/// Messages each broadcast channel holds for consumers that fall behind,
+/// by default.
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+/// What a `ChannelBridge` mirrors and how.
+#[derive(Clone, Debug)]
+pub struct ChannelConfig {
+    /// Groups whose traffic is mirrored, each into channels of its own.
+    pub groups: Vec<String>,
+    /// Messages each broadcast channel holds for its slowest consumer.
+    pub capacity: usize,
+    /// Options for the forwarding threads, named `channels-inbound` and
+    /// `channels-outbound`.
+    pub threads: ThreadOptions
+}
+
+impl ChannelConfig {
+    pub fn new(groups: &[&str]) -> ChannelConfig {
+        ChannelConfig {
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            capacity: DEFAULT_CHANNEL_CAPACITY,
+            threads: ThreadOptions::new()
+        }
+    }
+}
+
+// The sending ends of one group's channels.
+struct GroupSenders {
+    messages: broadcast::Sender<SpreadMessage>,
+    latest: watch::Sender<Option<SpreadMessage>>
+}
+
+// The receiving ends of one group's channels, kept to subscribe from.
+// Broadcast receivers are made by resubscribing, so the channels close
+// once the inbound thread stops.
+struct GroupReceivers {
+    messages: broadcast::Receiver<SpreadMessage>,
+    latest: watch::Receiver<Option<SpreadMessage>>
+}
+
+/// A running bridge between Spread groups and tokio channels.
+pub struct ChannelBridge {
+    receivers: HashMap<String, GroupReceivers>,
+    threads: ThreadOptions,
+    shutdown: Arc<AtomicBool>,
+    inbound: JoinHandle<()>,
+    outbound: Option<JoinHandle<()>>
+}
+
+impl ChannelBridge {
+    /// Join the configured groups on `client` and start mirroring them.
+    /// With a read timeout set on `client`, each timeout is a chance to
+    /// notice `shutdown`; without one, it is noticed after the next message.
+    /// The inbound direction stops, closing the channels, when the session
+    /// fails.
+    pub fn spawn(mut client: SpreadClient, config: ChannelConfig) -> Result<ChannelBridge, Error> {
+        for group in config.groups.iter() {
+            client.join(group.as_str())?;
+        }
+        let mut senders = HashMap::new();
+        let mut receivers = HashMap::new();
+        for group in config.groups.iter() {
+            let (messages, subscription) = broadcast::channel(config.capacity.max(1));
+            let (latest, watched) = watch::channel(None);
+            senders.insert(group.clone(), GroupSenders { messages: messages, latest: latest });
+            receivers.insert(group.clone(), GroupReceivers { messages: subscription, latest: watched });
+        }
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let inbound = {
+            let shutdown = shutdown.clone();
+            config.threads.spawn("channels-inbound", move || {
+                mirror_inbound(&mut client, &senders, &shutdown);
+                let _ = client.disconnect();
+            })
+        };
+        Ok(ChannelBridge {
+            receivers: receivers,
+            threads: config.threads,
+            shutdown: shutdown,
+            inbound: inbound,
+            outbound: None
+        })
+    }
+
+    /// Also multicast every payload sent on the channel `payloads` reads
+    /// from to `groups`, through `client`. This direction stops once every
+    /// sender of that channel has been dropped. Payloads the channel
+    /// dropped because the session couldn't keep up are logged and skipped.
+    pub fn with_outbound(mut self, mut client: SpreadClient, mut payloads: broadcast::Receiver<Vec<u8>>,
+                         groups: &[&str]) -> ChannelBridge {
+        let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+        self.outbound = Some(self.threads.spawn("channels-outbound", move || {
+            let groups: Vec<&str> = groups.iter().map(|g| g.as_str()).collect();
+            loop {
+                match payloads.blocking_recv() {
+                    Ok(data) => if let Err(error) = client.multicast(groups.as_slice(), data.as_slice()) {
+                        client_log!(client, Level::Warn, "Channel bridge multicast failed: {}", error);
+                    },
+                    Err(RecvError::Lagged(skipped)) => {
+                        client_log!(client, Level::Warn,
+                                    "Channel bridge fell behind; skipped {} payload(s)", skipped);
+                    },
+                    Err(RecvError::Closed) => break
+                }
+            }
+            let _ = client.disconnect();
+        }));
+        self
+    }
+
+    /// A new consumer of the messages received on `group` from now on, or
+    /// `None` if `group` isn't bridged.
+    pub fn subscribe(&self, group: &str) -> Option<broadcast::Receiver<SpreadMessage>> {
+        self.receivers.get(group).map(|receivers| receivers.messages.resubscribe())
+    }
+
+    /// A watcher of the latest message received on `group`, `None` until
+    /// the first arrives, or `None` if `group` isn't bridged.
+    pub fn watch(&self, group: &str) -> Option<watch::Receiver<Option<SpreadMessage>>> {
+        self.receivers.get(group).map(|receivers| receivers.latest.clone())
+    }
+
+    /// Stop mirroring and wait for both directions to finish. The
+    /// outbound direction only finishes once its channel has closed.
+    pub fn shutdown(self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.inbound.join();
+        if let Some(outbound) = self.outbound {
+            let _ = outbound.join();
+        }
+    }
+}
+
+fn mirror_inbound(client: &mut SpreadClient, senders: &HashMap<String, GroupSenders>, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::SeqCst) {
+        let message = match client.receive() {
+            Ok(message) => message,
+            Err(Error::Timeout) => continue,
+            Err(error) => {
+                client_log!(client, Level::Warn, "Channel bridge receive failed: {}", error);
+                return;
+            }
+        };
+        if message.is_membership() {
+            continue;
+        }
+        for group in message.groups().iter() {
+            if let Some(group_senders) = senders.get(group.as_str().trim_end_matches('\0')) {
+                // Sending only fails when nobody is subscribed.
+                let _ = group_senders.messages.send(message.clone());
+                group_senders.latest.send_replace(Some(message.clone()));
+            }
+        }
+    }
+}
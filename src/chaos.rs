@@ -0,0 +1,84 @@
+//! Runtime fault injection for resilience testing.
+//!
+//! Only exposed when the crate is built with the `chaos` feature. The hooks
+//! let an application simulate network trouble against a live daemon
+//! without external tooling.
+
+use time::Duration;
+
+/// Fault-injection switches consulted by a `SpreadClient` on every read and
+/// write.
+pub struct ChaosHooks {
+    disconnect_requested: bool,
+    write_delay: Option<Duration>,
+    frames_to_drop: usize,
+    receive_paused: bool
+}
+
+impl ChaosHooks {
+    pub fn new() -> ChaosHooks {
+        ChaosHooks {
+            disconnect_requested: false,
+            write_delay: None,
+            frames_to_drop: 0,
+            receive_paused: false
+        }
+    }
+
+    /// Sever the connection to the daemon before the next read or write.
+    pub fn force_disconnect(&mut self) {
+        self.disconnect_requested = true;
+    }
+
+    /// Sleep for `delay` before every subsequent write, or stop delaying if
+    /// `None`.
+    pub fn delay_writes(&mut self, delay: Option<Duration>) {
+        self.write_delay = delay;
+    }
+
+    /// Silently discard the next `n` outbound frames.
+    pub fn drop_next_frames(&mut self, n: usize) {
+        self.frames_to_drop = n;
+    }
+
+    /// Make `receive` fail immediately until `resume_receive` is called.
+    pub fn pause_receive(&mut self) {
+        self.receive_paused = true;
+    }
+
+    /// Undo a previous `pause_receive`.
+    pub fn resume_receive(&mut self) {
+        self.receive_paused = false;
+    }
+
+    /// Clear every hook.
+    pub fn reset(&mut self) {
+        *self = ChaosHooks::new();
+    }
+
+    // Returns true (once) if a disconnect has been requested.
+    pub fn take_disconnect(&mut self) -> bool {
+        let requested = self.disconnect_requested;
+        self.disconnect_requested = false;
+        requested
+    }
+
+    // Returns true if the next outbound frame should be dropped, consuming
+    // one unit of the drop budget.
+    pub fn take_dropped_frame(&mut self) -> bool {
+        if self.frames_to_drop > 0 {
+            self.frames_to_drop -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn write_delay(&self) -> Option<Duration> {
+        self.write_delay
+    }
+
+    pub fn is_receive_paused(&self) -> bool {
+        self.receive_paused
+    }
+}
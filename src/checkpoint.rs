@@ -0,0 +1,208 @@
+//! Saving a client's session intent to disk and restoring it after a
+//! restart.
+//!
+//! A checkpoint records the private name, the groups joined (and which are
+//! membership-only), the next outgoing sequence number, and optionally the
+//! next sequence number expected from each sender by a `Resequencer`. It is
+//! stored as a small line-oriented text file:
+//!
+//! ```text
+//! spread-checkpoint 1
+//! private_name #worker#daemon1
+//! group orders
+//! monitor presence
+//! next_sequence 42
+//! expected 17 #producer#daemon2
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(windows)]
+use time::Duration;
+use resequence::Resequencer;
+#[cfg(windows)]
+use util;
+use {Error, Level, SpreadClient};
+
+static HEADER: &'static str = "spread-checkpoint 1";
+
+// The errors Windows reports when replacing a file another process holds
+// open, and how many times to retry before giving up.
+#[cfg(windows)]
+static ERROR_ACCESS_DENIED: i32 = 5;
+#[cfg(windows)]
+static ERROR_SHARING_VIOLATION: i32 = 32;
+#[cfg(windows)]
+static REPLACE_ATTEMPTS: u32 = 6;
+
+/// A snapshot of a client's session intent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionCheckpoint {
+    /// The private group name of the checkpointed session. Reconnect with
+    /// the same user name to get it back.
+    pub private_name: String,
+    /// Groups joined for their messages.
+    pub groups: Vec<String>,
+    /// Groups joined for membership changes only.
+    pub monitored_groups: Vec<String>,
+    /// The sequence number of the next outgoing message, if sequence
+    /// stamping was on.
+    pub next_sequence: Option<u64>,
+    /// The next sequence number expected from each sender.
+    pub expected: BTreeMap<String, u64>
+}
+
+impl SessionCheckpoint {
+    /// Capture `client`'s session intent.
+    pub fn capture(client: &SpreadClient) -> SessionCheckpoint {
+        let (monitored, groups) = client.groups().iter().cloned()
+            .partition(|g| client.is_monitor_only(g.as_str()));
+        SessionCheckpoint {
+            private_name: client.private_name.clone(),
+            groups: groups,
+            monitored_groups: monitored,
+            next_sequence: client.next_sequence(),
+            expected: BTreeMap::new()
+        }
+    }
+
+    /// Also record `resequencer`'s position for each sender.
+    pub fn with_resequencer(mut self, resequencer: &Resequencer) -> SessionCheckpoint {
+        self.expected = resequencer.positions();
+        self
+    }
+
+    /// The user name to reconnect with to get the same private name back.
+    pub fn user_name(&self) -> &str {
+        self.private_name.as_str().split('#').nth(1).unwrap_or("")
+    }
+
+    /// Re-join the checkpointed groups on `client` and resume its outgoing
+    /// sequence.
+    pub fn restore(&self, client: &mut SpreadClient) -> Result<(), Error> {
+        if client.private_name != self.private_name {
+            client_log!(client, Level::Warn, "Restoring checkpoint of \"{}\" onto session \"{}\"",
+                        self.private_name, client.private_name);
+        }
+        for group in self.groups.iter() {
+            client.join(group.as_str())?;
+        }
+        for group in self.monitored_groups.iter() {
+            client.join_monitor(group.as_str())?;
+        }
+        if let Some(next) = self.next_sequence {
+            client.resume_sequencing(next);
+        }
+        Ok(())
+    }
+
+    /// Resume `resequencer` at the checkpointed position for each sender.
+    pub fn restore_resequencer(&self, resequencer: &mut Resequencer) {
+        for (sender, next) in self.expected.iter() {
+            resequencer.resume(sender.as_str(), *next);
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut out = format!("{}\nprivate_name {}\n", HEADER, self.private_name);
+        for group in self.groups.iter() {
+            out.push_str(format!("group {}\n", group).as_str());
+        }
+        for group in self.monitored_groups.iter() {
+            out.push_str(format!("monitor {}\n", group).as_str());
+        }
+        if let Some(next) = self.next_sequence {
+            out.push_str(format!("next_sequence {}\n", next).as_str());
+        }
+        for (sender, next) in self.expected.iter() {
+            out.push_str(format!("expected {} {}\n", next, sender).as_str());
+        }
+        out
+    }
+
+    pub fn decode(text: &str) -> Result<SessionCheckpoint, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("missing checkpoint header".to_string());
+        }
+        let mut checkpoint = SessionCheckpoint {
+            private_name: String::new(),
+            groups: Vec::new(),
+            monitored_groups: Vec::new(),
+            next_sequence: None,
+            expected: BTreeMap::new()
+        };
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => return Err(format!("malformed line \"{}\"", line))
+            };
+            match key {
+                "private_name" => checkpoint.private_name = value.to_string(),
+                "group" => checkpoint.groups.push(value.to_string()),
+                "monitor" => checkpoint.monitored_groups.push(value.to_string()),
+                "next_sequence" => checkpoint.next_sequence = Some(parse_u64(value)?),
+                "expected" => {
+                    let (next, sender) = match value.find(' ') {
+                        Some(i) => (parse_u64(&value[..i])?, &value[i + 1..]),
+                        None => return Err(format!("malformed line \"{}\"", line))
+                    };
+                    checkpoint.expected.insert(sender.to_string(), next);
+                },
+                _ => return Err(format!("unknown key \"{}\"", key))
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    /// Write the checkpoint to `path`, replacing any previous one.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        // Write to a temporary file first so a crash mid-write cannot leave
+        // a truncated checkpoint behind.
+        let temporary = path.with_extension("tmp");
+        {
+            let mut file = File::create(&temporary)?;
+            file.write_all(self.encode().as_bytes())?;
+            file.sync_all()?;
+        }
+        replace(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Read a checkpoint written by `save`.
+    pub fn load(path: &Path) -> Result<SessionCheckpoint, Error> {
+        let text = fs::read_to_string(path)?;
+        SessionCheckpoint::decode(&text)
+            .map_err(|reason| Error::InvalidInput(format!("Malformed session checkpoint: {}", reason)))
+    }
+}
+
+// Move `from` over `to`. Windows refuses while another process, such as a
+// virus scanner or indexer, briefly holds `to` open, so retry with backoff
+// for up to about a second there.
+#[cfg(windows)]
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match fs::rename(from, to) {
+            Err(ref error) if attempt < REPLACE_ATTEMPTS &&
+                (error.raw_os_error() == Some(ERROR_ACCESS_DENIED) ||
+                 error.raw_os_error() == Some(ERROR_SHARING_VIOLATION)) => {
+                util::sleep(Duration::milliseconds(16 << attempt));
+                attempt += 1;
+            },
+            result => return result
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to)
+}
+
+fn parse_u64(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("invalid number \"{}\"", value))
+}
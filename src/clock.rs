@@ -0,0 +1,85 @@
+//! Injectable time sources.
+//!
+//! Every timestamp, rate, latency and wait computed by a client comes from
+//! its `Clock`, so tests can substitute a `MockClock` and control time
+//! explicitly.
+
+use std::sync::Arc;
+use sync::Mutex;
+use time::{get_time, precise_time_ns, Duration, Timespec};
+use util;
+
+/// A source of the current time.
+pub trait Clock: Send {
+    /// Returns the current wall-clock time.
+    fn now(&self) -> Timespec;
+
+    /// Returns nanoseconds since an arbitrary fixed point, for measuring
+    /// intervals. Unlike `now`, it never goes backwards.
+    fn monotonic_ns(&self) -> u64 {
+        precise_time_ns()
+    }
+
+    /// Block the calling thread for `duration`, or not at all if it is
+    /// negative.
+    fn sleep(&self, duration: Duration) {
+        util::sleep(duration);
+    }
+}
+
+/// The system clock.
+#[derive(Copy, Clone, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timespec {
+        get_time()
+    }
+}
+
+/// A manually-advanced clock for deterministic tests.
+///
+/// Clones share the same underlying time, so a test can hand one clone to a
+/// client and keep another to move time forward. Sleeping on it returns at
+/// once, advancing the time by the duration slept.
+#[derive(Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Timespec>>
+}
+
+impl MockClock {
+    /// Create a clock frozen at `start`.
+    pub fn new(start: Timespec) -> MockClock {
+        MockClock { now: Arc::new(Mutex::new(start)) }
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + duration;
+    }
+
+    /// Set the clock to `time`.
+    pub fn set(&self, time: Timespec) {
+        *self.now.lock().unwrap() = time;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timespec {
+        *self.now.lock().unwrap()
+    }
+
+    /// The current time in nanoseconds since the Unix epoch. Unlike a real
+    /// monotonic clock, this goes backwards if `set` moves it back.
+    fn monotonic_ns(&self) -> u64 {
+        let now = self.now();
+        (now.sec as u64).wrapping_mul(1_000_000_000).wrapping_add(now.nsec as u64)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        if duration > Duration::zero() {
+            self.advance(duration);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! The client API as it was before the port to `std::net` and the
+//! structured `Error` type, kept for one release so code written against it
+//! can be moved over a call at a time.
+//!
+//! `compat::SpreadClient` wraps a `spread::SpreadClient` and gives the
+//! connection, group, send and receive methods their old signatures, which
+//! report failures as `io::Error`. Every other method is reached through
+//! `Deref` with its current signature, and `into_client` hands over the
+//! wrapped client once nothing needs the old signatures.
+//!
+//! Errors keep the kinds the old API used: timeouts are `TimedOut`, a
+//! rejected session is `ConnectionRefused`, a closed connection is
+//! `UnexpectedEof`, malformed input either way is `InvalidInput`, quota,
+//! memory-cap and chaos-pause failures are `WouldBlock`, and daemon error
+//! codes are `Other`.
+
+#![allow(deprecated)]
+
+use std::io;
+use std::net::ToSocketAddrs;
+use std::ops::{Deref, DerefMut};
+use time::Duration;
+use {InFlightMessage, OutboundMessage, RawFrame, Received, SessionSummary, SpreadErrorCode,
+     SpreadMessage, SpreadMessageRef, Transport};
+
+/// The result type the old API returned.
+#[deprecated(note = "use `Result<T, spread::Error>`")]
+pub type IoResult<T> = io::Result<T>;
+
+/// The old name of `SpreadErrorCode`.
+#[deprecated(note = "renamed to `spread::SpreadErrorCode`")]
+pub type SpreadError = SpreadErrorCode;
+
+/// Connect to the daemon at `addr`, as `spread::connect` does.
+#[deprecated(note = "use `spread::connect`")]
+pub fn connect<A: ToSocketAddrs>(
+    addr: A,
+    private_name: &str,
+    receive_membership_messages: bool
+) -> IoResult<SpreadClient> {
+    let client = ::connect(addr, private_name, receive_membership_messages)?;
+    Ok(SpreadClient::from_client(client))
+}
+
+/// Open a session over `stream`, as `spread::connect_with_transport` does.
+#[deprecated(note = "use `spread::connect_with_transport`")]
+pub fn connect_with_transport(
+    stream: Box<dyn Transport>,
+    private_name: &str,
+    receive_membership_messages: bool
+) -> IoResult<SpreadClient> {
+    let client = ::connect_with_transport(stream, private_name, receive_membership_messages)?;
+    Ok(SpreadClient::from_client(client))
+}
+
+/// A client whose core methods keep their old signatures.
+#[deprecated(note = "use `spread::SpreadClient`")]
+pub struct SpreadClient {
+    client: ::SpreadClient
+}
+
+impl SpreadClient {
+    /// Wrap a client made with the current API.
+    pub fn from_client(client: ::SpreadClient) -> SpreadClient {
+        SpreadClient { client: client }
+    }
+
+    /// The wrapped client.
+    pub fn into_client(self) -> ::SpreadClient {
+        self.client
+    }
+
+    pub fn disconnect(&mut self) -> IoResult<SessionSummary> {
+        Ok(self.client.disconnect()?)
+    }
+
+    pub fn join(&mut self, group_name: &str) -> IoResult<()> {
+        Ok(self.client.join(group_name)?)
+    }
+
+    pub fn join_monitor(&mut self, group_name: &str) -> IoResult<()> {
+        Ok(self.client.join_monitor(group_name)?)
+    }
+
+    pub fn leave(&mut self, group_name: &str) -> IoResult<()> {
+        Ok(self.client.leave(group_name)?)
+    }
+
+    pub fn leave_all(&mut self) -> IoResult<()> {
+        Ok(self.client.leave_all()?)
+    }
+
+    pub fn multicast(&mut self, groups: &[&str], data: &[u8]) -> IoResult<()> {
+        Ok(self.client.multicast(groups, data)?)
+    }
+
+    pub fn multicast_str(&mut self, groups: &[&str], text: &str) -> IoResult<()> {
+        Ok(self.client.multicast_str(groups, text)?)
+    }
+
+    pub fn send_batch(&mut self, messages: Vec<OutboundMessage>) -> IoResult<()> {
+        Ok(self.client.send_batch(messages)?)
+    }
+
+    pub fn resend_in_flight(&mut self, messages: Vec<InFlightMessage>) -> IoResult<usize> {
+        Ok(self.client.resend_in_flight(messages)?)
+    }
+
+    pub fn resend_range(&mut self, first: u64, last: u64) -> IoResult<usize> {
+        Ok(self.client.resend_range(first, last)?)
+    }
+
+    pub fn receive(&mut self) -> IoResult<SpreadMessage> {
+        Ok(self.client.receive()?)
+    }
+
+    pub fn try_receive(&mut self) -> IoResult<Option<SpreadMessage>> {
+        Ok(self.client.try_receive()?)
+    }
+
+    pub fn receive_timeout(&mut self, timeout: Duration) -> IoResult<SpreadMessage> {
+        Ok(self.client.receive_timeout(timeout)?)
+    }
+
+    pub fn receive_event(&mut self) -> IoResult<Received> {
+        Ok(self.client.receive_event()?)
+    }
+
+    pub fn receive_raw(&mut self) -> IoResult<RawFrame> {
+        Ok(self.client.receive_raw()?)
+    }
+
+    pub fn receive_ref(&mut self) -> IoResult<SpreadMessageRef<'_>> {
+        Ok(self.client.receive_ref()?)
+    }
+
+    /// Set the read timeout. The old API couldn't fail here, so a transport
+    /// that refuses the timeout is reported to the `on_error` hook instead.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        if let Err(error) = self.client.set_read_timeout(timeout) {
+            self.client.report_error(&error);
+        }
+    }
+
+    /// Set the write timeout, reporting failure as `set_read_timeout` does.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        if let Err(error) = self.client.set_write_timeout(timeout) {
+            self.client.report_error(&error);
+        }
+    }
+
+    pub fn report_error(&mut self, error: &io::Error) {
+        let error = ::Error::from(io::Error::new(error.kind(), error.to_string()));
+        self.client.report_error(&error);
+    }
+
+    pub fn on_error(&mut self, hook: Option<Box<dyn FnMut(&io::Error) + Send>>) {
+        self.client.on_error(hook.map(|mut hook| {
+            Box::new(move |error: &::Error| hook(&io::Error::from(error.clone())))
+                as Box<dyn FnMut(&::Error) + Send>
+        }));
+    }
+}
+
+impl Deref for SpreadClient {
+    type Target = ::SpreadClient;
+
+    fn deref(&self) -> &::SpreadClient {
+        &self.client
+    }
+}
+
+impl DerefMut for SpreadClient {
+    fn deref_mut(&mut self) -> &mut ::SpreadClient {
+        &mut self.client
+    }
+}
@@ -0,0 +1,72 @@
+//! Copying a sample of a client's traffic to a diagnostics group.
+
+use capture::Direction;
+use envelope::{Envelope, TAG_DIRECTION, TAG_GROUPS, TAG_ORIGIN};
+use error::catch_panic;
+use Error;
+
+/// Settings for republishing a client's inbound and outbound messages to a
+/// debug group, where an operator can watch them from another session.
+///
+/// Each copy is enveloped with the client's private name, the direction of
+/// the original message, and its groups; the payload is unchanged.
+pub struct DebugMirror {
+    group: String,
+    sample_every: u64,
+    seen: u64,
+    filter: Option<Box<dyn Fn(Direction, &[&str], &[u8]) -> bool + Send>>
+}
+
+impl DebugMirror {
+    /// Copy traffic to `group`.
+    pub fn new(group: &str) -> DebugMirror {
+        DebugMirror { group: group.to_string(), sample_every: 1, seen: 0, filter: None }
+    }
+
+    /// Copy only one in every `n` messages that pass the filter.
+    pub fn sample_every(mut self, n: u64) -> DebugMirror {
+        self.sample_every = if n == 0 { 1 } else { n };
+        self
+    }
+
+    /// Only copy messages for which `filter`, given the direction, groups
+    /// and payload, returns true.
+    pub fn filter(mut self, filter: Box<dyn Fn(Direction, &[&str], &[u8]) -> bool + Send>) -> DebugMirror {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn group(&self) -> &str {
+        self.group.as_str()
+    }
+
+    // Decide whether to copy a message, advancing the sampling counter.
+    // Fails if the filter panics.
+    pub fn should_mirror(&mut self, direction: Direction, groups: &[&str], data: &[u8])
+                         -> Result<bool, Error> {
+        // Never copy traffic on the debug group itself.
+        if groups.contains(&self.group.as_str()) {
+            return Ok(false);
+        }
+        if let Some(ref filter) = self.filter {
+            if !catch_panic("Debug mirror filter", || (**filter)(direction, groups, data))? {
+                return Ok(false);
+            }
+        }
+        self.seen += 1;
+        Ok((self.seen - 1).is_multiple_of(self.sample_every))
+    }
+
+    // Build the payload of the copy.
+    pub fn encode(origin: &str, direction: Direction, groups: &[&str], data: &[u8])
+        -> Result<Vec<u8>, Error> {
+        let mut envelope = Envelope::new(data);
+        envelope.set_field(TAG_ORIGIN, origin.as_bytes());
+        envelope.set_field(TAG_DIRECTION, match direction {
+            Direction::Inbound => b"in",
+            Direction::Outbound => b"out"
+        });
+        envelope.set_field(TAG_GROUPS, groups.join("\n").as_bytes());
+        envelope.encode()
+    }
+}
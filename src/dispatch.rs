@@ -0,0 +1,991 @@
+//! Routing received messages to registered handlers.
+//!
+//! A `Dispatcher` holds named handlers and the groups routed to each. Every
+//! message it is given is passed to the handlers of each of its groups; a
+//! message no handler accepts is kept in the dispatcher's dead-letter queue
+//! together with the handler and the error, rather than lost. The queue can
+//! be inspected, retried through the handlers or drained. It holds a fixed
+//! number of entries in memory and, if given a spill file, appends older
+//! ones to it as `tap` JSON lines with `handler`, `error` and `attempts`
+//! fields added. Given a client's `MemoryBudget`, the letters it holds in
+//! memory also count against that client's memory cap.
+//!
+//! Besides routing whole groups, a dispatcher can be given `RoutingRule`s
+//! matching the message type, the sender, a group or the payload, each
+//! sending matches to a named handler or to a named queue to be taken later.
+//! Rules can be loaded from a text file, one rule per line:
+//!
+//! ```text
+//! # Alerts from any monitor are paged; JSON orders are queued for the gateway.
+//! type 9 sender #monitor#* -> handler pager
+//! group orders.* payload json -> queue gateway
+//! ```
+//!
+//! `payload` names a predicate registered with `register_predicate`.
+//!
+//! A handler that panics doesn't take the receive loop down with it: the
+//! message is dead-lettered, the panic is reported as an error, and the
+//! handler's `Supervision` decides whether it is restarted, cut off from
+//! the message's groups, or shuts the dispatcher down.
+//!
+//! With the `tokio` feature, handlers can also be async, registered with
+//! `register_async`. Each message routed to one is handed to it as a task
+//! on a tokio runtime, so dispatching never waits for it, and at most a set
+//! number of such tasks per group run at once; the rest wait their turn on
+//! the runtime. Failed tasks are dead-lettered when the dispatcher next
+//! collects them, and a panic in one disables its group or shuts the
+//! dispatcher down as its `Supervision` says; restarting has no effect on
+//! an async handler, which keeps serving other tasks.
+//!
+//! With the `opentelemetry` feature, the trace context a message carries is
+//! current while its handlers run (see the `trace_context` module).
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+use time::Timespec;
+#[cfg(all(feature = "tokio", feature = "opentelemetry"))]
+use opentelemetry::context::FutureExt;
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle;
+#[cfg(feature = "tokio")]
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+use error::catch_panic;
+use memory::{message_bytes, MemoryBudget, MemoryCapPolicy};
+#[cfg(feature = "tokio")]
+use sync::{channel, Receiver, Sender};
+use tap::{from_json_line, to_json_line};
+#[cfg(feature = "opentelemetry")]
+use trace_context;
+use util::{glob_match, json_string, parse_json_object, JsonValue};
+use {Error, Level, SpreadClient, SpreadMessage};
+
+/// Dead letters held in memory by default before older ones are spilled
+/// or dropped.
+pub const DEFAULT_DEAD_LETTER_CAPACITY: usize = 1024;
+
+/// Async handler tasks that run at once for each group by default.
+#[cfg(feature = "tokio")]
+pub const DEFAULT_GROUP_CONCURRENCY: usize = 16;
+
+/// Something that processes received messages.
+pub trait Handler: Send {
+    /// Process `message`. An error sends the message to the dead-letter
+    /// queue with the error as its reason.
+    fn handle(&mut self, message: &SpreadMessage) -> Result<(), String>;
+}
+
+impl<F: FnMut(&SpreadMessage) -> Result<(), String> + Send> Handler for F {
+    fn handle(&mut self, message: &SpreadMessage) -> Result<(), String> {
+        self(message)
+    }
+}
+
+/// Something that processes received messages as tasks on a tokio
+/// runtime, e.g. an `async` closure taking a `SpreadMessage`.
+#[cfg(feature = "tokio")]
+pub trait AsyncHandler: Send + Sync {
+    /// The task processing `message`. An error sends the message to the
+    /// dead-letter queue with the error as its reason.
+    fn handle(&self, message: SpreadMessage) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+}
+
+#[cfg(feature = "tokio")]
+impl<F, T> AsyncHandler for F
+    where F: Fn(SpreadMessage) -> T + Send + Sync, T: Future<Output = Result<(), String>> + Send + 'static
+{
+    fn handle(&self, message: SpreadMessage) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        Box::pin(self(message))
+    }
+}
+
+/// A message a handler failed to process.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeadLetter {
+    pub message: SpreadMessage,
+    /// The name of the handler that failed.
+    pub handler: String,
+    /// Why the handler failed.
+    pub error: String,
+    /// When the handler last failed.
+    pub failed_at: Timespec,
+    /// How many times the handler has been given the message.
+    pub attempts: u32
+}
+
+/// Messages handlers failed to process, oldest first.
+pub struct DeadLetterQueue {
+    entries: VecDeque<DeadLetter>,
+    capacity: usize,
+    spill: Option<PathBuf>,
+    spilled: usize,
+    dropped: u64,
+    bytes: usize,
+    budget: Option<MemoryBudget>
+}
+
+impl DeadLetterQueue {
+    /// Hold up to `capacity` dead letters in memory, dropping the oldest
+    /// beyond that.
+    pub fn new(capacity: usize) -> DeadLetterQueue {
+        DeadLetterQueue {
+            entries: VecDeque::new(),
+            capacity: capacity,
+            spill: None,
+            spilled: 0,
+            dropped: 0,
+            bytes: 0,
+            budget: None
+        }
+    }
+
+    /// Count the letters held in memory against `budget`, e.g. the one
+    /// returned by `SpreadClient::memory_budget`, and keep within its cap
+    /// as its policy dictates. Letters beyond the cap are spilled, if there
+    /// is a spill file, rather than dropped.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> DeadLetterQueue {
+        budget.charge(self.bytes);
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Append dead letters beyond the in-memory capacity to the file at
+    /// `path` instead of dropping them. Letters already in the file, e.g.
+    /// from an earlier run, are counted and returned by `drain`.
+    pub fn with_spill_file(mut self, path: PathBuf) -> DeadLetterQueue {
+        self.spilled = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().count(),
+            Err(_) => 0
+        };
+        self.spill = Some(path);
+        self
+    }
+
+    /// Dead letters in memory and in the spill file.
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Dead letters appended to the spill file and not yet drained.
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    /// Dead letters forgotten because the queue was full and had no spill
+    /// file, or the spill file couldn't be written.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Bytes of the dead letters held in memory.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The dead letters held in memory, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &DeadLetter> {
+        self.entries.iter()
+    }
+
+    /// Add a dead letter, spilling or dropping the oldest one if the queue
+    /// is full. If it doesn't fit within the memory budget's cap, it is
+    /// handled as the budget's policy dictates: under
+    /// `MemoryCapPolicy::Error` it is dropped and `Error::BufferLimit`
+    /// returned.
+    pub fn push(&mut self, letter: DeadLetter) -> Result<(), Error> {
+        let bytes = letter_bytes(&letter);
+        if let Some(budget) = self.budget.clone() {
+            while let Err(error) = budget.check(bytes) {
+                match budget.policy() {
+                    MemoryCapPolicy::DropOldest if !self.entries.is_empty() => {
+                        self.evict_oldest();
+                        continue;
+                    },
+                    MemoryCapPolicy::DropNewest => {
+                        self.evict(letter);
+                        return Ok(());
+                    },
+                    _ => {
+                        self.dropped += 1;
+                        return Err(error);
+                    }
+                }
+            }
+            budget.charge(bytes);
+        }
+        self.bytes += bytes;
+        self.entries.push_back(letter);
+        while self.entries.len() > self.capacity {
+            self.evict_oldest();
+        }
+        Ok(())
+    }
+
+    /// Remove and return every dead letter, those spilled to disk first.
+    pub fn drain(&mut self) -> Result<Vec<DeadLetter>, Error> {
+        let mut letters = self.read_spilled()?;
+        if let Some(ref path) = self.spill {
+            if let Err(error) = fs::remove_file(path) {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(Error::from(error));
+                }
+            }
+        }
+        self.spilled = 0;
+        letters.extend(self.entries.drain(..));
+        if let Some(ref budget) = self.budget {
+            budget.release(self.bytes);
+        }
+        self.bytes = 0;
+        Ok(letters)
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self.entries.pop_front().unwrap();
+        let bytes = letter_bytes(&oldest);
+        self.bytes -= bytes;
+        if let Some(ref budget) = self.budget {
+            budget.release(bytes);
+        }
+        self.evict(oldest);
+    }
+
+    // Spill a letter no longer held in memory, or drop it.
+    fn evict(&mut self, letter: DeadLetter) {
+        if self.spill_letter(&letter).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    fn spill_letter(&mut self, letter: &DeadLetter) -> Result<(), ::std::io::Error> {
+        let path = match self.spill {
+            Some(ref path) => path,
+            None => return Err(::std::io::Error::other("no spill file"))
+        };
+        let line = to_json_line(letter.failed_at, &letter.message);
+        let line = format!("{},\"handler\":{},\"error\":{},\"attempts\":{}}}\n",
+                           line.trim_end().trim_end_matches('}'), json_string(letter.handler.as_str()),
+                           json_string(letter.error.as_str()), letter.attempts);
+        OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))?;
+        self.spilled += 1;
+        Ok(())
+    }
+
+    fn read_spilled(&self) -> Result<Vec<DeadLetter>, Error> {
+        let file = match self.spill {
+            Some(ref path) => match File::open(path) {
+                Ok(file) => file,
+                Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(error) => return Err(Error::from(error))
+            },
+            None => return Ok(Vec::new())
+        };
+        let mut letters = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let (failed_at, message) = from_json_line(line.as_str())?;
+            let fields = parse_json_object(line.as_str()).map_err(Error::InvalidInput)?;
+            let mut letter = DeadLetter {
+                message: message,
+                handler: String::new(),
+                error: String::new(),
+                failed_at: failed_at,
+                attempts: 0
+            };
+            for (key, value) in fields {
+                match (key.as_str(), value) {
+                    ("handler", JsonValue::String(handler)) => letter.handler = handler,
+                    ("error", JsonValue::String(error)) => letter.error = error,
+                    ("attempts", JsonValue::Number(attempts)) => letter.attempts = attempts as u32,
+                    _ => {}
+                }
+            }
+            letters.push(letter);
+        }
+        Ok(letters)
+    }
+}
+
+// The bytes a dead letter holds in memory.
+fn letter_bytes(letter: &DeadLetter) -> usize {
+    message_bytes(&letter.message) + letter.handler.len() + letter.error.len()
+}
+
+/// One condition of a `RoutingRule`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleMatch {
+    /// The message has this message type.
+    MessageType(i16),
+    /// The sender's private group name matches this pattern, in which `*`
+    /// and `?` are wildcards.
+    Sender(String),
+    /// One of the message's groups matches this pattern.
+    Group(String),
+    /// The predicate registered under this name accepts the payload.
+    Payload(String)
+}
+
+/// Where a `RoutingRule` sends the messages it matches.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteTarget {
+    /// The handler registered under this name.
+    Handler(String),
+    /// The dispatcher's queue of this name, created on first use.
+    Queue(String)
+}
+
+/// Sends messages meeting every one of its conditions to a target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutingRule {
+    pub matches: Vec<RuleMatch>,
+    pub target: RouteTarget
+}
+
+impl RoutingRule {
+    /// Parse rules, one per line, from the format described in the module
+    /// documentation. Blank lines and lines starting with `#` are skipped.
+    pub fn parse_all(text: &str) -> Result<Vec<RoutingRule>, String> {
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(RoutingRule::parse(line)?);
+        }
+        Ok(rules)
+    }
+
+    /// Parse one rule, e.g. `type 9 sender #monitor#* -> handler pager`.
+    pub fn parse(line: &str) -> Result<RoutingRule, String> {
+        let malformed = || format!("malformed rule \"{}\"", line);
+        let (conditions, target) = line.split_once("->").ok_or_else(malformed)?;
+        let mut matches = Vec::new();
+        let mut words = conditions.split_whitespace();
+        while let Some(key) = words.next() {
+            let value = words.next().ok_or_else(malformed)?;
+            matches.push(match key {
+                "type" => RuleMatch::MessageType(
+                    value.parse().map_err(|_| format!("invalid message type \"{}\"", value))?),
+                "sender" => RuleMatch::Sender(value.to_string()),
+                "group" => RuleMatch::Group(value.to_string()),
+                "payload" => RuleMatch::Payload(value.to_string()),
+                _ => return Err(format!("unknown condition \"{}\"", key))
+            });
+        }
+        let target = match target.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["handler", name] => RouteTarget::Handler(name.to_string()),
+            ["queue", name] => RouteTarget::Queue(name.to_string()),
+            _ => return Err(malformed())
+        };
+        Ok(RoutingRule { matches: matches, target: target })
+    }
+}
+
+/// How a dispatched message fared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DispatchReport {
+    /// Handlers that processed the message.
+    pub handled: usize,
+    /// Async handlers the message was handed to. How they fare is reported
+    /// by `Dispatcher::collect_async_failures`.
+    pub spawned: usize,
+    /// Handlers that failed, each adding a dead letter.
+    pub dead_lettered: usize,
+    /// Queues the message was added to by routing rules.
+    pub queued: usize,
+    /// Panics caught from handlers, as `Error::CallbackPanicked`. Each is
+    /// also counted in `dead_lettered`.
+    pub panics: Vec<Error>,
+    /// Why dead letters couldn't be kept, as `Error::BufferLimit` from the
+    /// queue's memory budget. Each is also counted in `dead_lettered`.
+    pub unqueued: Vec<Error>
+}
+
+impl DispatchReport {
+    /// True if no handler or queue was routed the message.
+    pub fn is_unrouted(&self) -> bool {
+        self.handled == 0 && self.spawned == 0 && self.dead_lettered == 0 && self.queued == 0
+    }
+}
+
+/// What a dispatcher does when a handler panics. The message it panicked
+/// on is dead-lettered whatever the policy.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Supervision {
+    /// Carry on with a new handler from the factory the handler was
+    /// registered with, or with the same handler if it has none.
+    RestartHandler,
+    /// Stop routing the groups of the message it panicked on to the
+    /// handler.
+    DisableGroup,
+    /// Stop dispatching: `dispatch` routes nothing more and `dispatch_next`
+    /// fails with the panic.
+    Shutdown
+}
+
+// Why a handler failed on a message: the panic, if it panicked, and why the
+// message couldn't be dead-lettered, if it couldn't.
+struct Failure {
+    panic: Option<Error>,
+    unqueued: Option<Error>
+}
+
+struct Registered {
+    name: String,
+    handler: Box<dyn Handler>,
+    factory: Option<Box<dyn FnMut() -> Box<dyn Handler> + Send>>,
+    supervision: Supervision
+}
+
+/// Named handlers and the groups and rules routed to them.
+pub struct Dispatcher {
+    handlers: Vec<Registered>,
+    routes: Vec<(String, String)>,
+    rules: Vec<RoutingRule>,
+    predicates: HashMap<String, Box<dyn Fn(&[u8]) -> bool + Send>>,
+    queues: HashMap<String, VecDeque<SpreadMessage>>,
+    dead_letters: DeadLetterQueue,
+    shut_down: Option<Error>,
+    #[cfg(feature = "tokio")]
+    tasks: AsyncTasks
+}
+
+impl Dispatcher {
+    pub fn new() -> Dispatcher {
+        Dispatcher {
+            handlers: Vec::new(),
+            routes: Vec::new(),
+            rules: Vec::new(),
+            predicates: HashMap::new(),
+            queues: HashMap::new(),
+            dead_letters: DeadLetterQueue::new(DEFAULT_DEAD_LETTER_CAPACITY),
+            shut_down: None,
+            #[cfg(feature = "tokio")]
+            tasks: AsyncTasks::new()
+        }
+    }
+
+    /// Keep dead letters in `queue` rather than the default in-memory queue.
+    pub fn with_dead_letters(mut self, queue: DeadLetterQueue) -> Dispatcher {
+        self.dead_letters = queue;
+        self
+    }
+
+    /// Register `handler` as `name`, replacing any handler of that name.
+    /// It is supervised with `Supervision::RestartHandler` until `supervise`
+    /// says otherwise.
+    pub fn register<H: Handler + 'static>(&mut self, name: &str, handler: H) {
+        self.add(name, Box::new(handler), None);
+    }
+
+    /// Register async `handler` as `name`, replacing any handler of that
+    /// name. It is supervised with `Supervision::RestartHandler`, which
+    /// leaves it be, until `supervise` says otherwise.
+    #[cfg(feature = "tokio")]
+    pub fn register_async<H: AsyncHandler + 'static>(&mut self, name: &str, handler: H) {
+        self.handlers.retain(|r| r.name != name);
+        self.tasks.handlers.insert(name.to_string(), (Arc::new(handler), Supervision::RestartHandler));
+    }
+
+    /// Run async handlers on `runtime`. Without one, they run on the
+    /// runtime `dispatch` is called within, and messages dispatched outside
+    /// any runtime are dead-lettered.
+    #[cfg(feature = "tokio")]
+    pub fn with_runtime(mut self, runtime: Handle) -> Dispatcher {
+        self.tasks.runtime = Some(runtime);
+        self
+    }
+
+    /// Let at most `limit` async handler tasks run at once for messages
+    /// dispatched from now on to `group`.
+    #[cfg(feature = "tokio")]
+    pub fn set_group_concurrency(&mut self, group: &str, limit: usize) {
+        self.tasks.permits.insert(group.to_string(), Arc::new(Semaphore::new(limit.max(1))));
+    }
+
+    /// Async handler tasks running or waiting for their group's turn.
+    #[cfg(feature = "tokio")]
+    pub fn running_tasks(&self) -> usize {
+        self.tasks.running.load(Ordering::SeqCst)
+    }
+
+    /// Dead-letter the messages async handler tasks have failed on since
+    /// last called, applying the handlers' supervision to panics. Failures
+    /// are held until collected, which `dispatch_next` also does.
+    #[cfg(feature = "tokio")]
+    pub fn collect_async_failures(&mut self) -> DispatchReport {
+        let mut report = DispatchReport::default();
+        while let Ok((letter, panic)) = self.tasks.failed.try_recv() {
+            if let Some(ref panic) = panic {
+                self.supervise_async_panic(letter.handler.as_str(), &letter.message, panic);
+            }
+            report.dead_lettered += 1;
+            report.panics.extend(panic);
+            report.unqueued.extend(self.dead_letters.push(letter).err());
+        }
+        report
+    }
+
+    /// Register a handler made by `factory` as `name`, replacing any handler
+    /// of that name. If it panics under `Supervision::RestartHandler`, it
+    /// is replaced by a new one from `factory`.
+    pub fn register_restartable<H, F>(&mut self, name: &str, mut factory: F)
+        where H: Handler + 'static, F: FnMut() -> H + Send + 'static
+    {
+        let handler = Box::new(factory());
+        self.add(name, handler, Some(Box::new(move || Box::new(factory()) as Box<dyn Handler>)));
+    }
+
+    /// Apply `supervision` when the handler named `name` panics.
+    pub fn supervise(&mut self, name: &str, supervision: Supervision) {
+        if let Some(registered) = self.handlers.iter_mut().find(|r| r.name == name) {
+            registered.supervision = supervision;
+        }
+        #[cfg(feature = "tokio")]
+        {
+            if let Some(registered) = self.tasks.handlers.get_mut(name) {
+                registered.1 = supervision;
+            }
+        }
+    }
+
+    /// Pass messages addressed to `group` to the handler named `handler`.
+    pub fn route_group(&mut self, group: &str, handler: &str) {
+        let route = (group.to_string(), handler.to_string());
+        if !self.routes.contains(&route) {
+            self.routes.push(route);
+        }
+    }
+
+    /// Route messages matching `rule` to its target. Rules are checked in
+    /// the order they were added and each message goes to the target of the
+    /// first it matches, besides the handlers its groups are routed to.
+    pub fn add_rule(&mut self, rule: RoutingRule) {
+        self.rules.push(rule);
+    }
+
+    /// Add the rules in the file at `path`, in the format described in the
+    /// module documentation.
+    pub fn load_rules(&mut self, path: &Path) -> Result<(), Error> {
+        let text = fs::read_to_string(path)?;
+        let rules = RoutingRule::parse_all(&text)
+            .map_err(|reason| Error::InvalidInput(format!("Malformed routing rules: {}", reason)))?;
+        self.rules.extend(rules);
+        Ok(())
+    }
+
+    /// Make `predicate` available to rules as `payload <name>`. A rule
+    /// naming a predicate that isn't registered matches nothing.
+    pub fn register_predicate<P>(&mut self, name: &str, predicate: P)
+        where P: Fn(&[u8]) -> bool + Send + 'static
+    {
+        self.predicates.insert(name.to_string(), Box::new(predicate));
+    }
+
+    /// The messages routed to the queue named `name`, oldest first.
+    pub fn queued(&self, name: &str) -> Option<&VecDeque<SpreadMessage>> {
+        self.queues.get(name)
+    }
+
+    /// Remove and return the messages routed to the queue named `name`.
+    pub fn take_queued(&mut self, name: &str) -> Vec<SpreadMessage> {
+        self.queues.get_mut(name).map(|queue| queue.drain(..).collect()).unwrap_or_default()
+    }
+
+    /// Returns true if `group` is routed to the handler named `handler`,
+    /// i.e. the route was added and hasn't been disabled.
+    pub fn is_routed(&self, group: &str, handler: &str) -> bool {
+        self.routes.iter().any(|(g, h)| g == group && h == handler)
+    }
+
+    /// The panic that shut the dispatcher down, if a handler supervised
+    /// with `Supervision::Shutdown` has panicked.
+    pub fn shut_down(&self) -> Option<&Error> {
+        self.shut_down.as_ref()
+    }
+
+    pub fn dead_letters(&self) -> &DeadLetterQueue {
+        &self.dead_letters
+    }
+
+    pub fn dead_letters_mut(&mut self) -> &mut DeadLetterQueue {
+        &mut self.dead_letters
+    }
+
+    /// Pass `message`, received at `now`, to each handler routed one of
+    /// its groups and to the target of the first rule it matches, once per
+    /// handler. A handler that panics is dealt with by its supervision
+    /// policy.
+    pub fn dispatch(&mut self, now: Timespec, message: &SpreadMessage) -> DispatchReport {
+        let mut report = DispatchReport::default();
+        let mut names = self.handlers_for(message);
+        match self.rules.iter().find(|rule| self.rule_matches(rule, message)).map(|rule| &rule.target) {
+            Some(RouteTarget::Handler(name)) if !names.contains(name) => names.push(name.clone()),
+            Some(RouteTarget::Queue(name)) => {
+                self.queues.entry(name.clone()).or_default().push_back(message.clone());
+                report.queued += 1;
+            },
+            _ => {}
+        }
+        for name in names {
+            if self.shut_down.is_some() {
+                break;
+            }
+            #[cfg(feature = "tokio")]
+            {
+                if self.tasks.handlers.contains_key(name.as_str()) {
+                    match self.spawn(name.as_str(), now, message, 1) {
+                        Ok(()) => report.spawned += 1,
+                        Err(failure) => {
+                            report.dead_lettered += 1;
+                            report.panics.extend(failure.panic);
+                            report.unqueued.extend(failure.unqueued);
+                        }
+                    }
+                    continue;
+                }
+            }
+            match self.invoke(name.as_str(), now, message, 1) {
+                Ok(()) => report.handled += 1,
+                Err(failure) => {
+                    report.dead_lettered += 1;
+                    report.panics.extend(failure.panic);
+                    report.unqueued.extend(failure.unqueued);
+                }
+            }
+        }
+        report
+    }
+
+    /// Receive one message from `client` and dispatch it. Handler panics
+    /// and dead letters that couldn't be kept are reported to the client
+    /// with `report_error`, and once a panic has shut the dispatcher down it
+    /// is returned without receiving.
+    pub fn dispatch_next(&mut self, client: &mut SpreadClient) -> Result<DispatchReport, Error> {
+        #[cfg(feature = "tokio")]
+        {
+            let failures = self.collect_async_failures();
+            if failures.dead_lettered > 0 {
+                client_log!(client, Level::Warn, "{} async handler task(s) failed", failures.dead_lettered);
+            }
+            for error in failures.panics.iter().chain(failures.unqueued.iter()) {
+                client.report_error(error);
+            }
+        }
+        if let Some(ref error) = self.shut_down {
+            return Err(error.clone());
+        }
+        let message = client.receive()?;
+        let report = self.dispatch(client.clock().now(), &message);
+        if report.dead_lettered > 0 {
+            client_log!(client, Level::Warn, "{} handler(s) failed on a message from \"{}\"",
+                        report.dead_lettered, message.sender.trim_end_matches('\0'));
+        }
+        for error in report.panics.iter().chain(report.unqueued.iter()) {
+            client.report_error(error);
+        }
+        Ok(report)
+    }
+
+    /// Give every dead letter, at `now`, to its handler again. Letters
+    /// whose handler fails again, or is no longer registered, are queued
+    /// again; the number that succeeded is returned. Letters for async
+    /// handlers are handed to them again without waiting and aren't
+    /// counted.
+    pub fn retry_dead_letters(&mut self, now: Timespec) -> Result<usize, Error> {
+        let mut succeeded = 0;
+        for letter in self.dead_letters.drain()? {
+            let attempts = letter.attempts + 1;
+            #[cfg(feature = "tokio")]
+            {
+                if self.tasks.handlers.contains_key(letter.handler.as_str()) {
+                    let _ = self.spawn(letter.handler.as_str(), now, &letter.message, attempts);
+                    continue;
+                }
+            }
+            if self.invoke(letter.handler.as_str(), now, &letter.message, attempts).is_ok() {
+                succeeded += 1;
+            }
+        }
+        Ok(succeeded)
+    }
+
+    fn add(&mut self, name: &str, handler: Box<dyn Handler>,
+           factory: Option<Box<dyn FnMut() -> Box<dyn Handler> + Send>>) {
+        let registered = Registered {
+            name: name.to_string(),
+            handler: handler,
+            factory: factory,
+            supervision: Supervision::RestartHandler
+        };
+        match self.handlers.iter().position(|r| r.name == name) {
+            Some(i) => self.handlers[i] = registered,
+            None => self.handlers.push(registered)
+        }
+        #[cfg(feature = "tokio")]
+        self.tasks.handlers.remove(name);
+    }
+
+    // The names of the handlers routed any of `message`'s groups.
+    fn handlers_for(&self, message: &SpreadMessage) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for group in message.groups.iter() {
+            let group = group.as_str().trim_end_matches('\0');
+            for (routed, name) in self.routes.iter() {
+                if routed == group && !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    fn rule_matches(&self, rule: &RoutingRule, message: &SpreadMessage) -> bool {
+        rule.matches.iter().all(|condition| match *condition {
+            RuleMatch::MessageType(mess_type) => message.message_type() == mess_type,
+            RuleMatch::Sender(ref pattern) =>
+                glob_match(pattern.as_str(), message.sender.trim_end_matches('\0')),
+            RuleMatch::Group(ref pattern) => message.groups.iter()
+                .any(|group| glob_match(pattern.as_str(), group.trim_end_matches('\0'))),
+            RuleMatch::Payload(ref name) =>
+                self.predicates.get(name).is_some_and(|predicate| predicate(message.data.as_slice()))
+        })
+    }
+
+    // Give `message` to the handler named `name`, dead-lettering it on
+    // failure. A panic is also returned, after applying the handler's
+    // supervision policy.
+    fn invoke(&mut self, name: &str, now: Timespec, message: &SpreadMessage, attempts: u32)
+              -> Result<(), Failure> {
+        let result = match self.handlers.iter_mut().find(|r| r.name == name) {
+            Some(registered) => {
+                #[cfg(feature = "opentelemetry")]
+                let _trace = trace_context::attach(message);
+                let callback = format!("Handler \"{}\"", name);
+                catch_panic(callback.as_str(), || registered.handler.handle(message))
+            },
+            None => Ok(Err("no handler registered".to_string()))
+        };
+        let (error, panic) = match result {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(error)) => (error, None),
+            Err(panic) => {
+                self.supervise_panic(name, message, &panic);
+                (panic.to_string(), Some(panic))
+            }
+        };
+        let queued = self.dead_letters.push(DeadLetter {
+            message: message.clone(),
+            handler: name.to_string(),
+            error: error,
+            failed_at: now,
+            attempts: attempts
+        });
+        Err(Failure { panic: panic, unqueued: queued.err() })
+    }
+
+    // Hand `message` to the async handler named `name` as a task, to run
+    // once its group has a free turn. With no runtime to run it on, or if
+    // the handler panics making the task, the message is dead-lettered at
+    // once.
+    #[cfg(feature = "tokio")]
+    fn spawn(&mut self, name: &str, now: Timespec, message: &SpreadMessage, attempts: u32)
+             -> Result<(), Failure> {
+        let handler = match self.tasks.handlers.get(name) {
+            Some(registered) => registered.0.clone(),
+            None => return Ok(())
+        };
+        let callback = format!("Handler \"{}\"", name);
+        let made = self.tasks.runtime.clone().or_else(|| Handle::try_current().ok())
+            .ok_or_else(|| ("no tokio runtime to run the handler on".to_string(), None))
+            .and_then(|runtime| match catch_panic(callback.as_str(), || handler.handle(message.clone())) {
+                Ok(task) => Ok((runtime, task)),
+                Err(panic) => Err((panic.to_string(), Some(panic)))
+            });
+        let (runtime, task) = match made {
+            Ok(made) => made,
+            Err((error, panic)) => {
+                if let Some(ref panic) = panic {
+                    self.supervise_async_panic(name, message, panic);
+                }
+                let queued = self.dead_letters.push(DeadLetter {
+                    message: message.clone(),
+                    handler: name.to_string(),
+                    error: error,
+                    failed_at: now,
+                    attempts: attempts
+                });
+                return Err(Failure { panic: panic, unqueued: queued.err() });
+            }
+        };
+        #[cfg(feature = "opentelemetry")]
+        let task: Pin<Box<dyn Future<Output = Result<(), String>> + Send>> =
+            match trace_context::extract(message) {
+                Some(context) => Box::pin(task.with_context(context)),
+                None => task
+            };
+        let group = self.concurrency_group(name, message);
+        let permits = self.tasks.permits.entry(group)
+            .or_insert_with(|| Arc::new(Semaphore::new(DEFAULT_GROUP_CONCURRENCY)))
+            .clone();
+        self.tasks.running.fetch_add(1, Ordering::SeqCst);
+        runtime.spawn(HandlerTask {
+            letter: Some(DeadLetter {
+                message: message.clone(),
+                handler: name.to_string(),
+                error: String::new(),
+                failed_at: now,
+                attempts: attempts
+            }),
+            turn: Some(Box::pin(permits.acquire_owned())),
+            permit: None,
+            task: task,
+            failures: self.tasks.failures.clone(),
+            running: self.tasks.running.clone()
+        });
+        Ok(())
+    }
+
+    // Apply the supervision of the async handler named `name` to `panic`.
+    #[cfg(feature = "tokio")]
+    fn supervise_async_panic(&mut self, name: &str, message: &SpreadMessage, panic: &Error) {
+        let supervision = self.tasks.handlers.get(name).map_or(Supervision::RestartHandler, |&(_, s)| s);
+        match supervision {
+            Supervision::RestartHandler => {},
+            Supervision::DisableGroup => self.disable_groups(name, message),
+            Supervision::Shutdown => self.shut_down = Some(panic.clone())
+        }
+    }
+
+    // The group whose turns the handler named `name` takes for `message`:
+    // the first of its groups routed to the handler, or its first group if
+    // it was routed by a rule.
+    #[cfg(feature = "tokio")]
+    fn concurrency_group(&self, name: &str, message: &SpreadMessage) -> String {
+        let groups: Vec<&str> = message.groups.iter().map(|g| g.as_str().trim_end_matches('\0')).collect();
+        groups.iter()
+            .find(|group| self.routes.iter().any(|(g, h)| g == *group && h == name))
+            .or_else(|| groups.first())
+            .map_or(String::new(), |group| group.to_string())
+    }
+
+    fn supervise_panic(&mut self, name: &str, message: &SpreadMessage, panic: &Error) {
+        let registered = match self.handlers.iter_mut().find(|r| r.name == name) {
+            Some(registered) => registered,
+            None => return
+        };
+        match registered.supervision {
+            Supervision::RestartHandler => if let Some(ref mut factory) = registered.factory {
+                registered.handler = factory();
+            },
+            Supervision::DisableGroup => self.disable_groups(name, message),
+            Supervision::Shutdown => self.shut_down = Some(panic.clone())
+        }
+    }
+
+    // Stop routing the groups of `message` to the handler named `name`.
+    fn disable_groups(&mut self, name: &str, message: &SpreadMessage) {
+        for group in message.groups.iter() {
+            let group = group.as_str().trim_end_matches('\0');
+            self.routes.retain(|(g, h)| !(g == group && h == name));
+        }
+    }
+}
+
+// The async handlers of a dispatcher and the tasks running them.
+#[cfg(feature = "tokio")]
+struct AsyncTasks {
+    handlers: HashMap<String, (Arc<dyn AsyncHandler>, Supervision)>,
+    runtime: Option<Handle>,
+    // Each group's turns, taken by a task while it runs.
+    permits: HashMap<String, Arc<Semaphore>>,
+    running: Arc<AtomicUsize>,
+    // Messages tasks failed on, and the panics that failed them.
+    failures: Sender<(DeadLetter, Option<Error>)>,
+    failed: Receiver<(DeadLetter, Option<Error>)>
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTasks {
+    fn new() -> AsyncTasks {
+        let (failures, failed) = channel();
+        AsyncTasks {
+            handlers: HashMap::new(),
+            runtime: None,
+            permits: HashMap::new(),
+            running: Arc::new(AtomicUsize::new(0)),
+            failures: failures,
+            failed: failed
+        }
+    }
+}
+
+// An async handler's task for one message: it waits for a turn of its
+// group, then runs the handler's future, catching a panic in it, and
+// reports a failure back to the dispatcher.
+#[cfg(feature = "tokio")]
+struct HandlerTask {
+    // The message, to be dead-lettered if the handler fails on it.
+    letter: Option<DeadLetter>,
+    turn: Option<Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>>,
+    permit: Option<OwnedSemaphorePermit>,
+    task: Pin<Box<dyn Future<Output = Result<(), String>> + Send>>,
+    failures: Sender<(DeadLetter, Option<Error>)>,
+    running: Arc<AtomicUsize>
+}
+
+#[cfg(feature = "tokio")]
+impl Future for HandlerTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        if let Some(ref mut turn) = this.turn {
+            match turn.as_mut().poll(context) {
+                // The semaphore is never closed, so a permit always comes.
+                Poll::Ready(permit) => this.permit = permit.ok(),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+        this.turn = None;
+        let mut letter = match this.letter.take() {
+            Some(letter) => letter,
+            None => return Poll::Ready(())
+        };
+        let callback = format!("Handler \"{}\"", letter.handler);
+        let task = &mut this.task;
+        let (error, panic) = match catch_panic(callback.as_str(), || task.as_mut().poll(context)) {
+            Ok(Poll::Pending) => {
+                this.letter = Some(letter);
+                return Poll::Pending;
+            },
+            Ok(Poll::Ready(Ok(()))) => (None, None),
+            Ok(Poll::Ready(Err(error))) => (Some(error), None),
+            Err(panic) => (Some(panic.to_string()), Some(panic))
+        };
+        this.permit = None;
+        if let Some(error) = error {
+            letter.error = error;
+            let _ = this.failures.send((letter, panic));
+        }
+        this.running.fetch_sub(1, Ordering::SeqCst);
+        Poll::Ready(())
+    }
+}
@@ -0,0 +1,334 @@
+//! A small, optional header carried at the front of message payloads.
+//!
+//! Envelope-aware clients use it to attach metadata such as sequence numbers
+//! to messages. The format is:
+//!
+//! ```text
+//! magic:       4 bytes  "\xffSPE"
+//! version:     1 byte
+//! flags:       1 byte
+//! field count: 1 byte
+//! fields:      field count times { tag: 1 byte, length: 2 bytes (big-endian), value }
+//! payload:     remaining bytes
+//! ```
+//!
+//! Fields with unknown tags are preserved, so older clients can forward
+//! envelopes produced by newer ones.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use time::{precise_time_ns, Timespec};
+use Error;
+
+static MAGIC: &'static [u8] = b"\xffSPE";
+static VERSION: u8 = 1;
+
+/// Tag of the field holding the sender's sequence number.
+pub static TAG_SEQUENCE: u8 = 1;
+
+/// Tag of the field listing the relays a message has passed through,
+/// separated by newlines.
+pub static TAG_RELAY_PATH: u8 = 2;
+
+/// Tag of the field holding the private group name of the message's
+/// original sender, set by the first relay to forward it.
+pub static TAG_ORIGIN: u8 = 3;
+
+/// Tag of the field holding a 64-bit identifier shared by every copy of a
+/// logical message, e.g. those published through several daemons.
+pub static TAG_MESSAGE_ID: u8 = 4;
+
+/// Tag of the field holding the bit set of envelope features the sender
+/// supports (see the `capability` module).
+pub static TAG_CAPABILITIES: u8 = 5;
+
+/// Tag of the field holding the direction ("in" or "out") in which a
+/// debug-mirrored message originally travelled.
+pub static TAG_DIRECTION: u8 = 6;
+
+/// Tag of the field listing the original groups of a mirrored message,
+/// separated by newlines.
+pub static TAG_GROUPS: u8 = 7;
+
+/// Tag of the field holding a message's `UniqueId`: the 16-byte ID of the
+/// sending session followed by the message's 8-byte counter.
+pub static TAG_UNIQUE_ID: u8 = 8;
+
+/// Tag of the field holding the time a message was multicast, in
+/// nanoseconds since the epoch by the sender's clock, stamped while the
+/// sender has latency probing enabled.
+pub static TAG_SENT_AT: u8 = 9;
+
+/// Tag of the field holding the W3C `traceparent` header of the span the
+/// message was sent under, followed by a newline and its `tracestate`
+/// header if that isn't empty (see the `trace_context` module).
+pub static TAG_TRACE_CONTEXT: u8 = 10;
+
+/// Flag set when the payload is compressed (see the `transform` module).
+/// Receivers that cannot decompress it must not interpret the payload.
+pub static FLAG_COMPRESSED: u8 = 0x01;
+
+/// Flag set when the payload is encrypted, after any compression.
+/// Receivers that cannot decrypt it must not interpret the payload.
+pub static FLAG_ENCRYPTED: u8 = 0x02;
+
+/// A decoded envelope and the payload it wraps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub flags: u8,
+    fields: Vec<(u8, Vec<u8>)>,
+    pub payload: Vec<u8>
+}
+
+impl Envelope {
+    /// Wrap `payload` in an envelope with no fields set.
+    pub fn new(payload: &[u8]) -> Envelope {
+        Envelope { flags: 0, fields: Vec::new(), payload: payload.to_vec() }
+    }
+
+    /// Returns true if `data` starts with an envelope header.
+    pub fn is_enveloped(data: &[u8]) -> bool {
+        data.len() >= MAGIC.len() + 3 && data.starts_with(MAGIC)
+    }
+
+    /// Decode an enveloped payload, returning `None` if `data` is not
+    /// enveloped or is truncated.
+    pub fn decode(data: &[u8]) -> Option<Envelope> {
+        if !Envelope::is_enveloped(data) || data[MAGIC.len()] != VERSION {
+            return None;
+        }
+        let flags = data[MAGIC.len() + 1];
+        let field_count = data[MAGIC.len() + 2] as usize;
+        let mut offset = MAGIC.len() + 3;
+        let mut fields = Vec::with_capacity(field_count);
+        for _ in 0..field_count {
+            if offset + 3 > data.len() {
+                return None;
+            }
+            let tag = data[offset];
+            let len = ((data[offset + 1] as usize) << 8) | data[offset + 2] as usize;
+            offset += 3;
+            if offset + len > data.len() {
+                return None;
+            }
+            fields.push((tag, data[offset..offset + len].to_vec()));
+            offset += len;
+        }
+        Some(Envelope { flags: flags, fields: fields, payload: data[offset..].to_vec() })
+    }
+
+    /// Encode the envelope and payload for sending. Fails if the envelope
+    /// has more than 255 fields or a field value longer than 65535 bytes,
+    /// neither of which the header can represent.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        if self.fields.len() > u8::MAX as usize {
+            return Err(Error::InvalidInput(
+                format!("Envelope has {} fields, maximum {}", self.fields.len(), u8::MAX)));
+        }
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.push(self.flags);
+        out.push(self.fields.len() as u8);
+        for &(tag, ref value) in self.fields.iter() {
+            if value.len() > u16::MAX as usize {
+                return Err(Error::InvalidInput(format!("Envelope field {} is {} bytes, maximum {}",
+                                                       tag, value.len(), u16::MAX)));
+            }
+            out.push(tag);
+            out.push((value.len() >> 8) as u8);
+            out.push(value.len() as u8);
+            out.extend_from_slice(value.as_slice());
+        }
+        out.extend_from_slice(self.payload.as_slice());
+        Ok(out)
+    }
+
+    /// Returns the raw value of the field tagged `tag`.
+    pub fn field(&self, tag: u8) -> Option<&[u8]> {
+        self.fields.iter().find(|&&(t, _)| t == tag).map(|(_, value)| value.as_slice())
+    }
+
+    /// Set the raw value of the field tagged `tag`, replacing any previous
+    /// value.
+    pub fn set_field(&mut self, tag: u8, value: &[u8]) {
+        self.fields.retain(|&(t, _)| t != tag);
+        self.fields.push((tag, value.to_vec()));
+    }
+
+    /// The sender's sequence number, if stamped.
+    pub fn sequence(&self) -> Option<u64> {
+        self.field(TAG_SEQUENCE).and_then(decode_u64)
+    }
+
+    pub fn set_sequence(&mut self, sequence: u64) {
+        self.set_field(TAG_SEQUENCE, encode_u64(sequence).as_slice());
+    }
+
+    /// The logical message identifier, if set.
+    pub fn message_id(&self) -> Option<u64> {
+        self.field(TAG_MESSAGE_ID).and_then(decode_u64)
+    }
+
+    pub fn set_message_id(&mut self, id: u64) {
+        self.set_field(TAG_MESSAGE_ID, encode_u64(id).as_slice());
+    }
+
+    /// The message's unique ID, if stamped.
+    pub fn unique_id(&self) -> Option<UniqueId> {
+        self.field(TAG_UNIQUE_ID).and_then(UniqueId::decode)
+    }
+
+    pub fn set_unique_id(&mut self, id: UniqueId) {
+        self.set_field(TAG_UNIQUE_ID, id.encode().as_slice());
+    }
+
+    /// When the message was multicast, if stamped.
+    pub fn sent_at(&self) -> Option<Timespec> {
+        self.field(TAG_SENT_AT).and_then(decode_u64).map(|nanos| {
+            Timespec::new((nanos / 1_000_000_000) as i64, (nanos % 1_000_000_000) as i32)
+        })
+    }
+
+    pub fn set_sent_at(&mut self, at: Timespec) {
+        let nanos = at.sec.max(0) as u64 * 1_000_000_000 + at.nsec.max(0) as u64;
+        self.set_field(TAG_SENT_AT, encode_u64(nanos).as_slice());
+    }
+}
+
+/// Identifies one message among all messages sent by any session: a random
+/// per-session ID, laid out as a version 4 UUID, and the message's position
+/// among that session's messages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UniqueId {
+    pub session: [u8; 16],
+    pub counter: u64
+}
+
+impl UniqueId {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.session.to_vec();
+        out.extend_from_slice(encode_u64(self.counter).as_slice());
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<UniqueId> {
+        if bytes.len() != 24 {
+            return None;
+        }
+        let mut session = [0u8; 16];
+        for (i, &b) in bytes[..16].iter().enumerate() {
+            session[i] = b;
+        }
+        decode_u64(&bytes[16..]).map(|counter| UniqueId { session: session, counter: counter })
+    }
+}
+
+/// Formats as the session UUID and counter, e.g.
+/// `6f1c0d2e-93b4-4a57-8c1e-0f2d3a4b5c6d/42`.
+impl fmt::Display for UniqueId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, b) in self.session.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, "/{}", self.counter)
+    }
+}
+
+/// Stamps outgoing envelopes with `UniqueId`s under a session ID generated
+/// when it is created, counting from 1.
+#[derive(Clone)]
+pub struct IdStamper {
+    session: [u8; 16],
+    next: u64
+}
+
+impl IdStamper {
+    /// Generate a random session ID with the layout of a version 4 UUID.
+    /// The randomness comes from the keys std seeds from the operating
+    /// system for `RandomState`; `seed` (e.g. the private name) and the
+    /// current time are mixed in as well.
+    pub fn new(seed: &str) -> IdStamper {
+        let high = random_u64(seed);
+        let low = random_u64(seed);
+        let mut session = [0u8; 16];
+        for i in 0..8 {
+            session[i] = (high >> ((7 - i) * 8)) as u8;
+            session[i + 8] = (low >> ((7 - i) * 8)) as u8;
+        }
+        session[6] = (session[6] & 0x0f) | 0x40;
+        session[8] = (session[8] & 0x3f) | 0x80;
+        IdStamper { session: session, next: 1 }
+    }
+
+    pub fn session(&self) -> [u8; 16] {
+        self.session
+    }
+
+    /// The ID `next_id` will return, without consuming it.
+    pub fn peek_id(&self) -> UniqueId {
+        UniqueId { session: self.session, counter: self.next }
+    }
+
+    /// The ID for the next message sent by the session.
+    pub fn next_id(&mut self) -> UniqueId {
+        let id = UniqueId { session: self.session, counter: self.next };
+        self.next += 1;
+        id
+    }
+}
+
+/// Stamps outgoing payloads with a per-sender, monotonically increasing
+/// sequence number, starting at 1.
+#[derive(Clone)]
+pub struct Sequencer {
+    next: u64
+}
+
+impl Sequencer {
+    pub fn new() -> Sequencer {
+        Sequencer::starting_at(1)
+    }
+
+    /// Continue a previous sequence, e.g. one restored from disk.
+    pub fn starting_at(next: u64) -> Sequencer {
+        Sequencer { next: next }
+    }
+
+    /// The sequence number the next stamped message will carry.
+    pub fn next_sequence(&self) -> u64 {
+        self.next
+    }
+
+    /// Wrap `payload` in an envelope carrying the next sequence number.
+    pub fn stamp(&mut self, payload: &[u8]) -> Envelope {
+        let mut envelope = Envelope::new(payload);
+        envelope.set_sequence(self.next);
+        self.next += 1;
+        envelope
+    }
+}
+
+// 64 random bits: `seed` and the time hashed under a fresh, randomly keyed
+// SipHash.
+fn random_u64(seed: &str) -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write(seed.as_bytes());
+    hasher.write_u64(precise_time_ns());
+    hasher.finish()
+}
+
+pub fn encode_u64(value: u64) -> Vec<u8> {
+    (0..8).rev().map(|shift| (value >> (shift * 8)) as u8).collect()
+}
+
+pub fn decode_u64(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() != 8 {
+        return None;
+    }
+    Some(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+}
@@ -0,0 +1,243 @@
+//! The errors returned by this crate.
+
+use std::any::Any;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+/// Error codes, as per http://www.spread.org/docs/spread_docs_4/docs/error_codes.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SpreadErrorCode {
+    AcceptSession = 1,
+    IllegalSpread = -1,
+    CouldNotConnection = -2,
+    RejectQuota = -3,
+    RejectNOName = -4,
+    RejectIllegalName = -5,
+    RejectNotUnique = -6,
+    RejectVersion = -7,
+    ConnectionClosed = -8,
+    RejectAuth = -9,
+    IllegalSession = -11,
+    IllegalService = -12,
+    IllegalMessage = -13,
+    IllegalGroup = -14,
+    BufferTooShort = -15,
+    GroupsTooShort = -16,
+    MessageTooLong = -17,
+    NetErrorOnSession = -18
+}
+
+impl SpreadErrorCode {
+    /// The code with the given numeric value, if Spread defines one.
+    pub fn from_code(code: i32) -> Option<SpreadErrorCode> {
+        use self::SpreadErrorCode::*;
+        let known = [
+            AcceptSession, IllegalSpread, CouldNotConnection, RejectQuota, RejectNOName,
+            RejectIllegalName, RejectNotUnique, RejectVersion, ConnectionClosed, RejectAuth,
+            IllegalSession, IllegalService, IllegalMessage, IllegalGroup, BufferTooShort,
+            GroupsTooShort, MessageTooLong, NetErrorOnSession
+        ];
+        known.iter().find(|known| **known as i32 == code).cloned()
+    }
+
+    /// A short description of the code, as given in Spread's documentation.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            SpreadErrorCode::AcceptSession => "session accepted",
+            SpreadErrorCode::IllegalSpread => "illegal daemon name",
+            SpreadErrorCode::CouldNotConnection => "could not connect to the daemon",
+            SpreadErrorCode::RejectQuota => "daemon has too many sessions",
+            SpreadErrorCode::RejectNOName => "no private name given",
+            SpreadErrorCode::RejectIllegalName => "illegal private name",
+            SpreadErrorCode::RejectNotUnique => "private name already in use",
+            SpreadErrorCode::RejectVersion => "client version not supported by the daemon",
+            SpreadErrorCode::ConnectionClosed => "connection closed",
+            SpreadErrorCode::RejectAuth => "authentication failed",
+            SpreadErrorCode::IllegalSession => "illegal session",
+            SpreadErrorCode::IllegalService => "illegal service type",
+            SpreadErrorCode::IllegalMessage => "illegal message",
+            SpreadErrorCode::IllegalGroup => "illegal group",
+            SpreadErrorCode::BufferTooShort => "buffer too short",
+            SpreadErrorCode::GroupsTooShort => "groups buffer too short",
+            SpreadErrorCode::MessageTooLong => "message too long",
+            SpreadErrorCode::NetErrorOnSession => "network error on session"
+        }
+    }
+}
+
+impl fmt::Display for SpreadErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), *self as i32)
+    }
+}
+
+/// An error from a Spread session.
+///
+/// Errors raised by the underlying transport are wrapped in `Io`, except
+/// that timeouts become `Timeout` and a connection the daemon closed or
+/// reset becomes `Disconnected`. The transport error is shared rather than
+/// owned so that errors can be cloned into the event log and hooks whole.
+///
+/// Variants may be added, including by enabling features of this crate, so
+/// matches need a wildcard arm.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The daemon refused the session during the connect handshake.
+    ConnectionRejected(String),
+    /// The daemon sent something that isn't valid Spread protocol.
+    ProtocolError(String),
+    /// A name or payload couldn't be converted to or from its wire
+    /// encoding.
+    EncodingError(String),
+    /// A request the client refused to send, such as a message longer than
+    /// the maximum size or a group name longer than Spread allows.
+    InvalidInput(String),
+    /// The transport failed.
+    Io(Arc<io::Error>),
+    /// An operation didn't finish within its timeout.
+    Timeout,
+    /// The connection to the daemon was closed.
+    Disconnected,
+    /// The daemon reported one of Spread's error codes.
+    DaemonError(SpreadErrorCode),
+    /// A send was rejected by the quota described.
+    QuotaExceeded(String),
+    /// A received frame of `bytes` bytes was discarded because it wouldn't
+    /// fit within the client's memory cap of `cap` bytes.
+    BufferLimit { bytes: usize, cap: usize },
+    /// A user callback, such as a hook, payload transform or dispatcher
+    /// handler, panicked. The panic was caught and reported instead.
+    CallbackPanicked { callback: String, message: String },
+    /// Receiving is paused by `ChaosHooks::pause_receive`. Only exists with
+    /// the `chaos` feature, the only way to pause receiving.
+    #[cfg(feature = "chaos")]
+    ReceivePaused
+}
+
+impl Error {
+    /// Returns true for transport errors of the given kind.
+    pub fn is_io(&self, kind: ErrorKind) -> bool {
+        match *self {
+            Error::Io(ref error) => error.kind() == kind,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ConnectionRejected(ref reason) => write!(f, "Connection rejected: {}", reason),
+            Error::ProtocolError(ref reason) |
+            Error::EncodingError(ref reason) |
+            Error::InvalidInput(ref reason) => f.write_str(reason),
+            Error::Io(ref error) => write!(f, "{}", error),
+            Error::Timeout => f.write_str("Timed out"),
+            Error::Disconnected => f.write_str("Disconnected from daemon"),
+            Error::DaemonError(code) => write!(f, "Daemon error: {}", code),
+            Error::QuotaExceeded(ref quota) => write!(f, "Send quota exceeded: {}", quota),
+            Error::BufferLimit { bytes, cap } =>
+                write!(f, "Frame exceeds memory cap: {} bytes, cap {}", bytes, cap),
+            Error::CallbackPanicked { ref callback, ref message } =>
+                write!(f, "{} panicked: {}", callback, message),
+            #[cfg(feature = "chaos")]
+            Error::ReceivePaused => f.write_str("Receive paused by chaos hook")
+        }
+    }
+}
+
+// Errors are equal if they are the same variant with the same description;
+// transport errors must also be of the same kind. This lets events holding
+// errors be compared in tests.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        if let (Error::Io(error), Error::Io(other)) = (self, other) {
+            if error.kind() != other.kind() {
+                return false;
+            }
+        }
+        mem::discriminant(self) == mem::discriminant(other) && self.to_string() == other.to_string()
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(&**error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            ErrorKind::TimedOut => Error::Timeout,
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset |
+            ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => Error::Disconnected,
+            _ => Error::Io(Arc::new(error))
+        }
+    }
+}
+
+// Used by the `compat` module to give errors the kinds the old
+// `io::Error`-based API used.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        let kind = match error {
+            Error::Io(error) => return Arc::try_unwrap(error).unwrap_or_else(|shared| {
+                io::Error::new(shared.kind(), shared.to_string())
+            }),
+            Error::ConnectionRejected(_) => ErrorKind::ConnectionRefused,
+            Error::ProtocolError(_) | Error::EncodingError(_) |
+            Error::InvalidInput(_) => ErrorKind::InvalidInput,
+            Error::Timeout => ErrorKind::TimedOut,
+            Error::Disconnected => ErrorKind::UnexpectedEof,
+            Error::DaemonError(_) | Error::CallbackPanicked { .. } => ErrorKind::Other,
+            Error::QuotaExceeded(_) | Error::BufferLimit { .. } => ErrorKind::WouldBlock,
+            #[cfg(feature = "chaos")]
+            Error::ReceivePaused => ErrorKind::WouldBlock
+        };
+        io::Error::new(kind, error.to_string())
+    }
+}
+
+// `error` with `context` prepended to its message, if it has one.
+pub fn with_context(error: Error, context: &str) -> Error {
+    match error {
+        Error::ConnectionRejected(reason) => Error::ConnectionRejected(format!("{}: {}", context, reason)),
+        Error::ProtocolError(reason) => Error::ProtocolError(format!("{}: {}", context, reason)),
+        Error::EncodingError(reason) => Error::EncodingError(format!("{}: {}", context, reason)),
+        Error::InvalidInput(reason) => Error::InvalidInput(format!("{}: {}", context, reason)),
+        Error::QuotaExceeded(quota) => Error::QuotaExceeded(format!("{}: {}", context, quota)),
+        Error::Io(error) => Error::Io(Arc::new(
+            io::Error::new(error.kind(), format!("{}: {}", context, error))
+        )),
+        other => other
+    }
+}
+
+// Run `callback`, returning `Error::CallbackPanicked` with the panic's
+// message if it panics, so that user code can't unwind through the client.
+pub fn catch_panic<T, F: FnOnce() -> T>(name: &str, callback: F) -> Result<T, Error> {
+    panic::catch_unwind(AssertUnwindSafe(callback)).map_err(|payload| Error::CallbackPanicked {
+        callback: name.to_string(),
+        message: panic_message(&*payload)
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "non-string panic payload".to_string()
+        }
+    }
+}
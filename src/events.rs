@@ -0,0 +1,71 @@
+//! A bounded log of recent protocol-level events, kept for postmortems.
+
+use std::collections::VecDeque;
+use time::Timespec;
+use Error;
+
+/// Number of events retained by a client unless configured otherwise.
+pub static DEFAULT_EVENT_CAPACITY: usize = 128;
+
+/// Something that happened on a client's session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProtocolEventKind {
+    /// A frame with the given service type and total length was written.
+    FrameSent { service_type: u32, bytes: usize },
+    /// A frame with the given service type and total length was read.
+    FrameReceived { service_type: u32, bytes: usize },
+    /// The session moved to a new state, e.g. "connected".
+    StateChange(String),
+    /// A sender exceeded the flood guard's rate and its messages are being
+    /// dropped.
+    SenderThrottled(String),
+    /// An operation on the session failed.
+    Error(Error)
+}
+
+/// A timestamped protocol event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProtocolEvent {
+    pub timestamp: Timespec,
+    pub kind: ProtocolEventKind
+}
+
+// Ring buffer holding the most recent events.
+pub struct EventLog {
+    capacity: usize,
+    events: VecDeque<ProtocolEvent>
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> EventLog {
+        EventLog {
+            capacity: capacity,
+            events: VecDeque::with_capacity(capacity)
+        }
+    }
+
+    pub fn record(&mut self, now: Timespec, kind: ProtocolEventKind) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(ProtocolEvent {
+            timestamp: now,
+            kind: kind
+        });
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.events.len() > capacity {
+            self.events.pop_front();
+        }
+    }
+
+    // Oldest first.
+    pub fn to_vec(&self) -> Vec<ProtocolEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,133 @@
+//! Phi-accrual failure detection from periodic heartbeats.
+//!
+//! Each member calls `FailureDetector::heartbeat` at a regular interval.
+//! Receivers pass every message to `on_message` and call `check`
+//! periodically; a member is suspected once the phi value computed from the
+//! distribution of its past inter-arrival times exceeds the threshold,
+//! typically well before the daemon would report it as gone.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use time::Timespec;
+use {Error, SpreadClient, SpreadMessage};
+
+static HEARTBEAT: &'static [u8] = b"\x00spread-heartbeat";
+
+/// A change in the suspicion state of a member.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SuspicionEvent {
+    /// The member's heartbeats are overdue.
+    Suspect(String),
+    /// A previously suspected member's heartbeats have resumed.
+    Alive(String)
+}
+
+struct History {
+    last_arrival: Timespec,
+    intervals_ms: VecDeque<f64>
+}
+
+/// Tracks heartbeat arrivals and phi suspicion levels for group members.
+pub struct FailureDetector {
+    threshold: f64,
+    window: usize,
+    min_std_dev_ms: f64,
+    histories: HashMap<String, History>,
+    suspected: HashSet<String>
+}
+
+impl FailureDetector {
+    /// Suspect members whose phi exceeds `threshold` (8.0 is a common
+    /// choice), estimating arrival statistics from the last `window`
+    /// intervals.
+    pub fn new(threshold: f64, window: usize) -> FailureDetector {
+        FailureDetector {
+            threshold: threshold,
+            window: window,
+            min_std_dev_ms: 100.0,
+            histories: HashMap::new(),
+            suspected: HashSet::new()
+        }
+    }
+
+    /// Multicast a heartbeat for this client to `group`.
+    pub fn heartbeat(client: &mut SpreadClient, group: &str) -> Result<(), Error> {
+        client.multicast([group].as_slice(), HEARTBEAT)
+    }
+
+    /// Record a received message; heartbeats update the sender's history.
+    pub fn on_message(&mut self, now: Timespec, message: &SpreadMessage) -> Vec<SuspicionEvent> {
+        let mut events = Vec::new();
+        if message.data.as_slice() != HEARTBEAT {
+            return events;
+        }
+        let member = message.sender.as_str().trim_end_matches('\0').to_string();
+
+        let window = self.window;
+        if let Some(history) = self.histories.get_mut(&member) {
+            let interval = (now - history.last_arrival).num_milliseconds() as f64;
+            if history.intervals_ms.len() >= window {
+                history.intervals_ms.pop_front();
+            }
+            history.intervals_ms.push_back(interval);
+            history.last_arrival = now;
+        }
+        if !self.histories.contains_key(&member) {
+            self.histories.insert(member.clone(), History {
+                last_arrival: now,
+                intervals_ms: VecDeque::new()
+            });
+        }
+
+        if self.suspected.remove(&member) {
+            events.push(SuspicionEvent::Alive(member));
+        }
+        events
+    }
+
+    /// Re-evaluate every member, returning newly suspected ones.
+    pub fn check(&mut self, now: Timespec) -> Vec<SuspicionEvent> {
+        let mut events = Vec::new();
+        let members: Vec<String> = self.histories.keys().cloned().collect();
+        for member in members.into_iter() {
+            let over = match self.phi(member.as_str(), now) {
+                Some(phi) => phi > self.threshold,
+                None => false
+            };
+            if over && self.suspected.insert(member.clone()) {
+                events.push(SuspicionEvent::Suspect(member));
+            }
+        }
+        events
+    }
+
+    /// The current phi value for `member`, or `None` until at least two
+    /// heartbeats have been seen from it.
+    pub fn phi(&self, member: &str, now: Timespec) -> Option<f64> {
+        let history = match self.histories.get(member) {
+            Some(history) if !history.intervals_ms.is_empty() => history,
+            _ => return None
+        };
+        let n = history.intervals_ms.len() as f64;
+        let mean = history.intervals_ms.iter().fold(0.0, |acc, &i| acc + i) / n;
+        let variance = history.intervals_ms.iter()
+            .fold(0.0, |acc, &i| acc + (i - mean) * (i - mean)) / n;
+        let std_dev = variance.sqrt().max(self.min_std_dev_ms);
+        let elapsed = (now - history.last_arrival).num_milliseconds() as f64;
+        Some(phi(elapsed, mean, std_dev))
+    }
+
+    /// Forget `member`, e.g. once the daemon reports that it left.
+    pub fn remove(&mut self, member: &str) {
+        self.histories.remove(member);
+        self.suspected.remove(member);
+    }
+}
+
+// Phi for an elapsed time given normally distributed arrivals, using the
+// logistic approximation of the normal CDF.
+fn phi(elapsed: f64, mean: f64, std_dev: f64) -> f64 {
+    let y = (elapsed - mean) / std_dev;
+    let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+    let p_later = if elapsed > mean { e / (1.0 + e) } else { 1.0 - 1.0 / (1.0 + e) };
+    -p_later.max(1e-300).log10()
+}
@@ -0,0 +1,31 @@
+//! Sending one payload to many groups, with per-group outcomes.
+
+use group_name::group_name_error;
+
+/// The outcome of a `SpreadClient::fanout` call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanoutReport {
+    /// Groups the payload was sent to.
+    pub succeeded: Vec<String>,
+    /// Groups the payload was not sent to, with the reason.
+    pub failed: Vec<(String, String)>
+}
+
+impl FanoutReport {
+    pub fn new() -> FanoutReport {
+        FanoutReport { succeeded: Vec::new(), failed: Vec::new() }
+    }
+
+    /// Returns true if the payload reached every group.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Check that `group` can be sent on the wire, returning the reason if not.
+pub fn validate_group_name(group: &str) -> Result<(), String> {
+    match group_name_error(group) {
+        Some(reason) => Err(reason.to_string()),
+        None => Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Filtering received data messages by sender.
+
+use util::glob_match;
+
+/// What to do with a message from a sender the filter does not permit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterAction {
+    /// Discard the message without returning it from `receive`.
+    Drop,
+    /// Log a warning but deliver the message anyway.
+    Flag
+}
+
+/// Allow and deny lists of sender patterns.
+///
+/// Patterns are matched against the sender's private group name
+/// (`#user#daemon`) and may use `*` and `?` wildcards. A sender matching any
+/// deny pattern is rejected; otherwise, if any allow patterns are configured,
+/// the sender must match one of them.
+#[derive(Clone, Debug)]
+pub struct SenderFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+    action: FilterAction
+}
+
+impl SenderFilter {
+    pub fn new(action: FilterAction) -> SenderFilter {
+        SenderFilter { allow: Vec::new(), deny: Vec::new(), action: action }
+    }
+
+    pub fn allow(mut self, pattern: &str) -> SenderFilter {
+        self.allow.push(pattern.to_string());
+        self
+    }
+
+    pub fn deny(mut self, pattern: &str) -> SenderFilter {
+        self.deny.push(pattern.to_string());
+        self
+    }
+
+    /// Allow any sender connected through `daemon`.
+    pub fn allow_daemon(self, daemon: &str) -> SenderFilter {
+        self.allow(format!("#*#{}", daemon).as_str())
+    }
+
+    /// Deny any sender connected through `daemon`.
+    pub fn deny_daemon(self, daemon: &str) -> SenderFilter {
+        self.deny(format!("#*#{}", daemon).as_str())
+    }
+
+    pub fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    /// Returns true if messages from `sender` are acceptable.
+    pub fn permits(&self, sender: &str) -> bool {
+        let sender = sender.trim_end_matches('\0');
+        if self.deny.iter().any(|pattern| glob_match(pattern.as_str(), sender)) {
+            return false;
+        }
+        self.allow.is_empty()
+            || self.allow.iter().any(|pattern| glob_match(pattern.as_str(), sender))
+    }
+}
@@ -0,0 +1,151 @@
+//! Per-sender rate limiting of received data messages.
+
+use std::cmp;
+use std::collections::HashMap;
+use time::{Duration, Timespec};
+
+/// What to do with a sender that exceeds its rate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FloodAction {
+    /// Drop the sender's messages beyond the limit for the rest of the
+    /// current second.
+    Drop,
+    /// Drop all of the sender's messages for the given duration.
+    Quarantine(Duration)
+}
+
+/// The outcome of admitting one message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FloodVerdict {
+    /// The message is within the sender's limit.
+    Accept,
+    /// The sender just exceeded its limit; this message is dropped.
+    Throttled,
+    /// The sender was already over its limit; this message is dropped.
+    Drop
+}
+
+struct SenderRate {
+    second: i64,
+    count: u32,
+    quarantined_until: Option<Timespec>
+}
+
+/// The default bound on the number of senders a `FloodGuard` tracks.
+pub const DEFAULT_FLOOD_GUARD_SENDERS: usize = 4096;
+
+/// Tracks the number of messages received from each sender per second.
+///
+/// Only senders seen in the current second and senders under quarantine
+/// are tracked, and at most `max_senders` of them, so a stream of unique
+/// or spoofed sender names can't grow the guard without bound.
+pub struct FloodGuard {
+    max_per_second: u32,
+    action: FloodAction,
+    max_senders: usize,
+    senders: HashMap<String, SenderRate>,
+    // The second in which idle senders were last forgotten.
+    swept: i64
+}
+
+impl FloodGuard {
+    pub fn new(max_per_second: u32, action: FloodAction) -> FloodGuard {
+        FloodGuard {
+            max_per_second: max_per_second,
+            action: action,
+            max_senders: DEFAULT_FLOOD_GUARD_SENDERS,
+            senders: HashMap::new(),
+            swept: 0
+        }
+    }
+
+    /// Track at most `max_senders` senders, instead of
+    /// `DEFAULT_FLOOD_GUARD_SENDERS`. Beyond that, a new sender displaces one that
+    /// is within its limit, or failing that the quarantine ending soonest.
+    pub fn with_max_senders(mut self, max_senders: usize) -> FloodGuard {
+        self.max_senders = cmp::max(max_senders, 1);
+        self
+    }
+
+    /// The number of senders currently tracked.
+    pub fn tracked(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Count a message from `sender` received at `now`.
+    pub fn admit(&mut self, now: Timespec, sender: &str) -> FloodVerdict {
+        let sender = sender.trim_end_matches('\0');
+        if self.swept != now.sec {
+            self.forget_idle(now);
+        }
+        if !self.senders.contains_key(sender) {
+            if self.senders.len() >= self.max_senders {
+                self.evict_one(now);
+            }
+            self.senders.insert(sender.to_string(), SenderRate {
+                second: now.sec,
+                count: 0,
+                quarantined_until: None
+            });
+        }
+        let rate = self.senders.get_mut(sender).unwrap();
+
+        match rate.quarantined_until {
+            Some(until) if now < until => return FloodVerdict::Drop,
+            Some(_) => rate.quarantined_until = None,
+            None => ()
+        }
+        if rate.second != now.sec {
+            rate.second = now.sec;
+            rate.count = 0;
+        }
+        rate.count += 1;
+
+        if rate.count <= self.max_per_second {
+            FloodVerdict::Accept
+        } else if rate.count == self.max_per_second + 1 {
+            if let FloodAction::Quarantine(duration) = self.action {
+                rate.quarantined_until = Some(now + duration);
+            }
+            FloodVerdict::Throttled
+        } else {
+            FloodVerdict::Drop
+        }
+    }
+
+    /// Senders currently quarantined at `now`.
+    pub fn quarantined(&self, now: Timespec) -> Vec<String> {
+        self.senders.iter()
+            .filter(|&(_, rate)| rate.quarantined_until.is_some_and(|until| now < until))
+            .map(|(sender, _)| sender.clone())
+            .collect()
+    }
+
+    /// Lift any quarantine on `sender` and reset its count.
+    pub fn release(&mut self, sender: &str) {
+        self.senders.remove(sender.trim_end_matches('\0'));
+    }
+
+    // Forget senders whose count is from an earlier second and who aren't
+    // quarantined; their next message starts a fresh count anyway.
+    fn forget_idle(&mut self, now: Timespec) {
+        self.senders.retain(|_, rate| {
+            rate.second == now.sec || rate.quarantined_until.is_some_and(|until| now < until)
+        });
+        self.swept = now.sec;
+    }
+
+    // Make room for a new sender, preferring to drop one that isn't
+    // quarantined so that floods stay throttled.
+    fn evict_one(&mut self, now: Timespec) {
+        let victim = self.senders.iter()
+            .min_by_key(|&(_, rate)| match rate.quarantined_until {
+                Some(until) if now < until => (1, until.sec, until.nsec),
+                _ => (0, rate.second, 0)
+            })
+            .map(|(sender, _)| sender.clone());
+        if let Some(victim) = victim {
+            self.senders.remove(&victim);
+        }
+    }
+}
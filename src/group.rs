@@ -0,0 +1,107 @@
+//! Group handles that send and receive typed values through a codec.
+
+use std::marker::PhantomData;
+use {Error, SpreadClient, MEMBERSHIP_MESS};
+
+/// Converts values of type `T` to and from message payloads.
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Vec<u8>;
+
+    /// Decode a payload, describing the problem if it is not a valid `T`.
+    fn decode(&self, data: &[u8]) -> Result<T, String>;
+}
+
+/// Carries `String`s as UTF-8 bytes.
+#[derive(Copy, Clone, Debug)]
+pub struct Utf8Codec;
+
+impl Codec<String> for Utf8Codec {
+    fn encode(&self, value: &String) -> Vec<u8> {
+        value.as_bytes().to_vec()
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<String, String> {
+        String::from_utf8(data.to_vec()).map_err(|error| format!("{}", error))
+    }
+}
+
+/// Carries raw byte vectors unchanged.
+#[derive(Copy, Clone, Debug)]
+pub struct BytesCodec;
+
+impl Codec<Vec<u8>> for BytesCodec {
+    fn encode(&self, value: &Vec<u8>) -> Vec<u8> {
+        value.clone()
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(data.to_vec())
+    }
+}
+
+/// A group whose payloads are all of type `T`, encoded with `C`.
+///
+/// Fixing the payload type per group means sending the wrong type to a
+/// group is a compile error rather than a decode failure at the receiver.
+pub struct Group<'a, T, C: Codec<T>> {
+    client: &'a mut SpreadClient,
+    name: String,
+    codec: C,
+    marker: PhantomData<T>
+}
+
+impl<'a, T, C: Codec<T>> Group<'a, T, C> {
+    pub fn new(client: &'a mut SpreadClient, name: &str, codec: C) -> Group<'a, T, C> {
+        Group { client: client, name: name.to_string(), codec: codec, marker: PhantomData }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Join the group.
+    pub fn join(&mut self) -> Result<(), Error> {
+        self.client.join(self.name.as_str())
+    }
+
+    /// Encode `value` and multicast it to the group.
+    pub fn send(&mut self, value: &T) -> Result<(), Error> {
+        let data = self.codec.encode(value);
+        self.client.multicast([self.name.as_str()].as_slice(), data.as_slice())
+    }
+
+    /// Receive the next data message addressed to the group and decode it.
+    /// Membership messages and messages for other groups are discarded, so
+    /// the client should not be shared with code expecting those.
+    pub fn receive(&mut self) -> Result<T, Error> {
+        loop {
+            let message = self.client.receive()?;
+            if message.service_type & MEMBERSHIP_MESS != 0 ||
+                !message.groups.iter().any(|group| group.trim_end_matches('\0') == self.name) {
+                continue;
+            }
+            return self.codec.decode(message.data.as_slice()).map_err(|error| Error::EncodingError(
+                format!("Failed to decode group payload: group \"{}\": {}", self.name, error)
+            ));
+        }
+    }
+
+    /// An iterator over the values received by the group. Each item is the
+    /// result of `receive`.
+    pub fn messages<'b>(&'b mut self) -> Messages<'b, 'a, T, C> {
+        Messages { group: self }
+    }
+}
+
+/// An endless iterator of values received by a `Group`.
+pub struct Messages<'b, 'a: 'b, T: 'b, C: Codec<T> + 'b> {
+    group: &'b mut Group<'a, T, C>
+}
+
+impl<'b, 'a, T, C: Codec<T>> Iterator for Messages<'b, 'a, T, C> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Result<T, Error>> {
+        Some(self.group.receive())
+    }
+}
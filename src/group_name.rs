@@ -0,0 +1,107 @@
+//! Group names checked once, ahead of use.
+//!
+//! `spread_group!` turns a string literal into a `GroupName`, checking it
+//! against the rules `validate_group_name` applies at run time, but while
+//! compiling, so a typo or an over-long name in a hard-coded group constant
+//! fails the build:
+//!
+//! ```
+//! #[macro_use] extern crate spread;
+//! use spread::GroupName;
+//!
+//! static ORDERS: GroupName = spread_group!("orders");
+//!
+//! fn main() {
+//!     assert_eq!(ORDERS.as_str(), "orders");
+//! }
+//! ```
+//!
+//! ```compile_fail
+//! #[macro_use] extern crate spread;
+//!
+//! fn main() {
+//!     let _ = spread_group!("a-group-name-too-long-for-the-wire");
+//! }
+//! ```
+//!
+//! A `GroupName` dereferences to `str`, so it can be passed wherever a
+//! group name is expected, e.g. `client.join(&ORDERS)`.
+
+use std::fmt;
+use std::ops::Deref;
+use limits::MAX_GROUP_NAME_LENGTH;
+
+/// A group name known to be valid on the wire.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupName(&'static str);
+
+impl GroupName {
+    /// Check `name`, panicking with the reason if it isn't a valid group
+    /// name. In a constant, as `spread_group!` uses it, the panic is a
+    /// compile error.
+    pub const fn new(name: &'static str) -> GroupName {
+        if let Some(reason) = group_name_error(name) {
+            panic!("{}", reason);
+        }
+        GroupName(name)
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl Deref for GroupName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+impl AsRef<str> for GroupName {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl fmt::Display for GroupName {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(self.0)
+    }
+}
+
+/// A `GroupName` for the string literal given, validated at compile time.
+#[macro_export]
+macro_rules! spread_group {
+    ($name:expr) => ({
+        const NAME: $crate::GroupName = $crate::GroupName::new($name);
+        NAME
+    })
+}
+
+// The reason `group` can't be sent on the wire, if any. Shared by
+// `validate_group_name` at run time and `GroupName::new` at compile time.
+pub const fn group_name_error(group: &str) -> Option<&'static str> {
+    let bytes = group.as_bytes();
+    if bytes.is_empty() {
+        return Some("group name is empty");
+    }
+    // Names are NUL-terminated within a fixed-width field.
+    if bytes.len() >= MAX_GROUP_NAME_LENGTH {
+        return Some("group name longer than 31 bytes");
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == 0 {
+            return Some("group name contains a NUL byte");
+        }
+        // Characters above U+00FF, the last in ISO-8859-1, are the only
+        // ones whose UTF-8 encoding starts with 0xc4 or above.
+        if bytes[i] >= 0xc4 {
+            return Some("group name is not representable in ISO-8859-1");
+        }
+        i += 1;
+    }
+    None
+}
@@ -0,0 +1,171 @@
+//! An in-memory transport for hermetic tests of code that uses this crate.
+//!
+//! `pair()` returns a client end, to be passed to `connect_with_transport`,
+//! and a `ScriptedDaemon` end through which a test queues the bytes the
+//! "daemon" sends and inspects the bytes the client wrote.
+//!
+//! Reads never block: once the scripted bytes run out, the client end
+//! reports end-of-file, or `WouldBlock` while it is non-blocking or has a
+//! read timeout, as a socket would.
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use sync::Mutex;
+use transport::Transport;
+use util::int_to_bytes;
+use limits::MAX_GROUP_NAME_LENGTH;
+
+struct Pipes {
+    to_client: VecDeque<u8>,
+    from_client: Vec<u8>,
+    closed: bool
+}
+
+/// The client end of an in-memory connection.
+pub struct InMemoryTransport {
+    pipes: Arc<Mutex<Pipes>>,
+    read_timeout: Option<Duration>,
+    nonblocking: bool
+}
+
+/// The daemon end of an in-memory connection.
+#[derive(Clone)]
+pub struct ScriptedDaemon {
+    pipes: Arc<Mutex<Pipes>>
+}
+
+/// Create a connected client transport and scripted daemon.
+pub fn pair() -> (InMemoryTransport, ScriptedDaemon) {
+    let pipes = Arc::new(Mutex::new(Pipes {
+        to_client: VecDeque::new(),
+        from_client: Vec::new(),
+        closed: false
+    }));
+    let client = InMemoryTransport { pipes: pipes.clone(), read_timeout: None, nonblocking: false };
+    (client, ScriptedDaemon { pipes: pipes })
+}
+
+impl Read for InMemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipes = self.pipes.lock().unwrap();
+        if pipes.to_client.is_empty() {
+            if (self.nonblocking || self.read_timeout.is_some()) && !pipes.closed {
+                return Err(io::Error::new(ErrorKind::WouldBlock, "No scripted bytes available"));
+            }
+            return Ok(0);
+        }
+        let mut n = 0;
+        while n < buf.len() {
+            match pipes.to_client.pop_front() {
+                Some(b) => {
+                    buf[n] = b;
+                    n += 1;
+                },
+                None => break
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Write for InMemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipes = self.pipes.lock().unwrap();
+        if pipes.closed {
+            return Err(io::Error::new(ErrorKind::BrokenPipe, "In-memory transport closed"));
+        }
+        pipes.from_client.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for InMemoryTransport {
+    fn close(&mut self) -> io::Result<()> {
+        let mut pipes = self.pipes.lock().unwrap();
+        pipes.closed = true;
+        pipes.to_client.clear();
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+}
+
+impl ScriptedDaemon {
+    /// Queue raw bytes for the client to read. Once the client has closed
+    /// its end they are dropped, as a socket would drop them.
+    pub fn push(&self, bytes: &[u8]) {
+        let mut pipes = self.pipes.lock().unwrap();
+        if !pipes.closed {
+            pipes.to_client.extend(bytes.iter().copied());
+        }
+    }
+
+    /// Queue a successful handshake reply assigning `private_group` to the
+    /// client.
+    pub fn accept_session(&self, private_group: &str) {
+        let mut reply: Vec<u8> = Vec::new();
+        reply.push(4);
+        reply.extend_from_slice(b"NULL");
+        reply.push(1);
+        reply.extend_from_slice([4u8, 4, 0].as_slice());
+        reply.push(private_group.len() as u8);
+        reply.extend_from_slice(private_group.as_bytes());
+        self.push(reply.as_slice());
+    }
+
+    /// Queue a message frame for the client to receive.
+    pub fn push_message(&self, service_type: u32, sender: &str, groups: &[&str], data: &[u8]) {
+        self.push_typed_message(service_type, 0, sender, groups, data);
+    }
+
+    /// Queue a message frame with the message type `mess_type` for the
+    /// client to receive.
+    pub fn push_typed_message(&self, service_type: u32, mess_type: i16, sender: &str, groups: &[&str],
+                              data: &[u8]) {
+        let mut frame: Vec<u8> = Vec::new();
+        frame.extend_from_slice(int_to_bytes(service_type).as_slice());
+        push_padded_name(&mut frame, sender);
+        frame.extend_from_slice(int_to_bytes(groups.len() as u32).as_slice());
+        frame.extend_from_slice(int_to_bytes((mess_type as u16 as u32) << 8).as_slice());
+        frame.extend_from_slice(int_to_bytes(data.len() as u32).as_slice());
+        for group in groups.iter() {
+            push_padded_name(&mut frame, group);
+        }
+        frame.extend_from_slice(data);
+        self.push(frame.as_slice());
+    }
+
+    /// Take every byte written by the client so far.
+    pub fn take_written(&self) -> Vec<u8> {
+        let mut pipes = self.pipes.lock().unwrap();
+        let written = pipes.from_client.clone();
+        pipes.from_client.clear();
+        written
+    }
+
+    /// Returns true once the client has closed its end.
+    pub fn is_closed(&self) -> bool {
+        self.pipes.lock().unwrap().closed
+    }
+}
+
+fn push_padded_name(frame: &mut Vec<u8>, name: &str) {
+    frame.extend_from_slice(name.as_bytes());
+    for _ in name.len()..MAX_GROUP_NAME_LENGTH {
+        frame.push(0);
+    }
+}
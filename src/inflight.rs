@@ -0,0 +1,126 @@
+//! Re-sending messages that may have been lost with a broken session.
+//!
+//! A multicast that returned `Ok` has only been written to the socket; if
+//! the connection breaks before the daemon reads it, the message is lost.
+//! With `SpreadClient::set_resend_buffer`, the client keeps recently sent
+//! messages until they are acknowledged, either by a receipt from
+//! `send_with_receipt` or explicitly with `acknowledge_in_flight`.
+//! `SpreadClient::reconnect`, whether called directly or by auto-reconnect,
+//! sends them again, in order, once it has re-joined the client's groups,
+//! and keeps them buffered until they are acknowledged. To carry them over
+//! to a different client instead, hand the old client's `take_in_flight`
+//! to the new client's `resend_in_flight`. Messages are re-sent exactly as
+//! first sent, so receivers can discard any that did arrive by their unique
+//! ID or sequence number.
+
+use std::collections::VecDeque;
+use time::Timespec;
+use envelope::UniqueId;
+use ServiceType;
+
+/// A sent message not yet known to have reached the daemon.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InFlightMessage {
+    pub service: ServiceType,
+    /// The groups, as named on the wire.
+    pub groups: Vec<String>,
+    /// The payload, including any envelope added when it was sent.
+    pub data: Vec<u8>,
+    /// The unique ID the message was stamped with, if any.
+    pub id: Option<UniqueId>,
+    /// When the message stops being worth re-sending, if it was sent with
+    /// a TTL (see `SpreadClient::set_message_ttl`). Not serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub expires_at: Option<Timespec>
+}
+
+impl InFlightMessage {
+    /// Returns true if the message's TTL has run out at `now`.
+    pub fn is_expired(&self, now: Timespec) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+// The unacknowledged messages of a client, oldest first, bounded by count.
+pub struct InFlightBuffer {
+    capacity: usize,
+    messages: VecDeque<InFlightMessage>,
+    bytes: usize,
+    evicted: u64
+}
+
+impl InFlightBuffer {
+    pub fn new(capacity: usize) -> InFlightBuffer {
+        InFlightBuffer { capacity: capacity, messages: VecDeque::new(), bytes: 0, evicted: 0 }
+    }
+
+    pub fn push(&mut self, message: InFlightMessage) {
+        self.bytes += message.data.len();
+        self.messages.push_back(message);
+        while self.messages.len() > self.capacity {
+            let oldest = self.messages.pop_front().unwrap();
+            self.bytes -= oldest.data.len();
+            self.evicted += 1;
+        }
+    }
+
+    // Drop the message stamped `id` and every message sent before it: a
+    // sender's messages are delivered in the order sent, so its copy of one
+    // arriving means the daemon had all the earlier ones too. Returns how
+    // many were dropped.
+    pub fn acknowledge_through(&mut self, id: UniqueId) -> usize {
+        let position = match self.messages.iter().position(|message| message.id == Some(id)) {
+            Some(position) => position,
+            None => return 0
+        };
+        for _ in 0..position + 1 {
+            let message = self.messages.pop_front().unwrap();
+            self.bytes -= message.data.len();
+        }
+        position + 1
+    }
+
+    // Drop the oldest messages until at most `max_bytes` are held,
+    // returning how many were dropped.
+    pub fn trim_to(&mut self, max_bytes: usize) -> usize {
+        let before = self.messages.len();
+        while self.bytes > max_bytes {
+            let oldest = self.messages.pop_front().unwrap();
+            self.bytes -= oldest.data.len();
+            self.evicted += 1;
+        }
+        before - self.messages.len()
+    }
+
+    // Drop the messages whose TTL has run out at `now`, returning how many
+    // there were.
+    pub fn expire(&mut self, now: Timespec) -> usize {
+        let before = self.messages.len();
+        self.messages.retain(|message| !message.is_expired(now));
+        self.bytes = self.messages.iter().map(|message| message.data.len()).sum();
+        before - self.messages.len()
+    }
+
+    pub fn messages(&self) -> Vec<InFlightMessage> {
+        self.messages.iter().cloned().collect()
+    }
+
+    pub fn take(&mut self) -> Vec<InFlightMessage> {
+        self.bytes = 0;
+        self.messages.drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Messages dropped unacknowledged because the buffer was full.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+}
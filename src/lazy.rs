@@ -0,0 +1,71 @@
+//! A client handle that connects on first use.
+
+use address::DaemonAddress;
+use stats::SessionSummary;
+use {connect, Error, SpreadClient, SpreadMessage};
+
+/// Connection settings that only turn into a session when it is first
+/// needed, so that tools which may never touch Spread pay nothing for it.
+pub struct LazyClient {
+    connector: Box<dyn FnMut() -> Result<SpreadClient, Error> + Send>,
+    groups: Vec<String>,
+    client: Option<SpreadClient>
+}
+
+impl LazyClient {
+    /// Connect to `address` as `private_name` on first use.
+    pub fn new(address: DaemonAddress, private_name: &str, receive_membership_messages: bool) -> LazyClient {
+        let private_name = private_name.to_string();
+        LazyClient::with_connector(Box::new(move || {
+            connect(address.clone(), private_name.as_str(), receive_membership_messages)
+        }))
+    }
+
+    /// Establish the session with `connector` on first use.
+    pub fn with_connector(connector: Box<dyn FnMut() -> Result<SpreadClient, Error> + Send>) -> LazyClient {
+        LazyClient { connector: connector, groups: Vec::new(), client: None }
+    }
+
+    /// Join `group` as soon as the session is established (or right away,
+    /// if it already is).
+    pub fn join_on_connect(&mut self, group: &str) -> Result<(), Error> {
+        self.groups.push(group.to_string());
+        match self.client {
+            Some(ref mut client) => client.join(group),
+            None => Ok(())
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Connect and join the configured groups, unless already connected.
+    pub fn ensure_connected(&mut self) -> Result<&mut SpreadClient, Error> {
+        if self.client.is_none() {
+            let mut client = (*self.connector)()?;
+            for group in self.groups.iter() {
+                client.join(group.as_str())?;
+            }
+            self.client = Some(client);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+
+    pub fn multicast(&mut self, groups: &[&str], data: &[u8]) -> Result<(), Error> {
+        self.ensure_connected()?.multicast(groups, data)
+    }
+
+    pub fn receive(&mut self) -> Result<SpreadMessage, Error> {
+        self.ensure_connected()?.receive()
+    }
+
+    /// Disconnect if a session was ever established, returning its
+    /// summary.
+    pub fn disconnect(&mut self) -> Result<Option<SessionSummary>, Error> {
+        match self.client.take() {
+            Some(mut client) => client.disconnect().map(Some),
+            None => Ok(None)
+        }
+    }
+}
@@ -1,35 +1,193 @@
 #![crate_name = "spread"]
 #![crate_type = "lib"]
-#![feature(collections)]
-#![feature(core)]
-#![feature(io)]
-
-#[deny(non_camel_case_types)]
+#![deny(non_camel_case_types)]
+#![allow(clippy::new_without_default)]
+#![allow(clippy::redundant_field_names)]
+#![allow(clippy::redundant_static_lifetimes)]
+#![allow(clippy::type_complexity)]
 
 extern crate encoding;
 #[macro_use] extern crate log;
+extern crate time;
+#[cfg(feature = "metrics")]
+#[macro_use] extern crate metrics;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate webpki_roots;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "prometheus")]
+extern crate prometheus_client;
+#[cfg(feature = "opentelemetry")]
+extern crate opentelemetry;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "serde")]
+#[macro_use] extern crate serde_derive;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+// Log through a client's configured `LogSink`, formatting the message only
+// if the sink would keep it.
+macro_rules! client_log {
+    ($client:expr, $level:expr, $($arg:tt)+) => (
+        if $client.logger.enabled($level) {
+            $client.logger.log($level, &format!($($arg)+));
+        }
+    )
+}
 
-use encoding::{Encoding, EncoderTrap, DecoderTrap};
-use encoding::all::ISO_8859_1;
-use std::old_io::{ConnectionFailed, ConnectionRefused, IoError, IoResult, OtherIoError};
-use std::old_io::net::ip::ToSocketAddr;
-use std::old_io::net::tcp::TcpStream;
+use encoding::{Encoding, EncoderTrap, EncodingRef, DecoderTrap};
+use encoding::all::{ISO_8859_1, UTF_8};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::result::Result;
-use util::{bytes_to_int, flip_endianness, int_to_bytes, same_endianness};
+use std::time::Duration as StdDuration;
+use time::Timespec;
+use audit::{AuditAction, AuditCause, AuditEntry, AuditLog};
+use backfill::SendHistory;
+use envelope::{Envelope, IdStamper, Sequencer, UniqueId};
+use error::catch_panic;
+use inflight::InFlightBuffer;
+use memory::message_bytes;
+use pause::PausedGroups;
+use parser::{append_groups, decode_groups, header_int, message_event, read_groups, FrameHeader,
+             HEADER_LENGTH};
+use transport::{describe_peer, BufferedTransport};
+use util::{append_bytes, bytes_to_int, int_to_bytes, read_byte, read_bytes};
+use limits::{DEFAULT_MAX_MESSAGE_SIZE, MAX_AUTH_METHOD_COUNT, MAX_AUTH_NAME_LENGTH,
+             MAX_GROUP_NAME_LENGTH, MAX_PRIVATE_NAME_LENGTH};
 
+pub use address::DaemonAddress;
+pub use alias::GroupAliases;
+pub use batch::OutboundMessage;
+pub use capture::{Capture, CaptureSink, Direction};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use debug_mirror::DebugMirror;
+pub use error::{Error, SpreadErrorCode};
+pub use events::{DEFAULT_EVENT_CAPACITY, ProtocolEvent, ProtocolEventKind};
+pub use inflight::InFlightMessage;
+pub use fanout::{FanoutReport, validate_group_name};
+pub use group_name::GroupName;
+pub use filter::{FilterAction, SenderFilter};
+pub use flood::{DEFAULT_FLOOD_GUARD_SENDERS, FloodAction, FloodGuard, FloodVerdict};
+pub use lazy::LazyClient;
+pub use limits::DEFAULT_MAX_GROUPS_PER_MESSAGE;
+pub use logging::{CallbackSink, Level, LogCrateSink, LogSink, NullSink};
+pub use membership::MembershipMessage;
+pub use memory::{MemoryBudget, MemoryCapPolicy};
+pub use options::ConnectOptions;
+pub use pause::PausePolicy;
+pub use priority::DeliveryPriority;
+pub use parser::{GroupNames, Parser, RawFrame, SpreadEvent, SpreadMessageRef};
+pub use quota::{QuotaAction, QuotaDecision, Quotas, SendQuota};
+pub use receipt::Receipt;
+pub use reconnect::ReconnectEvent;
+pub use retry::{is_retryable, RetryPolicy};
+pub use slo::{SloEvent, SloMetric, SloMonitor, SloSample, SloThreshold};
+pub use stats::{ClientStats, GroupActivity, Histogram, HISTOGRAM_BUCKETS, RATE_WINDOW_SECS,
+                SessionSummary};
+pub use transform::PayloadTransform;
+pub use transport::Transport;
+pub use url::SpreadUrl;
+
+mod address;
+mod alias;
+pub mod audit;
+pub mod backfill;
+mod batch;
+#[cfg(feature = "libspread")]
+pub mod bench;
+pub mod bridge;
+pub mod capability;
+mod capture;
+#[cfg(feature = "tokio")]
+pub mod channels;
+pub mod checkpoint;
+mod clock;
+pub mod compat;
+pub mod dispatch;
+pub mod envelope;
+mod error;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(not(feature = "chaos"))]
+#[allow(dead_code)]
+mod chaos;
+mod debug_mirror;
+mod events;
+mod fanout;
+pub mod failure;
+mod filter;
+mod flood;
+pub mod group;
+#[macro_use]
+mod group_name;
+pub mod in_memory;
+mod inflight;
+mod lazy;
+pub mod limits;
+mod logging;
+pub mod membership;
+mod memory;
+pub mod mirror;
+mod options;
+mod parser;
+mod pause;
+pub mod presence;
+mod priority;
+mod quota;
+mod receipt;
+pub mod reconnect;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+pub mod rebalance;
+pub mod redundant;
+pub mod relay;
+pub mod replay;
+pub mod resequence;
+mod retry;
+pub mod segment;
+pub mod shard;
+mod slo;
+pub mod standby;
+mod stats;
+pub mod supervisor;
+mod sync;
+mod telemetry;
+pub mod tap;
+pub mod threads;
 mod test;
+pub mod timesync;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "opentelemetry")]
+pub mod trace_context;
+pub mod transform;
+mod transport;
+mod url;
 mod util;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+pub mod workqueue;
 
 pub static DEFAULT_SPREAD_PORT: i16 = 4803;
 
-static MAX_PRIVATE_NAME_LENGTH: usize = 10;
 static DEFAULT_AUTH_NAME: &'static str  = "NULL";
-static MAX_AUTH_NAME_LENGTH: usize = 30;
-static MAX_AUTH_METHOD_COUNT: usize = 3;
-static MAX_GROUP_NAME_LENGTH: usize = 32;
 
-// Control message types.
-// NOTE: The only currently-implemented service type for messaging is "reliable".
+// Control message types. Data messages use `ServiceType`; reliable
+// delivery is the default, e.g. for messages built by `SpreadMessage::new`.
+#[allow(clippy::enum_variant_names)]
 enum ControlServiceType {
     JoinMessage     = 0x00010000,
     LeaveMessage    = 0x00020000,
@@ -37,48 +195,241 @@ enum ControlServiceType {
     ReliableMessage = 0x00000002
 }
 
+/// The delivery guarantee of a multicast, from weakest to strongest.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ServiceType {
+    /// Best effort: messages may be lost or delivered out of order.
+    Unreliable = 0x00000001,
+    /// Delivered, in no particular order.
+    Reliable   = 0x00000002,
+    /// Delivered in the order each sender sent them.
+    Fifo       = 0x00000004,
+    /// Delivered in an order consistent with causality across senders.
+    Causal     = 0x00000008,
+    /// Delivered in the same total order to every member.
+    Agreed     = 0x00000010,
+    /// Totally ordered, and only delivered once every member's daemon has
+    /// received the message.
+    Safe       = 0x00000020
+}
+
+// Service type masks for received messages.
+static REGULAR_MESS: u32 = 0x0000003f;
+static MEMBERSHIP_MESS: u32 = 0x00003f00;
+static REG_MEMB_MESS: u32 = 0x00001000;
+static CAUSED_BY_LEAVE: u32 = 0x00000200;
+
 static SPREAD_MAJOR_VERSION: u8 = 4;
 static SPREAD_MINOR_VERSION: u8 = 4;
 static SPREAD_PATCH_VERSION: u8 = 0;
 
-// Error codes, as per http://www.spread.org/docs/spread_docs_4/docs/error_codes.html
-pub enum SpreadError {
-    AcceptSession = 1,
-    IllegalSpread = -1,
-    CouldNotConnection = -2,
-    RejectQuota = -3,
-    RejectNOName = -4,
-    RejectIllegalName = -5,
-    RejectNotUnique = -6,
-    RejectVersion = -7,
-    ConnectionClosed = -8,
-    RejectAuth = -9,
-    IllegalSession = -11,
-    IllegalService = -12,
-    IllegalMessage = -13,
-    IllegalGroup = -14,
-    BufferTooShort = -15,
-    GroupsTooShort = -16,
-    MessageTooLong = -17,
-    NetErrorOnSession = -18
-}
-
-impl Copy for SpreadError {}
-
 /// A message to be sent or received by a Spread client to/from a group.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SpreadMessage {
     service_type: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    mess_type: i16,
     pub groups: Vec<String>,
     pub sender: String,
     pub data: Vec<u8>,
 }
 
+impl SpreadMessage {
+    /// A data message from `sender` to `groups`, as it would be received
+    /// from a reliable multicast. Useful for testing code that handles
+    /// received messages.
+    pub fn new(sender: &str, groups: &[&str], data: &[u8]) -> SpreadMessage {
+        SpreadMessage::from_parts(
+            ControlServiceType::ReliableMessage as u32,
+            sender.to_string(),
+            groups.iter().map(|g| g.to_string()).collect(),
+            data.to_vec()
+        )
+    }
+
+    /// Assemble a message from the parts returned by `into_parts`.
+    pub fn from_parts(
+        service_type: u32,
+        sender: String,
+        groups: Vec<String>,
+        data: Vec<u8>
+    ) -> SpreadMessage {
+        SpreadMessage { service_type: service_type, mess_type: 0, groups: groups, sender: sender, data: data }
+    }
+
+    /// The message with its application-defined message type set to
+    /// `mess_type`.
+    pub fn with_message_type(mut self, mess_type: i16) -> SpreadMessage {
+        self.mess_type = mess_type;
+        self
+    }
+
+    /// Split the message into its service type, sender, groups and data.
+    pub fn into_parts(self) -> (u32, String, Vec<String>, Vec<u8>) {
+        (self.service_type, self.sender, self.groups, self.data)
+    }
+
+    /// The service type bits of the message's frame.
+    pub fn service_type(&self) -> u32 {
+        self.service_type
+    }
+
+    /// The application-defined message type the sender gave the message,
+    /// or 0 if it gave none (see `SpreadClient::set_message_type`).
+    pub fn message_type(&self) -> i16 {
+        self.mess_type
+    }
+
+    /// The sender's private group name, without NUL padding.
+    pub fn sender(&self) -> &str {
+        self.sender.as_str().trim_end_matches('\0')
+    }
+
+    pub fn groups(&self) -> &[String] {
+        self.groups.as_slice()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// The data as a string, if it is valid UTF-8.
+    pub fn data_as_str(&self) -> Option<&str> {
+        std::str::from_utf8(self.data.as_slice()).ok()
+    }
+
+    /// Decode the data as text in `encoding`, e.g. the one a sender set
+    /// with `set_text_encoding`.
+    pub fn decode_data(&self, encoding: EncodingRef) -> Result<String, Error> {
+        encoding.decode(self.data.as_slice(), DecoderTrap::Strict).map_err(|error| Error::EncodingError(
+            format!("Failed to decode message text: {} in {}", error, encoding.name())
+        ))
+    }
+
+    /// The unique ID the sender stamped the message with, if it enabled
+    /// `set_unique_ids`.
+    pub fn unique_id(&self) -> Option<UniqueId> {
+        Envelope::decode(self.data.as_slice()).and_then(|envelope| envelope.unique_id())
+    }
+
+    /// Returns true if this is a membership message rather than data.
+    pub fn is_membership(&self) -> bool {
+        self.service_type & MEMBERSHIP_MESS != 0
+    }
+
+    /// Returns true if the data is still compressed or encrypted because
+    /// the receiving client couldn't undo it (see the `transform` module).
+    pub fn is_opaque(&self) -> bool {
+        transform::is_opaque(self.data.as_slice())
+    }
+}
+
+/// A message returned by `SpreadClient::receive_event`: either data or a
+/// decoded membership notification.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Received {
+    Message(SpreadMessage),
+    Membership(MembershipMessage)
+}
+
+/// An endless iterator of the events received by a `SpreadClient`.
+pub struct Events<'a> {
+    client: &'a mut SpreadClient
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<SpreadEvent, Error>;
+
+    fn next(&mut self) -> Option<Result<SpreadEvent, Error>> {
+        Some(self.client.next_event())
+    }
+}
+
 /// Representation of a client connection to a Spread daemon.
 pub struct SpreadClient {
-    stream: TcpStream,
+    stream: BufferedTransport,
     pub private_name: String,
-    pub groups: Vec<String>,
-    receive_membership_messages: bool
+    groups: Vec<String>,
+    observed_groups: HashMap<String, bool>,
+    // The private name and membership setting the session was requested
+    // with, to request it again when reconnecting.
+    requested_name: String,
+    receive_membership_messages: bool,
+    dialer: Option<Box<dyn FnMut() -> Result<Box<dyn Transport>, Error> + Send>>,
+    auto_reconnect: Option<RetryPolicy>,
+    reconnect_hook: Option<Box<dyn FnMut(&ReconnectEvent) + Send>>,
+    chaos: chaos::ChaosHooks,
+    stats: stats::StatsRecorder,
+    capture: Option<Capture>,
+    events: events::EventLog,
+    clock: Box<dyn Clock>,
+    monitored_groups: HashSet<String>,
+    sequencer: Option<Sequencer>,
+    history: Option<SendHistory>,
+    sender_filter: Option<SenderFilter>,
+    flood_guard: Option<FloodGuard>,
+    aliases: Option<GroupAliases>,
+    namespace: Option<String>,
+    quotas: Option<Quotas>,
+    logger: Box<dyn LogSink>,
+    error_hook: Option<Box<dyn FnMut(&Error) + Send>>,
+    debug_mirror: Option<DebugMirror>,
+    slo: Option<SloMonitor>,
+    receive_backlog: usize,
+    receive_buffer: Vec<u8>,
+    memory: MemoryBudget,
+    text_encoding: EncodingRef,
+    message_type: i16,
+    message_ttl: Option<time::Duration>,
+    max_message_age: Option<time::Duration>,
+    connected_at: Timespec,
+    last_activity: Timespec,
+    max_message_size: usize,
+    max_groups_per_message: usize,
+    auto_join: Vec<String>,
+    id_stamper: Option<IdStamper>,
+    latency_probing: bool,
+    #[cfg(feature = "opentelemetry")]
+    trace_propagation: bool,
+    transforms: transform::PayloadTransforms,
+    audit: Option<AuditLog>,
+    paused: PausedGroups,
+    // Messages read while waiting for a receipt or competing for priority,
+    // to be returned by `receive`, with the times they were received.
+    pending: VecDeque<(Timespec, SpreadMessage)>,
+    pending_bytes: usize,
+    priority: Option<DeliveryPriority>,
+    // The `Reconnected` event `next_event` owes after reporting a lost
+    // connection.
+    reconnected_event: Option<SpreadEvent>,
+    in_flight: Option<InFlightBuffer>
+}
+
+// A message stamped, transformed and enveloped for sending, but not yet
+// recorded in the send history or written.
+struct PreparedSend {
+    enveloped: Option<Vec<u8>>,
+    sequence: Option<u64>,
+    unique_id: Option<UniqueId>
+}
+
+impl PreparedSend {
+    // The bytes to send in place of the message's `data`.
+    fn payload<'a>(&'a self, data: &'a [u8]) -> &'a [u8] {
+        self.enveloped.as_ref().map_or(data, |enveloped| enveloped.as_slice())
+    }
+
+    // The bytes the message takes in the send history, if it is kept
+    // there, when sent to `groups`.
+    fn history_bytes(&self, groups: &[&str]) -> usize {
+        match (self.sequence, self.enveloped.as_ref()) {
+            (Some(_), Some(enveloped)) => groups.iter().fold(enveloped.len(), |acc, group| acc + group.len()),
+            _ => 0
+        }
+    }
 }
 
 // Construct a byte vector representation of a connect message for the given
@@ -102,17 +453,18 @@ fn encode_connect_message(
     };
     vec.push(mask);
 
-    let private_name_buf = try!(ISO_8859_1.encode(private_name, EncoderTrap::Strict).map_err(
+    let private_name_buf = ISO_8859_1.encode(private_name, EncoderTrap::Strict).map_err(
         |_| format!("Failed to encode private name: {}", private_name)
-    ));
+    )?;
 
     vec.push(private_name.len() as u8);
-    vec.push_all(private_name_buf.as_slice());
+    vec.extend_from_slice(private_name_buf.as_slice());
     Ok(vec)
 }
 
 /// Establishes a named connection to a Spread daemon running at a given
-/// `SocketAddr`.
+/// `SocketAddr`. If the address resolves to several socket addresses, each
+/// is tried in turn until one accepts a connection.
 ///
 /// *Arguments:*
 ///
@@ -120,11 +472,90 @@ fn encode_connect_message(
 /// - `private_name`: A name to use privately to refer to the connection.
 /// - `receive_membership_messages`: If true, membership messages will be
 ///   received by the resultant client.
-pub fn connect<A: ToSocketAddr>(
+pub fn connect<A: ToSocketAddrs>(
     addr: A,
     private_name: &str,
     receive_membership_messages: bool
-) -> IoResult<SpreadClient> {
+) -> Result<SpreadClient, Error> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let stream = connect_any(&addrs)?;
+    let mut client = connect_with_transport(Box::new(stream), private_name, receive_membership_messages)?;
+    client.set_dialer(Some(Box::new(move || {
+        let stream = connect_any(&addrs)?;
+        Ok(Box::new(stream) as Box<dyn Transport>)
+    })));
+    Ok(client)
+}
+
+// Open a TCP connection to the first of `addrs` that accepts one, returning
+// the last error if none do.
+fn connect_any(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::other(
+        "Daemon address resolved to no addresses"
+    );
+    for addr in addrs.iter() {
+        match TcpStream::connect(*addr) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => {
+                debug!("Failed to connect to {}: {}", addr, error);
+                last_error = error;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+// The error for a session the daemon rejected by sending `code`, the low
+// byte of one of Spread's negative error codes, in place of an accept.
+fn rejection(code: u8) -> Error {
+    let code = (0xffffff00 | code as u32) as i32;
+    match SpreadErrorCode::from_code(code) {
+        Some(code) => Error::DaemonError(code),
+        None => Error::ConnectionRejected(format!("daemon sent unknown code {}", code))
+    }
+}
+
+/// Establishes a named connection to a Spread daemon at `host:port`,
+/// tunnelling through the proxy described by `proxy`. Shorthand for
+/// `ConnectOptions::via_proxy` followed by `connect_to`.
+#[cfg(feature = "proxy")]
+pub fn connect_via_proxy(
+    proxy: &proxy::ProxyConfig,
+    host: &str,
+    port: u16,
+    private_name: &str,
+    receive_membership_messages: bool
+) -> Result<SpreadClient, Error> {
+    ConnectOptions::new(private_name)
+        .membership_messages(receive_membership_messages)
+        .via_proxy(proxy.clone())
+        .connect_to(host, port)
+}
+
+/// Establishes a named connection to a Spread daemon behind a TLS
+/// terminator at `host:port`, verifying it per `tls`. Shorthand for
+/// `ConnectOptions::via_tls` followed by `connect_to`.
+#[cfg(feature = "tls")]
+pub fn connect_via_tls(
+    tls: &tls::TlsConfig,
+    host: &str,
+    port: u16,
+    private_name: &str,
+    receive_membership_messages: bool
+) -> Result<SpreadClient, Error> {
+    ConnectOptions::new(private_name)
+        .membership_messages(receive_membership_messages)
+        .via_tls(tls.clone())
+        .connect_to(host, port)
+}
+
+// Send the connect message for `private_name` over `stream` and complete
+// the handshake, returning the private group name the daemon assigned.
+fn open_session(
+    stream: &mut dyn Transport,
+    private_name: &str,
+    receive_membership_messages: bool
+) -> Result<String, Error> {
     // Truncate (if necessary) and write `private_name`.
     let truncated_private_name = match private_name {
         too_long if too_long.len() > MAX_PRIVATE_NAME_LENGTH =>
@@ -133,82 +564,61 @@ pub fn connect<A: ToSocketAddr>(
     };
 
     // Send the initial connect message.
-    let connect_message = try!(encode_connect_message(
+    let connect_message = encode_connect_message(
         truncated_private_name,
         receive_membership_messages
-    ).map_err(|error_msg| IoError {
-        kind: ConnectionFailed,
-        desc: "",
-        detail: Some(error_msg)
-    }));
+    ).map_err(Error::EncodingError)?;
 
-    let socket_addr = try!(addr.to_socket_addr());
-    let mut stream = try!(TcpStream::connect(socket_addr));
-    debug!("Sending connect message to {}", socket_addr);
-    try!(stream.write_all(connect_message.as_slice()));
+    let peer = describe_peer(stream);
+    let _span = telemetry::handshake_span(peer.as_str());
+    debug!("Sending connect message to {}", peer);
+    stream.write_all(connect_message.as_slice())?;
 
     // Read the authentication methods.
-    let authname_len = try!(stream.read_byte()) as i32;
+    let authname_len = read_byte(stream)? as i32;
     if authname_len == -1 {
-        return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Connection closed during connect attempt to read auth name length",
-            detail: None
-        });
+        return Err(Error::Disconnected);
     } else if authname_len >= 128 {
-        return Err(IoError {
-            kind: ConnectionRefused,
-            desc: "Connection attempt rejected",
-            detail: Some(format!("{}", (0xffffff00 | authname_len as u32) as i32))
-        });
+        return Err(rejection(authname_len as u8));
     }
 
     // Ignore the list.
     // TODO: Support IP-based auth?
-    let authname_vec = try!(stream.read_exact(authname_len as usize));
-    let authname = try!(ISO_8859_1.decode(
+    let authname_vec = read_bytes(stream, authname_len as usize)?;
+    let authname = ISO_8859_1.decode(
         authname_vec.as_slice(), DecoderTrap::Strict
-    ).map_err(|error| IoError {
-        kind: OtherIoError,
-        desc: "Failed to decode received authname",
-        detail: Some(String::from_str(&error))
-    }));
+    ).map_err(|error| Error::EncodingError(
+        format!("Failed to decode received authname: {}", error)
+    ))?;
     debug!("Received authentication method choice(s): {}", authname);
 
     // Send auth method choice.
     let mut authname_vec: Vec<u8> = match ISO_8859_1.encode(DEFAULT_AUTH_NAME, EncoderTrap::Strict) {
         Ok(vec) => vec,
-        Err(error) => return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Failed to encode authname",
-            detail: Some(format!("{}", error))
-        })
+        Err(error) => return Err(Error::EncodingError(
+            format!("Failed to encode authname: {}", error)
+        ))
     };
 
-    for _ in range(authname_len as usize, (MAX_AUTH_NAME_LENGTH * MAX_AUTH_METHOD_COUNT + 1)) {
-        authname_vec.push(0);
-    }
+    let padding = (MAX_AUTH_NAME_LENGTH * MAX_AUTH_METHOD_COUNT + 1).saturating_sub(authname_len as usize);
+    authname_vec.resize(authname_vec.len() + padding, 0);
 
     debug!("Sending authentication method choice of {}", DEFAULT_AUTH_NAME);
-    try!(stream.write_all(authname_vec.as_slice()));
+    stream.write_all(authname_vec.as_slice())?;
 
     // Check for an accept message.
-    let accepted: u8 = try!(stream.read_byte());
-    if accepted != SpreadError::AcceptSession as u8 {
-        return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Connection attempt rejected",
-            detail: Some(format!("{}", (0xffffff00 | accepted as u32) as i32))
-        });
+    let accepted: u8 = read_byte(stream)?;
+    if accepted != SpreadErrorCode::AcceptSession as u8 {
+        return Err(rejection(accepted));
     }
 
     debug!("Received session acceptance message from daemon");
 
     // Read the version of Spread that the server is running.
     let (major, minor, patch) =
-        (try!(stream.read_byte()) as i32,
-         try!(stream.read_byte()) as i32,
-         try!(stream.read_byte()) as i32);
+        (read_byte(stream)? as i32,
+         read_byte(stream)? as i32,
+         read_byte(stream)? as i32);
 
     debug!(
         "Received version message: daemon running Spread version {}.{}.{}",
@@ -216,53 +626,127 @@ pub fn connect<A: ToSocketAddr>(
     );
 
     if major == -1 || minor == -1 || patch == -1 {
-        return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Invalid version returned from server",
-            detail: Some(format!("{}.{}.{}", major, minor, patch))
-        });
+        return Err(Error::ProtocolError(
+            format!("Invalid version returned from server: {}.{}.{}", major, minor, patch)
+        ));
     }
 
     let version_sum = (major*10000) + (minor*100) + patch;
     if version_sum < 30100 {
-        return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Server is running old, unsupported version of Spread",
-            detail: Some(format!("{}.{}.{}", major, minor, patch))
-        });
+        return Err(Error::ConnectionRejected(
+            format!("Server is running old, unsupported version of Spread: {}.{}.{}", major, minor, patch)
+        ));
     }
 
     // Read the private group name.
-    let group_name_len = try!(stream.read_byte()) as i32;
+    let group_name_len = read_byte(stream)? as i32;
     if group_name_len == -1 {
-        return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Connection closed during connect attempt to read group name length",
-            detail: None
-        });
+        return Err(Error::Disconnected);
     }
-    let group_name_buf = try!(stream.read_exact(group_name_len as usize));
+    let group_name_buf = read_bytes(stream, group_name_len as usize)?;
     let private_group_name = match String::from_utf8(group_name_buf) {
         Ok(group_name) => group_name,
-        Err(error) => return Err(IoError {
-            kind: ConnectionFailed,
-            desc: "Server sent invalid group name",
-            detail: Some(format!("{}", error))
-        })
+        Err(error) => return Err(Error::EncodingError(
+            format!("Server sent invalid group name: {}", error)
+        ))
     };
 
     debug!("Received private name assignment from daemon: {}", private_group_name);
-    debug!("Client connected to daemon at {}", socket_addr);
+    debug!("Client connected to daemon at {}", peer);
+    Ok(private_group_name)
+}
+
+/// Establishes a named connection to a Spread daemon over an arbitrary,
+/// already-opened transport.
+///
+/// *Arguments:*
+///
+/// - `transport`: A byte stream connected to the Spread daemon.
+/// - `private_name`: A name to use privately to refer to the connection.
+/// - `receive_membership_messages`: If true, membership messages will be
+///   received by the resultant client.
+pub fn connect_with_transport(
+    mut stream: Box<dyn Transport>,
+    private_name: &str,
+    receive_membership_messages: bool
+) -> Result<SpreadClient, Error> {
+    let _span = telemetry::connect_span(private_name);
+    let private_group_name = open_session(&mut *stream, private_name, receive_membership_messages)
+        .inspect_err(telemetry::connect_failed)?;
+    telemetry::connected(private_group_name.as_str());
+    let peer = describe_peer(&mut *stream);
+
+    let clock: Box<dyn Clock> = Box::new(SystemClock);
+    let connected_at = clock.now();
+    let mut events = events::EventLog::new(DEFAULT_EVENT_CAPACITY);
+    events.record(connected_at, ProtocolEventKind::StateChange(
+        format!("connected to {} as {}", peer, private_group_name)
+    ));
 
     Ok(SpreadClient {
-        stream: stream,
+        stream: BufferedTransport::new(stream),
         private_name: private_group_name,
         groups: Vec::new(),
-        receive_membership_messages: receive_membership_messages
+        observed_groups: HashMap::new(),
+        requested_name: private_name.to_string(),
+        receive_membership_messages: receive_membership_messages,
+        dialer: None,
+        auto_reconnect: None,
+        reconnect_hook: None,
+        chaos: chaos::ChaosHooks::new(),
+        stats: stats::StatsRecorder::new(),
+        capture: None,
+        events: events,
+        clock: clock,
+        monitored_groups: HashSet::new(),
+        sequencer: None,
+        history: None,
+        sender_filter: None,
+        flood_guard: None,
+        aliases: None,
+        namespace: None,
+        quotas: None,
+        logger: Box::new(LogCrateSink),
+        error_hook: None,
+        debug_mirror: None,
+        slo: None,
+        receive_backlog: 0,
+        receive_buffer: Vec::new(),
+        memory: MemoryBudget::new(),
+        text_encoding: UTF_8,
+        message_type: 0,
+        message_ttl: None,
+        max_message_age: None,
+        connected_at: connected_at,
+        last_activity: connected_at,
+        max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        max_groups_per_message: DEFAULT_MAX_GROUPS_PER_MESSAGE,
+        auto_join: Vec::new(),
+        id_stamper: None,
+        latency_probing: false,
+        #[cfg(feature = "opentelemetry")]
+        trace_propagation: false,
+        transforms: transform::PayloadTransforms::new(),
+        audit: None,
+        paused: PausedGroups::new(),
+        pending: VecDeque::new(),
+        pending_bytes: 0,
+        priority: None,
+        reconnected_event: None,
+        in_flight: None
     })
 }
 
 impl SpreadClient {
+    /// Perform the Spread handshake over `stream`, an already-established
+    /// connection to a daemon (e.g. one dialed by custom logic or
+    /// inherited from a parent process), then join the groups in
+    /// `options`.
+    pub fn handshake<T: Transport + 'static>(stream: T, options: &ConnectOptions)
+                                             -> Result<SpreadClient, Error> {
+        options.connect_with_transport(Box::new(stream))
+    }
+
     // Encode a service message for dispatch to a Spread daemon.
     fn encode_message(
         service_type: u32,
@@ -271,186 +755,2160 @@ impl SpreadClient {
         data: &[u8]
     ) -> Result<Vec<u8>, String> {
         let mut vec: Vec<u8> = Vec::new();
-        vec.push_all(int_to_bytes(service_type).as_slice());
+        vec.extend_from_slice(int_to_bytes(service_type).as_slice());
 
-        let private_name_buf = try!(ISO_8859_1.encode(private_name, EncoderTrap::Strict).map_err(
+        let private_name_buf = ISO_8859_1.encode(private_name, EncoderTrap::Strict).map_err(
             |_| format!("Failed to encode private name: {}", private_name)
-        ));
-        vec.push_all(private_name_buf.as_slice());
-        for _ in range(private_name.len(), (MAX_GROUP_NAME_LENGTH)) {
-            vec.push(0);
-        }
+        )?;
+        vec.extend_from_slice(private_name_buf.as_slice());
+        vec.resize(vec.len() + MAX_GROUP_NAME_LENGTH.saturating_sub(private_name.len()), 0);
 
-        vec.push_all(int_to_bytes(groups.len() as u32).as_slice());
-        vec.push_all(int_to_bytes(0).as_slice());
-        vec.push_all(int_to_bytes(data.len() as u32).as_slice());
+        vec.extend_from_slice(int_to_bytes(groups.len() as u32).as_slice());
+        vec.extend_from_slice(int_to_bytes(0).as_slice());
+        vec.extend_from_slice(int_to_bytes(data.len() as u32).as_slice());
 
         // Encode and push each group name, converting any encoding errors
         // to error message strings.
         for group in groups.iter() {
-            let group_buf = try!(ISO_8859_1.encode(*group, EncoderTrap::Strict).map_err(
+            let group_buf = ISO_8859_1.encode(group, EncoderTrap::Strict).map_err(
                 |_| format!("Failed to encode group name: {}", group)
-            ));
-            vec.push_all(group_buf.as_slice());
-            for _ in range(group.len(), (MAX_GROUP_NAME_LENGTH)) {
-                vec.push(0);
-            }
+            )?;
+            vec.extend_from_slice(group_buf.as_slice());
+            vec.resize(vec.len() + MAX_GROUP_NAME_LENGTH.saturating_sub(group.len()), 0);
         }
 
-        vec.push_all(data);
+        vec.extend_from_slice(data);
         Ok(vec)
     }
 
+    // Write an encoded frame, whose last `payload_len` bytes are application
+    // data, to the daemon, applying any active chaos hooks.
+    fn write_frame(&mut self, frame: &[u8], payload_len: usize) -> Result<(), Error> {
+        self.write_frames(&[(frame, payload_len)])
+    }
+
+    // Write encoded frames, each paired with the number of application
+    // data bytes at its end, to the daemon in a single write, applying any
+    // active chaos hooks to each.
+    fn write_frames(&mut self, frames: &[(&[u8], usize)]) -> Result<(), Error> {
+        self.apply_forced_disconnect();
+        let mut kept: Vec<&[u8]> = Vec::with_capacity(frames.len());
+        for &(frame, payload_len) in frames.iter() {
+            if let Some(ref mut capture) = self.capture {
+                let (header, payload) = frame.split_at(frame.len() - payload_len);
+                capture.record(Direction::Outbound, header, payload, &*self.logger);
+            }
+            if self.chaos.take_dropped_frame() {
+                client_log!(self, Level::Debug,
+                            "Chaos: dropping outbound frame of {} bytes", frame.len());
+                continue;
+            }
+            kept.push(frame);
+        }
+        if kept.is_empty() {
+            return Ok(());
+        }
+        if let Some(delay) = self.chaos.write_delay() {
+            self.clock.sleep(delay);
+        }
+        let written = if kept.len() == 1 {
+            self.stream.write_all(kept[0])
+        } else {
+            self.stream.write_all(kept.concat().as_slice())
+        };
+        match written.map_err(Error::from) {
+            Ok(()) => {
+                let now = self.clock.now();
+                self.last_activity = now;
+                for frame in kept.iter() {
+                    self.events.record(now, ProtocolEventKind::FrameSent {
+                        service_type: bytes_to_int(&frame[0..4]),
+                        bytes: frame.len()
+                    });
+                }
+                Ok(())
+            },
+            Err(error) => {
+                self.record_error(&error);
+                Err(error)
+            }
+        }
+    }
+
+    // Note a failure in the statistics and the event log, and report it to
+    // the error hook.
+    fn record_error(&mut self, error: &Error) {
+        let now = self.clock.now();
+        self.stats.record_error(error);
+        telemetry::record_error(error);
+        self.events.record(now, ProtocolEventKind::Error(error.clone()));
+        let panicked = match self.error_hook {
+            Some(ref mut hook) => catch_panic("Error hook", || (**hook)(error)).err(),
+            None => None
+        };
+        // Record a panicking hook's own error without calling it again.
+        if let Some(panic) = panicked {
+            client_log!(self, Level::Error, "{}", panic);
+            self.stats.record_error(&panic);
+            telemetry::record_error(&panic);
+            self.events.record(now, ProtocolEventKind::Error(panic));
+        }
+    }
+
+    /// Record an error raised outside the client, such as a supervisor
+    /// giving up on a worker, as if the client had encountered it.
+    pub fn report_error(&mut self, error: &Error) {
+        self.record_error(error);
+    }
+
+    /// Call `hook` with every error the client encounters, including send
+    /// failures that `multicast_with_retry` retries and sends rejected by a
+    /// quota, or stop calling it if `None`. If the hook panics, the panic
+    /// is logged and recorded as an `Error::CallbackPanicked`, which isn't
+    /// passed to the hook.
+    pub fn on_error(&mut self, hook: Option<Box<dyn FnMut(&Error) + Send>>) {
+        self.error_hook = hook;
+    }
+
+    // Close the underlying stream if a chaos disconnect has been requested.
+    fn apply_forced_disconnect(&mut self) {
+        if self.chaos.take_disconnect() {
+            client_log!(self, Level::Debug,
+                        "Chaos: forcing disconnect of client \"{}\"", self.private_name);
+            let _ = self.stream.close();
+        }
+    }
+
+    /// Start capturing every frame exchanged with the daemon, or stop
+    /// capturing if `None`.
+    pub fn set_capture(&mut self, capture: Option<Capture>) {
+        self.capture = capture;
+    }
+
+    /// Send this client's diagnostics to `logger` instead of the `log`
+    /// crate, including those of helpers driving the client, such as
+    /// bridges, relays, backfill and wire capture. Messages logged while
+    /// connecting, before the client exists, and by helpers used without a
+    /// client, always go to the `log` crate.
+    pub fn set_logger(&mut self, logger: Box<dyn LogSink>) {
+        self.logger = logger;
+    }
+
+    /// Replace the time source used for timestamps, rates, latencies and
+    /// the waits between retries, reconnect attempts and delayed sends.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        // Carry the connection age and idle time over to the new clock.
+        let shift = clock.now() - self.clock.now();
+        self.connected_at = self.connected_at + shift;
+        self.last_activity = self.last_activity + shift;
+        self.clock = clock;
+    }
+
+    /// The client's time source, for code driving the client that waits
+    /// or measures time alongside it.
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// The local address of the connection to the daemon, if the transport
+    /// has one.
+    pub fn local_addr(&mut self) -> Option<SocketAddr> {
+        self.stream.socket_name()
+    }
+
+    /// The address of the daemon this client is attached to, if the
+    /// transport has one.
+    pub fn peer_addr(&mut self) -> Option<SocketAddr> {
+        self.stream.peer_name()
+    }
+
+    /// When the session with the daemon was established.
+    pub fn connected_at(&self) -> Timespec {
+        self.connected_at
+    }
+
+    /// How long the session has been established.
+    pub fn connection_age(&self) -> time::Duration {
+        self.clock.now() - self.connected_at
+    }
+
+    /// How long it has been since a frame was last sent or received.
+    pub fn idle_time(&self) -> time::Duration {
+        self.clock.now() - self.last_activity
+    }
+
+    /// Returns the most recent protocol events seen by the session, oldest
+    /// first.
+    pub fn recent_events(&self) -> Vec<ProtocolEvent> {
+        self.events.to_vec()
+    }
+
+    /// Change how many protocol events are retained for `recent_events`.
+    pub fn set_event_capacity(&mut self, capacity: usize) {
+        self.events.set_capacity(capacity);
+    }
+
+    /// Returns a snapshot of the client's traffic statistics.
+    pub fn stats(&self) -> ClientStats {
+        let mut stats = self.stats.snapshot(self.clock.now());
+        stats.receive_queue_depth = self.receive_queue_depth() as u64;
+        stats.buffered_bytes = self.buffered_bytes() as u64;
+        stats
+    }
+
+    // Messages `receive` can return without waiting on the daemon: those
+    // read ahead while awaiting a receipt, those released by resuming a
+    // paused group, and whole frames in the read buffer.
+    fn receive_queue_depth(&self) -> usize {
+        self.pending.len() + self.paused.resumed() + self.stream.buffered_frames()
+    }
+
+    // Bytes held in the client's queues and buffers, and in the queues
+    // sharing its memory budget. The budget is brought up to date with
+    // the client's own.
+    fn buffered_bytes(&self) -> usize {
+        let history = self.history.as_ref().map_or(0, |history| history.bytes());
+        let in_flight = self.in_flight.as_ref().map_or(0, |buffer| buffer.bytes());
+        let received = self.pending_bytes + self.paused.bytes() + self.stream.buffered_bytes();
+        self.memory.set_client_bytes(history + in_flight + received + self.receive_buffer.capacity());
+        self.memory.used()
+    }
+
+    /// Bound the bytes the client holds to `cap`, or remove the bound if
+    /// `None`. Counted are the send history, the resend buffer, received
+    /// messages queued for `receive` or held for paused groups, the read
+    /// and receive buffers, and queues sharing the client's
+    /// `memory_budget`, such as a dispatcher's dead letters. Before
+    /// anything is added to one of them, the cap is checked and, if it
+    /// would be exceeded, the policy set by `set_memory_cap_policy` is
+    /// applied, by default failing with `Error::BufferLimit`. Reading ahead
+    /// from the daemon stops at the cap, leaving further frames in the
+    /// socket. A frame too large for the receive buffer to hold within the
+    /// cap is discarded and `receive_ref` fails with `Error::BufferLimit`,
+    /// as does `receive` for a frame whose header and group names alone
+    /// exceed it.
+    pub fn set_memory_cap(&mut self, cap: Option<usize>) {
+        self.memory.set_cap(cap);
+        self.make_room(0);
+    }
+
+    /// Choose what happens when adding to a queue would take the client
+    /// over its memory cap. Defaults to `MemoryCapPolicy::Error`.
+    pub fn set_memory_cap_policy(&mut self, policy: MemoryCapPolicy) {
+        self.memory.set_policy(policy);
+        self.make_room(0);
+    }
+
+    /// The account of bytes counted against the client's memory cap, to
+    /// share with queues kept outside the client, e.g. with
+    /// `DeadLetterQueue::with_memory_budget`.
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.memory.clone()
+    }
+
+    // Under `MemoryCapPolicy::DropOldest`, evict the oldest sent messages
+    // kept for backfill and then for re-sending until `needed` more bytes
+    // fit within the cap. Returns true if they fit.
+    fn make_room(&mut self, needed: usize) -> bool {
+        self.buffered_bytes();
+        let available = match self.memory.available() {
+            Some(available) => available,
+            None => return true
+        };
+        if needed <= available {
+            return true;
+        }
+        if self.memory.policy() != MemoryCapPolicy::DropOldest {
+            return false;
+        }
+        let mut shortfall = needed - available;
+        let mut evicted = 0;
+        if let Some(ref mut history) = self.history {
+            let before = history.bytes();
+            evicted += history.trim_to(before.saturating_sub(shortfall));
+            shortfall -= before - history.bytes();
+        }
+        if let Some(ref mut buffer) = self.in_flight {
+            let before = buffer.bytes();
+            evicted += buffer.trim_to(before.saturating_sub(shortfall));
+            shortfall -= before - buffer.bytes();
+        }
+        if evicted > 0 {
+            client_log!(self, Level::Debug,
+                        "Evicted {} sent message(s) from the send history and resend buffer to stay \
+                         within the memory cap", evicted);
+        }
+        shortfall == 0
+    }
+
+    // Check that a send's entries in the send history and resend buffer,
+    // as far as they are kept, fit within the memory cap, making room or
+    // refusing as the policy dictates. Returns false if the send should
+    // go ahead without being kept.
+    fn admit_send(&mut self, history_bytes: usize, in_flight_bytes: usize) -> Result<bool, Error> {
+        let needed = if self.history.is_some() { history_bytes } else { 0 } +
+            if self.in_flight.is_some() { in_flight_bytes } else { 0 };
+        if self.make_room(needed) {
+            return Ok(true);
+        }
+        if self.memory.policy() == MemoryCapPolicy::DropNewest {
+            client_log!(self, Level::Debug,
+                        "Memory cap reached; sending without keeping the message for backfill or re-sending");
+            return Ok(false);
+        }
+        self.memory.check(needed).map(|()| true).inspect_err(|error| self.record_error(error))
+    }
+
+    /// Returns the traffic counters for `group`, or `None` if no message
+    /// has been sent to or received from it.
+    pub fn group_activity(&self, group: &str) -> Option<GroupActivity> {
+        self.stats.group_activity(group)
+    }
+
+    /// Returns the traffic counters for every group the client has sent to
+    /// or received from.
+    pub fn group_activities(&self) -> HashMap<String, GroupActivity> {
+        self.stats.group_activities()
+    }
+
+    /// Register the client's statistics with `registry`, labelled with its
+    /// private group name, and keep them up to date from now on. See the
+    /// `prometheus` module for what is exported.
+    #[cfg(feature = "prometheus")]
+    pub fn register_metrics(&mut self, registry: &mut prometheus_client::registry::Registry) {
+        let metrics = prometheus::ClientMetrics::register(registry, self.private_name.as_str());
+        metrics.set_buffers(self.receive_queue_depth(), self.buffered_bytes());
+        self.stats.export_to(metrics);
+    }
+
+    /// Access the fault-injection hooks for this client.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(&mut self) -> &mut chaos::ChaosHooks {
+        &mut self.chaos
+    }
+
     /// Disconnects the client from the Spread daemon.
     // TODO: Prevent further usage of client?
-    pub fn disconnect(&mut self) -> IoResult<()> {
-        let name_slice = self.private_name.as_slice();
-        let kill_message = try!(SpreadClient::encode_message(
+    pub fn disconnect(&mut self) -> Result<SessionSummary, Error> {
+        let name_slice = self.private_name.as_str();
+        let kill_message = SpreadClient::encode_message(
             ControlServiceType::KillMessage as u32,
             name_slice,
             [name_slice].as_slice(),
             [].as_slice()
-        ).map_err(|error_msg| IoError {
-            kind: OtherIoError,
-            desc: "Disconnection failed",
-            detail: Some(error_msg)
-        }));
+        ).map_err(|error_msg| Error::EncodingError(
+            format!("Disconnection failed: {}", error_msg)
+        ))?;
+
+        let peer = describe_peer(&mut self.stream);
+        client_log!(self, Level::Debug, "Disconnecting from daemon at {}", peer);
+        self.write_frame(kill_message.as_slice(), 0)?;
+        let now = self.clock.now();
+        self.events.record(now, ProtocolEventKind::StateChange("disconnected".to_string()));
+
+        let stats = self.stats.snapshot(now);
+        Ok(SessionSummary {
+            duration: now - self.connected_at,
+            messages_sent: stats.messages_sent,
+            bytes_sent: stats.bytes_sent,
+            messages_received: stats.messages_received,
+            bytes_received: stats.bytes_received,
+            reconnects: stats.reconnects,
+            groups: self.groups.clone()
+        })
+    }
+
+    /// Open new connections to the daemon with `dialer` when reconnecting,
+    /// or leave the client unable to reconnect if `None`. Clients made by
+    /// `connect` dial the address they first connected to.
+    pub fn set_dialer(&mut self,
+                      dialer: Option<Box<dyn FnMut() -> Result<Box<dyn Transport>, Error> + Send>>) {
+        self.dialer = dialer;
+    }
+
+    /// Dial the daemon again, repeat the handshake under the private name
+    /// the client connected with, and re-join every group it belongs to.
+    /// Messages already received but not yet returned are kept; the rest
+    /// of a frame partly read from the old connection is lost. Messages in
+    /// the resend buffer (see `set_resend_buffer`) are then sent again, in
+    /// order, and stay buffered until acknowledged. If dialing or the
+    /// handshake fails, the client is left as it was; if re-joining or
+    /// re-sending fails, the new session is kept and the error returned.
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        let _span = telemetry::reconnect_span(self.requested_name.as_str());
+        let mut transport = match self.dialer {
+            Some(ref mut dialer) => (**dialer)()?,
+            None => return Err(Error::InvalidInput(
+                "No dialer to reconnect with: set one with set_dialer".to_string()
+            ))
+        };
+        let private_name = open_session(&mut *transport, self.requested_name.as_str(),
+                                        self.receive_membership_messages)?;
+        let peer = describe_peer(&mut *transport);
+        self.stream.replace(transport)?;
+        client_log!(self, Level::Info, "Reconnected to daemon at {} as \"{}\"", peer, private_name);
+        self.private_name = private_name;
+        self.observed_groups.clear();
+        let now = self.clock.now();
+        self.last_activity = now;
+        self.stats.record_reconnect();
+        telemetry::reconnected(self.private_name.as_str());
+        self.events.record(now, ProtocolEventKind::StateChange(
+            format!("reconnected to {} as {}", peer, self.private_name)
+        ));
+        for group in self.groups.clone().iter() {
+            self.send_join(group.as_str())?;
+            self.audit(group.as_str(), AuditAction::Join, AuditCause::Reconnect);
+        }
+        self.expire_in_flight();
+        let in_flight = self.in_flight.as_ref().map(|buffer| buffer.messages()).unwrap_or_default();
+        for message in in_flight.iter() {
+            let groups: Vec<&str> = message.groups.iter().map(|g| g.as_str()).collect();
+            self.write_message(message.service, groups.as_slice(), message.data.as_slice())?;
+        }
+        if !in_flight.is_empty() {
+            client_log!(self, Level::Info, "Re-sent {} in-flight message(s) after reconnecting",
+                        in_flight.len());
+        }
+        Ok(())
+    }
+
+    /// Reconnect automatically, making up to `policy.max_attempts`
+    /// attempts, when `receive`, `try_receive` or a multicast finds the
+    /// connection closed, or fail with `Error::Disconnected` if `None`. A
+    /// receive carries on waiting on the new session and a multicast is
+    /// sent again over it; `next_event` also reports where the break was.
+    pub fn set_auto_reconnect(&mut self, policy: Option<RetryPolicy>) {
+        self.auto_reconnect = policy;
+    }
+
+    /// Call `hook` with each step of an automatic reconnect, or stop
+    /// calling it if `None`. If the hook panics, the panic is reported as
+    /// an `Error::CallbackPanicked` and the reconnect carries on.
+    pub fn on_reconnect(&mut self, hook: Option<Box<dyn FnMut(&ReconnectEvent) + Send>>) {
+        self.reconnect_hook = hook;
+    }
+
+    // Reconnect per the auto-reconnect policy if `error` shows that the
+    // connection was lost. Returns `error` if it doesn't, or the last
+    // attempt's error once the policy gives up.
+    fn recover(&mut self, error: Error) -> Result<(), Error> {
+        let policy = match (self.auto_reconnect.clone(), &error) {
+            (Some(policy), &Error::Disconnected) => policy,
+            _ => return Err(error)
+        };
+        self.notify_reconnect(ReconnectEvent::Lost { error: error });
+        let mut attempt = 1;
+        loop {
+            match self.reconnect() {
+                Ok(()) => {
+                    let event = ReconnectEvent::Reconnected {
+                        attempts: attempt,
+                        private_name: self.private_name.clone(),
+                        groups: self.groups.clone()
+                    };
+                    self.notify_reconnect(event);
+                    return Ok(());
+                },
+                Err(error) => {
+                    self.record_error(&error);
+                    self.notify_reconnect(ReconnectEvent::AttemptFailed {
+                        attempt: attempt,
+                        error: error.clone()
+                    });
+                    if attempt >= policy.max_attempts {
+                        self.notify_reconnect(ReconnectEvent::GaveUp {
+                            attempts: attempt,
+                            error: error.clone()
+                        });
+                        return Err(error);
+                    }
+                    self.clock.sleep(policy.backoff(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
 
-        debug!("Disconnecting from daemon at {}", try!(self.stream.peer_name()));
-        self.stream.write_all(kill_message.as_slice())
+    fn notify_reconnect(&mut self, event: ReconnectEvent) {
+        let panicked = match self.reconnect_hook {
+            Some(ref mut hook) => catch_panic("Reconnect hook", || (**hook)(&event)).err(),
+            None => None
+        };
+        if let Some(panic) = panicked {
+            self.record_error(&panic);
+        }
     }
 
     /// Join a named Spread group.
     ///
     /// All messages sent to the group will be received by the client until it
     /// has left the group.
-    pub fn join(&mut self, group_name: &str) -> IoResult<()> {
-        let join_message = try!(SpreadClient::encode_message(
+    pub fn join(&mut self, group_name: &str) -> Result<(), Error> {
+        self.send_join(group_name)?;
+        if !self.is_member(group_name) {
+            self.groups.push(group_name.to_string());
+        }
+        self.audit(group_name, AuditAction::Join, AuditCause::Requested);
+        telemetry::joined();
+        Ok(())
+    }
+
+    // Send the join message for `group_name`.
+    fn send_join(&mut self, group_name: &str) -> Result<(), Error> {
+        let physical = self.physical_group(group_name);
+        let join_message = SpreadClient::encode_message(
             ControlServiceType::JoinMessage as u32,
-            self.private_name.as_slice(),
-            [group_name].as_slice(),
+            self.private_name.as_str(),
+            [physical.as_str()].as_slice(),
             [].as_slice()
-        ).map_err(|error_msg| IoError {
-            kind: OtherIoError,
-            desc: "Group join failed",
-            detail: Some(error_msg)
-        }));
-
-        debug!("Client \"{}\" joining group \"{}\"", self.private_name, group_name);
-        try!(self.stream.write_all(join_message.as_slice()));
-        self.groups.push(group_name.to_string());
-        Ok(())
+        ).map_err(|error_msg| Error::EncodingError(
+            format!("Group join failed: {}", error_msg)
+        ))?;
+
+        client_log!(self, Level::Debug,
+                    "Client \"{}\" joining group \"{}\"", self.private_name, group_name);
+        self.write_frame(join_message.as_slice(), 0)
     }
 
     /// Leave a named Spread group.
-    pub fn leave(&mut self, group_name: &str) -> IoResult<()> {
-        let leave_message = try!(SpreadClient::encode_message(
+    pub fn leave(&mut self, group_name: &str) -> Result<(), Error> {
+        let physical = self.physical_group(group_name);
+        let leave_message = SpreadClient::encode_message(
             ControlServiceType::LeaveMessage as u32,
-            self.private_name.as_slice(),
-            [group_name].as_slice(),
+            self.private_name.as_str(),
+            [physical.as_str()].as_slice(),
             [].as_slice()
-        ).map_err(|error_msg| IoError {
-            kind: OtherIoError,
-            desc: "Group leave failed",
-            detail: Some(error_msg)
-        }));
-
-        debug!("Client \"{}\" leaving group \"{}\"", self.private_name, group_name);
-        try!(self.stream.write_all(leave_message.as_slice()));
-        self.groups.push(group_name.to_string());
+        ).map_err(|error_msg| Error::EncodingError(
+            format!("Group leave failed: {}", error_msg)
+        ))?;
+
+        client_log!(self, Level::Debug,
+                    "Client \"{}\" leaving group \"{}\"", self.private_name, group_name);
+        self.write_frame(leave_message.as_slice(), 0)?;
+        self.groups.retain(|g| g.as_str() != group_name);
+        self.audit(group_name, AuditAction::Leave, AuditCause::Requested);
+        telemetry::left();
         Ok(())
     }
 
-    /// Send a message to a set of named groups.
-    pub fn multicast(
-        &mut self,
-        groups: &[&str],
-        data: &[u8]
-    ) -> IoResult<()> {
-        let message = try!(SpreadClient::encode_message(
-            ControlServiceType::ReliableMessage as u32,
-            self.private_name.as_slice(),
-            groups,
-            data
-        ).map_err(|error_msg| IoError {
-            kind: OtherIoError,
-            desc: "Multicast failed",
-            detail: Some(error_msg)
-        }));
+    /// Record the groups joined at connect time, to be joined again
+    /// whenever the session is re-established.
+    pub fn set_auto_join(&mut self, groups: &[String]) {
+        self.auto_join = groups.to_vec();
+    }
 
-        debug!("Client \"{}\" multicasting {} bytes to group(s) {:?}",
-               self.private_name, data.len(), groups);
-        self.stream.write_all(message.as_slice())
+    /// The groups joined at connect time.
+    pub fn auto_join_groups(&self) -> &[String] {
+        self.auto_join.as_slice()
     }
 
-    /// Receive the next available message. If there are no messages available,
-    /// the call will block until either a message is received or a timeout
-    /// expires.
-    pub fn receive(&mut self) -> IoResult<SpreadMessage> {
-        // Header format (sizes in bytes):
-        //   svc_type:   4
-        //   sender:    32
-        //   num_groups: 4
-        //   hint:       4
-        //   data_len:   4
-        let header_vec = try!(self.stream.read_exact(MAX_GROUP_NAME_LENGTH + 16));
-        let is_correct_endianness = same_endianness(bytes_to_int(&header_vec[0..4]));
-
-        let svc_type = match (is_correct_endianness, bytes_to_int(&header_vec[0..4])) {
-            (true, correct) => correct,
-            (false, incorrect) => flip_endianness(incorrect)
-        };
+    /// The groups this client has joined, in the order it joined them.
+    pub fn groups(&self) -> &[String] {
+        self.groups.as_slice()
+    }
 
-        let sender = try!(
-            ISO_8859_1.decode(
-                &header_vec[4..36],
-                DecoderTrap::Strict
-            ).map_err(|error| IoError {
-                kind: OtherIoError,
-                desc: "Failed to decode sender name",
-                detail: Some(String::from_str(&error))
-            })
-        );
+    /// Returns true if this client has joined `group_name`.
+    pub fn is_member(&self, group_name: &str) -> bool {
+        self.groups.iter().any(|g| g.as_str() == group_name)
+    }
 
-        let num_groups = match (is_correct_endianness, bytes_to_int(&header_vec[36..40])) {
-            (true, correct) => correct,
-            (false, incorrect) => flip_endianness(incorrect)
-        };
-        let data_len = match (is_correct_endianness, bytes_to_int(&header_vec[44..48])) {
-            (true, correct) => correct,
-            (false, incorrect) => flip_endianness(incorrect)
+    /// Leave every joined group, stopping at the first failure.
+    pub fn leave_all(&mut self) -> Result<(), Error> {
+        for group in self.groups.clone().iter() {
+            self.leave(group.as_str())?;
+        }
+        Ok(())
+    }
+
+    /// Reconcile the joined groups with the membership messages received
+    /// since the last call: groups whose latest view includes this client
+    /// are added, and groups it has left (e.g. because another connection
+    /// under the same name left them) are removed. Returns the groups that
+    /// changed, sorted. Only has an effect on clients that receive
+    /// membership messages.
+    pub fn resync(&mut self) -> Vec<String> {
+        let observed = mem::take(&mut self.observed_groups);
+        let mut changed = Vec::new();
+        for (group, member) in observed.into_iter() {
+            if member && !self.is_member(group.as_str()) {
+                self.groups.push(group.clone());
+                self.audit(group.as_str(), AuditAction::Join, AuditCause::Resync);
+                changed.push(group);
+            } else if !member && self.is_member(group.as_str()) {
+                self.groups.retain(|g| *g != group);
+                self.audit(group.as_str(), AuditAction::Leave, AuditCause::Resync);
+                changed.push(group);
+            }
+        }
+        changed.sort();
+        changed
+    }
+
+    // Note whether a membership message shows this client in or out of the
+    // group it is about.
+    fn observe_membership(&mut self, message: &SpreadMessage) {
+        if message.service_type & MEMBERSHIP_MESS == 0 {
+            return;
+        }
+        if message.service_type & REG_MEMB_MESS != 0 {
+            let members = message.groups.iter()
+                .map(|m| m.as_str().trim_end_matches('\0').to_string())
+                .collect();
+            self.audit(message.sender(), AuditAction::Membership(members),
+                       AuditCause::from_service_type(message.service_type));
+        }
+        let member = if message.service_type & REG_MEMB_MESS != 0 {
+            let private_name = self.private_name.as_str().trim_end_matches('\0');
+            message.groups.iter().any(|m| m.as_str().trim_end_matches('\0') == private_name)
+        } else if message.service_type & CAUSED_BY_LEAVE != 0 {
+            // A self-leave notification.
+            false
+        } else {
+            return;
         };
+        self.observed_groups.insert(message.sender().to_string(), member);
+    }
 
-        // Groups format (sizes in bytes):
-        //   groups: num_groups
-        let groups_vec =
-            try!(self.stream.read_exact(MAX_GROUP_NAME_LENGTH * num_groups as usize));
-        let mut groups = Vec::new();
-
-        for n in range(0, num_groups) {
-            let i: usize = n as usize * MAX_GROUP_NAME_LENGTH;
-            let group = try!(
-                ISO_8859_1.decode(&groups_vec[i..i + MAX_GROUP_NAME_LENGTH], DecoderTrap::Strict)
-                    .map_err(|error| IoError {
-                        kind: OtherIoError,
-                        desc: "Failed to decode group name",
-                        detail: Some(String::from_str(&error))
-                    }));
-            groups.push(group);
+    /// Record every join, leave and membership change of this session in
+    /// `log`, or stop recording if `None`.
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        self.audit = log;
+    }
+
+    /// The audit log, if one is set.
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit.as_ref()
+    }
+
+    // Add an entry to the audit log, if there is one. Failing to export the
+    // entry is logged rather than failing the operation being audited.
+    fn audit(&mut self, group: &str, action: AuditAction, cause: AuditCause) {
+        let now = self.clock.now();
+        let result = match self.audit {
+            Some(ref mut log) => log.record(AuditEntry {
+                timestamp: now,
+                group: group.to_string(),
+                action: action,
+                cause: cause
+            }),
+            None => return
+        };
+        if let Err(error) = result {
+            client_log!(self, Level::Warn, "Failed to export audit entry for \"{}\": {}", group, error);
         }
+    }
 
-        // Data format (sizes in bytes):
-        //   data: data_len
-        let data_vec = try!(self.stream.read_exact(data_len as usize));
+    /// Encode `text` with `set_text_encoding`'s encoding (UTF-8 by
+    /// default) and send it to a set of named groups. Fails without sending
+    /// if `text` cannot be encoded.
+    pub fn multicast_str(&mut self, groups: &[&str], text: &str) -> Result<(), Error> {
+        let data = self.text_encoding.encode(text, EncoderTrap::Strict).map_err(|error| Error::EncodingError(
+            format!("Failed to encode message text: {} in {}", error, self.text_encoding.name())
+        ))?;
+        self.multicast(groups, data.as_slice())
+    }
 
-        debug!("Received {} bytes from \"{}\" sent to group(s) {:?}",
-               data_len, sender, groups);
+    /// Encode text sent with `multicast_str` in `encoding`.
+    pub fn set_text_encoding(&mut self, encoding: EncodingRef) {
+        self.text_encoding = encoding;
+    }
 
-        Ok(SpreadMessage {
-            service_type: svc_type as u32,
-            groups: groups,
+    /// Give every message multicast from now on the application-defined
+    /// message type `mess_type`, which receivers read with
+    /// `SpreadMessage::message_type`. Messages are sent with type 0 until
+    /// this is called, and messages re-sent after a reconnect carry the
+    /// type set when they are re-sent.
+    pub fn set_message_type(&mut self, mess_type: i16) {
+        self.message_type = mess_type;
+    }
+
+    /// The message type multicasts are sent with.
+    pub fn message_type(&self) -> i16 {
+        self.message_type
+    }
+
+    /// Give every message multicast from now on a time to live of `ttl`,
+    /// or none if `None`. A message in the resend buffer is dropped rather
+    /// than re-sent once its TTL has run out, and a multicast that a send
+    /// quota would delay for its whole TTL fails with
+    /// `Error::QuotaExceeded` instead of waiting. Both are counted in
+    /// `ClientStats::expired_sent`.
+    pub fn set_message_ttl(&mut self, ttl: Option<time::Duration>) {
+        self.message_ttl = ttl;
+    }
+
+    /// Drop received messages that have waited longer than `max_age` to be
+    /// returned, e.g. while queued behind a backlog or held for a paused
+    /// group, or keep them however old if `None`. Dropped messages are
+    /// counted in `ClientStats::expired_received`.
+    pub fn set_max_message_age(&mut self, max_age: Option<time::Duration>) {
+        self.max_message_age = max_age;
+    }
+
+    /// Send a message to a set of named groups, with reliable delivery.
+    pub fn multicast(
+        &mut self,
+        groups: &[&str],
+        data: &[u8]
+    ) -> Result<(), Error> {
+        self.multicast_with_service(ServiceType::Reliable, groups, data)
+    }
+
+    /// Send a message to a set of named groups with the delivery guarantee
+    /// of `service`, e.g. `Agreed` for the same total order at every
+    /// member.
+    pub fn multicast_with_service(
+        &mut self,
+        service: ServiceType,
+        groups: &[&str],
+        data: &[u8]
+    ) -> Result<(), Error> {
+        let physical: Vec<String> = groups.iter().map(|g| self.physical_group(g)).collect();
+        let physical: Vec<&str> = physical.iter().map(|g| g.as_str()).collect();
+        let groups = physical.as_slice();
+        self.enforce_quotas(groups, data.len())?;
+        let (sequencer, id_stamper) = (self.sequencer.clone(), self.id_stamper.clone());
+        let prepared = self.prepare_send(data)?;
+        let payload = prepared.payload(data);
+        let keep = match self.admit_send(prepared.history_bytes(groups), payload.len()) {
+            Ok(keep) => keep,
+            Err(error) => {
+                // Nothing was sent, so its sequence number and ID go to the next send.
+                self.sequencer = sequencer;
+                self.id_stamper = id_stamper;
+                return Err(error);
+            }
+        };
+        if keep {
+            self.record_history(groups, &prepared);
+        }
+        self.send_frame(service, groups, payload)?;
+        if keep {
+            self.track_in_flight(service, groups, payload, prepared.unique_id);
+        }
+        Ok(())
+    }
+
+    // Stamp, transform and envelope `data` as configured. Stamped and
+    // transformed messages are enveloped anyway, so they also advertise
+    // this client's capabilities.
+    fn prepare_send(&mut self, data: &[u8]) -> Result<PreparedSend, Error> {
+        let mut stamped = self.sequencer.as_mut().map(|sequencer| sequencer.stamp(data));
+        let mut unique_id = None;
+        if let Some(ref mut stamper) = self.id_stamper {
+            let mut envelope = stamped.take().unwrap_or_else(|| Envelope::new(data));
+            let id = stamper.next_id();
+            envelope.set_unique_id(id);
+            unique_id = Some(id);
+            stamped = Some(envelope);
+        }
+        if self.latency_probing {
+            let mut envelope = stamped.take().unwrap_or_else(|| Envelope::new(data));
+            envelope.set_sent_at(self.clock.now());
+            stamped = Some(envelope);
+        }
+        #[cfg(feature = "opentelemetry")]
+        {
+            let context = opentelemetry::Context::current();
+            if self.trace_propagation && trace_context::is_traced(&context) {
+                let mut envelope = stamped.take().unwrap_or_else(|| Envelope::new(data));
+                trace_context::inject(&mut envelope, &context);
+                stamped = Some(envelope);
+            }
+        }
+        if !self.transforms.is_empty() {
+            let mut envelope = stamped.take().unwrap_or_else(|| Envelope::new(data));
+            self.transforms.apply(&mut envelope)?;
+            stamped = Some(envelope);
+        }
+        match stamped {
+            Some(mut envelope) => {
+                let capabilities = capability::local_capabilities() | self.transforms.capabilities();
+                capability::advertise(&mut envelope, capabilities);
+                Ok(PreparedSend {
+                    enveloped: Some(envelope.encode()?),
+                    sequence: envelope.sequence(),
+                    unique_id: unique_id
+                })
+            },
+            None => Ok(PreparedSend { enveloped: None, sequence: None, unique_id: None })
+        }
+    }
+
+    // Keep a prepared, sequenced message in the send history, if any.
+    fn record_history(&mut self, groups: &[&str], prepared: &PreparedSend) {
+        if let (Some(sequence), Some(enveloped)) = (prepared.sequence, prepared.enveloped.as_ref()) {
+            if let Some(ref mut history) = self.history {
+                history.record(sequence, groups, enveloped.as_slice());
+            }
+        }
+    }
+
+    /// Keep the last `capacity` sent messages until they are acknowledged,
+    /// so they can be re-sent after the session breaks (see the
+    /// `inflight` module). A capacity of zero disables the buffer and
+    /// discards its contents.
+    pub fn set_resend_buffer(&mut self, capacity: usize) {
+        self.in_flight = if capacity == 0 { None } else { Some(InFlightBuffer::new(capacity)) };
+    }
+
+    // Remember a successfully written message until it is acknowledged.
+    fn track_in_flight(&mut self, service: ServiceType, groups: &[&str], data: &[u8],
+                       id: Option<UniqueId>) {
+        if self.in_flight.is_none() {
+            return;
+        }
+        let expires_at = self.message_ttl.map(|ttl| self.clock.now() + ttl);
+        self.buffer_in_flight(InFlightMessage {
+            service: service,
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            data: data.to_vec(),
+            id: id,
+            expires_at: expires_at
+        });
+    }
+
+    fn buffer_in_flight(&mut self, message: InFlightMessage) {
+        let evicted = match self.in_flight {
+            Some(ref mut buffer) => {
+                let before = buffer.evicted();
+                buffer.push(message);
+                buffer.evicted() - before
+            },
+            None => return
+        };
+        if evicted > 0 {
+            client_log!(self, Level::Warn,
+                        "Resend buffer full; {} unacknowledged message(s) can no longer be re-sent",
+                        evicted);
+        }
+    }
+
+    /// Mark every message in the resend buffer as having reached the
+    /// daemon, e.g. after an application-level acknowledgement.
+    pub fn acknowledge_in_flight(&mut self) {
+        if let Some(ref mut buffer) = self.in_flight {
+            buffer.take();
+        }
+    }
+
+    /// The number of sent messages not yet acknowledged.
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.as_ref().map_or(0, |buffer| buffer.len())
+    }
+
+    /// Remove and return the unacknowledged messages, oldest first, to
+    /// pass to `resend_in_flight` on a new session.
+    pub fn take_in_flight(&mut self) -> Vec<InFlightMessage> {
+        match self.in_flight {
+            Some(ref mut buffer) => buffer.take(),
+            None => Vec::new()
+        }
+    }
+
+    /// Send `messages`, taken from a broken session with `take_in_flight`,
+    /// exactly as they were first sent, returning how many were sent. Those
+    /// whose TTL has run out are dropped instead. They are tracked in this
+    /// client's resend buffer in turn.
+    pub fn resend_in_flight(&mut self, messages: Vec<InFlightMessage>) -> Result<usize, Error> {
+        let now = self.clock.now();
+        let (expired, messages): (Vec<InFlightMessage>, Vec<InFlightMessage>) =
+            messages.into_iter().partition(|message| message.is_expired(now));
+        self.record_expired_sent(expired.len());
+        let total = messages.len();
+        for message in messages.into_iter() {
+            {
+                let groups: Vec<&str> = message.groups.iter().map(|g| g.as_str()).collect();
+                self.send_frame(message.service, groups.as_slice(), message.data.as_slice())?;
+            }
+            self.buffer_in_flight(message);
+        }
+        client_log!(self, Level::Info, "Re-sent {} in-flight message(s)", total);
+        Ok(total)
+    }
+
+    // Drop messages whose TTL has run out from the resend buffer.
+    fn expire_in_flight(&mut self) {
+        let now = self.clock.now();
+        let expired = self.in_flight.as_mut().map_or(0, |buffer| buffer.expire(now));
+        self.record_expired_sent(expired);
+    }
+
+    fn record_expired_sent(&mut self, count: usize) {
+        if count > 0 {
+            client_log!(self, Level::Debug, "Dropped {} sent message(s) past their TTL", count);
+            self.stats.record_expired_sent(count);
+        }
+    }
+
+    /// Enforce `quotas` on every multicast, or remove them if `None`.
+    /// Quotas are matched against group names as sent on the wire, i.e.
+    /// after alias translation and namespace prefixing.
+    pub fn set_quotas(&mut self, quotas: Option<Quotas>) {
+        self.quotas = quotas;
+    }
+
+    // Check a send against the quotas, waiting or failing as they dictate.
+    fn enforce_quotas(&mut self, groups: &[&str], bytes: usize) -> Result<(), Error> {
+        let now = self.clock.now();
+        let decision = match self.quotas {
+            Some(ref mut quotas) => quotas.admit_logging_to(now, groups, bytes, &*self.logger),
+            None => return Ok(())
+        };
+        match decision {
+            QuotaDecision::Allow => Ok(()),
+            QuotaDecision::Delay(wait) if self.message_ttl.is_some_and(|ttl| wait >= ttl) => {
+                self.record_expired_sent(1);
+                let error = Error::QuotaExceeded(format!(
+                    "send quota delay of {}ms outlasts the message TTL", wait.num_milliseconds()
+                ));
+                self.record_error(&error);
+                Err(error)
+            },
+            QuotaDecision::Delay(wait) => {
+                client_log!(self, Level::Debug,
+                            "Send quota exceeded; delaying multicast by {}ms", wait.num_milliseconds());
+                self.clock.sleep(wait);
+                let later = self.clock.now();
+                if let Some(ref mut quotas) = self.quotas {
+                    quotas.charge(later, groups, bytes);
+                }
+                Ok(())
+            },
+            QuotaDecision::Reject(quota) => {
+                let error = Error::QuotaExceeded(quota);
+                self.record_error(&error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Reject multicasts with payloads larger than `size` bytes, to match
+    /// a daemon configured with a non-default maximum. Defaults to
+    /// `limits::DEFAULT_MAX_MESSAGE_SIZE`.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Address at most `count` groups in one frame, instead of
+    /// `DEFAULT_MAX_GROUPS_PER_MESSAGE`: `fanout` splits longer group lists
+    /// across frames and `send_batch` rejects messages addressed to more.
+    pub fn set_max_groups_per_message(&mut self, count: usize) {
+        self.max_groups_per_message = cmp::max(count, 1);
+    }
+
+    // Send a message without applying sequence stamping.
+    fn multicast_unstamped(&mut self, groups: &[&str], data: &[u8]) -> Result<(), Error> {
+        self.send_frame(ServiceType::Reliable, groups, data)
+    }
+
+    // Send a message as given, with the service type `service`, sending it
+    // again if the connection was lost and automatically re-established.
+    fn send_frame(&mut self, service: ServiceType, groups: &[&str], data: &[u8]) -> Result<(), Error> {
+        match self.write_message(service, groups, data) {
+            Err(error) => {
+                self.recover(error)?;
+                self.write_message(service, groups, data)
+            },
+            ok => ok
+        }
+    }
+
+    // Encode and write one message frame.
+    fn write_message(&mut self, service: ServiceType, groups: &[&str], data: &[u8]) -> Result<(), Error> {
+        if data.len() > self.max_message_size {
+            let error = Error::InvalidInput(
+                format!("Message too long: {} bytes, maximum {}", data.len(), self.max_message_size)
+            );
+            self.record_error(&error);
+            return Err(error);
+        }
+        self.write_messages(service, &[(groups, data)])
+    }
+
+    // Send the messages of a batch as given, sending them all again if the
+    // connection was lost and automatically re-established.
+    fn send_frames(&mut self, service: ServiceType, sends: &[(&[&str], &[u8])]) -> Result<(), Error> {
+        match self.write_messages(service, sends) {
+            Err(error) => {
+                self.recover(error)?;
+                self.write_messages(service, sends)
+            },
+            ok => ok
+        }
+    }
+
+    // Encode a frame for each message, each a payload and the groups it is
+    // addressed to, and write them all at once.
+    fn write_messages(&mut self, service: ServiceType, sends: &[(&[&str], &[u8])]) -> Result<(), Error> {
+        let mut frames = Vec::with_capacity(sends.len());
+        for &(groups, data) in sends.iter() {
+            let mut message = SpreadClient::encode_message(
+                service as u32,
+                self.private_name.as_str(),
+                groups,
+                data
+            ).map_err(|error_msg| Error::EncodingError(
+                format!("Multicast failed: {}", error_msg)
+            ))?;
+            // The message type goes in the hint field, as the C client
+            // library puts it.
+            message[40..44].copy_from_slice(int_to_bytes((self.message_type as u16 as u32) << 8).as_slice());
+            client_log!(self, Level::Debug, "Client \"{}\" multicasting {} bytes to group(s) {:?}",
+                        self.private_name, data.len(), groups);
+            frames.push(message);
+        }
+
+        let framed: Vec<(&[u8], usize)> = frames.iter().zip(sends.iter())
+            .map(|(frame, &(_, data))| (frame.as_slice(), data.len()))
+            .collect();
+        let started = self.clock.monotonic_ns();
+        self.write_frames(framed.as_slice())?;
+        // Frames written together share the time taken to write them.
+        let write_ns = self.clock.monotonic_ns().saturating_sub(started) / sends.len() as u64;
+        let now = self.clock.now();
+        for &(groups, data) in sends.iter() {
+            self.stats.record_write_latency(write_ns / 1000);
+            self.stats.record_send(now, groups, data.len());
+            telemetry::sent(data.len(), write_ns);
+            self.mirror_to_debug(Direction::Outbound, groups, data);
+        }
+        self.check_slos();
+        Ok(())
+    }
+
+    /// Check the client's send latency, receive backlog and message rates
+    /// against the thresholds of `monitor` after every send and receive,
+    /// logging breaches at each threshold's level, or stop checking if
+    /// `None`.
+    pub fn set_slo_monitor(&mut self, monitor: Option<SloMonitor>) {
+        self.slo = monitor;
+    }
+
+    /// Report how many received messages the application has yet to
+    /// process, for checking against `ReceiveBacklog` thresholds.
+    pub fn report_receive_backlog(&mut self, depth: usize) {
+        self.receive_backlog = depth;
+        self.check_slos();
+    }
+
+    // Check the current stats against the SLO monitor, if any, and log any
+    // alarms that fire or clear. Registered buffer gauges are refreshed here
+    // too, since this runs after every send and receive.
+    fn check_slos(&mut self) {
+        #[cfg(feature = "prometheus")]
+        if let Some(metrics) = self.stats.exported() {
+            metrics.set_buffers(self.receive_queue_depth(), self.buffered_bytes());
+        }
+        if self.slo.is_none() {
+            return;
+        }
+        let now = self.clock.now();
+        let stats = self.stats.snapshot(now);
+        let sample = SloSample {
+            send_latency_p99_us: stats.write_latency_us.quantile(0.99),
+            receive_backlog: self.receive_backlog,
+            send_rate: stats.send_rate,
+            receive_rate: stats.receive_rate
+        };
+        let (events, panics) = match self.slo {
+            Some(ref mut monitor) => (monitor.check(now, &sample), monitor.take_panics()),
+            None => return
+        };
+        for panic in panics.iter() {
+            self.record_error(panic);
+        }
+        for event in events.into_iter() {
+            match event {
+                SloEvent::Breached { metric, value, limit, level } =>
+                    client_log!(self, level, "SLO breached: {} is {} (limit {})",
+                                metric.name(), value, limit),
+                SloEvent::Recovered { metric, value, limit } =>
+                    client_log!(self, Level::Info, "SLO recovered: {} is {} (limit {})",
+                                metric.name(), value, limit)
+            }
+        }
+    }
+
+    /// Copy a sample of this client's traffic to a debug group, or stop
+    /// copying if `None`. Failures to send a copy are logged and otherwise
+    /// ignored.
+    pub fn set_debug_mirror(&mut self, mirror: Option<DebugMirror>) {
+        self.debug_mirror = mirror;
+    }
+
+    // Send a copy of a message to the debug group, if configured to.
+    fn mirror_to_debug(&mut self, direction: Direction, groups: &[&str], data: &[u8]) {
+        let debug_group = match self.debug_mirror {
+            Some(ref mut mirror) => match mirror.should_mirror(direction, groups, data) {
+                Ok(true) => mirror.group().to_string(),
+                Ok(false) => return,
+                Err(error) => {
+                    self.record_error(&error);
+                    return;
+                }
+            },
+            None => return
+        };
+        let copied = DebugMirror::encode(self.private_name.as_str(), direction, groups, data)
+            .and_then(|copy| self.multicast_unstamped([debug_group.as_str()].as_slice(), copy.as_slice()));
+        if let Err(error) = copied {
+            client_log!(self, Level::Warn, "Failed to mirror message to \"{}\": {}", debug_group, error);
+        }
+    }
+
+    /// Send `data` to every group in `groups`, validating each name and
+    /// splitting the group list across several frames if it exceeds the
+    /// client's maximum groups per message. Invalid groups, and every group of a frame
+    /// that fails to send, are listed in the report's `failed` entries.
+    pub fn fanout(&mut self, groups: &[&str], data: &[u8]) -> FanoutReport {
+        let mut report = FanoutReport::new();
+        let mut valid: Vec<&str> = Vec::new();
+        for group in groups.iter() {
+            match validate_group_name(group) {
+                Ok(()) => valid.push(*group),
+                Err(reason) => report.failed.push((group.to_string(), reason))
+            }
+        }
+
+        for chunk in valid.chunks(self.max_groups_per_message) {
+            match self.multicast(chunk, data) {
+                Ok(()) => report.succeeded.extend(chunk.iter().map(|g| g.to_string())),
+                Err(error) => report.failed.extend(
+                    chunk.iter().map(|g| (g.to_string(), format!("{}", error)))
+                )
+            }
+        }
+        report
+    }
+
+    /// Send several messages in order, all or none. Every message is
+    /// checked, charged to the quotas, stamped, transformed and encoded
+    /// before any is written, and then all of them are written to the
+    /// daemon at once. If any message has no groups, too many groups, an
+    /// invalid group name or an oversized payload, exceeds a quota, or
+    /// can't be transformed or enveloped, the error names it and nothing
+    /// is sent. A batch over a `Delay` quota waits for it once and is
+    /// rejected if it still doesn't fit. As with `multicast`, a batch
+    /// whose write breaks the connection is written again once the
+    /// session is automatically re-established.
+    pub fn send_batch(&mut self, messages: Vec<OutboundMessage>) -> Result<(), Error> {
+        let total = messages.len();
+        if total == 0 {
+            return Ok(());
+        }
+        let mut physical: Vec<Vec<String>> = Vec::with_capacity(total);
+        for (i, message) in messages.iter().enumerate() {
+            let groups: Vec<String> = message.groups.iter()
+                .map(|g| self.physical_group(g.as_str()))
+                .collect();
+            if let Err(reason) = batch::validate(groups.as_slice(), message.data.len(),
+                                                 self.max_groups_per_message, self.max_message_size) {
+                let error = Error::InvalidInput(
+                    format!("Batch rejected: message {} of {}: {}", i + 1, total, reason)
+                );
+                self.record_error(&error);
+                return Err(error);
+            }
+            physical.push(groups);
+        }
+        let groups: Vec<Vec<&str>> = physical.iter()
+            .map(|groups| groups.iter().map(|g| g.as_str()).collect())
+            .collect();
+
+        let (sequencer, id_stamper) = (self.sequencer.clone(), self.id_stamper.clone());
+        let prepared = self.prepare_batch(groups.as_slice(), messages.as_slice())?;
+        let sends: Vec<(&[&str], &[u8])> = groups.iter().zip(messages.iter()).zip(prepared.iter())
+            .map(|((groups, message), prepared)| {
+                (groups.as_slice(), prepared.payload(message.data.as_slice()))
+            })
+            .collect();
+        let kept_bytes = sends.iter().zip(prepared.iter())
+            .fold((0, 0), |(history, in_flight), (&(groups, payload), prepared)| {
+                (history + prepared.history_bytes(groups), in_flight + payload.len())
+            });
+        let keep = match self.admit_send(kept_bytes.0, kept_bytes.1) {
+            Ok(keep) => keep,
+            Err(error) => {
+                self.sequencer = sequencer;
+                self.id_stamper = id_stamper;
+                return Err(error::with_context(error, &format!("batch of {} messages not sent", total)));
+            }
+        };
+        if keep {
+            for (&(groups, _), prepared) in sends.iter().zip(prepared.iter()) {
+                self.record_history(groups, prepared);
+            }
+        }
+        if let Err(error) = self.send_frames(ServiceType::Reliable, sends.as_slice()) {
+            return Err(error::with_context(error, &format!("batch of {} messages not sent", total)));
+        }
+        if keep {
+            for (&(groups, payload), prepared) in sends.iter().zip(prepared.iter()) {
+                self.track_in_flight(ServiceType::Reliable, groups, payload, prepared.unique_id);
+            }
+        }
+        Ok(())
+    }
+
+    // Charge every message of a batch to the quotas and prepare it for
+    // sending, leaving the quotas, sequence numbers and unique IDs as they
+    // were if any message can't be sent.
+    fn prepare_batch(&mut self, groups: &[Vec<&str>], messages: &[OutboundMessage])
+                     -> Result<Vec<PreparedSend>, Error> {
+        let sizes: Vec<(&[&str], usize)> = groups.iter().zip(messages.iter())
+            .map(|(groups, message)| (groups.as_slice(), message.data.len()))
+            .collect();
+        let mut delayed = false;
+        loop {
+            let sequencer = self.sequencer.clone();
+            let id_stamper = self.id_stamper.clone();
+            let (i, error) = match self.prepare_each(messages) {
+                Ok(prepared) => {
+                    let now = self.clock.now();
+                    let admitted = match self.quotas {
+                        Some(ref mut quotas) => quotas.admit_batch(now, sizes.as_slice(), &*self.logger),
+                        None => Ok(())
+                    };
+                    match admitted {
+                        Ok(()) => return Ok(prepared),
+                        Err((_, QuotaDecision::Delay(wait))) if !delayed => {
+                            self.sequencer = sequencer;
+                            self.id_stamper = id_stamper;
+                            client_log!(self, Level::Debug, "Send quota exceeded; delaying batch by {}ms",
+                                        wait.num_milliseconds());
+                            self.clock.sleep(wait);
+                            delayed = true;
+                            continue;
+                        },
+                        Err((i, QuotaDecision::Reject(quota))) => (i, Error::QuotaExceeded(quota)),
+                        Err((i, _)) => {
+                            (i, Error::QuotaExceeded("batch too large for a delaying quota".to_string()))
+                        }
+                    }
+                },
+                Err(failure) => failure
+            };
+            self.sequencer = sequencer;
+            self.id_stamper = id_stamper;
+            let error = error::with_context(
+                error,
+                &format!("Batch rejected: message {} of {}", i + 1, messages.len())
+            );
+            self.record_error(&error);
+            return Err(error);
+        }
+    }
+
+    // Prepare every message of a batch, failing with the index of the first
+    // that can't be sent, e.g. because it is too large once enveloped.
+    fn prepare_each(&mut self, messages: &[OutboundMessage]) -> Result<Vec<PreparedSend>, (usize, Error)> {
+        let mut prepared = Vec::with_capacity(messages.len());
+        for (i, message) in messages.iter().enumerate() {
+            let data = message.data.as_slice();
+            let send = self.prepare_send(data).map_err(|error| (i, error))?;
+            let size = send.payload(data).len();
+            if size > self.max_message_size {
+                return Err((i, Error::InvalidInput(
+                    format!("Message too long: {} bytes, maximum {}", size, self.max_message_size)
+                )));
+            }
+            prepared.push(send);
+        }
+        Ok(prepared)
+    }
+
+    /// Keep the last `capacity` sequenced messages sent by this client so
+    /// they can be re-sent on request (see the `backfill` module). A
+    /// capacity of zero disables the history.
+    pub fn set_send_history(&mut self, capacity: usize) {
+        self.history = if capacity == 0 { None } else { Some(SendHistory::new(capacity)) };
+    }
+
+    /// Re-send the retained messages with sequence numbers in
+    /// `first..last` (inclusive) to their original groups, returning how
+    /// many were re-sent.
+    pub fn resend_range(&mut self, first: u64, last: u64) -> Result<usize, Error> {
+        let entries = match self.history {
+            Some(ref history) => history.range(first, last),
+            None => Vec::new()
+        };
+        for (groups, enveloped) in entries.iter() {
+            let groups: Vec<&str> = groups.iter().map(|g| g.as_str()).collect();
+            self.multicast_unstamped(groups.as_slice(), enveloped.as_slice())?;
+        }
+        Ok(entries.len())
+    }
+
+    /// Stamp every multicast with a `UniqueId` made of a session ID
+    /// generated now and a per-message counter, giving receivers a stable
+    /// key for deduplication, archiving and tracing. Disabling and
+    /// re-enabling stamping starts a new session ID.
+    pub fn set_unique_ids(&mut self, enabled: bool) {
+        self.id_stamper = if enabled {
+            Some(IdStamper::new(self.private_name.as_str()))
+        } else {
+            None
+        };
+    }
+
+    /// Turn latency probing on or off. While on, every multicast is
+    /// enveloped with the time it was sent, and received messages carrying
+    /// that time are measured against the client's clock: its own copies
+    /// into the `send_latency_us` stats, other members' messages into
+    /// `receive_latency_us`. Receive latency is only as accurate as the
+    /// agreement between the members' clocks.
+    pub fn set_latency_probing(&mut self, enabled: bool) {
+        self.latency_probing = enabled;
+    }
+
+    /// Turn trace-context propagation on or off. While on, every multicast
+    /// made while an OpenTelemetry span is current is enveloped with that
+    /// span's W3C trace context, for receivers to continue the trace (see
+    /// the `trace_context` module).
+    #[cfg(feature = "opentelemetry")]
+    pub fn set_trace_propagation(&mut self, enabled: bool) {
+        self.trace_propagation = enabled;
+    }
+
+    /// Compress the payload of every multicast with `compression`, or stop
+    /// if `None`. Received messages flagged as compressed are decompressed
+    /// with it either way (see the `transform` module).
+    pub fn set_compression(&mut self, compression: Option<Box<dyn PayloadTransform>>) {
+        self.transforms.set_compression(compression);
+    }
+
+    /// Encrypt the payload of every multicast with `encryption`, after any
+    /// compression, or stop if `None`. Received messages flagged as
+    /// encrypted are decrypted with it either way.
+    pub fn set_encryption(&mut self, encryption: Option<Box<dyn PayloadTransform>>) {
+        self.transforms.set_encryption(encryption);
+    }
+
+    /// The session ID of the unique IDs stamped on multicasts, if enabled.
+    pub fn session_id(&self) -> Option<[u8; 16]> {
+        self.id_stamper.as_ref().map(|stamper| stamper.session())
+    }
+
+    /// Turn sequence stamping of outgoing messages on or off. While on,
+    /// every multicast payload is wrapped in an `Envelope` carrying this
+    /// client's next sequence number.
+    pub fn set_sequencing(&mut self, enabled: bool) {
+        if enabled && self.sequencer.is_none() {
+            self.sequencer = Some(Sequencer::new());
+        } else if !enabled {
+            self.sequencer = None;
+        }
+    }
+
+    /// Turn sequence stamping on, continuing a previous sequence at `next`,
+    /// e.g. one restored from a checkpoint.
+    pub fn resume_sequencing(&mut self, next: u64) {
+        self.sequencer = Some(Sequencer::starting_at(next));
+    }
+
+    /// The sequence number the next multicast will carry, if sequence
+    /// stamping is on.
+    pub fn next_sequence(&self) -> Option<u64> {
+        self.sequencer.as_ref().map(|sequencer| sequencer.next_sequence())
+    }
+
+    /// Send a message to a set of named groups, retrying per `policy` if
+    /// the send fails with a transient error.
+    pub fn multicast_with_retry(
+        &mut self,
+        groups: &[&str],
+        data: &[u8],
+        policy: &RetryPolicy
+    ) -> Result<(), Error> {
+        let mut attempt = 1;
+        loop {
+            match self.multicast(groups, data) {
+                Ok(()) => return Ok(()),
+                Err(ref error) if attempt < policy.max_attempts && is_retryable(error) => {
+                    let delay = policy.backoff(attempt);
+                    client_log!(self, Level::Debug,
+                                "Multicast attempt {} failed ({}); retrying in {}ms",
+                                attempt, error, delay.num_milliseconds());
+                    self.clock.sleep(delay);
+                    attempt += 1;
+                },
+                Err(error) => return Err(error)
+            }
+        }
+    }
+
+    /// Receive the next available message. If there are no messages available,
+    /// the call will block until either a message is received or a timeout
+    /// expires.
+    pub fn receive(&mut self) -> Result<SpreadMessage, Error> {
+        loop {
+            match self.next_queued() {
+                Ok(Some(message)) => return Ok(message),
+                Ok(None) => {},
+                Err(error) => self.recover(error)?
+            }
+            let message = self.receive_next()?;
+            if self.priority.is_none() {
+                return Ok(message);
+            }
+            self.queue_pending(message);
+        }
+    }
+
+    /// Return received messages in the order `priority` ranks them rather
+    /// than the order they arrived, or in arrival order if `None`. Every
+    /// message that has arrived, including frames that can be read from
+    /// the transport without waiting, competes, so when the application
+    /// falls behind its most urgent messages are returned first.
+    pub fn set_delivery_priority(&mut self, priority: Option<DeliveryPriority>) {
+        self.priority = priority;
+    }
+
+    // The next message already received and queued: one released by
+    // resuming a paused group, or else the next pending one. With a
+    // delivery priority, every frame that has arrived is queued first so
+    // that the highest-ranked message is taken.
+    fn next_queued(&mut self) -> Result<Option<SpreadMessage>, Error> {
+        self.expire_queued();
+        if let Some(message) = self.paused.next_resumed() {
+            return Ok(Some(message));
+        }
+        if self.priority.is_none() {
+            return Ok(self.pop_pending());
+        }
+        // A transport failure here fails the next read from the daemon
+        // instead, once the messages already queued have been returned.
+        let max_read = self.max_read_ahead();
+        let _ = self.stream.fill_available(max_read);
+        while self.stream.has_frame() && self.make_room(0) {
+            if let Some(message) = self.receive_frame()? {
+                self.queue_pending(message);
+            }
+        }
+        let pending = &mut self.pending;
+        let message = self.priority.as_ref().and_then(|priority| priority.take_highest(pending));
+        if let Some(ref message) = message {
+            self.pending_bytes -= message_bytes(message);
+        }
+        Ok(message)
+    }
+
+    // The most the read buffer may hold without taking the client over
+    // its memory cap, if it has one.
+    fn max_read_ahead(&self) -> Option<usize> {
+        self.buffered_bytes();
+        self.memory.available().map(|available| available + self.stream.buffered_bytes())
+    }
+
+    fn queue_pending(&mut self, message: SpreadMessage) {
+        let now = self.clock.now();
+        self.pending_bytes += message_bytes(&message);
+        self.pending.push_back((now, message));
+    }
+
+    fn pop_pending(&mut self) -> Option<SpreadMessage> {
+        let (_, message) = self.pending.pop_front()?;
+        self.pending_bytes -= message_bytes(&message);
+        Some(message)
+    }
+
+    // Queue a message read while awaiting a receipt, keeping within the
+    // memory cap as its policy dictates.
+    fn hold_pending(&mut self, message: SpreadMessage) -> Result<(), Error> {
+        self.buffered_bytes();
+        let bytes = message_bytes(&message);
+        let mut dropped = 0;
+        let mut freed = 0;
+        let mut result = Ok(());
+        while let Err(error) = self.memory.check(bytes.saturating_sub(freed)) {
+            match self.memory.policy() {
+                MemoryCapPolicy::DropOldest if !self.pending.is_empty() => {
+                    freed += self.pop_pending().map_or(0, |oldest| message_bytes(&oldest));
+                    dropped += 1;
+                    continue;
+                },
+                MemoryCapPolicy::DropNewest => {},
+                _ => {
+                    self.record_error(&error);
+                    result = Err(error);
+                }
+            }
+            client_log!(self, Level::Debug, "Memory cap reached; dropped {} received message(s)",
+                        dropped + 1);
+            return result;
+        }
+        if dropped > 0 {
+            client_log!(self, Level::Debug, "Memory cap reached; dropped {} received message(s)", dropped);
+        }
+        self.queue_pending(message);
+        result
+    }
+
+    // Drop queued messages older than the maximum message age.
+    fn expire_queued(&mut self) {
+        let cutoff = match self.max_message_age {
+            Some(max_age) => self.clock.now() - max_age,
+            None => return
+        };
+        let before = self.pending.len();
+        self.pending.retain(|(received_at, _)| *received_at >= cutoff);
+        self.pending_bytes = self.pending.iter().map(|(_, message)| message_bytes(message)).sum();
+        let expired = before - self.pending.len() + self.paused.expire(cutoff);
+        if expired > 0 {
+            client_log!(self, Level::Debug, "Dropped {} received message(s) past the maximum age", expired);
+            self.stats.record_expired_received(expired);
+        }
+    }
+
+    /// Return the next message if one has already arrived, or `None`
+    /// without waiting if not, e.g. to poll from an event loop. Bytes of a
+    /// partly received frame are buffered until the rest arrives. On a
+    /// transport that doesn't support read timeouts this blocks like
+    /// `receive`.
+    pub fn try_receive(&mut self) -> Result<Option<SpreadMessage>, Error> {
+        loop {
+            match self.next_queued() {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => {},
+                Err(error) => {
+                    self.recover(error)?;
+                    continue;
+                }
+            }
+            let max_read = self.max_read_ahead();
+            if let Err(error) = self.stream.fill_available(max_read).map_err(Error::from) {
+                self.record_error(&error);
+                self.recover(error)?;
+                continue;
+            }
+            if !self.stream.has_frame() {
+                return Ok(None);
+            }
+            match self.receive_frame() {
+                Ok(Some(message)) if self.priority.is_none() => return Ok(Some(message)),
+                Ok(Some(message)) => self.queue_pending(message),
+                Ok(None) => {},
+                Err(error) => self.recover(error)?
+            }
+        }
+    }
+
+    /// Make `receive` and the other receive methods fail with `Error::Timeout`
+    /// if no message arrives within `timeout`, or wait indefinitely if
+    /// `None`. A frame that is partly received when the timeout expires is
+    /// kept for the next call.
+    pub fn set_read_timeout(&mut self, timeout: Option<time::Duration>) -> Result<(), Error> {
+        self.stream.set_read_timeout(timeout.map(std_timeout))?;
+        Ok(())
+    }
+
+    /// Make sends fail with `Error::Timeout` if the daemon doesn't accept the
+    /// frame within `timeout`, or wait indefinitely if `None`. A send that
+    /// times out may have written part of its frame, so the session should
+    /// be disconnected rather than reused.
+    pub fn set_write_timeout(&mut self, timeout: Option<time::Duration>) -> Result<(), Error> {
+        self.stream.set_write_timeout(timeout.map(std_timeout))?;
+        Ok(())
+    }
+
+    /// Receive the next message, failing with `Error::Timeout` if none arrives
+    /// within `timeout`. The read timeout set by `set_read_timeout` applies
+    /// again afterwards.
+    pub fn receive_timeout(&mut self, timeout: time::Duration) -> Result<SpreadMessage, Error> {
+        let previous = self.stream.read_timeout();
+        self.stream.set_read_timeout(Some(std_timeout(timeout)))?;
+        let result = self.receive();
+        self.stream.set_read_timeout(previous)?;
+        result
+    }
+
+    /// Like `receive`, but decode membership messages into a
+    /// `MembershipMessage`. Membership messages are only delivered if the
+    /// client connected with `receive_membership_messages` set.
+    pub fn receive_event(&mut self) -> Result<Received, Error> {
+        let message = self.receive()?;
+        if message.is_membership() {
+            MembershipMessage::decode(&message).map(Received::Membership)
+        } else {
+            Ok(Received::Message(message))
+        }
+    }
+
+    /// Like `receive`, but return each message as a `SpreadEvent`, and
+    /// when auto-reconnect re-establishes a lost session, report the break
+    /// in place: `SpreadEvent::Disconnected` with the error that ended the
+    /// old session, then `SpreadEvent::Reconnected` with the groups joined
+    /// again on the new one, before any message received over it. Without
+    /// auto-reconnect, a lost connection fails as it does for `receive`.
+    pub fn next_event(&mut self) -> Result<SpreadEvent, Error> {
+        if let Some(event) = self.reconnected_event.take() {
+            return Ok(event);
+        }
+        loop {
+            let (received, queued) = match self.next_queued() {
+                Ok(None) => (self.receive_frame(), false),
+                queued => (queued, true)
+            };
+            match received {
+                // Queue a newly read message when prioritizing, so that it
+                // competes with any others that arrived with it.
+                Ok(Some(message)) if !queued && self.priority.is_some() => self.queue_pending(message),
+                Ok(Some(message)) => return Ok(message_event(message)),
+                Ok(None) => {},
+                Err(error) => {
+                    let lost = error.clone();
+                    self.recover(error)?;
+                    self.reconnected_event = Some(SpreadEvent::Reconnected {
+                        rejoined_groups: self.groups.clone()
+                    });
+                    return Ok(SpreadEvent::Disconnected(lost));
+                }
+            }
+        }
+    }
+
+    /// An endless iterator over the client's events. Each item is the
+    /// result of `next_event`.
+    pub fn events(&mut self) -> Events<'_> {
+        Events { client: self }
+    }
+
+    // Read the next message from the daemon that isn't held back by a
+    // paused group.
+    fn receive_next(&mut self) -> Result<SpreadMessage, Error> {
+        loop {
+            match self.receive_frame() {
+                Ok(Some(message)) => return Ok(message),
+                Ok(None) => {},
+                Err(error) => self.recover(error)?
+            }
+        }
+    }
+
+    // Read one frame from the daemon, returning `None` if it was dropped or
+    // is held back by a paused group.
+    fn receive_frame(&mut self) -> Result<Option<SpreadMessage>, Error> {
+        match self.read_message() {
+            Ok(Some(message)) => {
+                let _span = telemetry::receive_span(&message);
+                let now = self.clock.now();
+                let membership = message.service_type & MEMBERSHIP_MESS != 0;
+                telemetry::received(message.data.len(), membership);
+                if membership {
+                    self.stats.record_membership_receive(now, message.data.len());
+                } else {
+                    self.stats.record_receive(now, message.groups.as_slice(), message.data.len());
+                    self.record_latency(&message, now);
+                    let groups: Vec<&str> = message.groups.iter()
+                        .map(|g| g.as_str().trim_end_matches('\0'))
+                        .collect();
+                    self.mirror_to_debug(Direction::Inbound, groups.as_slice(), message.data.as_slice());
+                }
+                self.check_slos();
+                let message = self.reverse_transforms(message);
+                let message = self.to_logical_groups(message);
+                self.observe_membership(&message);
+                self.buffered_bytes();
+                self.paused.filter(now, message, self.groups.as_slice(), &self.memory)
+                    .inspect_err(|error| self.record_error(error))
+            },
+            Ok(None) => Ok(None),
+            Err(error) => {
+                self.record_error(&error);
+                Err(error)
+            }
+        }
+    }
+
+    // Time a message stamped by a sender with latency probing on, if this
+    // client has it on too: the client's own copies give its send latency,
+    // other members' messages its receive latency.
+    fn record_latency(&mut self, message: &SpreadMessage, now: Timespec) {
+        if !self.latency_probing || !Envelope::is_enveloped(message.data.as_slice()) {
+            return;
+        }
+        let sent_at = match Envelope::decode(message.data.as_slice()).and_then(|e| e.sent_at()) {
+            Some(sent_at) => sent_at,
+            None => return
+        };
+        let micros = (now - sent_at).num_microseconds().unwrap_or(0).max(0) as u64;
+        if message.sender() == self.private_name.as_str() {
+            self.stats.record_send_latency(micros);
+        } else {
+            self.stats.record_receive_latency(micros);
+        }
+    }
+
+    // Undo the compression or encryption flagged on a data message,
+    // delivering it unchanged if that isn't possible.
+    fn reverse_transforms(&mut self, mut message: SpreadMessage) -> SpreadMessage {
+        if message.service_type & MEMBERSHIP_MESS != 0 {
+            return message;
+        }
+        match self.transforms.reverse(message.data.as_slice()) {
+            Ok(Some(data)) => message.data = data,
+            Ok(None) => {},
+            Err(error) => {
+                client_log!(self, Level::Warn,
+                            "Delivering opaque message from \"{}\": {}", message.sender(), error);
+                if let Error::CallbackPanicked { .. } = error {
+                    self.record_error(&error);
+                }
+            }
+        }
+        message
+    }
+
+    /// Multicast `data` to `groups` and to this client's private group,
+    /// then wait for the client's own copy to come back, confirming that
+    /// the daemon accepted and ordered the message. Messages received while
+    /// waiting are returned by later calls to `receive`; the copy itself is
+    /// not. Requires `set_unique_ids(true)`, whose IDs identify the copy.
+    ///
+    /// Fails with `Error::Timeout` if the copy hasn't arrived within
+    /// `timeout`, even on an otherwise quiet session. The read timeout set
+    /// by `set_read_timeout` applies again afterwards.
+    pub fn send_with_receipt(&mut self, groups: &[&str], data: &[u8], timeout: time::Duration)
+                             -> Result<Receipt, Error> {
+        let id = match self.id_stamper {
+            Some(ref stamper) => stamper.peek_id(),
+            None => return Err(Error::InvalidInput(
+                "Receipts require unique IDs: enable set_unique_ids before send_with_receipt".to_string()
+            ))
+        };
+        let private_name = self.private_name.as_str().trim_end_matches('\0').to_string();
+        let mut destination = groups.to_vec();
+        destination.push(private_name.as_str());
+        let sent_at = self.clock.now();
+        self.multicast(destination.as_slice(), data)?;
+
+        let previous = self.stream.read_timeout();
+        let result = self.await_receipt(id, private_name.as_str(), sent_at, timeout);
+        self.stream.set_read_timeout(previous)?;
+        match result {
+            Ok(round_trip) => Ok(Receipt {
+                id: id,
+                groups: groups.iter().map(|g| g.to_string()).collect(),
+                round_trip: round_trip
+            }),
+            Err(Error::Timeout) => {
+                client_log!(self, Level::Debug,
+                            "No receipt for multicast: message {} not echoed within {}ms",
+                            id, timeout.num_milliseconds());
+                self.record_error(&Error::Timeout);
+                Err(Error::Timeout)
+            },
+            Err(error) => Err(error)
+        }
+    }
+
+    // Wait for this client's own copy of message `id`, sent at `sent_at`,
+    // limiting each read to the time left of `timeout`, and return the
+    // round trip.
+    fn await_receipt(&mut self, id: UniqueId, private_name: &str, sent_at: Timespec,
+                     timeout: time::Duration) -> Result<time::Duration, Error> {
+        loop {
+            let remaining = timeout - (self.clock.now() - sent_at);
+            if remaining <= time::Duration::zero() {
+                return Err(Error::Timeout);
+            }
+            self.stream.set_read_timeout(Some(std_timeout(remaining)))?;
+            let message = self.receive_next()?;
+            if message.sender() == private_name && message.unique_id() == Some(id) {
+                if let Some(ref mut buffer) = self.in_flight {
+                    buffer.acknowledge_through(id);
+                }
+                return Ok(self.clock.now() - sent_at);
+            }
+            self.hold_pending(message)?;
+        }
+    }
+
+    /// Stop delivering data messages for `group` from `receive` while
+    /// staying in the group, buffering or dropping them according to
+    /// `policy`. Messages also addressed to a joined group that isn't
+    /// paused are still delivered. Pausing a paused group changes its
+    /// policy.
+    pub fn pause(&mut self, group: &str, policy: PausePolicy) {
+        client_log!(self, Level::Debug, "Pausing delivery for group \"{}\"", group);
+        self.paused.pause(group, policy);
+    }
+
+    /// Resume delivery for `group`. Messages buffered while it was paused
+    /// are returned by `receive` before any new ones. Returns the number of
+    /// buffered messages.
+    pub fn resume(&mut self, group: &str) -> usize {
+        let dropped = self.paused.dropped(group);
+        let buffered = self.paused.resume(group);
+        client_log!(self, Level::Debug,
+                    "Resuming delivery for group \"{}\" ({} buffered, {} dropped)",
+                    group, buffered, dropped);
+        buffered
+    }
+
+    /// Returns true if delivery for `group` is paused.
+    pub fn is_paused(&self, group: &str) -> bool {
+        self.paused.is_paused(group)
+    }
+
+    /// Messages for paused `group` dropped since it was paused.
+    pub fn paused_dropped(&self, group: &str) -> u64 {
+        self.paused.dropped(group)
+    }
+
+    // Read and decode the next frame from the daemon, returning `None` if
+    // the sender filter or flood guard dropped it.
+    fn read_message(&mut self) -> Result<Option<SpreadMessage>, Error> {
+        self.apply_forced_disconnect();
+        #[cfg(feature = "chaos")]
+        {
+            if self.chaos.is_receive_paused() {
+                return Err(Error::ReceivePaused);
+            }
+        }
+
+        self.stream.await_frame()?;
+        let message = match self.read_frame()? {
+            Some(message) => message,
+            None => return Ok(None)
+        };
+        if self.permits_sender(&message) && self.admit_sender(&message) {
+            Ok(Some(message))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Apply the sender filter, if any, to a received data message.
+    fn permits_sender(&self, message: &SpreadMessage) -> bool {
+        let filter = match self.sender_filter {
+            Some(ref filter) => filter,
+            None => return true
+        };
+        if message.service_type & MEMBERSHIP_MESS != 0 || filter.permits(message.sender.as_str()) {
+            return true;
+        }
+        match filter.action() {
+            FilterAction::Drop => {
+                client_log!(self, Level::Debug,
+                            "Dropping message from filtered sender \"{}\"", message.sender);
+                false
+            },
+            FilterAction::Flag => {
+                client_log!(self, Level::Warn,
+                            "Received message from unexpected sender \"{}\"", message.sender);
+                true
+            }
+        }
+    }
+
+    // Apply the flood guard, if any, to a received data message.
+    fn admit_sender(&mut self, message: &SpreadMessage) -> bool {
+        if message.service_type & MEMBERSHIP_MESS != 0 {
+            return true;
+        }
+        let now = self.clock.now();
+        let verdict = match self.flood_guard {
+            Some(ref mut guard) => guard.admit(now, message.sender.as_str()),
+            None => return true
+        };
+        match verdict {
+            FloodVerdict::Accept => true,
+            FloodVerdict::Throttled => {
+                let sender = message.sender.as_str().trim_end_matches('\0').to_string();
+                client_log!(self, Level::Warn,
+                            "Throttling messages from flooding sender \"{}\"", sender);
+                self.events.record(now, ProtocolEventKind::SenderThrottled(sender));
+                false
+            },
+            FloodVerdict::Drop => false
+        }
+    }
+
+    /// Translate logical group names through `aliases` on every join,
+    /// leave, multicast and receive, or stop translating if `None`.
+    pub fn set_group_aliases(&mut self, aliases: Option<GroupAliases>) {
+        self.aliases = aliases;
+    }
+
+    /// Prefix every group name this client joins, leaves or sends to with
+    /// `namespace`, and strip it from received group names, so that
+    /// tenants or environments sharing a daemon cannot see each other's
+    /// groups. Private group names (those starting with `#`) are left
+    /// alone. `None` removes the namespace.
+    pub fn set_namespace(&mut self, namespace: Option<&str>) {
+        self.namespace = namespace.map(|ns| ns.to_string());
+    }
+
+    // The name on the wire of the group the application calls `group`.
+    fn physical_group(&self, group: &str) -> String {
+        let aliased = match self.aliases {
+            Some(ref aliases) => aliases.to_physical(group),
+            None => group.to_string()
+        };
+        match self.namespace {
+            Some(ref namespace) if !aliased.as_str().starts_with("#") =>
+                format!("{}{}", namespace, aliased),
+            _ => aliased
+        }
+    }
+
+    // The name the application uses for the group called `group` on the wire.
+    fn logical_group(&self, group: &str) -> String {
+        let mut group = group.trim_end_matches('\0');
+        if let Some(ref namespace) = self.namespace {
+            if group.starts_with(namespace.as_str()) {
+                group = &group[namespace.len()..];
+            }
+        }
+        match self.aliases {
+            Some(ref aliases) => aliases.to_logical(group),
+            None => group.to_string()
+        }
+    }
+
+    // Rewrite the group names of a received message, including the group a
+    // membership message is about, into their logical names.
+    fn to_logical_groups(&self, mut message: SpreadMessage) -> SpreadMessage {
+        if self.aliases.is_none() && self.namespace.is_none() {
+            return message;
+        }
+        message.groups = message.groups.iter().map(|g| self.logical_group(g.as_str())).collect();
+        if message.service_type & MEMBERSHIP_MESS != 0 {
+            message.sender = self.logical_group(message.sender.as_str());
+        }
+        message
+    }
+
+    /// Limit the rate of data messages accepted from each sender, or remove
+    /// the limit if `None`. Membership messages are never limited.
+    pub fn set_flood_guard(&mut self, guard: Option<FloodGuard>) {
+        self.flood_guard = guard;
+    }
+
+    /// Filter received data messages by sender, or stop filtering if
+    /// `None`. Membership messages are never filtered.
+    pub fn set_sender_filter(&mut self, filter: Option<SenderFilter>) {
+        self.sender_filter = filter;
+    }
+
+    // Read the next frame from the daemon, returning `None` if it was a data
+    // message discarded because of membership monitoring.
+    fn read_frame(&mut self) -> Result<Option<SpreadMessage>, Error> {
+        // The header and group names are read into the receive buffer,
+        // reusing its allocation as `receive_ref` does, so only the decoded
+        // names and the payload are allocated for each message.
+        let mut buffer = mem::take(&mut self.receive_buffer);
+        buffer.clear();
+        let result = self.read_frame_into(&mut buffer);
+        self.receive_buffer = buffer;
+        if result.is_ok() {
+            self.make_room(0);
+        }
+        result
+    }
+
+    fn read_frame_into(&mut self, buffer: &mut Vec<u8>) -> Result<Option<SpreadMessage>, Error> {
+        append_bytes(&mut self.stream, HEADER_LENGTH, buffer)?;
+        let FrameHeader { service_type: svc_type, sender, num_groups, mess_type, data_len } =
+            FrameHeader::decode(buffer.as_slice())?;
+        if let Some(cap) = self.memory.cap() {
+            let held = HEADER_LENGTH + MAX_GROUP_NAME_LENGTH * num_groups as usize;
+            if held > cap {
+                discard_exact(&mut self.stream, held - HEADER_LENGTH + data_len as usize)?;
+                return Err(Error::BufferLimit { bytes: held, cap: cap });
+            }
+        }
+
+        // Groups format (sizes in bytes):
+        //   groups: MAX_GROUP_NAME_LENGTH * num_groups
+        append_groups(&mut self.stream, num_groups, buffer)?;
+        let groups = decode_groups(&buffer[HEADER_LENGTH..], num_groups)?;
+
+        // Data messages addressed only to monitored groups are skipped
+        // without buffering their payloads.
+        if self.is_monitored_data(svc_type, groups.as_slice()) {
+            discard_exact(&mut self.stream, data_len as usize)?;
+            client_log!(self, Level::Debug,
+                        "Discarded {} bytes from \"{}\" sent to monitored group(s) {:?}",
+                        data_len, sender, groups);
+            return Ok(None);
+        }
+
+        // Data format (sizes in bytes):
+        //   data: data_len
+        let data_vec = read_bytes(&mut self.stream, data_len as usize)?;
+        let (header, groups_vec) = buffer.split_at(HEADER_LENGTH);
+        self.record_inbound(svc_type, header, groups_vec, data_vec.as_slice());
+
+        client_log!(self, Level::Debug, "Received {} bytes from \"{}\" sent to group(s) {:?}",
+                    data_len, sender, groups);
+
+        Ok(Some(SpreadMessage {
+            service_type: svc_type,
+            mess_type: mess_type,
+            groups: groups,
             sender: sender,
             data: data_vec
+        }))
+    }
+
+    // Log and capture a received frame.
+    fn record_inbound(&mut self, service_type: u32, header: &[u8], groups: &[u8], payload: &[u8]) {
+        let now = self.clock.now();
+        self.last_activity = now;
+        self.events.record(now, ProtocolEventKind::FrameReceived {
+            service_type: service_type,
+            bytes: header.len() + groups.len() + payload.len()
+        });
+
+        if let Some(ref mut capture) = self.capture {
+            let mut frame_header = header.to_vec();
+            frame_header.extend_from_slice(groups);
+            capture.record(Direction::Inbound, frame_header.as_slice(), payload, &*self.logger);
+        }
+    }
+
+    /// Receive the next frame without decoding its sender, groups or
+    /// payload, e.g. to forward it verbatim or archive the exact wire bytes.
+    /// Sender filtering, flood protection, group translation and
+    /// membership-only monitoring do not apply to raw frames.
+    pub fn receive_raw(&mut self) -> Result<RawFrame, Error> {
+        self.apply_forced_disconnect();
+        let result = self.read_raw_frame();
+        match result {
+            Ok(ref frame) => {
+                let now = self.clock.now();
+                self.stats.record_receive(now, [].as_slice(), frame.payload.len());
+                self.check_slos();
+            },
+            Err(ref error) => self.record_error(error)
+        }
+        result
+    }
+
+    /// Receive the next message into the client's receive buffer and borrow
+    /// it from there, without copying its sender, groups or payload. The
+    /// message is valid until the next receive. As with `receive_raw`,
+    /// sender filtering, flood protection, group translation and
+    /// membership-only monitoring do not apply.
+    pub fn receive_ref(&mut self) -> Result<SpreadMessageRef<'_>, Error> {
+        self.apply_forced_disconnect();
+        match self.fill_receive_buffer() {
+            Ok(data_len) => {
+                let now = self.clock.now();
+                self.stats.record_receive(now, [].as_slice(), data_len);
+                self.check_slos();
+            },
+            Err(error) => {
+                self.record_error(&error);
+                return Err(error);
+            }
+        }
+        SpreadMessageRef::from_frame(self.receive_buffer.as_slice())
+    }
+
+    // Read the next frame into the receive buffer, reusing its allocation,
+    // and return the frame's payload length.
+    fn fill_receive_buffer(&mut self) -> Result<usize, Error> {
+        self.stream.await_frame()?;
+        let mut buffer = mem::take(&mut self.receive_buffer);
+        buffer.clear();
+        append_bytes(&mut self.stream, HEADER_LENGTH, &mut buffer)?;
+        let (groups_len, data_len) = match FrameHeader::body_lengths(buffer.as_slice()) {
+            Ok(lengths) => lengths,
+            Err(error) => {
+                self.receive_buffer = buffer;
+                return Err(error);
+            }
+        };
+        let body_len = groups_len + data_len;
+        if let Some(cap) = self.memory.cap() {
+            if HEADER_LENGTH + body_len > cap {
+                self.receive_buffer = buffer;
+                discard_exact(&mut self.stream, body_len)?;
+                return Err(Error::BufferLimit { bytes: HEADER_LENGTH + body_len, cap: cap });
+            }
+        }
+        append_bytes(&mut self.stream, body_len, &mut buffer)?;
+
+        let groups_end = HEADER_LENGTH + groups_len;
+        self.record_inbound(header_int(buffer.as_slice(), 0), &buffer[..HEADER_LENGTH],
+                            &buffer[HEADER_LENGTH..groups_end], &buffer[groups_end..]);
+        self.receive_buffer = buffer;
+        self.make_room(0);
+        Ok(data_len)
+    }
+
+    fn read_raw_frame(&mut self) -> Result<RawFrame, Error> {
+        self.stream.await_frame()?;
+        let header = read_bytes(&mut self.stream, HEADER_LENGTH)?;
+        let decoded = FrameHeader::decode(header.as_slice())?;
+        let groups = read_groups(&mut self.stream, decoded.num_groups)?;
+        let payload = read_bytes(&mut self.stream, decoded.data_len as usize)?;
+        self.record_inbound(decoded.service_type, header.as_slice(), groups.as_slice(), payload.as_slice());
+        Ok(RawFrame {
+            service_type: decoded.service_type,
+            header: header,
+            groups: groups,
+            payload: payload
         })
     }
+
+    // Returns true if a message of type `service_type` is a data message
+    // whose every destination group is monitored for membership only.
+    fn is_monitored_data(&self, service_type: u32, groups: &[String]) -> bool {
+        !self.monitored_groups.is_empty()
+            && service_type & REGULAR_MESS != 0
+            && service_type & MEMBERSHIP_MESS == 0
+            && !groups.is_empty()
+            && groups.iter().all(|group| {
+                self.monitored_groups.contains(group.as_str().trim_end_matches('\0'))
+            })
+    }
+
+    /// Join `group` purely to observe its membership: data messages sent to
+    /// it are discarded by `receive` while membership messages are still
+    /// delivered. Data messages also addressed to a non-monitored group are
+    /// delivered as usual.
+    pub fn join_monitor(&mut self, group_name: &str) -> Result<(), Error> {
+        self.join(group_name)?;
+        self.set_monitor_only(group_name, true);
+        Ok(())
+    }
+
+    /// Returns true if `group` is joined for membership changes only.
+    pub fn is_monitor_only(&self, group_name: &str) -> bool {
+        self.monitored_groups.contains(&self.physical_group(group_name))
+    }
+
+    /// Turn membership-only monitoring of `group` on or off.
+    pub fn set_monitor_only(&mut self, group_name: &str, monitor_only: bool) {
+        let physical = self.physical_group(group_name);
+        if monitor_only {
+            self.monitored_groups.insert(physical);
+        } else {
+            self.monitored_groups.remove(&physical);
+        }
+    }
+}
+
+// A timeout as transports take it. Sockets reject a zero timeout, so
+// shorter timeouts, including negative ones, are rounded up to a
+// millisecond.
+fn std_timeout(timeout: time::Duration) -> StdDuration {
+    let floor = StdDuration::from_millis(1);
+    cmp::max(timeout.to_std().unwrap_or(floor), floor)
+}
+
+// Read and throw away exactly `len` bytes from `reader`, failing with
+// `Disconnected` if the stream ends first.
+fn discard_exact(reader: &mut dyn Read, len: usize) -> Result<(), Error> {
+    let discarded = io::copy(&mut reader.take(len as u64), &mut io::sink())?;
+    if discarded < len as u64 {
+        return Err(Error::Disconnected);
+    }
+    Ok(())
 }
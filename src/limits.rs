@@ -0,0 +1,30 @@
+//! Limits imposed by the Spread protocol and daemon, and by this crate.
+//!
+//! The name lengths are fixed by the wire format. The message size is a
+//! soft limit: it matches a daemon built with the default configuration
+//! and can be changed per client with `SpreadClient::set_max_message_size`.
+//! The group count is this crate's own policy, changed per client with
+//! `SpreadClient::set_max_groups_per_message`.
+
+/// Longest private name a client can connect with. Longer names are
+/// truncated.
+pub const MAX_PRIVATE_NAME_LENGTH: usize = 10;
+
+/// Width of the fixed, NUL-padded group name fields in a frame. Names must
+/// be at least one byte shorter to leave room for the terminator.
+pub const MAX_GROUP_NAME_LENGTH: usize = 32;
+
+/// Longest name of an authentication method.
+pub const MAX_AUTH_NAME_LENGTH: usize = 30;
+
+/// Most authentication methods a client can offer.
+pub const MAX_AUTH_METHOD_COUNT: usize = 3;
+
+/// Default for the largest number of groups a client addresses in one
+/// frame. The wire format has no such limit; this keeps frames, and the
+/// daemon's work per message, modest. `SpreadClient::fanout` splits longer
+/// group lists across frames and `send_batch` rejects them.
+pub const DEFAULT_MAX_GROUPS_PER_MESSAGE: usize = 100;
+
+/// Largest message payload a default daemon accepts, in bytes.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 144000;
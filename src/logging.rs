@@ -0,0 +1,87 @@
+//! Routing of a client's diagnostics to a chosen backend.
+
+use log;
+
+/// Severity of a diagnostic message.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace
+}
+
+/// A destination for a client's diagnostic messages.
+pub trait LogSink: Send {
+    fn log(&self, level: Level, message: &str);
+
+    /// Returns false if messages at `level` would be discarded, so that
+    /// the client can skip formatting them.
+    fn enabled(&self, level: Level) -> bool {
+        let _ = level;
+        true
+    }
+}
+
+/// Forwards messages to the `log` crate. This is the default sink.
+pub struct LogCrateSink;
+
+impl LogSink for LogCrateSink {
+    fn log(&self, level: Level, message: &str) {
+        match level {
+            Level::Error => error!("{}", message),
+            Level::Warn => warn!("{}", message),
+            Level::Info => info!("{}", message),
+            Level::Debug => debug!("{}", message),
+            Level::Trace => trace!("{}", message)
+        }
+    }
+
+    fn enabled(&self, level: Level) -> bool {
+        match level {
+            Level::Error => log_enabled!(log::Level::Error),
+            Level::Warn => log_enabled!(log::Level::Warn),
+            Level::Info => log_enabled!(log::Level::Info),
+            Level::Debug => log_enabled!(log::Level::Debug),
+            Level::Trace => log_enabled!(log::Level::Trace)
+        }
+    }
+}
+
+/// Passes messages at or above a minimum level to a callback, e.g. an
+/// application's own structured logger.
+pub struct CallbackSink {
+    min_level: Level,
+    callback: Box<dyn Fn(Level, &str) + Send>
+}
+
+impl CallbackSink {
+    /// Pass messages at least as severe as `min_level` to `callback`.
+    pub fn new(min_level: Level, callback: Box<dyn Fn(Level, &str) + Send>) -> CallbackSink {
+        CallbackSink { min_level: min_level, callback: callback }
+    }
+}
+
+impl LogSink for CallbackSink {
+    fn log(&self, level: Level, message: &str) {
+        if self.enabled(level) {
+            (*self.callback)(level, message);
+        }
+    }
+
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.min_level
+    }
+}
+
+/// Discards every message.
+pub struct NullSink;
+
+impl LogSink for NullSink {
+    fn log(&self, _: Level, _: &str) {}
+
+    fn enabled(&self, _: Level) -> bool {
+        false
+    }
+}
@@ -0,0 +1,390 @@
+//! Tracking group membership views and reporting changes between them.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use time::{Duration, Timespec};
+use limits::MAX_GROUP_NAME_LENGTH;
+use segment;
+use util::{bytes_to_int, flip_endianness, same_endianness};
+use {Error, SpreadMessage, CAUSED_BY_LEAVE, MEMBERSHIP_MESS, REG_MEMB_MESS};
+
+static CAUSED_BY_JOIN: u32 = 0x00000100;
+static CAUSED_BY_DISCONNECT: u32 = 0x00000400;
+static CAUSED_BY_NETWORK: u32 = 0x00000800;
+static TRANSITION_MESS: u32 = 0x00002000;
+
+/// A membership notification, decoded from the service type and payload
+/// of a membership message. `group` is the group whose membership changed
+/// and `members` its members after the change, sorted as the daemon sent
+/// them.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MembershipMessage {
+    /// `changed` joined the group.
+    Join { group: String, changed: String, members: Vec<String> },
+    /// `changed` left the group.
+    Leave { group: String, changed: String, members: Vec<String> },
+    /// `changed` disconnected from its daemon.
+    Disconnect { group: String, changed: String, members: Vec<String> },
+    /// The daemons partitioned or merged. `sets` lists the members that
+    /// moved to the new view together, one set per previous view, and
+    /// `sets[local_set]` is the one this client moved with.
+    NetworkPartition { group: String, members: Vec<String>, sets: Vec<Vec<String>>, local_set: usize },
+    /// Messages delivered from now until the next regular membership
+    /// message may not have reached every member of the previous view.
+    TransitionalSignal { group: String },
+    /// This client left the group.
+    SelfLeave { group: String }
+}
+
+impl MembershipMessage {
+    /// Decode `message`, which must be a membership message.
+    ///
+    /// The payload of a regular membership message is laid out as (sizes in
+    /// bytes):
+    ///
+    /// ```text
+    /// group_id: 12, num_vs_sets: 4, local_vs_set_offset: 4,
+    /// num_vs_sets * (num_members: 4, members: num_members * 32)
+    /// ```
+    ///
+    /// in the daemon's byte order, where `local_vs_set_offset` is the byte
+    /// offset of the local virtual synchrony set from the start of the
+    /// first set. The member that joined, left or disconnected is the one
+    /// member of the local set.
+    pub fn decode(message: &SpreadMessage) -> Result<MembershipMessage, Error> {
+        let service_type = message.service_type;
+        let group = message.sender().to_string();
+        if service_type & MEMBERSHIP_MESS == 0 {
+            return Err(malformed(format!("service type {:#x} is not membership", service_type)));
+        }
+        if service_type & TRANSITION_MESS != 0 {
+            return Ok(MembershipMessage::TransitionalSignal { group: group });
+        }
+        if service_type & REG_MEMB_MESS == 0 {
+            if service_type & CAUSED_BY_LEAVE != 0 {
+                return Ok(MembershipMessage::SelfLeave { group: group });
+            }
+            return Err(malformed(format!("unknown membership service type {:#x}", service_type)));
+        }
+
+        let members: Vec<String> = message.groups.iter()
+            .map(|m| m.as_str().trim_end_matches('\0').to_string())
+            .collect();
+        let (local, sets) = decode_vs_sets(message.data.as_slice(), same_endianness(service_type))?;
+        let changed = sets[local].first().cloned().ok_or_else(|| malformed("no changed member".to_string()));
+        if service_type & CAUSED_BY_JOIN != 0 {
+            Ok(MembershipMessage::Join { group: group, changed: changed?, members: members })
+        } else if service_type & CAUSED_BY_LEAVE != 0 {
+            Ok(MembershipMessage::Leave { group: group, changed: changed?, members: members })
+        } else if service_type & CAUSED_BY_DISCONNECT != 0 {
+            Ok(MembershipMessage::Disconnect { group: group, changed: changed?, members: members })
+        } else if service_type & CAUSED_BY_NETWORK != 0 {
+            Ok(MembershipMessage::NetworkPartition {
+                group: group,
+                members: members,
+                sets: sets,
+                local_set: local
+            })
+        } else {
+            Err(malformed(format!("membership service type {:#x} has no cause", service_type)))
+        }
+    }
+
+    /// The group the message is about.
+    pub fn group(&self) -> &str {
+        match *self {
+            MembershipMessage::Join { ref group, .. } |
+            MembershipMessage::Leave { ref group, .. } |
+            MembershipMessage::Disconnect { ref group, .. } |
+            MembershipMessage::NetworkPartition { ref group, .. } |
+            MembershipMessage::TransitionalSignal { ref group } |
+            MembershipMessage::SelfLeave { ref group } => group.as_str()
+        }
+    }
+}
+
+fn malformed(detail: String) -> Error {
+    Error::ProtocolError(format!("Malformed membership message: {}", detail))
+}
+
+// Decode the virtual synchrony sets of a regular membership payload,
+// returning the index of the local set, found by its byte offset, and the
+// sets.
+fn decode_vs_sets(data: &[u8], same_order: bool) -> Result<(usize, Vec<Vec<String>>), Error> {
+    let int_at = |offset: usize| -> Result<usize, Error> {
+        if offset + 4 > data.len() {
+            return Err(malformed(format!("payload truncated at byte {}", offset)));
+        }
+        let value = bytes_to_int(&data[offset..offset + 4]);
+        Ok((if same_order { value } else { flip_endianness(value) }) as usize)
+    };
+    let num_sets = int_at(12)?;
+    let local_offset = int_at(16)?;
+    let mut offset = 20;
+    let mut local = None;
+    let mut sets = Vec::new();
+    for _ in 0..num_sets {
+        if offset - 20 == local_offset {
+            local = Some(sets.len());
+        }
+        let num_members = int_at(offset)?;
+        offset += 4;
+        let end = offset + num_members * MAX_GROUP_NAME_LENGTH;
+        if end > data.len() {
+            return Err(malformed(format!("{} members overrun the payload", num_members)));
+        }
+        let set = data[offset..end].chunks(MAX_GROUP_NAME_LENGTH)
+            .map(|name| String::from_utf8_lossy(name).as_ref().trim_end_matches('\0').to_string())
+            .collect();
+        sets.push(set);
+        offset = end;
+    }
+    match local {
+        Some(local) => Ok((local, sets)),
+        None => Err(malformed(format!("no virtual synchrony set at offset {}", local_offset)))
+    }
+}
+
+/// The members added to and removed from a group between two consecutive
+/// views.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MembershipDiff {
+    pub group: String,
+    /// Members present in the new view but not the previous one, sorted.
+    pub joined: Vec<String>,
+    /// Members present in the previous view but not the new one, sorted.
+    pub left: Vec<String>
+}
+
+impl MembershipDiff {
+    /// Returns true if the views contained the same members.
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.left.is_empty()
+    }
+}
+
+/// A group's membership crossing its quorum threshold.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuorumEvent {
+    /// The group has fewer than `threshold` members.
+    Lost { group: String, members: usize, threshold: usize },
+    /// The group has at least `threshold` members again.
+    Restored { group: String, members: usize, threshold: usize }
+}
+
+/// How much a group's membership changed over a window of time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupChurn {
+    pub group: String,
+    pub window: Duration,
+    /// Members that joined within the window.
+    pub joins: u64,
+    /// Members that left within the window.
+    pub leaves: u64,
+    /// Views that differed from the one before, within the window.
+    pub view_changes: u64,
+    /// The mean number of members across the views within the window.
+    pub average_members: f64
+}
+
+impl GroupChurn {
+    /// Joins and leaves per minute over the window.
+    pub fn changes_per_minute(&self) -> f64 {
+        let minutes = self.window.num_milliseconds() as f64 / 60000.0;
+        if minutes <= 0.0 { 0.0 } else { (self.joins + self.leaves) as f64 / minutes }
+    }
+}
+
+// A view change recorded for churn statistics.
+struct ViewChange {
+    at: Timespec,
+    joined: usize,
+    left: usize,
+    members: usize
+}
+
+/// The latest membership view of each group, with callbacks notified of
+/// every change.
+pub struct MembershipTracker {
+    views: HashMap<String, BTreeSet<String>>,
+    callbacks: Vec<Box<dyn FnMut(&MembershipDiff) + Send>>,
+    quorums: HashMap<String, usize>,
+    below_quorum: HashSet<String>,
+    quorum_callbacks: Vec<Box<dyn FnMut(&QuorumEvent) + Send>>,
+    churn_window: Option<Duration>,
+    changes: HashMap<String, VecDeque<ViewChange>>
+}
+
+impl MembershipTracker {
+    pub fn new() -> MembershipTracker {
+        MembershipTracker {
+            views: HashMap::new(),
+            callbacks: Vec::new(),
+            quorums: HashMap::new(),
+            below_quorum: HashSet::new(),
+            quorum_callbacks: Vec::new(),
+            churn_window: None,
+            changes: HashMap::new()
+        }
+    }
+
+    /// Keep the views recorded with `update_at` over the last `window`, for
+    /// `churn`.
+    pub fn track_churn(&mut self, window: Duration) {
+        self.churn_window = Some(window);
+    }
+
+    /// Record a new view of `group` received at `now`, as `update` does,
+    /// also counting it towards the group's churn if `track_churn` is set.
+    pub fn update_at(&mut self, now: Timespec, group: &str, members: &[String]) -> MembershipDiff {
+        let diff = self.update(group, members);
+        let window = match self.churn_window {
+            Some(window) => window,
+            None => return diff
+        };
+        if !self.changes.contains_key(group) {
+            self.changes.insert(group.to_string(), VecDeque::new());
+        }
+        let changes = self.changes.get_mut(group).unwrap();
+        changes.push_back(ViewChange {
+            at: now,
+            joined: diff.joined.len(),
+            left: diff.left.len(),
+            members: members.len()
+        });
+        while changes.front().is_some_and(|change| now - change.at > window) {
+            changes.pop_front();
+        }
+        diff
+    }
+
+    /// The churn of `group` over the window ending at `now`, or `None` if
+    /// no view of it was recorded within the window.
+    pub fn churn(&self, now: Timespec, group: &str) -> Option<GroupChurn> {
+        let window = self.churn_window?;
+        let recent: Vec<&ViewChange> = match self.changes.get(group) {
+            Some(changes) => changes.iter().filter(|change| now - change.at <= window).collect(),
+            None => return None
+        };
+        if recent.is_empty() {
+            return None;
+        }
+        Some(GroupChurn {
+            group: group.to_string(),
+            window: window,
+            joins: recent.iter().fold(0, |acc, change| acc + change.joined as u64),
+            leaves: recent.iter().fold(0, |acc, change| acc + change.left as u64),
+            view_changes: recent.iter().filter(|change| change.joined + change.left > 0).count() as u64,
+            average_members: recent.iter().fold(0, |acc, change| acc + change.members) as f64 /
+                recent.len() as f64
+        })
+    }
+
+    /// The churn of every group with a view recorded within the window
+    /// ending at `now`, sorted by group name.
+    pub fn churn_all(&self, now: Timespec) -> Vec<GroupChurn> {
+        let mut groups: Vec<&String> = self.changes.keys().collect();
+        groups.sort();
+        groups.iter().filter_map(|group| self.churn(now, group.as_str())).collect()
+    }
+
+    /// Alert when `group` has fewer than `threshold` members, and again
+    /// when it recovers. Takes effect from the group's next view.
+    pub fn set_quorum(&mut self, group: &str, threshold: usize) {
+        self.quorums.insert(group.to_string(), threshold);
+    }
+
+    /// Stop checking `group` against a quorum threshold.
+    pub fn clear_quorum(&mut self, group: &str) {
+        self.quorums.remove(group);
+        self.below_quorum.remove(group);
+    }
+
+    /// Register a callback invoked whenever a group crosses its quorum
+    /// threshold in either direction.
+    pub fn on_quorum(&mut self, callback: Box<dyn FnMut(&QuorumEvent) + Send>) {
+        self.quorum_callbacks.push(callback);
+    }
+
+    /// Returns true if the latest view of `group` is below its quorum.
+    pub fn is_below_quorum(&self, group: &str) -> bool {
+        self.below_quorum.contains(group)
+    }
+
+    /// Register a callback invoked with every non-empty diff.
+    pub fn on_change(&mut self, callback: Box<dyn FnMut(&MembershipDiff) + Send>) {
+        self.callbacks.push(callback);
+    }
+
+    /// Record a new view of `group`, returning how it differs from the
+    /// previous one.
+    pub fn update(&mut self, group: &str, members: &[String]) -> MembershipDiff {
+        let new_view: BTreeSet<String> = members.iter().cloned().collect();
+        let diff = {
+            let empty = BTreeSet::new();
+            let old_view = self.views.get(group).unwrap_or(&empty);
+            MembershipDiff {
+                group: group.to_string(),
+                joined: new_view.difference(old_view).cloned().collect(),
+                left: old_view.difference(&new_view).cloned().collect()
+            }
+        };
+
+        if new_view.is_empty() {
+            self.views.remove(group);
+        } else {
+            self.views.insert(group.to_string(), new_view);
+        }
+
+        if !diff.is_empty() {
+            for callback in self.callbacks.iter_mut() {
+                callback(&diff);
+            }
+        }
+        self.check_quorum(group, members.len());
+        diff
+    }
+
+    fn check_quorum(&mut self, group: &str, members: usize) {
+        let threshold = match self.quorums.get(group) {
+            Some(threshold) => *threshold,
+            None => return
+        };
+        let event = if members < threshold && self.below_quorum.insert(group.to_string()) {
+            QuorumEvent::Lost { group: group.to_string(), members: members, threshold: threshold }
+        } else if members >= threshold && self.below_quorum.remove(group) {
+            QuorumEvent::Restored { group: group.to_string(), members: members, threshold: threshold }
+        } else {
+            return;
+        };
+        for callback in self.quorum_callbacks.iter_mut() {
+            callback(&event);
+        }
+    }
+
+    /// Forget `group`, e.g. after the client leaves it.
+    pub fn remove(&mut self, group: &str) {
+        self.views.remove(group);
+    }
+
+    /// Returns the members of `group` in the latest view, sorted.
+    pub fn members(&self, group: &str) -> Vec<String> {
+        match self.views.get(group) {
+            Some(view) => view.iter().cloned().collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Returns the members of `group` in the latest view, grouped by the
+    /// daemon each is connected to.
+    pub fn members_by_daemon(&self, group: &str) -> BTreeMap<String, Vec<String>> {
+        segment::by_daemon(self.members(group).as_slice())
+    }
+
+    /// Returns the groups for which a non-empty view is known.
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups: Vec<String> = self.views.keys().cloned().collect();
+        groups.sort();
+        groups
+    }
+}
@@ -0,0 +1,112 @@
+//! Accounting for the bytes a client holds in its queues and buffers.
+
+use std::sync::Arc;
+use sync::Mutex;
+use {Error, SpreadMessage};
+
+/// What happens when adding to one of a client's queues would take the
+/// bytes it holds over its memory cap.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryCapPolicy {
+    /// Refuse with `Error::BufferLimit`: a multicast fails before anything
+    /// is written, and a received message or dead letter that can't be
+    /// queued is dropped and the call that received it fails.
+    Error,
+    /// Make room by dropping the oldest entries of the queue being added
+    /// to, failing as under `Error` if that isn't enough.
+    DropOldest,
+    /// Keep the queue as it is and drop the new entry without failing: a
+    /// multicast is still sent but not kept for backfill or re-sending.
+    DropNewest
+}
+
+struct Usage {
+    cap: Option<usize>,
+    policy: MemoryCapPolicy,
+    client: usize,
+    shared: usize
+}
+
+/// A client's memory cap and the bytes counted against it, shared with
+/// queues kept outside the client, such as a dispatcher's dead letters.
+/// Clones share the same account.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    usage: Arc<Mutex<Usage>>
+}
+
+impl MemoryBudget {
+    /// An account with no cap, refusing with `MemoryCapPolicy::Error` once
+    /// one is set.
+    pub fn new() -> MemoryBudget {
+        MemoryBudget {
+            usage: Arc::new(Mutex::new(Usage {
+                cap: None,
+                policy: MemoryCapPolicy::Error,
+                client: 0,
+                shared: 0
+            }))
+        }
+    }
+
+    pub fn cap(&self) -> Option<usize> {
+        self.usage.lock().unwrap().cap
+    }
+
+    pub fn set_cap(&self, cap: Option<usize>) {
+        self.usage.lock().unwrap().cap = cap;
+    }
+
+    pub fn policy(&self) -> MemoryCapPolicy {
+        self.usage.lock().unwrap().policy
+    }
+
+    pub fn set_policy(&self, policy: MemoryCapPolicy) {
+        self.usage.lock().unwrap().policy = policy;
+    }
+
+    /// Bytes held by the client and by every queue sharing the account.
+    pub fn used(&self) -> usize {
+        let usage = self.usage.lock().unwrap();
+        usage.client + usage.shared
+    }
+
+    /// Bytes that can be added before reaching the cap, or `None` if there
+    /// is no cap.
+    pub fn available(&self) -> Option<usize> {
+        let usage = self.usage.lock().unwrap();
+        usage.cap.map(|cap| cap.saturating_sub(usage.client + usage.shared))
+    }
+
+    /// Succeed if `bytes` more can be held within the cap, or fail with
+    /// `Error::BufferLimit` giving the total they would make.
+    pub fn check(&self, bytes: usize) -> Result<(), Error> {
+        let usage = self.usage.lock().unwrap();
+        let total = usage.client + usage.shared + bytes;
+        match usage.cap {
+            Some(cap) if total > cap => Err(Error::BufferLimit { bytes: total, cap: cap }),
+            _ => Ok(())
+        }
+    }
+
+    /// Count `bytes` added to a queue sharing the account.
+    pub fn charge(&self, bytes: usize) {
+        self.usage.lock().unwrap().shared += bytes;
+    }
+
+    /// Stop counting `bytes` removed from a queue sharing the account.
+    pub fn release(&self, bytes: usize) {
+        let mut usage = self.usage.lock().unwrap();
+        usage.shared = usage.shared.saturating_sub(bytes);
+    }
+
+    // Record the bytes held by the client's own queues and buffers.
+    pub fn set_client_bytes(&self, bytes: usize) {
+        self.usage.lock().unwrap().client = bytes;
+    }
+}
+
+// The bytes a queued message holds.
+pub fn message_bytes(message: &SpreadMessage) -> usize {
+    message.data.len() + message.sender.len() + message.groups.iter().map(|g| g.len()).sum::<usize>()
+}
@@ -0,0 +1,94 @@
+//! Re-publishing received messages to other groups according to rules.
+//!
+//! Each rule names a source group, an optional filter, the destination
+//! groups, and an optional payload transform. Passing every received message
+//! to `Mirror::on_message` multicasts a copy for each matching rule, e.g. to
+//! tap production traffic into a staging or audit group.
+
+use {Error, SpreadClient, SpreadMessage, MEMBERSHIP_MESS};
+
+/// One mirroring rule.
+pub struct MirrorRule {
+    source_group: String,
+    destinations: Vec<String>,
+    filter: Option<Box<dyn Fn(&SpreadMessage) -> bool + Send>>,
+    transform: Option<Box<dyn Fn(&[u8]) -> Vec<u8> + Send>>
+}
+
+impl MirrorRule {
+    /// Copy messages received on `source_group` to `destinations`.
+    pub fn new(source_group: &str, destinations: &[&str]) -> MirrorRule {
+        MirrorRule {
+            source_group: source_group.to_string(),
+            destinations: destinations.iter().map(|g| g.to_string()).collect(),
+            filter: None,
+            transform: None
+        }
+    }
+
+    /// Only copy messages for which `filter` returns true.
+    pub fn filter(mut self, filter: Box<dyn Fn(&SpreadMessage) -> bool + Send>) -> MirrorRule {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Publish `transform(payload)` instead of the original payload.
+    pub fn transform(mut self, transform: Box<dyn Fn(&[u8]) -> Vec<u8> + Send>) -> MirrorRule {
+        self.transform = Some(transform);
+        self
+    }
+
+    fn matches(&self, message: &SpreadMessage) -> bool {
+        message.groups.iter().any(|g| g.as_str().trim_end_matches('\0') == self.source_group.as_str())
+            && self.filter.as_ref().is_none_or(|filter| (**filter)(message))
+    }
+
+    fn payload(&self, message: &SpreadMessage) -> Vec<u8> {
+        match self.transform {
+            Some(ref transform) => (**transform)(message.data.as_slice()),
+            None => message.data.clone()
+        }
+    }
+}
+
+/// An ordered set of mirroring rules.
+pub struct Mirror {
+    rules: Vec<MirrorRule>
+}
+
+impl Mirror {
+    pub fn new() -> Mirror {
+        Mirror { rules: Vec::new() }
+    }
+
+    pub fn add_rule(&mut self, rule: MirrorRule) {
+        self.rules.push(rule);
+    }
+
+    /// The copies to publish for `message`, as (destination groups, payload)
+    /// pairs, one per matching rule.
+    pub fn copies(&self, message: &SpreadMessage) -> Vec<(Vec<String>, Vec<u8>)> {
+        if message.service_type & MEMBERSHIP_MESS != 0 {
+            return Vec::new();
+        }
+        self.rules.iter()
+            .filter(|rule| rule.matches(message))
+            .map(|rule| (rule.destinations.clone(), rule.payload(message)))
+            .collect()
+    }
+
+    /// Publish the copies of `message` through `client`, returning how many
+    /// were sent. Messages sent by `client` itself are never mirrored, so a
+    /// client that has also joined a destination group does not loop.
+    pub fn on_message(&self, client: &mut SpreadClient, message: &SpreadMessage) -> Result<usize, Error> {
+        if message.sender.as_str().trim_end_matches('\0') == client.private_name.as_str() {
+            return Ok(0);
+        }
+        let copies = self.copies(message);
+        for (destinations, payload) in copies.iter() {
+            let groups: Vec<&str> = destinations.iter().map(|g| g.as_str()).collect();
+            client.multicast(groups.as_slice(), payload.as_slice())?;
+        }
+        Ok(copies.len())
+    }
+}
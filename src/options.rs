@@ -0,0 +1,220 @@
+//! Settings for establishing a session with a daemon.
+
+use std::net::ToSocketAddrs;
+#[cfg(any(feature = "proxy", feature = "tls"))]
+use std::io;
+#[cfg(any(feature = "proxy", feature = "tls"))]
+use std::net::TcpStream;
+#[cfg(feature = "proxy")]
+use proxy::{self, ProxyConfig};
+use retry::RetryPolicy;
+#[cfg(feature = "tls")]
+use tls::{TlsConfig, TlsTransport};
+use transport::Transport;
+use {connect, connect_with_transport, Error, SpreadClient};
+
+/// The private name, membership setting and groups to join for a new
+/// session.
+///
+/// Groups added with `join` are joined as part of connecting, before the
+/// client is handed back, so no setup code runs between the handshake and
+/// the joins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectOptions {
+    pub private_name: String,
+    pub receive_membership_messages: bool,
+    pub groups: Vec<String>,
+    /// Reconnect automatically per this policy if the connection is lost.
+    pub reconnect: Option<RetryPolicy>,
+    /// Reach the daemon, and reconnect, through this proxy.
+    #[cfg(feature = "proxy")]
+    pub proxy: Option<ProxyConfig>,
+    /// Speak TLS to the daemon's terminator, per this configuration.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>
+}
+
+impl ConnectOptions {
+    pub fn new(private_name: &str) -> ConnectOptions {
+        ConnectOptions {
+            private_name: private_name.to_string(),
+            receive_membership_messages: false,
+            groups: Vec::new(),
+            reconnect: None,
+            #[cfg(feature = "proxy")]
+            proxy: None,
+            #[cfg(feature = "tls")]
+            tls: None
+        }
+    }
+
+    pub fn membership_messages(mut self, receive: bool) -> ConnectOptions {
+        self.receive_membership_messages = receive;
+        self
+    }
+
+    /// Join `group` as soon as the session is established.
+    pub fn join(mut self, group: &str) -> ConnectOptions {
+        self.groups.push(group.to_string());
+        self
+    }
+
+    /// Re-establish the session and re-join its groups automatically if
+    /// the connection is lost, as set by `SpreadClient::set_auto_reconnect`.
+    pub fn auto_reconnect(mut self, policy: RetryPolicy) -> ConnectOptions {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Tunnel the connection, and any reconnects, through `proxy`. Only
+    /// available with the `proxy` feature.
+    #[cfg(feature = "proxy")]
+    pub fn via_proxy(mut self, proxy: ProxyConfig) -> ConnectOptions {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Speak TLS, per `tls`, to a terminator in front of the daemon, on
+    /// the connection and any reconnects. With a proxy, TLS runs through
+    /// the tunnel. Only available with the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn via_tls(mut self, tls: TlsConfig) -> ConnectOptions {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Connect to a daemon at `addr` and join the configured groups. With
+    /// a proxy, `addr` is resolved locally and the proxy asked for a tunnel
+    /// to each address in turn. With TLS and no server name configured,
+    /// the certificate must name the address connected to.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<SpreadClient, Error> {
+        #[cfg(any(feature = "proxy", feature = "tls"))]
+        {
+            if !self.dials_directly() {
+                let targets = addr.to_socket_addrs()?
+                    .map(|addr| (addr.ip().to_string(), addr.port()))
+                    .collect();
+                let client = self.connect_via(self.dialer(targets))?;
+                return self.join_groups(client);
+            }
+        }
+        let client = connect(addr, self.private_name.as_str(),
+                             self.receive_membership_messages)?;
+        self.join_groups(client)
+    }
+
+    /// Connect to a daemon at `host:port` and join the configured groups.
+    /// With a proxy, `host` is resolved by the proxy.
+    pub fn connect_to(&self, host: &str, port: u16) -> Result<SpreadClient, Error> {
+        #[cfg(any(feature = "proxy", feature = "tls"))]
+        {
+            if !self.dials_directly() {
+                let client = self.connect_via(self.dialer(vec![(host.to_string(), port)]))?;
+                return self.join_groups(client);
+            }
+        }
+        self.connect((host, port))
+    }
+
+    // Returns true if neither a proxy nor TLS is configured.
+    #[cfg(any(feature = "proxy", feature = "tls"))]
+    fn dials_directly(&self) -> bool {
+        #[cfg(feature = "proxy")]
+        {
+            if self.proxy.is_some() {
+                return false;
+            }
+        }
+        #[cfg(feature = "tls")]
+        {
+            if self.tls.is_some() {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Dial `targets` through the configured proxy and TLS.
+    #[cfg(any(feature = "proxy", feature = "tls"))]
+    fn dialer(&self, targets: Vec<(String, u16)>) -> Dialer {
+        Dialer {
+            targets: targets,
+            #[cfg(feature = "proxy")]
+            proxy: self.proxy.clone(),
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone()
+        }
+    }
+
+    // Open a session over the first of the dialer's targets it reaches,
+    // dialing the same way to reconnect.
+    #[cfg(any(feature = "proxy", feature = "tls"))]
+    fn connect_via(&self, dialer: Dialer) -> Result<SpreadClient, Error> {
+        let mut client = connect_with_transport(dialer.dial_any()?, self.private_name.as_str(),
+                                                self.receive_membership_messages)?;
+        client.set_dialer(Some(Box::new(move || Ok(dialer.dial_any()?))));
+        Ok(client)
+    }
+
+    /// Establish a session over `transport` and join the configured groups.
+    pub fn connect_with_transport(&self, transport: Box<dyn Transport>) -> Result<SpreadClient, Error> {
+        let client = connect_with_transport(transport, self.private_name.as_str(),
+                                            self.receive_membership_messages)?;
+        self.join_groups(client)
+    }
+
+    fn join_groups(&self, mut client: SpreadClient) -> Result<SpreadClient, Error> {
+        for group in self.groups.iter() {
+            client.join(group.as_str())?;
+        }
+        client.set_auto_join(self.groups.as_slice());
+        client.set_auto_reconnect(self.reconnect.clone());
+        Ok(client)
+    }
+}
+
+// Dials daemons through a proxy, over TLS, or both.
+#[cfg(any(feature = "proxy", feature = "tls"))]
+struct Dialer {
+    targets: Vec<(String, u16)>,
+    #[cfg(feature = "proxy")]
+    proxy: Option<ProxyConfig>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>
+}
+
+#[cfg(any(feature = "proxy", feature = "tls"))]
+impl Dialer {
+    // Connect to the first of the targets that can be reached, returning
+    // the last error if none can.
+    fn dial_any(&self) -> io::Result<Box<dyn Transport>> {
+        let mut last_error = io::Error::other("Daemon address resolved to no addresses");
+        for &(ref host, port) in self.targets.iter() {
+            match self.dial(host.as_str(), port) {
+                Ok(stream) => return Ok(stream),
+                Err(error) => {
+                    debug!("Failed to reach {}:{}: {}", host, port, error);
+                    last_error = error;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn dial(&self, host: &str, port: u16) -> io::Result<Box<dyn Transport>> {
+        #[cfg(feature = "proxy")]
+        let stream = match self.proxy {
+            Some(ref proxy) => proxy::open_tunnel(proxy, host, port)?,
+            None => TcpStream::connect((host, port))?
+        };
+        #[cfg(not(feature = "proxy"))]
+        let stream = TcpStream::connect((host, port))?;
+        #[cfg(feature = "tls")]
+        {
+            if let Some(ref tls) = self.tls {
+                return Ok(Box::new(TlsTransport::wrap(Box::new(stream), host, tls)?));
+            }
+        }
+        Ok(Box::new(stream))
+    }
+}
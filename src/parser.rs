@@ -0,0 +1,342 @@
+//! Decoding of frames received from a daemon, independent of any I/O.
+//!
+//! `Parser` accepts the byte stream in arbitrarily sized chunks, e.g. from
+//! an existing poll loop or a packet capture, and emits each message as soon
+//! as its frame is complete.
+
+use encoding::{Encoding, DecoderTrap};
+use encoding::all::ISO_8859_1;
+use std::cmp;
+use std::io::Read;
+use std::slice::Chunks;
+use std::str;
+use util::{append_bytes, bytes_to_int, flip_endianness, same_endianness};
+use limits::MAX_GROUP_NAME_LENGTH;
+use {Error, SpreadMessage, MEMBERSHIP_MESS};
+
+// Header format (sizes in bytes):
+//   svc_type:   4
+//   sender:    32
+//   num_groups: 4
+//   hint:       4
+//   data_len:   4
+pub static HEADER_LENGTH: usize = 48;
+
+// Group names are read this many at a time, so a frame addressed to a very
+// large number of groups grows its buffer as the names arrive rather than
+// trusting the header for a single allocation.
+static GROUP_READ_CHUNK: usize = 128;
+
+/// The fixed-size header at the start of every received frame.
+pub struct FrameHeader {
+    pub service_type: u32,
+    pub sender: String,
+    pub num_groups: u32,
+    pub mess_type: i16,
+    pub data_len: u32
+}
+
+impl FrameHeader {
+    /// Decode a header from its `HEADER_LENGTH` bytes.
+    pub fn decode(header: &[u8]) -> Result<FrameHeader, Error> {
+        let int_at = |offset: usize| header_int(header, offset);
+        let sender = ISO_8859_1.decode(&header[4..36], DecoderTrap::Strict).map_err(|error| {
+            Error::EncodingError(format!("Failed to decode sender name: {}", error))
+        })?;
+        FrameHeader::body_lengths(header)?;
+        Ok(FrameHeader {
+            service_type: int_at(0),
+            sender: sender,
+            num_groups: int_at(36),
+            mess_type: header_mess_type(header),
+            data_len: int_at(44)
+        })
+    }
+
+    /// Length of the group names following the header.
+    pub fn groups_len(&self) -> usize {
+        MAX_GROUP_NAME_LENGTH * self.num_groups as usize
+    }
+
+    /// Lengths of the group names and payload following a header, without
+    /// decoding the sender. The daemon sends both counts as signed
+    /// integers; a negative one means the stream is misaligned or corrupt,
+    /// so nothing after the header can be trusted.
+    pub fn body_lengths(header: &[u8]) -> Result<(usize, usize), Error> {
+        let num_groups = header_int(header, 36) as i32;
+        let data_len = header_int(header, 44) as i32;
+        if num_groups < 0 || data_len < 0 {
+            return Err(Error::ProtocolError(
+                format!("Malformed frame header: {} groups, {} data bytes", num_groups, data_len)
+            ));
+        }
+        Ok((MAX_GROUP_NAME_LENGTH * num_groups as usize, data_len as usize))
+    }
+}
+
+/// Read the header field at `offset`, in the daemon's byte order.
+pub fn header_int(header: &[u8], offset: usize) -> u32 {
+    let value = bytes_to_int(&header[offset..offset + 4]);
+    if same_endianness(bytes_to_int(&header[0..4])) { value } else { flip_endianness(value) }
+}
+
+/// The message type carried in the hint field of a header, as the C client
+/// library encodes it.
+pub fn header_mess_type(header: &[u8]) -> i16 {
+    (header_int(header, 40) >> 8) as u16 as i16
+}
+
+/// Read `count` fixed-width group names from `reader`, undecoded.
+pub fn read_groups(reader: &mut dyn Read, count: u32) -> Result<Vec<u8>, Error> {
+    let mut raw = Vec::new();
+    append_groups(reader, count, &mut raw)?;
+    Ok(raw)
+}
+
+/// Read `count` fixed-width group names from `reader`, undecoded,
+/// appending them to `buf`.
+pub fn append_groups(reader: &mut dyn Read, count: u32, buf: &mut Vec<u8>) -> Result<(), Error> {
+    let mut remaining = count as usize;
+    while remaining > 0 {
+        let names = cmp::min(remaining, GROUP_READ_CHUNK);
+        let bytes = names * MAX_GROUP_NAME_LENGTH;
+        append_bytes(reader, bytes, buf)?;
+        remaining -= names;
+    }
+    Ok(())
+}
+
+/// Decode `count` fixed-width group names.
+pub fn decode_groups(groups: &[u8], count: u32) -> Result<Vec<String>, Error> {
+    let mut decoded = Vec::with_capacity(count as usize);
+    for n in 0..count {
+        let i = n as usize * MAX_GROUP_NAME_LENGTH;
+        let group =
+            ISO_8859_1.decode(&groups[i..i + MAX_GROUP_NAME_LENGTH], DecoderTrap::Strict)
+                .map_err(|error| Error::EncodingError(
+                    format!("Failed to decode group name: {}", error)
+                ))?;
+        decoded.push(group);
+    }
+    Ok(decoded)
+}
+
+/// A frame as received, with only its service type decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RawFrame {
+    pub service_type: u32,
+    /// The `HEADER_LENGTH`-byte header, in the daemon's byte order.
+    pub header: Vec<u8>,
+    /// The fixed-width, NUL-padded group names.
+    pub groups: Vec<u8>,
+    pub payload: Vec<u8>
+}
+
+impl RawFrame {
+    /// The frame's exact wire bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.clone();
+        bytes.extend_from_slice(self.groups.as_slice());
+        bytes.extend_from_slice(self.payload.as_slice());
+        bytes
+    }
+
+    /// Decode the frame into a message.
+    pub fn decode(&self) -> Result<SpreadMessage, Error> {
+        let header = FrameHeader::decode(self.header.as_slice())?;
+        let groups = decode_groups(self.groups.as_slice(), header.num_groups)?;
+        Ok(SpreadMessage {
+            service_type: header.service_type,
+            mess_type: header.mess_type,
+            groups: groups,
+            sender: header.sender,
+            data: self.payload.clone()
+        })
+    }
+}
+
+/// A received message that borrows its sender, groups and payload from the
+/// frame bytes rather than copying them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpreadMessageRef<'a> {
+    pub service_type: u32,
+    mess_type: i16,
+    sender: &'a str,
+    groups: &'a [u8],
+    pub data: &'a [u8]
+}
+
+impl<'a> SpreadMessageRef<'a> {
+    /// Borrow the message in a frame's exact wire bytes. Fails if the
+    /// sender or a group name is not valid UTF-8, since they could then
+    /// only be decoded into owned strings.
+    pub fn from_frame(frame: &'a [u8]) -> Result<SpreadMessageRef<'a>, Error> {
+        let (groups_len, data_len) = FrameHeader::body_lengths(&frame[..HEADER_LENGTH])?;
+        let groups_end = HEADER_LENGTH + groups_len;
+        if frame.len() != groups_end + data_len {
+            return Err(Error::ProtocolError(
+                format!("Frame length does not match its header: {} bytes, header claims {}",
+                        frame.len(), groups_end + data_len)
+            ));
+        }
+        let sender = borrow_name(&frame[4..36])?;
+        let groups = &frame[HEADER_LENGTH..groups_end];
+        for name in groups.chunks(MAX_GROUP_NAME_LENGTH) {
+            borrow_name(name)?;
+        }
+        Ok(SpreadMessageRef {
+            service_type: header_int(frame, 0),
+            mess_type: header_mess_type(frame),
+            sender: sender,
+            groups: groups,
+            data: &frame[groups_end..]
+        })
+    }
+
+    /// The sender's private group name, without NUL padding.
+    pub fn sender(&self) -> &'a str {
+        self.sender
+    }
+
+    /// The destination group names, without NUL padding.
+    pub fn groups(&self) -> GroupNames<'a> {
+        GroupNames { chunks: self.groups.chunks(MAX_GROUP_NAME_LENGTH) }
+    }
+
+    /// The application-defined message type the sender gave the message.
+    pub fn message_type(&self) -> i16 {
+        self.mess_type
+    }
+
+    pub fn is_membership(&self) -> bool {
+        self.service_type & MEMBERSHIP_MESS != 0
+    }
+
+    /// Copy the message into an owned `SpreadMessage`.
+    pub fn to_message(&self) -> SpreadMessage {
+        SpreadMessage {
+            service_type: self.service_type,
+            mess_type: self.mess_type,
+            groups: self.groups().map(|group| group.to_string()).collect(),
+            sender: self.sender.to_string(),
+            data: self.data.to_vec()
+        }
+    }
+}
+
+/// An iterator over the group names of a `SpreadMessageRef`.
+pub struct GroupNames<'a> {
+    chunks: Chunks<'a, u8>
+}
+
+impl<'a> Iterator for GroupNames<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        // Names were checked to be valid UTF-8 by `from_frame`.
+        self.chunks.next().map(|name| borrow_name(name).unwrap())
+    }
+}
+
+// Borrow a NUL-padded name as a string.
+fn borrow_name(name: &[u8]) -> Result<&str, Error> {
+    str::from_utf8(name).map(|name| name.trim_end_matches('\0')).map_err(|error| Error::EncodingError(
+        format!("Name is not valid UTF-8: {:?}", error)
+    ))
+}
+
+/// A complete item decoded by a `Parser`, or returned by
+/// `SpreadClient::next_event`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpreadEvent {
+    /// A data message sent to one or more groups.
+    Data(SpreadMessage),
+    /// A membership change of a group.
+    Membership(SpreadMessage),
+    /// A frame that could not be decoded. The parser skips it and carries
+    /// on with the next frame.
+    Malformed(Error),
+    /// The connection to the daemon was lost, with this error. Only
+    /// returned by a client with auto-reconnect enabled, and always
+    /// followed by `Reconnected`.
+    Disconnected(Error),
+    /// A new session replaced the lost one. Messages sent to the groups
+    /// while no session was established may have been missed.
+    Reconnected {
+        /// The groups joined again on the new session.
+        rejoined_groups: Vec<String>
+    }
+}
+
+// The event for a received message.
+pub fn message_event(message: SpreadMessage) -> SpreadEvent {
+    if message.service_type & MEMBERSHIP_MESS != 0 {
+        SpreadEvent::Membership(message)
+    } else {
+        SpreadEvent::Data(message)
+    }
+}
+
+/// An incremental decoder for the stream of frames sent by a daemon after
+/// the connection handshake.
+pub struct Parser {
+    buffer: Vec<u8>
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser { buffer: Vec::new() }
+    }
+
+    /// Append `bytes` to the stream, returning every event completed by
+    /// them.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SpreadEvent> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        let mut consumed = 0;
+        while let Some((event, len)) = parse_frame(&self.buffer[consumed..]) {
+            events.push(event);
+            consumed += len;
+        }
+        // Shift the incomplete tail down in place rather than copying it
+        // into a new buffer, keeping the allocation for the next call.
+        self.buffer.drain(..consumed);
+        events
+    }
+
+    /// Number of bytes buffered towards an incomplete frame.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+// Parse the frame at the start of `bytes`, if it is complete, returning the
+// event and the frame's length.
+fn parse_frame(bytes: &[u8]) -> Option<(SpreadEvent, usize)> {
+    if bytes.len() < HEADER_LENGTH {
+        return None;
+    }
+    let header = match FrameHeader::decode(&bytes[..HEADER_LENGTH]) {
+        Ok(header) => header,
+        Err(error) => return Some((SpreadEvent::Malformed(error), HEADER_LENGTH))
+    };
+    let groups_end = HEADER_LENGTH + header.groups_len();
+    let frame_len = groups_end + header.data_len as usize;
+    if bytes.len() < frame_len {
+        return None;
+    }
+
+    let groups = match decode_groups(&bytes[HEADER_LENGTH..groups_end], header.num_groups) {
+        Ok(groups) => groups,
+        Err(error) => return Some((SpreadEvent::Malformed(error), frame_len))
+    };
+    let message = SpreadMessage {
+        service_type: header.service_type,
+        mess_type: header.mess_type,
+        groups: groups,
+        sender: header.sender,
+        data: bytes[groups_end..frame_len].to_vec()
+    };
+    Some((message_event(message), frame_len))
+}
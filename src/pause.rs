@@ -0,0 +1,158 @@
+//! Holding back a group's data messages without leaving the group.
+
+use std::collections::{HashMap, VecDeque};
+use time::Timespec;
+use memory::{message_bytes, MemoryBudget, MemoryCapPolicy};
+use {Error, SpreadMessage, MEMBERSHIP_MESS};
+
+/// What happens to a paused group's data messages.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PausePolicy {
+    /// Keep up to this many messages for delivery on resume, dropping any
+    /// that arrive once it is full.
+    Buffer(usize),
+    /// Drop every message.
+    Drop
+}
+
+struct PausedGroup {
+    policy: PausePolicy,
+    held: VecDeque<(Timespec, SpreadMessage)>,
+    dropped: u64
+}
+
+// The paused groups of a client, by logical name, and the messages held
+// back from delivery with the times they were received.
+pub struct PausedGroups {
+    paused: HashMap<String, PausedGroup>,
+    resumed: VecDeque<(Timespec, SpreadMessage)>,
+    bytes: usize
+}
+
+impl PausedGroups {
+    pub fn new() -> PausedGroups {
+        PausedGroups { paused: HashMap::new(), resumed: VecDeque::new(), bytes: 0 }
+    }
+
+    pub fn pause(&mut self, group: &str, policy: PausePolicy) {
+        if let Some(paused) = self.paused.get_mut(group) {
+            paused.policy = policy;
+            return;
+        }
+        self.paused.insert(group.to_string(),
+                           PausedGroup { policy: policy, held: VecDeque::new(), dropped: 0 });
+    }
+
+    // Queue the group's held messages for delivery, returning how many
+    // there were.
+    pub fn resume(&mut self, group: &str) -> usize {
+        match self.paused.remove(group) {
+            Some(paused) => {
+                let count = paused.held.len();
+                self.resumed.extend(paused.held);
+                count
+            },
+            None => 0
+        }
+    }
+
+    pub fn is_paused(&self, group: &str) -> bool {
+        self.paused.contains_key(group)
+    }
+
+    pub fn dropped(&self, group: &str) -> u64 {
+        self.paused.get(group).map_or(0, |paused| paused.dropped)
+    }
+
+    // Number of messages released by `resume` and not yet delivered.
+    pub fn resumed(&self) -> usize {
+        self.resumed.len()
+    }
+
+    // Bytes of the messages held or resumed and not yet delivered.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn next_resumed(&mut self) -> Option<SpreadMessage> {
+        let (_, message) = self.resumed.pop_front()?;
+        self.bytes -= message_bytes(&message);
+        Some(message)
+    }
+
+    // Drop every held or resumed message received before `cutoff`,
+    // returning how many there were.
+    pub fn expire(&mut self, cutoff: Timespec) -> usize {
+        let before = self.held();
+        for paused in self.paused.values_mut() {
+            paused.held.retain(|(received_at, _)| *received_at >= cutoff);
+        }
+        self.resumed.retain(|(received_at, _)| *received_at >= cutoff);
+        self.bytes = self.paused.values().flat_map(|paused| paused.held.iter())
+            .chain(self.resumed.iter())
+            .map(|(_, message)| message_bytes(message))
+            .sum();
+        before - self.held()
+    }
+
+    // Messages held for paused groups or resumed and not yet delivered.
+    fn held(&self) -> usize {
+        self.paused.values().map(|paused| paused.held.len()).sum::<usize>() + self.resumed.len()
+    }
+
+    // Hold back or drop `message`, received at `now`, if it is a data
+    // message for a paused group and for no unpaused group in `joined`;
+    // otherwise hand it back. A message held back is kept within `budget`
+    // as its policy dictates.
+    pub fn filter(&mut self, now: Timespec, message: SpreadMessage, joined: &[String],
+                  budget: &MemoryBudget) -> Result<Option<SpreadMessage>, Error> {
+        if self.paused.is_empty() || message.service_type & MEMBERSHIP_MESS != 0 {
+            return Ok(Some(message));
+        }
+        let group = {
+            let groups: Vec<&str> = message.groups.iter()
+                .map(|g| g.as_str().trim_end_matches('\0'))
+                .collect();
+            let for_unpaused = groups.iter().any(|group| {
+                !self.paused.contains_key(*group) && joined.iter().any(|j| j.as_str() == *group)
+            });
+            match groups.iter().find(|group| self.paused.contains_key(**group)) {
+                Some(group) if !for_unpaused => Some(group.to_string()),
+                _ => None
+            }
+        };
+        let group = match group {
+            Some(group) => group,
+            None => return Ok(Some(message))
+        };
+        let paused = self.paused.get_mut(&group).unwrap();
+        match paused.policy {
+            PausePolicy::Buffer(limit) if paused.held.len() < limit => {},
+            _ => {
+                paused.dropped += 1;
+                return Ok(None);
+            }
+        }
+        let bytes = message_bytes(&message);
+        let mut freed = 0;
+        let mut result = Ok(None);
+        while let Err(error) = budget.check(bytes.saturating_sub(freed)) {
+            match budget.policy() {
+                MemoryCapPolicy::DropOldest if !paused.held.is_empty() => {
+                    let (_, oldest) = paused.held.pop_front().unwrap();
+                    freed += message_bytes(&oldest);
+                    paused.dropped += 1;
+                    continue;
+                },
+                MemoryCapPolicy::DropNewest => {},
+                _ => result = Err(error)
+            }
+            paused.dropped += 1;
+            self.bytes -= freed;
+            return result;
+        }
+        paused.held.push_back((now, message));
+        self.bytes = self.bytes - freed + bytes;
+        result
+    }
+}
@@ -0,0 +1,151 @@
+//! Tracking which members are online in a group via presence beacons.
+//!
+//! Each participant periodically calls `Presence::announce`, which multicasts
+//! a small beacon (optionally carrying metadata) to the group. Every received
+//! message is passed to `Presence::observe`, which recognizes beacons and
+//! keeps a per-group roster; `Presence::expire` drops members whose beacons
+//! have stopped arriving.
+
+use std::collections::HashMap;
+use time::{Duration, Timespec};
+use {Error, SpreadClient, SpreadMessage};
+
+static BEACON_PREFIX: &'static [u8] = b"\x00spread-presence:";
+static BEACON_ALIVE: u8 = 1;
+static BEACON_DEPARTING: u8 = 0;
+
+/// A member of a group, as last announced by its beacon.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemberInfo {
+    /// The member's private group name.
+    pub member: String,
+    /// When the member's most recent beacon was observed.
+    pub last_seen: Timespec,
+    /// Metadata attached to the member's most recent beacon.
+    pub metadata: Vec<u8>
+}
+
+/// A change in a group's roster.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PresenceEvent {
+    /// A member was seen for the first time.
+    Online { group: String, member: String },
+    /// A member changed the metadata attached to its beacons.
+    Updated { group: String, member: String },
+    /// A member announced its departure or its beacons expired.
+    Offline { group: String, member: String }
+}
+
+/// Per-group rosters built from presence beacons.
+pub struct Presence {
+    timeout: Duration,
+    groups: HashMap<String, HashMap<String, MemberInfo>>
+}
+
+impl Presence {
+    /// Track presence, considering members offline once no beacon has been
+    /// seen from them for `timeout`.
+    pub fn new(timeout: Duration) -> Presence {
+        Presence { timeout: timeout, groups: HashMap::new() }
+    }
+
+    /// Multicast a beacon announcing this client in `group`.
+    pub fn announce(client: &mut SpreadClient, group: &str, metadata: &[u8]) -> Result<(), Error> {
+        client.multicast([group].as_slice(), encode_beacon(BEACON_ALIVE, metadata).as_slice())
+    }
+
+    /// Multicast a beacon announcing that this client is leaving `group`.
+    pub fn depart(client: &mut SpreadClient, group: &str) -> Result<(), Error> {
+        client.multicast([group].as_slice(), encode_beacon(BEACON_DEPARTING, [].as_slice()).as_slice())
+    }
+
+    /// Returns true if `data` is a presence beacon rather than application
+    /// traffic.
+    pub fn is_beacon(data: &[u8]) -> bool {
+        data.len() > BEACON_PREFIX.len() && data.starts_with(BEACON_PREFIX)
+    }
+
+    /// Update the rosters from a received message, returning any changes.
+    /// Messages that are not beacons are ignored.
+    pub fn observe(&mut self, now: Timespec, message: &SpreadMessage) -> Vec<PresenceEvent> {
+        let mut events = Vec::new();
+        if !Presence::is_beacon(message.data.as_slice()) {
+            return events;
+        }
+        let kind = message.data[BEACON_PREFIX.len()];
+        let metadata = &message.data[BEACON_PREFIX.len() + 1..];
+
+        for group in message.groups.iter() {
+            if !self.groups.contains_key(group) {
+                self.groups.insert(group.clone(), HashMap::new());
+            }
+            let roster = self.groups.get_mut(group).unwrap();
+
+            if kind == BEACON_DEPARTING {
+                if roster.remove(&message.sender).is_some() {
+                    events.push(PresenceEvent::Offline {
+                        group: group.clone(),
+                        member: message.sender.clone()
+                    });
+                }
+                continue;
+            }
+
+            let event = match roster.get(&message.sender) {
+                None => Some(PresenceEvent::Online {
+                    group: group.clone(),
+                    member: message.sender.clone()
+                }),
+                Some(info) if info.metadata.as_slice() != metadata => Some(PresenceEvent::Updated {
+                    group: group.clone(),
+                    member: message.sender.clone()
+                }),
+                Some(_) => None
+            };
+            roster.insert(message.sender.clone(), MemberInfo {
+                member: message.sender.clone(),
+                last_seen: now,
+                metadata: metadata.to_vec()
+            });
+            if let Some(event) = event {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Remove members whose last beacon is older than the timeout,
+    /// returning an `Offline` event for each.
+    pub fn expire(&mut self, now: Timespec) -> Vec<PresenceEvent> {
+        let mut events = Vec::new();
+        let timeout = self.timeout;
+        for (group, roster) in self.groups.iter_mut() {
+            let expired: Vec<String> = roster.values()
+                .filter(|info| now - info.last_seen > timeout)
+                .map(|info| info.member.clone())
+                .collect();
+            for member in expired.into_iter() {
+                roster.remove(&member);
+                events.push(PresenceEvent::Offline { group: group.clone(), member: member });
+            }
+        }
+        events
+    }
+
+    /// Returns the members currently online in `group`, sorted by name.
+    pub fn online(&self, group: &str) -> Vec<MemberInfo> {
+        let mut members: Vec<MemberInfo> = match self.groups.get(group) {
+            Some(roster) => roster.values().cloned().collect(),
+            None => Vec::new()
+        };
+        members.sort_by(|a, b| a.member.cmp(&b.member));
+        members
+    }
+}
+
+fn encode_beacon(kind: u8, metadata: &[u8]) -> Vec<u8> {
+    let mut beacon = BEACON_PREFIX.to_vec();
+    beacon.push(kind);
+    beacon.extend_from_slice(metadata);
+    beacon
+}
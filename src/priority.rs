@@ -0,0 +1,40 @@
+//! Ordering received messages by priority rather than arrival.
+
+use std::collections::VecDeque;
+use time::Timespec;
+use SpreadMessage;
+
+/// How `SpreadClient::receive` ranks messages that have arrived but not
+/// yet been returned. The highest-ranked is returned first; equally ranked
+/// messages keep their arrival order.
+pub enum DeliveryPriority {
+    /// Rank data messages by their message type, putting membership
+    /// messages ahead of all of them.
+    MessageType,
+    /// Rank messages by the value the classifier returns for them.
+    Classifier(Box<dyn Fn(&SpreadMessage) -> i64 + Send>)
+}
+
+impl DeliveryPriority {
+    /// The rank of `message`; higher is delivered sooner.
+    pub fn rank(&self, message: &SpreadMessage) -> i64 {
+        match *self {
+            DeliveryPriority::MessageType if message.is_membership() => i64::MAX,
+            DeliveryPriority::MessageType => message.message_type() as i64,
+            DeliveryPriority::Classifier(ref classify) => (**classify)(message)
+        }
+    }
+
+    // Remove and return the highest-ranked message in `queue`, the earliest
+    // of those ranked equally.
+    pub fn take_highest(&self, queue: &mut VecDeque<(Timespec, SpreadMessage)>) -> Option<SpreadMessage> {
+        let mut best: Option<(usize, i64)> = None;
+        for (i, (_, message)) in queue.iter().enumerate() {
+            let rank = self.rank(message);
+            if best.is_none_or(|(_, best_rank)| rank > best_rank) {
+                best = Some((i, rank));
+            }
+        }
+        best.and_then(|(i, _)| queue.remove(i)).map(|(_, message)| message)
+    }
+}
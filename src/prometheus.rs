@@ -0,0 +1,236 @@
+//! Client statistics as `prometheus-client` metrics.
+//!
+//! Only available when the crate is built with the `prometheus` feature.
+//! `SpreadClient::register_metrics` registers a client's counters, gauges
+//! and histograms with the application's `prometheus_client` registry, so
+//! they are exported by whatever already serves that registry. Every metric
+//! is prefixed with `spread_` and labelled with the client's private group
+//! name as `client`; the per-group counters also carry a `group` label.
+//!
+//! Counters start from zero when they are registered. Message rates are not
+//! exported, since Prometheus derives them from the counters.
+
+use std::sync::atomic::AtomicU64;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use membership::GroupChurn;
+use stats::HISTOGRAM_BUCKETS;
+
+type GroupLabel = [(&'static str, String); 1];
+
+/// The metrics of one client, as registered with a registry. The client
+/// updates them as it records its own statistics.
+#[derive(Clone, Debug)]
+pub struct ClientMetrics {
+    messages_sent: Counter,
+    bytes_sent: Counter,
+    messages_received: Counter,
+    bytes_received: Counter,
+    reconnects: Counter,
+    buffered_bytes: Gauge,
+    receive_queue_depth: Gauge,
+    sent_payload_bytes: Histogram,
+    received_payload_bytes: Histogram,
+    sent_fanout_groups: Histogram,
+    received_fanout_groups: Histogram,
+    write_latency_us: Histogram,
+    send_latency_us: Histogram,
+    receive_latency_us: Histogram,
+    group_messages_sent: Family<GroupLabel, Counter>,
+    group_bytes_sent: Family<GroupLabel, Counter>,
+    group_messages_received: Family<GroupLabel, Counter>,
+    group_bytes_received: Family<GroupLabel, Counter>
+}
+
+impl ClientMetrics {
+    /// Create the metrics of the client named `private_name` and register
+    /// them with `registry`.
+    pub fn register(registry: &mut Registry, private_name: &str) -> ClientMetrics {
+        let metrics = ClientMetrics {
+            messages_sent: Counter::default(),
+            bytes_sent: Counter::default(),
+            messages_received: Counter::default(),
+            bytes_received: Counter::default(),
+            reconnects: Counter::default(),
+            buffered_bytes: Gauge::default(),
+            receive_queue_depth: Gauge::default(),
+            sent_payload_bytes: histogram(),
+            received_payload_bytes: histogram(),
+            sent_fanout_groups: histogram(),
+            received_fanout_groups: histogram(),
+            write_latency_us: histogram(),
+            send_latency_us: histogram(),
+            receive_latency_us: histogram(),
+            group_messages_sent: Family::default(),
+            group_bytes_sent: Family::default(),
+            group_messages_received: Family::default(),
+            group_bytes_received: Family::default()
+        };
+
+        let registry = client_registry(registry, private_name);
+        registry.register("messages_sent", "Data messages multicast by the client",
+                          metrics.messages_sent.clone());
+        registry.register("bytes_sent", "Payload bytes multicast by the client",
+                          metrics.bytes_sent.clone());
+        registry.register("messages_received", "Messages received by the client",
+                          metrics.messages_received.clone());
+        registry.register("bytes_received", "Payload bytes received by the client",
+                          metrics.bytes_received.clone());
+        registry.register("reconnects", "Times the session has been re-established",
+                          metrics.reconnects.clone());
+        registry.register("buffered_bytes", "Bytes held in the client's queues and buffers",
+                          metrics.buffered_bytes.clone());
+        registry.register("receive_queue_depth",
+                          "Received messages waiting to be returned by receive",
+                          metrics.receive_queue_depth.clone());
+        registry.register("sent_payload_bytes", "Payload sizes of multicast messages",
+                          metrics.sent_payload_bytes.clone());
+        registry.register("received_payload_bytes", "Payload sizes of received messages",
+                          metrics.received_payload_bytes.clone());
+        registry.register("sent_fanout_groups", "Destination group counts of multicast messages",
+                          metrics.sent_fanout_groups.clone());
+        registry.register("received_fanout_groups",
+                          "Destination group counts of received messages",
+                          metrics.received_fanout_groups.clone());
+        registry.register("write_latency_microseconds",
+                          "Time taken to write each multicast frame",
+                          metrics.write_latency_us.clone());
+        registry.register("send_latency_microseconds",
+                          "Time from multicasting a message to receiving its own copy, while probing",
+                          metrics.send_latency_us.clone());
+        registry.register("receive_latency_microseconds",
+                          "Time from another member multicasting a message to receiving it, while probing",
+                          metrics.receive_latency_us.clone());
+        registry.register("group_messages_sent", "Data messages multicast to a group",
+                          metrics.group_messages_sent.clone());
+        registry.register("group_bytes_sent", "Payload bytes multicast to a group",
+                          metrics.group_bytes_sent.clone());
+        registry.register("group_messages_received",
+                          "Messages received that were addressed to a group",
+                          metrics.group_messages_received.clone());
+        registry.register("group_bytes_received",
+                          "Payload bytes received that were addressed to a group",
+                          metrics.group_bytes_received.clone());
+        metrics
+    }
+
+    pub fn record_send(&self, groups: &[&str], bytes: usize) {
+        self.messages_sent.inc();
+        self.bytes_sent.inc_by(bytes as u64);
+        self.sent_payload_bytes.observe(bytes as f64);
+        self.sent_fanout_groups.observe(groups.len() as f64);
+        for group in groups.iter() {
+            let label = group_label(group);
+            self.group_messages_sent.get_or_create(&label).inc();
+            self.group_bytes_sent.get_or_create(&label).inc_by(bytes as u64);
+        }
+    }
+
+    pub fn record_receive(&self, groups: &[String], bytes: usize) {
+        self.record_membership_receive(bytes);
+        self.received_payload_bytes.observe(bytes as f64);
+        self.received_fanout_groups.observe(groups.len() as f64);
+        for group in groups.iter() {
+            let label = group_label(group.as_str().trim_end_matches('\0'));
+            self.group_messages_received.get_or_create(&label).inc();
+            self.group_bytes_received.get_or_create(&label).inc_by(bytes as u64);
+        }
+    }
+
+    pub fn record_membership_receive(&self, bytes: usize) {
+        self.messages_received.inc();
+        self.bytes_received.inc_by(bytes as u64);
+    }
+
+    pub fn record_write_latency(&self, micros: u64) {
+        self.write_latency_us.observe(micros as f64);
+    }
+
+    pub fn record_send_latency(&self, micros: u64) {
+        self.send_latency_us.observe(micros as f64);
+    }
+
+    pub fn record_receive_latency(&self, micros: u64) {
+        self.receive_latency_us.observe(micros as f64);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.inc();
+    }
+
+    pub fn set_buffers(&self, receive_queue_depth: usize, buffered_bytes: usize) {
+        self.receive_queue_depth.set(receive_queue_depth as i64);
+        self.buffered_bytes.set(buffered_bytes as i64);
+    }
+}
+
+/// Membership churn gauges, from `MembershipTracker::churn_all`, as
+/// registered with a registry.
+#[derive(Clone, Debug)]
+pub struct ChurnMetrics {
+    joins: Family<GroupLabel, Gauge>,
+    leaves: Family<GroupLabel, Gauge>,
+    view_changes: Family<GroupLabel, Gauge>,
+    average_members: Family<GroupLabel, Gauge<f64, AtomicU64>>
+}
+
+impl ChurnMetrics {
+    /// Create the churn gauges of the client named `private_name` and
+    /// register them with `registry`.
+    pub fn register(registry: &mut Registry, private_name: &str) -> ChurnMetrics {
+        let metrics = ChurnMetrics {
+            joins: Family::default(),
+            leaves: Family::default(),
+            view_changes: Family::default(),
+            average_members: Family::default()
+        };
+        let registry = client_registry(registry, private_name);
+        registry.register("group_member_joins",
+                          "Members that joined a group within the churn window",
+                          metrics.joins.clone());
+        registry.register("group_member_leaves",
+                          "Members that left a group within the churn window",
+                          metrics.leaves.clone());
+        registry.register("group_view_changes",
+                          "Membership view changes within the churn window",
+                          metrics.view_changes.clone());
+        registry.register("group_average_members",
+                          "Mean members per view within the churn window",
+                          metrics.average_members.clone());
+        metrics
+    }
+
+    /// Replace the gauges with `churn`, dropping groups no longer tracked.
+    pub fn update(&self, churn: &[GroupChurn]) {
+        self.joins.clear();
+        self.leaves.clear();
+        self.view_changes.clear();
+        self.average_members.clear();
+        for group in churn.iter() {
+            let label = group_label(group.group.as_str());
+            self.joins.get_or_create(&label).set(group.joins as i64);
+            self.leaves.get_or_create(&label).set(group.leaves as i64);
+            self.view_changes.get_or_create(&label).set(group.view_changes as i64);
+            self.average_members.get_or_create(&label).set(group.average_members);
+        }
+    }
+}
+
+// The sub-registry every metric of the client named `private_name` is
+// registered in.
+fn client_registry<'a>(registry: &'a mut Registry, private_name: &str) -> &'a mut Registry {
+    registry.sub_registry_with_prefix("spread")
+        .sub_registry_with_label(("client".into(), private_name.to_string().into()))
+}
+
+// A histogram with the same power-of-two bounds as `stats::Histogram`.
+fn histogram() -> Histogram {
+    Histogram::new(exponential_buckets(1.0, 2.0, (HISTOGRAM_BUCKETS - 1) as u16))
+}
+
+fn group_label(group: &str) -> GroupLabel {
+    [("group", group.to_string())]
+}
@@ -0,0 +1,180 @@
+//! Reaching a daemon through a SOCKS5 or HTTP CONNECT proxy.
+//!
+//! Only available when the crate is built with the `proxy` feature. Set a
+//! proxy with `ConnectOptions::via_proxy`. The proxy tunnel is established
+//! before the Spread handshake begins, so the daemon sees an ordinary
+//! client connection, and reconnects go through the same proxy.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use util::{base64_encode, read_byte, read_bytes};
+
+/// The protocol spoken by a proxy.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProxyKind {
+    Socks5,
+    HttpConnect
+}
+
+/// How to reach a proxy and, optionally, authenticate with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    /// The proxy's address, in `host:port` form.
+    pub address: String,
+    /// A username and password to present to the proxy.
+    pub credentials: Option<(String, String)>
+}
+
+impl ProxyConfig {
+    pub fn socks5(address: &str) -> ProxyConfig {
+        ProxyConfig { kind: ProxyKind::Socks5, address: address.to_string(), credentials: None }
+    }
+
+    pub fn http_connect(address: &str) -> ProxyConfig {
+        ProxyConfig { kind: ProxyKind::HttpConnect, address: address.to_string(), credentials: None }
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> ProxyConfig {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+}
+
+/// Connect to the proxy described by `config` and ask it to open a tunnel to
+/// `host:port`. The host name is resolved by the proxy.
+pub fn open_tunnel(config: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(config.address.as_str())?;
+    debug!("Opening {:?} tunnel to {}:{} via {}", config.kind, host, port, config.address);
+    handshake(&mut stream, config, host, port)?;
+    Ok(stream)
+}
+
+/// Ask the proxy at the other end of `stream` to open a tunnel to
+/// `host:port`, leaving `stream` positioned at the start of the tunnelled
+/// bytes.
+pub fn handshake<S: Read + Write>(stream: &mut S, config: &ProxyConfig, host: &str, port: u16)
+                                  -> io::Result<()> {
+    match config.kind {
+        ProxyKind::Socks5 => socks5_handshake(stream, config, host, port),
+        ProxyKind::HttpConnect => http_connect_handshake(stream, config, host, port)
+    }
+}
+
+fn proxy_error(desc: &'static str, detail: Option<String>) -> io::Error {
+    match detail {
+        Some(detail) => io::Error::other(format!("{}: {}", desc, detail)),
+        None => io::Error::other(desc)
+    }
+}
+
+fn socks5_handshake<S: Read + Write>(
+    stream: &mut S,
+    config: &ProxyConfig,
+    host: &str,
+    port: u16
+) -> io::Result<()> {
+    // Offer "no authentication", plus username/password if we have them.
+    let greeting = match config.credentials {
+        Some(_) => vec!(5u8, 2, 0x00, 0x02),
+        None => vec!(5u8, 1, 0x00)
+    };
+    stream.write_all(greeting.as_slice())?;
+
+    let choice = read_bytes(stream, 2)?;
+    if choice[0] != 5 {
+        return Err(proxy_error("Proxy is not a SOCKS5 server", None));
+    }
+    match (choice[1], &config.credentials) {
+        (0x00, _) => (),
+        (0x02, &Some((ref username, ref password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(proxy_error("SOCKS5 credentials too long", None));
+            }
+            let mut auth = vec!(1u8, username.len() as u8);
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(auth.as_slice())?;
+
+            let status = read_bytes(stream, 2)?;
+            if status[1] != 0 {
+                return Err(io::Error::new(
+                    ErrorKind::ConnectionRefused,
+                    "SOCKS5 proxy rejected credentials"
+                ));
+            }
+        },
+        (method, _) => return Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("SOCKS5 proxy offered no acceptable authentication method: {}", method)
+        ))
+    }
+
+    // Request a tunnel to the target by domain name.
+    if host.len() > 255 {
+        return Err(proxy_error("Target host name too long for SOCKS5", None));
+    }
+    let mut request = vec!(5u8, 1, 0, 3, host.len() as u8);
+    request.extend_from_slice(host.as_bytes());
+    request.push((port >> 8) as u8);
+    request.push((port & 0xff) as u8);
+    stream.write_all(request.as_slice())?;
+
+    let reply = read_bytes(stream, 4)?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("SOCKS5 proxy refused to open tunnel: reply code {}", reply[1])
+        ));
+    }
+
+    // Skip the bound address and port.
+    let addr_len = match reply[3] {
+        1 => 4,
+        3 => read_byte(stream)? as usize,
+        4 => 16,
+        other => return Err(proxy_error(
+            "SOCKS5 proxy sent unknown address type",
+            Some(format!("{}", other))
+        ))
+    };
+    read_bytes(stream, addr_len + 2)?;
+    Ok(())
+}
+
+fn http_connect_handshake<S: Read + Write>(
+    stream: &mut S,
+    config: &ProxyConfig,
+    host: &str,
+    port: u16
+) -> io::Result<()> {
+    let mut request = format!("CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n", host, port);
+    if let Some((ref username, ref password)) = config.credentials {
+        let token = base64_encode(format!("{}:{}", username, password).as_bytes());
+        request.push_str(format!("Proxy-Authorization: Basic {}\r\n", token).as_str());
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // Read the response headers one byte at a time so that no bytes of the
+    // tunnelled stream are consumed.
+    let mut response: Vec<u8> = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        response.push(read_byte(stream)?);
+        if response.len() > 8192 {
+            return Err(proxy_error("HTTP proxy response headers too long", None));
+        }
+    }
+
+    let response = String::from_utf8_lossy(response.as_slice()).into_owned();
+    let status_line = response.as_str().lines().next().unwrap_or("");
+    let status = status_line.split(' ').nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        return Err(io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("HTTP proxy refused to open tunnel: {}", status_line)
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Client-side send quotas per group or group-name prefix.
+
+use time::{Duration, Timespec};
+use logging::{Level, LogCrateSink, LogSink};
+
+/// How a client enforces an exceeded quota.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum QuotaAction {
+    /// Fail the send with `Error::QuotaExceeded`.
+    Reject,
+    /// Block the send until the quota's interval rolls over.
+    Delay,
+    /// Log a warning and send anyway.
+    Log
+}
+
+/// A limit on messages and bytes sent per interval.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SendQuota {
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub interval: Duration,
+    pub action: QuotaAction
+}
+
+impl SendQuota {
+    /// No limits yet; add them with `messages` and `bytes`.
+    pub fn per(interval: Duration, action: QuotaAction) -> SendQuota {
+        SendQuota { max_messages: None, max_bytes: None, interval: interval, action: action }
+    }
+
+    pub fn messages(mut self, max: u64) -> SendQuota {
+        self.max_messages = Some(max);
+        self
+    }
+
+    pub fn bytes(mut self, max: u64) -> SendQuota {
+        self.max_bytes = Some(max);
+        self
+    }
+}
+
+/// The outcome of checking a send against the quotas.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuotaDecision {
+    /// Send now; the usage has been charged.
+    Allow,
+    /// Wait this long, then `charge` the send.
+    Delay(Duration),
+    /// Do not send; the string names the exceeded quota.
+    Reject(String)
+}
+
+#[derive(Clone)]
+struct Usage {
+    window_start: Timespec,
+    messages: u64,
+    bytes: u64
+}
+
+// A quota applies to the group named `key`, or to every group starting with
+// `key` if `prefix` is set.
+struct Rule {
+    key: String,
+    prefix: bool,
+    quota: SendQuota,
+    usage: Option<Usage>
+}
+
+impl Rule {
+    fn applies_to(&self, group: &str) -> bool {
+        if self.prefix { group.starts_with(self.key.as_str()) } else { group == self.key.as_str() }
+    }
+
+    fn describe(&self) -> String {
+        format!("{}{}", self.key, if self.prefix { "*" } else { "" })
+    }
+
+    fn roll(&mut self, now: Timespec) {
+        let expired = match self.usage {
+            Some(ref usage) => now >= usage.window_start + self.quota.interval,
+            None => true
+        };
+        if expired {
+            self.usage = Some(Usage { window_start: now, messages: 0, bytes: 0 });
+        }
+    }
+
+    fn would_exceed(&self, bytes: u64) -> bool {
+        let usage = self.usage.as_ref().unwrap();
+        self.quota.max_messages.is_some_and(|max| usage.messages + 1 > max)
+            || self.quota.max_bytes.is_some_and(|max| usage.bytes + bytes > max)
+    }
+
+    fn charge(&mut self, bytes: u64) {
+        let usage = self.usage.as_mut().unwrap();
+        usage.messages += 1;
+        usage.bytes += bytes;
+    }
+}
+
+/// A set of send quotas checked before every multicast.
+pub struct Quotas {
+    rules: Vec<Rule>
+}
+
+impl Quotas {
+    pub fn new() -> Quotas {
+        Quotas { rules: Vec::new() }
+    }
+
+    /// Limit sends to `group`.
+    pub fn limit_group(&mut self, group: &str, quota: SendQuota) {
+        self.rules.push(Rule { key: group.to_string(), prefix: false, quota: quota, usage: None });
+    }
+
+    /// Limit sends to all groups starting with `prefix` together, e.g. a
+    /// tenant's namespace.
+    pub fn limit_prefix(&mut self, prefix: &str, quota: SendQuota) {
+        self.rules.push(Rule { key: prefix.to_string(), prefix: true, quota: quota, usage: None });
+    }
+
+    /// Check a send of `bytes` to `groups` at `now`, charging it to every
+    /// applicable quota if it is allowed. A message sent to several groups
+    /// under one quota counts once.
+    pub fn admit(&mut self, now: Timespec, groups: &[&str], bytes: usize) -> QuotaDecision {
+        self.admit_logging_to(now, groups, bytes, &LogCrateSink)
+    }
+
+    // `admit`, logging breaches of `Log` quotas to `logger`, e.g. that of
+    // the client making the send.
+    pub fn admit_logging_to(&mut self, now: Timespec, groups: &[&str], bytes: usize,
+                            logger: &dyn LogSink) -> QuotaDecision {
+        let mut delay: Option<Duration> = None;
+        for rule in self.rules.iter_mut() {
+            if !groups.iter().any(|g| rule.applies_to(g)) {
+                continue;
+            }
+            rule.roll(now);
+            if !rule.would_exceed(bytes as u64) {
+                continue;
+            }
+            match rule.quota.action {
+                QuotaAction::Reject => return QuotaDecision::Reject(rule.describe()),
+                QuotaAction::Delay => {
+                    let wait = rule.usage.as_ref().unwrap().window_start + rule.quota.interval - now;
+                    if delay.is_none_or(|d| wait > d) {
+                        delay = Some(wait);
+                    }
+                },
+                QuotaAction::Log => if logger.enabled(Level::Warn) {
+                    let message = format!("Send quota for \"{}\" exceeded", rule.describe());
+                    logger.log(Level::Warn, message.as_str());
+                }
+            }
+        }
+        match delay {
+            Some(wait) => QuotaDecision::Delay(wait),
+            None => {
+                self.charge(now, groups, bytes);
+                QuotaDecision::Allow
+            }
+        }
+    }
+
+    /// Check `sends`, each a message of some bytes to some groups, as if
+    /// they were made one after another at `now`, charging every one of
+    /// them only if all are allowed. Otherwise nothing is charged, and the
+    /// index of the first send not allowed is returned with its decision.
+    pub fn admit_batch(&mut self, now: Timespec, sends: &[(&[&str], usize)], logger: &dyn LogSink)
+                       -> Result<(), (usize, QuotaDecision)> {
+        let saved: Vec<Option<Usage>> = self.rules.iter().map(|rule| rule.usage.clone()).collect();
+        for (i, &(groups, bytes)) in sends.iter().enumerate() {
+            match self.admit_logging_to(now, groups, bytes, logger) {
+                QuotaDecision::Allow => {},
+                decision => {
+                    for (rule, usage) in self.rules.iter_mut().zip(saved) {
+                        rule.usage = usage;
+                    }
+                    return Err((i, decision));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Charge a send to every applicable quota without checking it.
+    pub fn charge(&mut self, now: Timespec, groups: &[&str], bytes: usize) {
+        for rule in self.rules.iter_mut() {
+            if groups.iter().any(|g| rule.applies_to(g)) {
+                rule.roll(now);
+                rule.charge(bytes as u64);
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Automatic assignment of partitions to the members of a coordination group.
+//!
+//! Every consumer joins a common coordination group and, whenever the
+//! daemon delivers a new membership view of it, recomputes the assignment
+//! with rendezvous (highest-random-weight) hashing. Spread delivers views in
+//! the same order to every member, and the assignment depends only on the
+//! view, so all members agree on it without exchanging further messages.
+//! Adding or removing a member only moves the partitions it gains or loses.
+
+use std::collections::BTreeMap;
+use shard::ShardedGroup;
+use util::fnv1a;
+use {Error, SpreadClient};
+
+/// Assign each of `partitions` partitions to one of `members`.
+pub fn assign_partitions(members: &[String], partitions: u32) -> BTreeMap<String, Vec<u32>> {
+    let mut assignment: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for member in members.iter() {
+        assignment.insert(member.clone(), Vec::new());
+    }
+    for partition in 0..partitions {
+        let owner = members.iter().max_by_key(|member| weight(member.as_str(), partition));
+        if let Some(owner) = owner {
+            assignment.get_mut(owner).unwrap().push(partition);
+        }
+    }
+    assignment
+}
+
+fn weight(member: &str, partition: u32) -> u64 {
+    fnv1a(format!("{}/{}", member, partition).as_bytes())
+}
+
+/// Keeps a client's owned partitions in line with the coordination group's
+/// membership.
+pub struct Rebalancer {
+    sharded: ShardedGroup,
+    coordination_group: String
+}
+
+impl Rebalancer {
+    pub fn new(sharded: ShardedGroup, coordination_group: &str) -> Rebalancer {
+        Rebalancer { sharded: sharded, coordination_group: coordination_group.to_string() }
+    }
+
+    /// Join the coordination group. Partitions are acquired once the first
+    /// view is passed to `on_view`.
+    pub fn start(&mut self, client: &mut SpreadClient) -> Result<(), Error> {
+        client.join(self.coordination_group.as_str())
+    }
+
+    pub fn coordination_group(&self) -> &str {
+        self.coordination_group.as_str()
+    }
+
+    /// Apply a new view of the coordination group, joining and leaving
+    /// partition groups as needed. Returns the partitions now owned.
+    pub fn on_view(&mut self, client: &mut SpreadClient, members: &[String]) -> Result<Vec<u32>, Error> {
+        let assignment = assign_partitions(members, self.sharded.partitions());
+        let mine = assignment.get(&client.private_name).cloned().unwrap_or_default();
+        debug!("Rebalancing \"{}\": {} member(s), owning partitions {:?}",
+               self.coordination_group, members.len(), mine);
+        self.sharded.own(client, mine.as_slice())?;
+        Ok(mine)
+    }
+
+    pub fn sharded_group(&self) -> &ShardedGroup {
+        &self.sharded
+    }
+}
@@ -0,0 +1,17 @@
+//! Confirmation that the daemon accepted a multicast.
+
+use time::Duration;
+use envelope::UniqueId;
+
+/// Returned by `SpreadClient::send_with_receipt` once the client has
+/// received its own copy of a message, showing the daemon accepted it and
+/// placed it in the groups' delivery order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Receipt {
+    /// The unique ID the message was stamped with.
+    pub id: UniqueId,
+    /// The groups the message was sent to.
+    pub groups: Vec<String>,
+    /// Time from sending the message to receiving the copy.
+    pub round_trip: Duration
+}
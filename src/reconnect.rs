@@ -0,0 +1,28 @@
+//! Re-establishing a session after the connection to the daemon breaks.
+//!
+//! A client made by `connect` remembers the address it dialed; one made
+//! over another transport can be given a way to open a new one with
+//! `SpreadClient::set_dialer`. `SpreadClient::reconnect` then performs the
+//! handshake again under the same private name, re-joins every group the
+//! client belonged to and re-sends unacknowledged messages from the resend
+//! buffer (see the `inflight` module). With `set_auto_reconnect`, receives
+//! and sends that find the connection closed reconnect by themselves,
+//! reporting each step to the hook set with `on_reconnect`.
+
+use Error;
+
+/// Something that happened while re-establishing a session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReconnectEvent {
+    /// The connection to the daemon was lost and reconnecting has begun.
+    Lost { error: Error },
+    /// Reconnect attempt `attempt`, counting from 1, failed.
+    AttemptFailed { attempt: u32, error: Error },
+    /// The session was re-established under the private group
+    /// `private_name` after `attempts` attempts, and `groups` were joined
+    /// again.
+    Reconnected { attempts: u32, private_name: String, groups: Vec<String> },
+    /// Every attempt the retry policy allows failed, so the client stays
+    /// disconnected.
+    GaveUp { attempts: u32, error: Error }
+}
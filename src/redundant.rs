@@ -0,0 +1,182 @@
+//! Redundant publishing and receiving through sessions on two daemons.
+//!
+//! A `DualWriter` sends every message through both sessions, wrapping it in
+//! an envelope whose message ID is the same on both copies, so that the
+//! failure of either daemon does not lose in-flight traffic. A
+//! `RedundantReceiver` consumes the same groups through two sessions and
+//! uses the ID to deliver each logical message once.
+//!
+//! The sessions should not have sequence stamping turned on, since each
+//! would then stamp its copy with its own sequence number.
+
+use std::collections::{HashSet, VecDeque};
+use time::precise_time_ns;
+use envelope::Envelope;
+use sync::{channel, Receiver, Sender};
+use threads::ThreadOptions;
+use util::fnv1a;
+use {Error, Level, SpreadClient, SpreadMessage};
+
+/// Publishes each message through two sessions.
+pub struct DualWriter {
+    primary: SpreadClient,
+    secondary: SpreadClient,
+    next_id: u64
+}
+
+impl DualWriter {
+    /// Publish through `primary` and `secondary`, which should be connected
+    /// to different daemons.
+    pub fn new(primary: SpreadClient, secondary: SpreadClient) -> DualWriter {
+        // Start IDs at a value unlikely to be shared with any other writer.
+        let seed = format!("{}/{}/{}", primary.private_name, secondary.private_name, precise_time_ns());
+        DualWriter { primary: primary, secondary: secondary, next_id: fnv1a(seed.as_bytes()) }
+    }
+
+    /// Send `data` to `groups` through both sessions. Succeeds if at least
+    /// one copy was sent; if both fail, the primary's error is returned.
+    pub fn multicast(&mut self, groups: &[&str], data: &[u8]) -> Result<(), Error> {
+        let mut envelope = match Envelope::decode(data) {
+            Some(envelope) => envelope,
+            None => Envelope::new(data)
+        };
+        envelope.set_message_id(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        let enveloped = envelope.encode()?;
+
+        let primary = self.primary.multicast(groups, enveloped.as_slice());
+        let secondary = self.secondary.multicast(groups, enveloped.as_slice());
+        match (primary, secondary) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(error), Ok(())) => {
+                client_log!(self.primary, Level::Warn, "Dual write degraded to one session: {}", error);
+                Ok(())
+            },
+            (Ok(()), Err(error)) => {
+                client_log!(self.secondary, Level::Warn, "Dual write degraded to one session: {}", error);
+                Ok(())
+            },
+            (Err(error), Err(_)) => Err(error)
+        }
+    }
+
+    pub fn primary(&mut self) -> &mut SpreadClient {
+        &mut self.primary
+    }
+
+    pub fn secondary(&mut self) -> &mut SpreadClient {
+        &mut self.secondary
+    }
+
+    /// Disconnect both sessions.
+    pub fn disconnect(mut self) -> Result<(), Error> {
+        let secondary = self.secondary.disconnect();
+        self.primary.disconnect()?;
+        secondary.map(|_| ())
+    }
+}
+
+/// Remembers the IDs of recently seen messages.
+pub struct Deduplicator {
+    capacity: usize,
+    order: VecDeque<u64>,
+    seen: HashSet<u64>
+}
+
+impl Deduplicator {
+    /// Remember the last `capacity` message IDs.
+    pub fn new(capacity: usize) -> Deduplicator {
+        Deduplicator { capacity: capacity, order: VecDeque::new(), seen: HashSet::new() }
+    }
+
+    /// Returns false if a message with the same ID was seen recently.
+    /// Messages without an ID are always new.
+    pub fn is_new(&mut self, message: &SpreadMessage) -> bool {
+        let id = match Envelope::decode(message.data.as_slice()).and_then(|e| e.message_id()) {
+            Some(id) => id,
+            None => return true
+        };
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        while self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.seen.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// Receives the same groups through two sessions, delivering each message
+/// published by a `DualWriter` once.
+///
+/// Each session is read on its own thread, so either daemon may fail
+/// without stalling delivery from the other. Messages that carry no message
+/// ID cannot be deduplicated and are delivered as often as they arrive.
+pub struct RedundantReceiver {
+    messages: Receiver<SpreadMessage>,
+    deduplicator: Deduplicator
+}
+
+impl RedundantReceiver {
+    /// Join `groups` on both sessions and start reading from them,
+    /// remembering the last `capacity` message IDs.
+    pub fn spawn(
+        primary: SpreadClient,
+        secondary: SpreadClient,
+        groups: &[&str],
+        capacity: usize
+    ) -> Result<RedundantReceiver, Error> {
+        RedundantReceiver::spawn_with_threads(primary, secondary, groups, capacity, ThreadOptions::new())
+    }
+
+    /// As `spawn`, starting the reading threads, named `redundant-primary`
+    /// and `redundant-secondary`, with `threads`.
+    pub fn spawn_with_threads(
+        primary: SpreadClient,
+        secondary: SpreadClient,
+        groups: &[&str],
+        capacity: usize,
+        threads: ThreadOptions
+    ) -> Result<RedundantReceiver, Error> {
+        let (sender, messages) = channel();
+        let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+        let clients = vec!(("redundant-primary", primary), ("redundant-secondary", secondary));
+        for (role, client) in clients.into_iter() {
+            let mut client = client;
+            for group in groups.iter() {
+                client.join(group.as_str())?;
+            }
+            spawn_reader(&threads, role, client, sender.clone());
+        }
+        Ok(RedundantReceiver { messages: messages, deduplicator: Deduplicator::new(capacity) })
+    }
+
+    /// Block until the next message not seen through the other session.
+    /// Fails once both sessions have failed.
+    pub fn receive(&mut self) -> Result<SpreadMessage, Error> {
+        loop {
+            let message = self.messages.recv().map_err(|_| Error::Disconnected)?;
+            if self.deduplicator.is_new(&message) {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+fn spawn_reader(threads: &ThreadOptions, role: &str, mut client: SpreadClient,
+                sender: Sender<SpreadMessage>) {
+    threads.spawn(role, move || {
+        loop {
+            match client.receive() {
+                Ok(message) => if sender.send(message).is_err() { break },
+                Err(error) => {
+                    client_log!(client, Level::Warn,
+                                "Redundant session \"{}\" failed: {}", client.private_name, error);
+                    break;
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,167 @@
+//! Relaying group traffic between two daemons, e.g. in different sites.
+//!
+//! A relay holds two sessions with each daemon: one receiving traffic on the
+//! configured groups and one multicasting what arrives from the other side.
+//! Every forwarded message is enveloped and tagged with the relay's id, so a
+//! message that comes back around (through this relay or a chain of relays)
+//! is recognized and not forwarded again.
+
+use std::sync::Arc;
+use envelope::{Envelope, TAG_ORIGIN, TAG_RELAY_PATH};
+use sync::{AtomicBool, Ordering};
+use sync::thread::JoinHandle;
+use threads::ThreadOptions;
+use {Level, SpreadClient, SpreadMessage, MEMBERSHIP_MESS};
+
+/// Settings for a `Relay`.
+#[derive(Clone, Debug)]
+pub struct RelayConfig {
+    /// Identifies this relay in forwarded messages. Must be unique among
+    /// relays that can see each other's traffic, and must not contain a
+    /// newline.
+    pub relay_id: String,
+    /// Groups forwarded in both directions.
+    pub groups: Vec<String>,
+    /// Messages that have already passed through this many relays are not
+    /// forwarded further.
+    pub max_hops: usize,
+    /// Options for the two forwarding threads, named `relay-forward` and
+    /// `relay-reverse`.
+    pub threads: ThreadOptions
+}
+
+impl RelayConfig {
+    pub fn new(relay_id: &str, groups: &[&str]) -> RelayConfig {
+        RelayConfig {
+            relay_id: relay_id.to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            max_hops: 8,
+            threads: ThreadOptions::new()
+        }
+    }
+}
+
+/// A running relay between two daemons.
+pub struct Relay {
+    shutdown: Arc<AtomicBool>,
+    forward: JoinHandle<()>,
+    reverse: JoinHandle<()>
+}
+
+impl Relay {
+    /// Start relaying the configured groups between site A and site B. Each
+    /// site needs a receiving and a sending session, so that neither
+    /// direction blocks the other.
+    pub fn spawn(
+        a_receiver: SpreadClient,
+        a_sender: SpreadClient,
+        b_receiver: SpreadClient,
+        b_sender: SpreadClient,
+        config: RelayConfig
+    ) -> Relay {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let forward = spawn_direction("relay-forward", a_receiver, b_sender, config.clone(),
+                                      shutdown.clone());
+        let reverse = spawn_direction("relay-reverse", b_receiver, a_sender, config, shutdown.clone());
+        Relay { shutdown: shutdown, forward: forward, reverse: reverse }
+    }
+
+    /// Ask both directions to stop and wait for them. Each direction notices
+    /// the request after its next received message.
+    pub fn shutdown(self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.forward.join();
+        let _ = self.reverse.join();
+    }
+}
+
+/// The payload `relay_id` should forward for `message`, or `None` if the
+/// message has already passed through this relay or too many others, or
+/// its relay path has outgrown the envelope.
+pub fn relay_payload(relay_id: &str, max_hops: usize, message: &SpreadMessage) -> Option<Vec<u8>> {
+    let mut envelope = match Envelope::decode(message.data.as_slice()) {
+        Some(envelope) => envelope,
+        None => Envelope::new(message.data.as_slice())
+    };
+
+    let mut path: Vec<String> = match envelope.field(TAG_RELAY_PATH) {
+        Some(value) => String::from_utf8_lossy(value).as_ref()
+            .split('\n')
+            .map(|id| id.to_string())
+            .collect(),
+        None => Vec::new()
+    };
+    if path.len() >= max_hops || path.iter().any(|id| id.as_str() == relay_id) {
+        return None;
+    }
+    path.push(relay_id.to_string());
+    envelope.set_field(TAG_RELAY_PATH, path.join("\n").as_bytes());
+
+    if envelope.field(TAG_ORIGIN).is_none() {
+        let origin = message.sender.as_str().trim_end_matches('\0');
+        envelope.set_field(TAG_ORIGIN, origin.as_bytes());
+    }
+    match envelope.encode() {
+        Ok(payload) => Some(payload),
+        Err(error) => {
+            warn!("Relay {} can't forward message from {}: {}",
+                  relay_id, message.sender.as_str().trim_end_matches('\0'), error);
+            None
+        }
+    }
+}
+
+fn spawn_direction(
+    role: &str,
+    mut receiver: SpreadClient,
+    mut sender: SpreadClient,
+    config: RelayConfig,
+    shutdown: Arc<AtomicBool>
+) -> JoinHandle<()> {
+    let threads = config.threads.clone();
+    threads.spawn(role, move || {
+        relay(&mut receiver, &mut sender, &config, &shutdown);
+        let _ = receiver.disconnect();
+        let _ = sender.disconnect();
+    })
+}
+
+fn relay(receiver: &mut SpreadClient, sender: &mut SpreadClient, config: &RelayConfig,
+         shutdown: &AtomicBool) {
+    for group in config.groups.iter() {
+        if let Err(error) = receiver.join(group.as_str()) {
+            client_log!(receiver, Level::Error,
+                        "Relay {} failed to join group \"{}\": {}", config.relay_id, group, error);
+            return;
+        }
+    }
+
+    while !shutdown.load(Ordering::SeqCst) {
+        let message = match receiver.receive() {
+            Ok(message) => message,
+            Err(error) => {
+                client_log!(receiver, Level::Error, "Relay {} receive failed: {}", config.relay_id, error);
+                return;
+            }
+        };
+        if message.service_type & MEMBERSHIP_MESS != 0 {
+            continue;
+        }
+
+        let groups: Vec<&str> = message.groups.iter()
+            .map(|g| g.as_str().trim_end_matches('\0'))
+            .filter(|g| config.groups.iter().any(|c| c.as_str() == *g))
+            .collect();
+        if groups.is_empty() {
+            continue;
+        }
+        let payload = match relay_payload(config.relay_id.as_str(), config.max_hops, &message) {
+            Some(payload) => payload,
+            None => continue
+        };
+        if let Err(error) = sender.multicast(groups.as_slice(), payload.as_slice()) {
+            client_log!(sender, Level::Warn,
+                        "Relay {} failed to forward to {:?}: {}", config.relay_id, groups, error);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Re-publishing archived messages.
+//!
+//! An archive is a file of `tap` JSON lines, e.g. written by a `JsonTap`
+//! on a recording client. A `Replay` reads it back and multicasts the
+//! messages again, to the groups they were received on or to groups of
+//! the caller's choosing, either at the pace they were originally received
+//! or at a fixed rate, to reproduce realistic load or feed a downstream
+//! consumer again. Messages can be selected by when they were received and
+//! by the groups they were received on. Pacing waits on the client's clock.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use time::{Duration, Timespec};
+use tap::from_json_line;
+use util::glob_match;
+use {Error, Level, ServiceType, SpreadClient, SpreadMessage};
+
+/// How quickly archived messages are re-published.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pacing {
+    /// With the same gaps between them as when they were received.
+    Original,
+    /// At this many messages per second.
+    FixedRate(u32)
+}
+
+/// What a replay did with the archive.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    /// Messages multicast again.
+    pub published: usize,
+    /// Messages outside the time range or groups selected, and membership
+    /// messages, which are never re-published.
+    pub skipped: usize
+}
+
+/// Which archived messages to re-publish, where, and how quickly.
+pub struct Replay {
+    pacing: Pacing,
+    from: Option<Timespec>,
+    until: Option<Timespec>,
+    groups: Vec<String>,
+    targets: Option<Vec<String>>
+}
+
+impl Replay {
+    /// Re-publish every data message in the archive to the groups it was
+    /// received on.
+    pub fn new(pacing: Pacing) -> Replay {
+        Replay { pacing: pacing, from: None, until: None, groups: Vec::new(), targets: None }
+    }
+
+    /// Only re-publish messages received at or after `from`, if given, and
+    /// before `until`, if given.
+    pub fn between(mut self, from: Option<Timespec>, until: Option<Timespec>) -> Replay {
+        self.from = from;
+        self.until = until;
+        self
+    }
+
+    /// Only re-publish messages received on a group matching one of
+    /// `patterns`, in which `*` and `?` are wildcards.
+    pub fn only_groups(mut self, patterns: &[&str]) -> Replay {
+        self.groups = patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Multicast to `groups` instead of the groups each message was
+    /// received on.
+    pub fn to_groups(mut self, groups: &[&str]) -> Replay {
+        self.targets = Some(groups.iter().map(|g| g.to_string()).collect());
+        self
+    }
+
+    /// Re-publish the archive in the file at `path` through `client`.
+    pub fn run_file(&self, client: &mut SpreadClient, path: &Path) -> Result<ReplayReport, Error> {
+        self.run(client, BufReader::new(File::open(path)?))
+    }
+
+    /// Re-publish the archive read from `archive` through `client`, with
+    /// each message's original service and message type. Stops at the
+    /// first line that can't be read or message that can't be sent.
+    pub fn run<R: BufRead>(&self, client: &mut SpreadClient, archive: R) -> Result<ReplayReport, Error> {
+        let mut report = ReplayReport::default();
+        // When the first message was re-published, and when it was received.
+        let mut start: Option<(Timespec, Timespec)> = None;
+        for line in archive.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (received_at, message) = from_json_line(line.as_str())?;
+            if !self.selects(received_at, &message) {
+                report.skipped += 1;
+                continue;
+            }
+            let (started_at, first_received_at) = *start.get_or_insert((client.clock().now(), received_at));
+            let due = match self.pacing {
+                Pacing::Original => started_at + (received_at - first_received_at),
+                Pacing::FixedRate(rate) => {
+                    let interval_ns = 1_000_000_000 / rate.max(1) as i64;
+                    started_at + Duration::nanoseconds(report.published as i64 * interval_ns)
+                }
+            };
+            let now = client.clock().now();
+            if due > now {
+                client.clock().sleep(due - now);
+            }
+            self.publish(client, &message)?;
+            report.published += 1;
+        }
+        client_log!(client, Level::Info, "Replayed {} archived message(s), skipping {}",
+                    report.published, report.skipped);
+        Ok(report)
+    }
+
+    fn selects(&self, received_at: Timespec, message: &SpreadMessage) -> bool {
+        !message.is_membership()
+            && self.from.is_none_or(|from| received_at >= from)
+            && self.until.is_none_or(|until| received_at < until)
+            && (self.groups.is_empty() || message.groups().iter().any(|group| {
+                self.groups.iter().any(|pattern| glob_match(pattern.as_str(), group.as_str()))
+            }))
+    }
+
+    fn publish(&self, client: &mut SpreadClient, message: &SpreadMessage) -> Result<(), Error> {
+        let groups: Vec<&str> = match self.targets {
+            Some(ref targets) => targets.iter().map(|g| g.as_str()).collect(),
+            None => message.groups().iter().map(|g| g.as_str()).collect()
+        };
+        let mess_type = client.message_type();
+        client.set_message_type(message.message_type());
+        let result = client.multicast_with_service(service_for(message.service_type()), groups.as_slice(),
+                                                   message.data.as_slice());
+        client.set_message_type(mess_type);
+        result
+    }
+}
+
+// The strongest service among the bits of a received message's service
+// type, or `Reliable` if it has none.
+fn service_for(service_type: u32) -> ServiceType {
+    [ServiceType::Safe, ServiceType::Agreed, ServiceType::Causal, ServiceType::Fifo,
+     ServiceType::Reliable, ServiceType::Unreliable].iter()
+        .find(|service| service_type & (**service as u32) != 0)
+        .cloned()
+        .unwrap_or(ServiceType::Reliable)
+}
@@ -0,0 +1,118 @@
+//! Receive-side reordering of sequenced messages, with gap detection.
+//!
+//! Messages stamped by a sender's `Sequencer` are released in sequence
+//! order. Out-of-order arrivals are held back within a bounded window; once
+//! the window overflows, the missing range is reported as a gap and
+//! delivery resumes after it. Messages without a sequence number pass
+//! straight through.
+
+use std::collections::{BTreeMap, HashMap};
+use envelope::Envelope;
+use SpreadMessage;
+
+/// Output of a `Resequencer`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ResequencerEvent {
+    /// A message ready for the application, in sender order.
+    Deliver(SpreadMessage),
+    /// Sequence numbers `first..last` (inclusive) from `sender` are
+    /// considered lost.
+    Gap { sender: String, first: u64, last: u64 }
+}
+
+struct SenderState {
+    next_expected: u64,
+    held: BTreeMap<u64, SpreadMessage>
+}
+
+/// Per-sender reordering buffer.
+pub struct Resequencer {
+    window: usize,
+    senders: HashMap<String, SenderState>
+}
+
+impl Resequencer {
+    /// Hold back at most `window` out-of-order messages per sender.
+    pub fn new(window: usize) -> Resequencer {
+        Resequencer { window: window, senders: HashMap::new() }
+    }
+
+    /// Feed a received message, returning the messages and gaps that
+    /// became ready as a result.
+    pub fn push(&mut self, message: SpreadMessage) -> Vec<ResequencerEvent> {
+        let mut events = Vec::new();
+        let sequence = match Envelope::decode(message.data.as_slice()).and_then(|e| e.sequence()) {
+            Some(sequence) => sequence,
+            None => {
+                events.push(ResequencerEvent::Deliver(message));
+                return events;
+            }
+        };
+
+        let window = self.window;
+        let sender = message.sender.clone();
+        if !self.senders.contains_key(&sender) {
+            // The first message seen from a sender defines where its
+            // sequence starts for us.
+            self.senders.insert(sender.clone(), SenderState {
+                next_expected: sequence,
+                held: BTreeMap::new()
+            });
+        }
+        let state = self.senders.get_mut(&sender).unwrap();
+
+        if sequence < state.next_expected || state.held.contains_key(&sequence) {
+            debug!("Dropping duplicate sequence {} from \"{}\"", sequence, sender);
+            return events;
+        }
+        state.held.insert(sequence, message);
+
+        loop {
+            // Release everything contiguous with what was last delivered.
+            while let Some(message) = state.held.remove(&state.next_expected) {
+                events.push(ResequencerEvent::Deliver(message));
+                state.next_expected += 1;
+            }
+            if state.held.len() <= window {
+                break;
+            }
+            // The window overflowed: give up on the missing range.
+            let resume_at = *state.held.keys().next().unwrap();
+            events.push(ResequencerEvent::Gap {
+                sender: sender.clone(),
+                first: state.next_expected,
+                last: resume_at - 1
+            });
+            state.next_expected = resume_at;
+        }
+        events
+    }
+
+    /// The next sequence number expected from `sender`, if any message from
+    /// it has been seen.
+    pub fn next_expected(&self, sender: &str) -> Option<u64> {
+        self.senders.get(sender).map(|state| state.next_expected)
+    }
+
+    /// The next sequence number expected from every sender seen so far.
+    pub fn positions(&self) -> BTreeMap<String, u64> {
+        self.senders.iter()
+            .map(|(sender, state)| (sender.clone(), state.next_expected))
+            .collect()
+    }
+
+    /// Expect `next` as the next sequence number from `sender`, e.g. when
+    /// resuming from a checkpoint. Anything held for it is discarded.
+    pub fn resume(&mut self, sender: &str, next: u64) {
+        self.senders.insert(sender.to_string(), SenderState {
+            next_expected: next,
+            held: BTreeMap::new()
+        });
+    }
+
+    /// Forget all state for `sender`, e.g. after it leaves the group.
+    pub fn forget(&mut self, sender: &str) {
+        self.senders.remove(sender);
+    }
+}
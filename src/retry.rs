@@ -0,0 +1,59 @@
+//! Retrying multicasts that fail with transient errors.
+
+use std::io::ErrorKind;
+use time::Duration;
+use Error;
+
+/// How many times, and how patiently, a failed multicast is retried.
+///
+/// Only data messages are ever retried. Joins, leaves and disconnects are
+/// sent exactly once, since resending them after an ambiguous failure could
+/// change group state behind the caller's back.
+///
+/// `SpreadClient::set_auto_reconnect` paces reconnect attempts with the
+/// same policy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first. `1` disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay, which doubles after every failed retry.
+    pub max_backoff: Duration
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, initial_backoff: Duration, max_backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts,
+            initial_backoff: initial_backoff,
+            max_backoff: max_backoff
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1, Duration::zero(), Duration::zero())
+    }
+
+    /// The delay to wait after `attempt` (counting from 1) has failed.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let mut delay = self.initial_backoff;
+        for _ in 1..attempt {
+            delay = delay + delay;
+            if delay > self.max_backoff {
+                return self.max_backoff;
+            }
+        }
+        delay
+    }
+}
+
+/// Returns true if a multicast that failed with `error` may be safely
+/// retried. Only errors raised before any bytes were written qualify;
+/// a broken or reset connection is not retryable without reconnecting,
+/// and `Error::QuotaExceeded` is a deliberate rejection, not a transient
+/// failure.
+pub fn is_retryable(error: &Error) -> bool {
+    error.is_io(ErrorKind::WouldBlock)
+}
@@ -0,0 +1,95 @@
+//! Locating members and senders on the daemons of a segment.
+//!
+//! Every client's private group name has the form `#<user>#<daemon>`, where
+//! `<daemon>` names the daemon the client is connected to. Grouping members
+//! and traffic by that component shows how a group is spread across the
+//! segment.
+
+use std::collections::{BTreeMap, HashMap};
+use SpreadMessage;
+
+// Split `#user#daemon` into its user and daemon components.
+fn components(private_group: &str) -> Option<(&str, &str)> {
+    let name = private_group.trim_end_matches('\0');
+    if !name.starts_with("#") {
+        return None;
+    }
+    let rest = &name[1..];
+    match rest.find('#') {
+        Some(i) if i + 1 < rest.len() => Some((&rest[..i], &rest[i + 1..])),
+        _ => None
+    }
+}
+
+/// The daemon component of a private group name, or `None` if `private_group`
+/// is not one.
+pub fn daemon_of(private_group: &str) -> Option<&str> {
+    components(private_group).map(|(_, daemon)| daemon)
+}
+
+/// The user component of a private group name, or `None` if `private_group`
+/// is not one.
+pub fn user_of(private_group: &str) -> Option<&str> {
+    components(private_group).map(|(user, _)| user)
+}
+
+/// Group `members` by the daemon each is connected to, in sorted order.
+/// Names that are not private group names are left out.
+pub fn by_daemon(members: &[String]) -> BTreeMap<String, Vec<String>> {
+    let mut grouped: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for member in members.iter() {
+        if let Some(daemon) = daemon_of(member.as_str()) {
+            if !grouped.contains_key(daemon) {
+                grouped.insert(daemon.to_string(), Vec::new());
+            }
+            grouped.get_mut(daemon).unwrap().push(member.clone());
+        }
+    }
+    for members in grouped.values_mut() {
+        members.sort();
+    }
+    grouped
+}
+
+/// Messages and payload bytes received from the senders on one daemon.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DaemonTraffic {
+    pub messages: u64,
+    pub bytes: u64
+}
+
+/// Tallies received messages by the daemon their sender is connected to.
+pub struct TrafficByDaemon {
+    daemons: HashMap<String, DaemonTraffic>
+}
+
+impl TrafficByDaemon {
+    pub fn new() -> TrafficByDaemon {
+        TrafficByDaemon { daemons: HashMap::new() }
+    }
+
+    /// Count `message` against its sender's daemon. Messages whose sender
+    /// is not a private group name are ignored.
+    pub fn record(&mut self, message: &SpreadMessage) {
+        let daemon = match daemon_of(message.sender.as_str()) {
+            Some(daemon) => daemon,
+            None => return
+        };
+        if !self.daemons.contains_key(daemon) {
+            self.daemons.insert(daemon.to_string(), DaemonTraffic { messages: 0, bytes: 0 });
+        }
+        let traffic = self.daemons.get_mut(daemon).unwrap();
+        traffic.messages += 1;
+        traffic.bytes += message.data.len() as u64;
+    }
+
+    /// Traffic received from senders on `daemon`.
+    pub fn daemon(&self, daemon: &str) -> Option<DaemonTraffic> {
+        self.daemons.get(daemon).copied()
+    }
+
+    /// Traffic received from each daemon, in sorted order.
+    pub fn daemons(&self) -> BTreeMap<String, DaemonTraffic> {
+        self.daemons.iter().map(|(daemon, traffic)| (daemon.clone(), *traffic)).collect()
+    }
+}
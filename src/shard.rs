@@ -0,0 +1,81 @@
+//! Spreading a logical stream over several partition groups by key.
+
+use std::collections::BTreeSet;
+use util::fnv1a;
+use {Error, SpreadClient};
+
+/// A logical group split into `partitions` physical groups named
+/// `<base>-0` through `<base>-<partitions - 1>`.
+///
+/// Senders route each message to a partition by hashing its key, so all
+/// messages with the same key stay in order within one group. Consumers join
+/// only the partitions they own.
+pub struct ShardedGroup {
+    base: String,
+    partitions: u32,
+    owned: BTreeSet<u32>
+}
+
+impl ShardedGroup {
+    pub fn new(base: &str, partitions: u32) -> ShardedGroup {
+        assert!(partitions > 0, "a sharded group needs at least one partition");
+        ShardedGroup { base: base.to_string(), partitions: partitions, owned: BTreeSet::new() }
+    }
+
+    pub fn partitions(&self) -> u32 {
+        self.partitions
+    }
+
+    /// The group name of partition `partition`.
+    pub fn partition_name(&self, partition: u32) -> String {
+        format!("{}-{}", self.base, partition)
+    }
+
+    /// The partition that messages with routing key `key` belong to. The
+    /// mapping is stable across processes and versions of this crate.
+    pub fn partition_for(&self, key: &[u8]) -> u32 {
+        (fnv1a(key) % self.partitions as u64) as u32
+    }
+
+    /// The group name that messages with routing key `key` are sent to.
+    pub fn group_for(&self, key: &[u8]) -> String {
+        self.partition_name(self.partition_for(key))
+    }
+
+    /// Send `data` to the partition owning `key`.
+    pub fn send_keyed(&self, client: &mut SpreadClient, key: &[u8], data: &[u8]) -> Result<(), Error> {
+        let group = self.group_for(key);
+        client.multicast([group.as_str()].as_slice(), data)
+    }
+
+    /// Make `client` own exactly `partitions`: join the partition groups it
+    /// does not yet own and leave the ones it no longer should.
+    pub fn own(&mut self, client: &mut SpreadClient, partitions: &[u32]) -> Result<(), Error> {
+        let wanted: BTreeSet<u32> = partitions.iter().copied()
+            .filter(|p| *p < self.partitions)
+            .collect();
+        let to_leave: Vec<u32> = self.owned.difference(&wanted).copied().collect();
+        let to_join: Vec<u32> = wanted.difference(&self.owned).copied().collect();
+
+        for partition in to_leave.into_iter() {
+            client.leave(self.partition_name(partition).as_str())?;
+            self.owned.remove(&partition);
+        }
+        for partition in to_join.into_iter() {
+            client.join(self.partition_name(partition).as_str())?;
+            self.owned.insert(partition);
+        }
+        Ok(())
+    }
+
+    /// Join every partition.
+    pub fn own_all(&mut self, client: &mut SpreadClient) -> Result<(), Error> {
+        let all: Vec<u32> = (0..self.partitions).collect();
+        self.own(client, all.as_slice())
+    }
+
+    /// The partitions currently owned, in ascending order.
+    pub fn owned(&self) -> Vec<u32> {
+        self.owned.iter().copied().collect()
+    }
+}
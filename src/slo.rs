@@ -0,0 +1,178 @@
+//! Alarms raised when a client's latency, backlog or throughput crosses a
+//! configured threshold.
+
+use error::catch_panic;
+use logging::Level;
+use time::{Duration, Timespec};
+use Error;
+
+/// A measurement a threshold can be set on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SloMetric {
+    /// 99th percentile time to write a multicast frame, in microseconds.
+    SendLatencyP99,
+    /// Messages the application has reported as received but unprocessed.
+    ReceiveBacklog,
+    /// Messages sent per second over the stats rate window.
+    SendRate,
+    /// Messages received per second over the stats rate window.
+    ReceiveRate
+}
+
+impl SloMetric {
+    /// A short name for log messages.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SloMetric::SendLatencyP99 => "p99 send latency (us)",
+            SloMetric::ReceiveBacklog => "receive backlog",
+            SloMetric::SendRate => "send rate (msg/s)",
+            SloMetric::ReceiveRate => "receive rate (msg/s)"
+        }
+    }
+}
+
+/// An upper limit on a metric.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SloThreshold {
+    pub metric: SloMetric,
+    pub limit: f64,
+    /// How long the metric must stay above `limit` before the alarm fires.
+    pub sustain: Duration,
+    /// The level at which a breach is logged.
+    pub level: Level
+}
+
+impl SloThreshold {
+    /// Alarm as soon as `metric` exceeds `limit`, logging at `Warn`.
+    pub fn above(metric: SloMetric, limit: f64) -> SloThreshold {
+        SloThreshold { metric: metric, limit: limit, sustain: Duration::zero(), level: Level::Warn }
+    }
+
+    pub fn sustained_for(mut self, sustain: Duration) -> SloThreshold {
+        self.sustain = sustain;
+        self
+    }
+
+    pub fn escalate_to(mut self, level: Level) -> SloThreshold {
+        self.level = level;
+        self
+    }
+}
+
+/// A metric crossing its threshold.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SloEvent {
+    /// The metric has been above `limit` for the threshold's sustain time.
+    Breached { metric: SloMetric, value: f64, limit: f64, level: Level },
+    /// The metric is back at or below `limit`.
+    Recovered { metric: SloMetric, value: f64, limit: f64 }
+}
+
+/// The values of every metric at one instant.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SloSample {
+    pub send_latency_p99_us: u64,
+    pub receive_backlog: usize,
+    pub send_rate: f64,
+    pub receive_rate: f64
+}
+
+impl SloSample {
+    fn value(&self, metric: SloMetric) -> f64 {
+        match metric {
+            SloMetric::SendLatencyP99 => self.send_latency_p99_us as f64,
+            SloMetric::ReceiveBacklog => self.receive_backlog as f64,
+            SloMetric::SendRate => self.send_rate,
+            SloMetric::ReceiveRate => self.receive_rate
+        }
+    }
+}
+
+struct Alarm {
+    threshold: SloThreshold,
+    over_since: Option<Timespec>,
+    breached: bool
+}
+
+/// Checks samples against thresholds, with callbacks notified whenever an
+/// alarm fires or clears.
+pub struct SloMonitor {
+    alarms: Vec<Alarm>,
+    callbacks: Vec<Box<dyn FnMut(&SloEvent) + Send>>,
+    panics: Vec<Error>
+}
+
+impl SloMonitor {
+    pub fn new() -> SloMonitor {
+        SloMonitor { alarms: Vec::new(), callbacks: Vec::new(), panics: Vec::new() }
+    }
+
+    /// Add a threshold to check every sample against.
+    pub fn watch(&mut self, threshold: SloThreshold) {
+        self.alarms.push(Alarm { threshold: threshold, over_since: None, breached: false });
+    }
+
+    /// Register a callback invoked whenever an alarm fires or clears. A
+    /// callback that panics is still called for later events; the panics
+    /// are returned by `take_panics`.
+    pub fn on_alarm(&mut self, callback: Box<dyn FnMut(&SloEvent) + Send>) {
+        self.callbacks.push(callback);
+    }
+
+    /// Remove and return the panics caught from alarm callbacks, as
+    /// `Error::CallbackPanicked`.
+    pub fn take_panics(&mut self) -> Vec<Error> {
+        ::std::mem::take(&mut self.panics)
+    }
+
+    /// Returns true if any threshold on `metric` is currently breached.
+    pub fn is_breached(&self, metric: SloMetric) -> bool {
+        self.alarms.iter().any(|alarm| alarm.threshold.metric == metric && alarm.breached)
+    }
+
+    /// Check a sample taken at `now`, returning the alarms that fired or
+    /// cleared.
+    pub fn check(&mut self, now: Timespec, sample: &SloSample) -> Vec<SloEvent> {
+        let mut events = Vec::new();
+        for alarm in self.alarms.iter_mut() {
+            let threshold = alarm.threshold;
+            let value = sample.value(threshold.metric);
+            if value > threshold.limit {
+                let since = match alarm.over_since {
+                    Some(since) => since,
+                    None => {
+                        alarm.over_since = Some(now);
+                        now
+                    }
+                };
+                if !alarm.breached && now - since >= threshold.sustain {
+                    alarm.breached = true;
+                    events.push(SloEvent::Breached {
+                        metric: threshold.metric,
+                        value: value,
+                        limit: threshold.limit,
+                        level: threshold.level
+                    });
+                }
+            } else {
+                alarm.over_since = None;
+                if alarm.breached {
+                    alarm.breached = false;
+                    events.push(SloEvent::Recovered {
+                        metric: threshold.metric,
+                        value: value,
+                        limit: threshold.limit
+                    });
+                }
+            }
+        }
+        for event in events.iter() {
+            for callback in self.callbacks.iter_mut() {
+                if let Err(error) = catch_panic("SLO alarm callback", || callback(event)) {
+                    self.panics.push(error);
+                }
+            }
+        }
+        events
+    }
+}
@@ -0,0 +1,137 @@
+//! Active/passive failover between two processes.
+//!
+//! Both processes join a coordination group. The active one multicasts
+//! heartbeats; the passive one feeds every message and membership view to
+//! its `StandbyPair`, and periodically calls `check`. It takes over either
+//! when the daemon reports that the active member left the group, or when
+//! the failure detector suspects it (e.g. the process is hung but its
+//! session is still up), so failover completes within roughly the detector's
+//! threshold times the heartbeat interval. On takeover it multicasts a notice
+//! so that a falsely suspected former active steps down.
+
+use time::Timespec;
+use failure::{FailureDetector, SuspicionEvent};
+use {Error, Level, SpreadClient, SpreadMessage};
+
+static TAKEOVER: &'static [u8] = b"\x00spread-standby-takeover";
+
+/// Which side of the pair this process is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    Active,
+    Passive
+}
+
+/// A change of this process's role.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StandbyEvent {
+    /// This process became active and should start sending/processing.
+    Promoted,
+    /// Another process took over; this one should stop.
+    Demoted
+}
+
+/// One process's view of an active/passive pair.
+pub struct StandbyPair {
+    group: String,
+    me: String,
+    active: Option<String>,
+    detector: FailureDetector
+}
+
+impl StandbyPair {
+    /// Coordinate through `group` as `me` (the client's private group name),
+    /// using `detector` to judge the active member's heartbeats.
+    pub fn new(group: &str, me: &str, detector: FailureDetector) -> StandbyPair {
+        StandbyPair { group: group.to_string(), me: me.to_string(), active: None, detector: detector }
+    }
+
+    /// Join the coordination group. The role is decided by the first view.
+    pub fn start(&self, client: &mut SpreadClient) -> Result<(), Error> {
+        client.join(self.group.as_str())
+    }
+
+    pub fn group(&self) -> &str {
+        self.group.as_str()
+    }
+
+    pub fn role(&self) -> Role {
+        if self.active.as_ref() == Some(&self.me) { Role::Active } else { Role::Passive }
+    }
+
+    /// The member currently considered active, if known.
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// Multicast a heartbeat if this process is active. Call at a regular
+    /// interval.
+    pub fn heartbeat(&self, client: &mut SpreadClient) -> Result<(), Error> {
+        match self.role() {
+            Role::Active => FailureDetector::heartbeat(client, self.group.as_str()),
+            Role::Passive => Ok(())
+        }
+    }
+
+    /// Apply a new membership view of the coordination group. If the active
+    /// member is gone (or none was known yet), the lowest-named member
+    /// becomes active, which every member computes identically.
+    pub fn on_view(&mut self, members: &[String]) -> Option<StandbyEvent> {
+        let was = self.role();
+        let still_present = match self.active {
+            Some(ref active) => members.contains(active),
+            None => false
+        };
+        if !still_present {
+            if let Some(ref active) = self.active {
+                self.detector.remove(active.as_str());
+            }
+            self.active = members.iter().min().cloned();
+        }
+        transition(was, self.role())
+    }
+
+    /// Feed a message received on the coordination group.
+    pub fn on_message(&mut self, now: Timespec, message: &SpreadMessage) -> Option<StandbyEvent> {
+        let sender = message.sender.as_str().trim_end_matches('\0').to_string();
+        if message.data.as_slice() == TAKEOVER {
+            let was = self.role();
+            self.active = Some(sender);
+            return transition(was, self.role());
+        }
+        if self.active.as_ref() == Some(&sender) {
+            self.detector.on_message(now, message);
+        }
+        None
+    }
+
+    /// Take over if the active member's heartbeats are overdue.
+    pub fn check(&mut self, client: &mut SpreadClient, now: Timespec) -> Result<Option<StandbyEvent>, Error> {
+        if self.role() == Role::Active {
+            return Ok(None);
+        }
+        let suspected = self.detector.check(now).into_iter().any(|event| match event {
+            SuspicionEvent::Suspect(member) => self.active.as_ref() == Some(&member),
+            SuspicionEvent::Alive(_) => false
+        });
+        if !suspected {
+            return Ok(None);
+        }
+        client_log!(client, Level::Warn,
+                    "Active member {:?} of \"{}\" is unresponsive; taking over", self.active, self.group);
+        client.multicast([self.group.as_str()].as_slice(), TAKEOVER)?;
+        if let Some(ref active) = self.active {
+            self.detector.remove(active.as_str());
+        }
+        self.active = Some(self.me.clone());
+        Ok(Some(StandbyEvent::Promoted))
+    }
+}
+
+fn transition(was: Role, now: Role) -> Option<StandbyEvent> {
+    match (was, now) {
+        (Role::Passive, Role::Active) => Some(StandbyEvent::Promoted),
+        (Role::Active, Role::Passive) => Some(StandbyEvent::Demoted),
+        _ => None
+    }
+}
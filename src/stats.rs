@@ -0,0 +1,439 @@
+//! Traffic statistics collected by a `SpreadClient`.
+
+use std::collections::{HashMap, VecDeque};
+use time::{Duration, Timespec};
+#[cfg(feature = "prometheus")]
+use prometheus::ClientMetrics;
+use Error;
+
+/// Length of the window over which message rates are computed.
+pub static RATE_WINDOW_SECS: u64 = 10;
+
+/// A point-in-time snapshot of a client's traffic counters.
+#[derive(Clone, Debug)]
+pub struct ClientStats {
+    /// Data messages multicast by the client.
+    pub messages_sent: u64,
+    /// Payload bytes multicast by the client.
+    pub bytes_sent: u64,
+    /// Messages received by the client.
+    pub messages_received: u64,
+    /// Payload bytes received by the client.
+    pub bytes_received: u64,
+    /// Number of times the session has been re-established.
+    pub reconnects: u64,
+    /// Received messages dropped unreturned for being older than the
+    /// client's maximum message age.
+    pub expired_received: u64,
+    /// Sent messages dropped from the resend buffer, or not sent at all,
+    /// because their TTL ran out.
+    pub expired_sent: u64,
+    /// The most recent error encountered by the client, if any.
+    pub last_error: Option<Error>,
+    /// Messages sent per second over the last `RATE_WINDOW_SECS` seconds.
+    pub send_rate: f64,
+    /// Messages received per second over the last `RATE_WINDOW_SECS` seconds.
+    pub receive_rate: f64,
+    /// Payload sizes of multicast messages, in bytes.
+    pub sent_payload_sizes: Histogram,
+    /// Payload sizes of received messages, in bytes.
+    pub received_payload_sizes: Histogram,
+    /// Number of destination groups of multicast messages.
+    pub sent_fanout: Histogram,
+    /// Number of destination groups of received messages.
+    pub received_fanout: Histogram,
+    /// Time taken to write each multicast frame to the transport, in
+    /// microseconds.
+    pub write_latency_us: Histogram,
+    /// Time from multicasting a message to receiving the client's own copy
+    /// of it, in microseconds. Only recorded while latency probing is
+    /// enabled, and only for groups the client is a member of.
+    pub send_latency_us: Histogram,
+    /// Time from another member multicasting a message to this client
+    /// receiving it, in microseconds, by the sender's and this client's
+    /// clocks. Only recorded for messages sent and received with latency
+    /// probing enabled.
+    pub receive_latency_us: Histogram,
+    /// Messages already received from the daemon that `receive` will
+    /// return without waiting on it.
+    pub receive_queue_depth: u64,
+    /// Bytes currently held in the client's send history, resend buffer
+    /// and receive buffer.
+    pub buffered_bytes: u64
+}
+
+/// A summary of a session, returned by `SpreadClient::disconnect`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionSummary {
+    /// How long the session was established.
+    pub duration: Duration,
+    /// Data messages multicast by the client.
+    pub messages_sent: u64,
+    /// Payload bytes multicast by the client.
+    pub bytes_sent: u64,
+    /// Messages received by the client.
+    pub messages_received: u64,
+    /// Payload bytes received by the client.
+    pub bytes_received: u64,
+    /// Number of times the session was re-established.
+    pub reconnects: u64,
+    /// Groups the client was a member of when it disconnected.
+    pub groups: Vec<String>
+}
+
+/// Number of buckets in a `Histogram`. Bucket `i` counts values no greater
+/// than `2^i`; the last bucket also counts anything larger.
+pub static HISTOGRAM_BUCKETS: usize = 32;
+
+/// A histogram of non-negative values with power-of-two bucket bounds.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; HISTOGRAM_BUCKETS],
+            count: 0,
+            sum: 0,
+            max: 0
+        }
+    }
+
+    /// Add a value to the histogram.
+    pub fn record(&mut self, value: u64) {
+        let mut bucket = 0;
+        while bucket < HISTOGRAM_BUCKETS - 1 && value > (1u64 << bucket) {
+            bucket += 1;
+        }
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+        if value > self.max {
+            self.max = value;
+        }
+    }
+
+    /// Number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of all values recorded.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Largest value recorded.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// An upper bound on the `q`-quantile (0.0 to 1.0) of the recorded
+    /// values: the bound of the bucket it falls in, capped at the maximum.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = (q * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank && count > 0 {
+                let bound = 1u64 << i;
+                return if bound < self.max { bound } else { self.max };
+            }
+        }
+        self.max
+    }
+
+    /// Returns `(upper_bound, count)` pairs for each non-empty bucket.
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        self.counts.iter().enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| (1u64 << i, count))
+            .collect()
+    }
+
+    /// Returns `(upper_bound, values_at_or_below)` for every bucket with a
+    /// finite bound, empty or not. The last bucket also holds values past
+    /// its bound, so it has no finite bound and only shows in `count`.
+    pub fn cumulative_buckets(&self) -> Vec<(u64, u64)> {
+        let mut seen = 0;
+        self.counts[..HISTOGRAM_BUCKETS - 1].iter().enumerate()
+            .map(|(i, &count)| {
+                seen += count;
+                (1u64 << i, seen)
+            })
+            .collect()
+    }
+}
+
+/// Traffic counters for a single group.
+#[derive(Clone, Debug)]
+pub struct GroupActivity {
+    /// Data messages multicast to the group by the client.
+    pub messages_sent: u64,
+    /// Payload bytes multicast to the group by the client.
+    pub bytes_sent: u64,
+    /// Messages received by the client that were addressed to the group.
+    pub messages_received: u64,
+    /// Payload bytes received by the client that were addressed to the group.
+    pub bytes_received: u64,
+    /// When a message was last sent to or received from the group.
+    pub last_activity: Timespec
+}
+
+impl GroupActivity {
+    fn new(now: Timespec) -> GroupActivity {
+        GroupActivity {
+            messages_sent: 0,
+            bytes_sent: 0,
+            messages_received: 0,
+            bytes_received: 0,
+            last_activity: now
+        }
+    }
+}
+
+// Counts events in one-second buckets over a sliding window.
+struct RateMeter {
+    buckets: VecDeque<(u64, u64)>
+}
+
+impl RateMeter {
+    fn new() -> RateMeter {
+        RateMeter { buckets: VecDeque::new() }
+    }
+
+    fn mark(&mut self, now_secs: u64) {
+        self.expire(now_secs);
+        let bumped = match self.buckets.back_mut() {
+            Some(bucket) if bucket.0 == now_secs => {
+                bucket.1 += 1;
+                true
+            },
+            _ => false
+        };
+        if !bumped {
+            self.buckets.push_back((now_secs, 1));
+        }
+    }
+
+    fn expire(&mut self, now_secs: u64) {
+        while let Some(&(second, _)) = self.buckets.front() {
+            if second + RATE_WINDOW_SECS > now_secs {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn rate(&self, now_secs: u64) -> f64 {
+        let total = self.buckets.iter()
+            .filter(|&&(second, _)| second + RATE_WINDOW_SECS > now_secs)
+            .fold(0, |acc, &(_, count)| acc + count);
+        total as f64 / RATE_WINDOW_SECS as f64
+    }
+}
+
+// Mutable counters owned by a client, from which snapshots are taken.
+pub struct StatsRecorder {
+    messages_sent: u64,
+    bytes_sent: u64,
+    messages_received: u64,
+    bytes_received: u64,
+    reconnects: u64,
+    expired_received: u64,
+    expired_sent: u64,
+    last_error: Option<Error>,
+    send_meter: RateMeter,
+    receive_meter: RateMeter,
+    groups: HashMap<String, GroupActivity>,
+    sent_payload_sizes: Histogram,
+    received_payload_sizes: Histogram,
+    sent_fanout: Histogram,
+    received_fanout: Histogram,
+    write_latency_us: Histogram,
+    send_latency_us: Histogram,
+    receive_latency_us: Histogram,
+    // Registered metrics updated alongside the counters above.
+    #[cfg(feature = "prometheus")]
+    exported: Option<ClientMetrics>
+}
+
+impl StatsRecorder {
+    pub fn new() -> StatsRecorder {
+        StatsRecorder {
+            messages_sent: 0,
+            bytes_sent: 0,
+            messages_received: 0,
+            bytes_received: 0,
+            reconnects: 0,
+            expired_received: 0,
+            expired_sent: 0,
+            last_error: None,
+            send_meter: RateMeter::new(),
+            receive_meter: RateMeter::new(),
+            groups: HashMap::new(),
+            sent_payload_sizes: Histogram::new(),
+            received_payload_sizes: Histogram::new(),
+            sent_fanout: Histogram::new(),
+            received_fanout: Histogram::new(),
+            write_latency_us: Histogram::new(),
+            send_latency_us: Histogram::new(),
+            receive_latency_us: Histogram::new(),
+            #[cfg(feature = "prometheus")]
+            exported: None
+        }
+    }
+
+    // Update `metrics` with everything recorded from now on.
+    #[cfg(feature = "prometheus")]
+    pub fn export_to(&mut self, metrics: ClientMetrics) {
+        self.exported = Some(metrics);
+    }
+
+    #[cfg(feature = "prometheus")]
+    pub fn exported(&self) -> Option<&ClientMetrics> {
+        self.exported.as_ref()
+    }
+
+    pub fn record_send(&mut self, now: Timespec, groups: &[&str], bytes: usize) {
+        self.messages_sent += 1;
+        self.bytes_sent += bytes as u64;
+        self.send_meter.mark(now.sec as u64);
+        self.sent_payload_sizes.record(bytes as u64);
+        self.sent_fanout.record(groups.len() as u64);
+
+        for group in groups.iter() {
+            let activity = self.group_entry(now, group);
+            activity.messages_sent += 1;
+            activity.bytes_sent += bytes as u64;
+            activity.last_activity = now;
+        }
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_send(groups, bytes);
+        }
+    }
+
+    pub fn record_receive(&mut self, now: Timespec, groups: &[String], bytes: usize) {
+        self.messages_received += 1;
+        self.bytes_received += bytes as u64;
+        self.receive_meter.mark(now.sec as u64);
+        self.received_payload_sizes.record(bytes as u64);
+        self.received_fanout.record(groups.len() as u64);
+
+        for group in groups.iter() {
+            let activity = self.group_entry(now, group.as_str().trim_end_matches('\0'));
+            activity.messages_received += 1;
+            activity.bytes_received += bytes as u64;
+            activity.last_activity = now;
+        }
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_receive(groups, bytes);
+        }
+    }
+
+    /// Count a received membership message. Its groups are the members of
+    /// the group it describes, so no group's activity is updated.
+    pub fn record_membership_receive(&mut self, now: Timespec, bytes: usize) {
+        self.messages_received += 1;
+        self.bytes_received += bytes as u64;
+        self.receive_meter.mark(now.sec as u64);
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_membership_receive(bytes);
+        }
+    }
+
+    pub fn record_write_latency(&mut self, micros: u64) {
+        self.write_latency_us.record(micros);
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_write_latency(micros);
+        }
+    }
+
+    pub fn record_send_latency(&mut self, micros: u64) {
+        self.send_latency_us.record(micros);
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_send_latency(micros);
+        }
+    }
+
+    pub fn record_receive_latency(&mut self, micros: u64) {
+        self.receive_latency_us.record(micros);
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_receive_latency(micros);
+        }
+    }
+
+    fn group_entry(&mut self, now: Timespec, group: &str) -> &mut GroupActivity {
+        if !self.groups.contains_key(group) {
+            self.groups.insert(group.to_string(), GroupActivity::new(now));
+        }
+        self.groups.get_mut(group).unwrap()
+    }
+
+    pub fn group_activity(&self, group: &str) -> Option<GroupActivity> {
+        self.groups.get(group).cloned()
+    }
+
+    pub fn group_activities(&self) -> HashMap<String, GroupActivity> {
+        self.groups.clone()
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+        #[cfg(feature = "prometheus")]
+        if let Some(ref metrics) = self.exported {
+            metrics.record_reconnect();
+        }
+    }
+
+    pub fn record_expired_received(&mut self, count: usize) {
+        self.expired_received += count as u64;
+    }
+
+    pub fn record_expired_sent(&mut self, count: usize) {
+        self.expired_sent += count as u64;
+    }
+
+    pub fn record_error(&mut self, error: &Error) {
+        self.last_error = Some(error.clone());
+    }
+
+    pub fn snapshot(&self, now: Timespec) -> ClientStats {
+        let now = now.sec as u64;
+        ClientStats {
+            messages_sent: self.messages_sent,
+            bytes_sent: self.bytes_sent,
+            messages_received: self.messages_received,
+            bytes_received: self.bytes_received,
+            reconnects: self.reconnects,
+            expired_received: self.expired_received,
+            expired_sent: self.expired_sent,
+            last_error: self.last_error.clone(),
+            send_rate: self.send_meter.rate(now),
+            receive_rate: self.receive_meter.rate(now),
+            sent_payload_sizes: self.sent_payload_sizes.clone(),
+            received_payload_sizes: self.received_payload_sizes.clone(),
+            sent_fanout: self.sent_fanout.clone(),
+            received_fanout: self.received_fanout.clone(),
+            write_latency_us: self.write_latency_us.clone(),
+            send_latency_us: self.send_latency_us.clone(),
+            receive_latency_us: self.receive_latency_us.clone(),
+            receive_queue_depth: 0,
+            buffered_bytes: 0
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Keeping background workers running.
+//!
+//! A `Supervisor` runs each worker on its own thread and watches it. A
+//! worker that returns an error or panics is restarted according to its
+//! `RestartPolicy`; once it fails more often than the policy allows, the
+//! supervisor gives up on it and reports a `GaveUp` event carrying an error
+//! suitable for `SpreadClient::report_error`.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use sync::{channel, AtomicBool, Mutex, Ordering};
+use sync::thread::JoinHandle;
+use time::Duration;
+use threads::ThreadOptions;
+use {Clock, Error, SystemClock};
+
+/// How often a failing worker may be restarted.
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    /// Restarts allowed within `window` before the supervisor gives up.
+    pub max_restarts: u32,
+    pub window: Duration,
+    /// Delay before each restart.
+    pub backoff: Duration
+}
+
+impl RestartPolicy {
+    pub fn new(max_restarts: u32, window: Duration) -> RestartPolicy {
+        RestartPolicy { max_restarts: max_restarts, window: window, backoff: Duration::zero() }
+    }
+
+    /// A policy that never restarts.
+    pub fn never() -> RestartPolicy {
+        RestartPolicy::new(0, Duration::zero())
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration) -> RestartPolicy {
+        self.backoff = backoff;
+        self
+    }
+}
+
+/// Something that happened to a supervised worker, named by `worker`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SupervisorEvent {
+    /// The worker returned `Ok` and will not be restarted.
+    Exited { worker: String },
+    /// The worker returned an error.
+    Failed { worker: String, error: Error },
+    /// The worker's thread panicked.
+    Panicked { worker: String },
+    /// The worker was started again after failing; `restarts` counts the
+    /// restarts within the policy's window, including this one.
+    Restarted { worker: String, restarts: u32 },
+    /// The worker failed more often than its policy allows and has been
+    /// abandoned.
+    GaveUp { worker: String, error: Error }
+}
+
+type Callbacks = Arc<Mutex<Vec<Box<dyn FnMut(&SupervisorEvent) + Send>>>>;
+
+/// Runs and restarts a set of background workers.
+pub struct Supervisor {
+    shutdown: Arc<AtomicBool>,
+    callbacks: Callbacks,
+    monitors: Vec<JoinHandle<()>>,
+    threads: ThreadOptions,
+    clock: Arc<dyn Clock + Sync>
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            shutdown: Arc::new(AtomicBool::new(false)),
+            callbacks: Arc::new(Mutex::new(Vec::new())),
+            monitors: Vec::new(),
+            threads: ThreadOptions::new(),
+            clock: Arc::new(SystemClock)
+        }
+    }
+
+    /// Start workers spawned from now on with `threads`. Each worker's
+    /// thread is named after the worker and watched by a thread named
+    /// `supervise-<worker>`.
+    pub fn set_thread_options(&mut self, threads: ThreadOptions) {
+        self.threads = threads;
+    }
+
+    /// Time restart windows and backoffs of workers spawned from now on
+    /// with `clock`.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock + Sync>) {
+        self.clock = clock;
+    }
+
+    /// Register a callback invoked, from the supervisor's threads, with
+    /// every event for every worker.
+    pub fn on_event(&mut self, callback: Box<dyn FnMut(&SupervisorEvent) + Send>) {
+        self.callbacks.lock().unwrap().push(callback);
+    }
+
+    /// Start `worker` under `policy`. The worker is passed the supervisor's
+    /// shutdown flag and should return once it is set; returning `Ok`
+    /// means the worker is finished and it is not restarted.
+    pub fn spawn<F>(&mut self, name: &str, policy: RestartPolicy, worker: F)
+        where F: Fn(&AtomicBool) -> Result<(), Error> + Send + Sync + 'static
+    {
+        let name = name.to_string();
+        let worker = Arc::new(worker);
+        let shutdown = self.shutdown.clone();
+        let callbacks = self.callbacks.clone();
+        let threads = self.threads.clone();
+        let clock = self.clock.clone();
+        let role = format!("supervise-{}", name);
+        self.monitors.push(self.threads.spawn(role.as_str(), move || {
+            monitor(name, policy, worker, shutdown, callbacks, threads, clock);
+        }));
+    }
+
+    /// Set the shutdown flag and wait for every worker to return.
+    pub fn shutdown(self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.wait();
+    }
+
+    /// Wait, without asking them to stop, until every worker has exited
+    /// or been given up on.
+    pub fn wait(self) {
+        for monitor in self.monitors.into_iter() {
+            let _ = monitor.join();
+        }
+    }
+}
+
+fn notify(callbacks: &Callbacks, event: SupervisorEvent) {
+    for callback in callbacks.lock().unwrap().iter_mut() {
+        callback(&event);
+    }
+}
+
+fn monitor<F>(name: String, policy: RestartPolicy, worker: Arc<F>, shutdown: Arc<AtomicBool>,
+              callbacks: Callbacks, threads: ThreadOptions, clock: Arc<dyn Clock + Sync>)
+    where F: Fn(&AtomicBool) -> Result<(), Error> + Send + Sync + 'static
+{
+    let window_ns = policy.window.num_nanoseconds().unwrap_or(i64::MAX) as u64;
+    let mut restarts: VecDeque<u64> = VecDeque::new();
+    loop {
+        let (tx, rx) = channel();
+        let run = {
+            let worker = worker.clone();
+            let shutdown = shutdown.clone();
+            threads.spawn(name.as_str(), move || {
+                let _ = tx.send((*worker)(&shutdown));
+            })
+        };
+        let error = match (run.join(), rx.recv()) {
+            (Ok(_), Ok(Ok(()))) => {
+                notify(&callbacks, SupervisorEvent::Exited { worker: name.clone() });
+                return;
+            },
+            (Ok(_), Ok(Err(error))) => {
+                notify(&callbacks, SupervisorEvent::Failed { worker: name.clone(), error: error.clone() });
+                error
+            },
+            _ => {
+                notify(&callbacks, SupervisorEvent::Panicked { worker: name.clone() });
+                Error::from(io::Error::other("Worker panicked"))
+            }
+        };
+        if shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let now = clock.monotonic_ns();
+        while restarts.front().is_some_and(|started| now - *started > window_ns) {
+            restarts.pop_front();
+        }
+        if restarts.len() as u32 >= policy.max_restarts {
+            notify(&callbacks, SupervisorEvent::GaveUp {
+                worker: name.clone(),
+                error: Error::from(io::Error::other(
+                    format!("Worker exceeded its restart limit: worker \"{}\": {}", name, error)
+                ))
+            });
+            return;
+        }
+        restarts.push_back(now);
+        clock.sleep(policy.backoff);
+        notify(&callbacks, SupervisorEvent::Restarted {
+            worker: name.clone(),
+            restarts: restarts.len() as u32
+        });
+    }
+}
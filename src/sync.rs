@@ -0,0 +1,122 @@
+//! The locks, atomics, channels and threads behind the crate's shared
+//! state.
+//!
+//! Pumps, relays, redundant receivers, supervisors, group aliases, mock
+//! clocks and in-memory transports take their primitives from here rather
+//! than from `std`. Built with `--cfg loom`, these are loom's model-checked
+//! versions instead, and the loom tests explore every interleaving of the
+//! paths that share them:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release loom
+//! ```
+//!
+//! `Arc` stays `std::sync::Arc`, since loom's can't hold trait objects.
+//! Channels under loom are built here on loom's mutex and condition
+//! variable, since loom's own channel never reports a hang-up, so a
+//! receiver outliving its senders would block forever instead of failing
+//! as it does with `std`.
+
+#[cfg(not(loom))]
+pub use std::sync::{Mutex, RwLock};
+#[cfg(not(loom))]
+pub use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(loom))]
+pub use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(not(loom))]
+pub use std::thread;
+
+#[cfg(loom)]
+pub use loom::sync::{Mutex, RwLock};
+#[cfg(loom)]
+pub use loom::sync::atomic::{AtomicBool, Ordering};
+#[cfg(loom)]
+pub use self::loom_channel::{channel, Receiver, Sender};
+#[cfg(loom)]
+pub use loom::thread;
+
+#[cfg(loom)]
+mod loom_channel {
+    use std::collections::VecDeque;
+    use std::sync::mpsc::{RecvError, SendError};
+    use loom::sync::{Arc, Condvar, Mutex};
+
+    struct Queue<T> {
+        items: VecDeque<T>,
+        senders: usize,
+        receiving: bool
+    }
+
+    struct Shared<T> {
+        queue: Mutex<Queue<T>>,
+        ready: Condvar
+    }
+
+    pub struct Sender<T> {
+        shared: Arc<Shared<T>>
+    }
+
+    pub struct Receiver<T> {
+        shared: Arc<Shared<T>>
+    }
+
+    pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue { items: VecDeque::new(), senders: 1, receiving: true }),
+            ready: Condvar::new()
+        });
+        (Sender { shared: shared.clone() }, Receiver { shared: shared })
+    }
+
+    impl<T> Sender<T> {
+        pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+            let mut queue = self.shared.queue.lock().unwrap();
+            if !queue.receiving {
+                return Err(SendError(item));
+            }
+            queue.items.push_back(item);
+            self.shared.ready.notify_one();
+            Ok(())
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Sender<T> {
+            self.shared.queue.lock().unwrap().senders += 1;
+            Sender { shared: self.shared.clone() }
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.senders -= 1;
+            if queue.senders == 0 {
+                self.shared.ready.notify_all();
+            }
+        }
+    }
+
+    impl<T> Receiver<T> {
+        pub fn recv(&self) -> Result<T, RecvError> {
+            let mut queue = self.shared.queue.lock().unwrap();
+            loop {
+                if let Some(item) = queue.items.pop_front() {
+                    return Ok(item);
+                }
+                if queue.senders == 0 {
+                    return Err(RecvError);
+                }
+                queue = self.shared.ready.wait(queue).unwrap();
+            }
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.receiving = false;
+            queue.items.clear();
+        }
+    }
+}
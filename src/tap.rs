@@ -0,0 +1,125 @@
+//! Exporting received messages as JSON lines.
+//!
+//! Each tapped message is written as one JSON object per line:
+//!
+//! ```text
+//! {"timestamp":"2015-02-01T12:00:00Z","sender":"#a#d1","groups":["g"],"service_type":2,"payload":"aGk="}
+//! ```
+//!
+//! with the payload base64-encoded, ready for `jq`, a log shipper or a flat
+//! file. Timestamps carry fractional seconds when they have any, and a
+//! non-zero message type is written as `mess_type`. `from_json_line` reads
+//! such a line back, ignoring any fields it doesn't know, so a tap file
+//! doubles as an archive for `replay`.
+
+use std::io::Write;
+use time::{self, Timespec};
+use util::{base64_decode, base64_encode, json_string, parse_json_object, JsonValue};
+use {Error, SpreadMessage};
+
+/// Writes selected messages to a writer as JSON lines.
+pub struct JsonTap {
+    writer: Box<dyn Write + Send>,
+    filter: Option<Box<dyn Fn(&SpreadMessage) -> bool + Send>>
+}
+
+impl JsonTap {
+    /// Tap every message to `writer`.
+    pub fn new(writer: Box<dyn Write + Send>) -> JsonTap {
+        JsonTap { writer: writer, filter: None }
+    }
+
+    /// Only tap messages for which `filter` returns true.
+    pub fn filter(mut self, filter: Box<dyn Fn(&SpreadMessage) -> bool + Send>) -> JsonTap {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Write `message`, received at `now`, if it passes the filter.
+    pub fn record(&mut self, now: Timespec, message: &SpreadMessage) -> Result<(), Error> {
+        if let Some(ref filter) = self.filter {
+            if !(**filter)(message) {
+                return Ok(());
+            }
+        }
+        let line = to_json_line(now, message);
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Render `message`, received at `now`, as a single JSON line including the
+/// trailing newline.
+pub fn to_json_line(now: Timespec, message: &SpreadMessage) -> String {
+    let groups: Vec<String> = message.groups.iter()
+        .map(|g| json_string(g.as_str().trim_end_matches('\0')))
+        .collect();
+    let mut timestamp = format!("{}", time::at_utc(now).rfc3339());
+    if now.nsec != 0 {
+        timestamp = format!("{}.{:09}Z", timestamp.trim_end_matches('Z'), now.nsec);
+    }
+    let mess_type = match message.message_type() {
+        0 => String::new(),
+        mess_type => format!(",\"mess_type\":{}", mess_type)
+    };
+    format!("{{\"timestamp\":{},\"sender\":{},\"groups\":[{}],\"service_type\":{}{},\"payload\":{}}}\n",
+            json_string(timestamp.as_str()),
+            json_string(message.sender.as_str().trim_end_matches('\0')),
+            groups.join(","),
+            message.service_type,
+            mess_type,
+            json_string(base64_encode(message.data.as_slice()).as_str()))
+}
+
+/// Read back a line written by `to_json_line`, returning the time the
+/// message was received and the message.
+pub fn from_json_line(line: &str) -> Result<(Timespec, SpreadMessage), Error> {
+    let invalid = |reason: String| Error::InvalidInput(format!("Bad tap line: {}", reason));
+    let fields = parse_json_object(line).map_err(invalid)?;
+    let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value);
+
+    let timestamp = match field("timestamp") {
+        Some(JsonValue::String(timestamp)) => parse_timestamp(timestamp.as_str())
+            .ok_or_else(|| invalid(format!("bad timestamp {}", timestamp)))?,
+        _ => return Err(invalid("no timestamp".to_string()))
+    };
+    let sender = match field("sender") {
+        Some(JsonValue::String(sender)) => sender.clone(),
+        _ => return Err(invalid("no sender".to_string()))
+    };
+    let groups = match field("groups") {
+        Some(JsonValue::Strings(groups)) => groups.clone(),
+        _ => return Err(invalid("no groups".to_string()))
+    };
+    let service_type = match field("service_type") {
+        Some(&JsonValue::Number(service_type)) => service_type as u32,
+        _ => return Err(invalid("no service_type".to_string()))
+    };
+    let data = match field("payload") {
+        Some(JsonValue::String(payload)) => base64_decode(payload.as_str())
+            .ok_or_else(|| invalid("payload is not base64".to_string()))?,
+        _ => return Err(invalid("no payload".to_string()))
+    };
+    let mess_type = match field("mess_type") {
+        Some(&JsonValue::Number(mess_type)) => mess_type as i16,
+        _ => 0
+    };
+    let message = SpreadMessage::from_parts(service_type, sender, groups, data).with_message_type(mess_type);
+    Ok((timestamp, message))
+}
+
+// Parse a UTC timestamp as written by `to_json_line`, with or without
+// fractional seconds.
+fn parse_timestamp(timestamp: &str) -> Option<Timespec> {
+    let timestamp = timestamp.strip_suffix('Z')?;
+    let (seconds, fraction) = match timestamp.split_once('.') {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (timestamp, "")
+    };
+    if fraction.len() > 9 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let nsec = format!("{:0<9}", fraction).parse().ok()?;
+    let seconds = time::strptime(seconds, "%Y-%m-%dT%H:%M:%S").ok()?.to_timespec();
+    Some(Timespec::new(seconds.sec, nsec))
+}
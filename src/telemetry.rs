@@ -0,0 +1,159 @@
+//! Client telemetry reported through the `metrics` and `tracing` crates.
+//!
+//! When the crate is built with the `metrics` feature, every client records
+//! the following into whichever `metrics` recorder the application has
+//! installed, so any compatible exporter collects them:
+//!
+//! ```text
+//! spread_client_connects_total            counter
+//! spread_client_connect_failures_total    counter
+//! spread_client_reconnects_total          counter
+//! spread_client_messages_sent_total       counter
+//! spread_client_bytes_sent_total          counter
+//! spread_client_send_duration_seconds     histogram  time to write each multicast frame
+//! spread_client_messages_received_total   counter    kind = "data" | "membership"
+//! spread_client_bytes_received_total      counter    kind = "data" | "membership"
+//! spread_client_group_joins_total         counter
+//! spread_client_group_leaves_total        counter
+//! spread_client_errors_total              counter    error = the `Error` variant, e.g. "timeout"
+//! ```
+//!
+//! With the `tracing` feature, connecting runs in a `spread.connect` span,
+//! reconnecting in `spread.reconnect`, and the session handshake of either
+//! in a nested `spread.handshake` span recording the daemon's address. Each
+//! received message is processed in a `spread.receive` span with `sender`,
+//! `groups`, `size` and `service_type` fields, and every error the client
+//! encounters is emitted as an `ERROR` event with `error` and `kind`
+//! fields.
+//!
+//! Without either feature every function here does nothing.
+
+#![cfg_attr(not(all(feature = "metrics", feature = "tracing")), allow(unused_variables))]
+
+use {Error, SpreadMessage};
+
+// A span that stays entered until dropped.
+pub struct Span {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan
+}
+
+// Enter the span of connecting as `private_name`.
+pub fn connect_span(private_name: &str) -> Span {
+    Span {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::info_span!("spread.connect", private_name = private_name).entered()
+    }
+}
+
+// Enter the span of re-establishing the session of `private_name`.
+pub fn reconnect_span(private_name: &str) -> Span {
+    Span {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::info_span!("spread.reconnect", private_name = private_name).entered()
+    }
+}
+
+// Enter the span of the session handshake with the daemon at `peer`.
+pub fn handshake_span(peer: &str) -> Span {
+    Span {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::debug_span!("spread.handshake", peer = peer).entered()
+    }
+}
+
+// Enter the span of processing a received message.
+pub fn receive_span(message: &SpreadMessage) -> Span {
+    Span {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::debug_span!(
+            "spread.receive",
+            sender = message.sender(),
+            groups = %message.groups.iter()
+                .map(|group| group.as_str().trim_end_matches('\0'))
+                .collect::<Vec<&str>>()
+                .join(","),
+            size = message.data.len(),
+            service_type = message.service_type
+        ).entered()
+    }
+}
+
+// A session was opened by `connect`.
+pub fn connected(private_group: &str) {
+    #[cfg(feature = "metrics")]
+    counter!("spread_client_connects_total").increment(1);
+    #[cfg(feature = "tracing")]
+    tracing::info!(private_group = private_group, "Connected to daemon");
+}
+
+// Opening a session failed with `error`.
+pub fn connect_failed(error: &Error) {
+    #[cfg(feature = "metrics")]
+    counter!("spread_client_connect_failures_total").increment(1);
+    record_error(error);
+}
+
+// A lost session was replaced.
+pub fn reconnected(private_group: &str) {
+    #[cfg(feature = "metrics")]
+    counter!("spread_client_reconnects_total").increment(1);
+    #[cfg(feature = "tracing")]
+    tracing::info!(private_group = private_group, "Reconnected to daemon");
+}
+
+// A multicast of `bytes` payload bytes took `write_ns` to write.
+pub fn sent(bytes: usize, write_ns: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        counter!("spread_client_messages_sent_total").increment(1);
+        counter!("spread_client_bytes_sent_total").increment(bytes as u64);
+        histogram!("spread_client_send_duration_seconds").record(write_ns as f64 / 1e9);
+    }
+}
+
+// A message of `bytes` payload bytes was received.
+pub fn received(bytes: usize, membership: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        let kind = if membership { "membership" } else { "data" };
+        counter!("spread_client_messages_received_total", "kind" => kind).increment(1);
+        counter!("spread_client_bytes_received_total", "kind" => kind).increment(bytes as u64);
+    }
+}
+
+pub fn joined() {
+    #[cfg(feature = "metrics")]
+    counter!("spread_client_group_joins_total").increment(1);
+}
+
+pub fn left() {
+    #[cfg(feature = "metrics")]
+    counter!("spread_client_group_leaves_total").increment(1);
+}
+
+pub fn record_error(error: &Error) {
+    #[cfg(feature = "metrics")]
+    counter!("spread_client_errors_total", "error" => error_label(error)).increment(1);
+    #[cfg(feature = "tracing")]
+    tracing::error!(error = %error, kind = error_label(error), "Spread client error");
+}
+
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+fn error_label(error: &Error) -> &'static str {
+    match *error {
+        Error::ConnectionRejected(_) => "connection_rejected",
+        Error::ProtocolError(_) => "protocol",
+        Error::EncodingError(_) => "encoding",
+        Error::InvalidInput(_) => "invalid_input",
+        Error::Io(_) => "io",
+        Error::Timeout => "timeout",
+        Error::Disconnected => "disconnected",
+        Error::DaemonError(_) => "daemon",
+        Error::QuotaExceeded(_) => "quota_exceeded",
+        Error::BufferLimit { .. } => "buffer_limit",
+        Error::CallbackPanicked { .. } => "callback_panicked",
+        #[cfg(feature = "chaos")]
+        Error::ReceivePaused => "receive_paused"
+    }
+}
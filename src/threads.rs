@@ -0,0 +1,165 @@
+//! Naming, pinning and prioritizing the crate's background threads.
+//!
+//! Relays, pumps, redundant receivers and supervisors each read or write on
+//! threads of their own. `ThreadOptions` set on their configuration give
+//! those threads recognizable names in debuggers and `top`, and can pin
+//! them to a CPU core or change their scheduling priority, e.g. to keep a
+//! receive loop on an isolated core. Pinning is supported on Linux and,
+//! for the first 64 cores, on Windows. Priorities are nice values on Unix;
+//! on Windows they map to the nearest thread priority level, from
+//! `THREAD_PRIORITY_HIGHEST` for -20 to `THREAD_PRIORITY_IDLE` for 19.
+//! Where pinning or reprioritizing fails, the thread logs a warning and
+//! runs anyway.
+
+use sync::thread::{self, JoinHandle};
+
+/// How to start a background thread.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThreadOptions {
+    /// Prefix for the names of the threads, followed by their role (e.g.
+    /// `"orders-relay-forward"`). Defaults to `"spread"`.
+    pub name: Option<String>,
+    /// Index of the CPU core to run on.
+    pub cpu: Option<usize>,
+    /// Nice value, from -20 (highest priority) to 19 (lowest).
+    pub nice: Option<i32>
+}
+
+impl ThreadOptions {
+    pub fn new() -> ThreadOptions {
+        ThreadOptions { name: None, cpu: None, nice: None }
+    }
+
+    pub fn name(mut self, name: &str) -> ThreadOptions {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn pin_to_cpu(mut self, cpu: usize) -> ThreadOptions {
+        self.cpu = Some(cpu);
+        self
+    }
+
+    pub fn nice(mut self, nice: i32) -> ThreadOptions {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// The name given to the thread with `role`.
+    pub fn thread_name(&self, role: &str) -> String {
+        match self.name {
+            Some(ref name) => format!("{}-{}", name, role),
+            None => format!("spread-{}", role)
+        }
+    }
+
+    /// Start a thread running `f`, named for `role`, with these options
+    /// applied.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, role: &str, f: F) -> JoinHandle<()> {
+        let name = self.thread_name(role);
+        let (cpu, nice) = (self.cpu, self.nice);
+        thread::Builder::new().name(name.clone()).spawn(move || {
+            if let Some(cpu) = cpu {
+                if let Err(reason) = pin_current_thread(cpu) {
+                    warn!("Failed to pin thread \"{}\" to CPU {}: {}", name, cpu, reason);
+                }
+            }
+            if let Some(nice) = nice {
+                if let Err(reason) = set_current_thread_nice(nice) {
+                    warn!("Failed to set nice value {} on thread \"{}\": {}", nice, name, reason);
+                }
+            }
+            f()
+        }).unwrap()
+    }
+}
+
+// Large enough for the kernel's default maximum of 1024 CPUs.
+#[cfg(target_os = "linux")]
+static CPU_SET_WORDS: usize = 16;
+
+#[cfg(target_os = "linux")]
+fn pin_current_thread(cpu: usize) -> Result<(), String> {
+    extern "C" {
+        fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const u64) -> i32;
+    }
+    if cpu >= CPU_SET_WORDS * 64 {
+        return Err(format!("CPU index above {}", CPU_SET_WORDS * 64 - 1));
+    }
+    let mut mask = [0u64; 16];
+    mask[cpu / 64] |= 1 << (cpu % 64);
+    // A pid of zero means the calling thread.
+    match unsafe { sched_setaffinity(0, CPU_SET_WORDS * 8, mask.as_ptr()) } {
+        0 => Ok(()),
+        _ => Err("sched_setaffinity failed".to_string())
+    }
+}
+
+#[cfg(windows)]
+fn pin_current_thread(cpu: usize) -> Result<(), String> {
+    if cpu >= 64 {
+        return Err("CPU index above 63".to_string());
+    }
+    match unsafe { kernel32::SetThreadAffinityMask(kernel32::GetCurrentThread(), 1 << cpu) } {
+        0 => Err("SetThreadAffinityMask failed".to_string()),
+        _ => Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn pin_current_thread(_: usize) -> Result<(), String> {
+    Err("CPU pinning is only supported on Linux and Windows".to_string())
+}
+
+#[cfg(unix)]
+fn set_current_thread_nice(nice: i32) -> Result<(), String> {
+    extern "C" {
+        fn setpriority(which: i32, who: u32, prio: i32) -> i32;
+    }
+    // PRIO_PROCESS with who = 0 applies to the calling thread on Linux
+    // and to the whole process elsewhere.
+    match unsafe { setpriority(0, 0, nice) } {
+        0 => Ok(()),
+        _ => Err("setpriority failed".to_string())
+    }
+}
+
+#[cfg(windows)]
+fn set_current_thread_nice(nice: i32) -> Result<(), String> {
+    match unsafe { kernel32::SetThreadPriority(kernel32::GetCurrentThread(), windows_priority(nice)) } {
+        0 => Err("SetThreadPriority failed".to_string()),
+        _ => Ok(())
+    }
+}
+
+// The Windows thread priority level nearest to `nice`. Nothing maps to
+// `THREAD_PRIORITY_TIME_CRITICAL`, which can starve the rest of the system.
+#[cfg(windows)]
+fn windows_priority(nice: i32) -> i32 {
+    match nice {
+        i32::MIN..=-10 => 2,
+        -9..=-1 => 1,
+        0 => 0,
+        1..=9 => -1,
+        10..=18 => -2,
+        _ => -15
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_current_thread_nice(_: i32) -> Result<(), String> {
+    Err("thread priorities are only supported on Unix and Windows".to_string())
+}
+
+// The kernel32 calls behind pinning and prioritizing on Windows.
+#[cfg(windows)]
+mod kernel32 {
+    use std::os::raw::c_void;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn GetCurrentThread() -> *mut c_void;
+        pub fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+        pub fn SetThreadPriority(thread: *mut c_void, priority: i32) -> i32;
+    }
+}
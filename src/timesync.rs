@@ -0,0 +1,164 @@
+//! Estimating clock offsets between the members of a group.
+//!
+//! A member multicasts a probe stamped with its send time `t1`. Every member
+//! receiving it replies directly to the prober with `t1`, its own receive
+//! time `t2` and reply time `t3`; the prober stamps the reply's arrival with
+//! `t4`. As in NTP, each round trip yields an offset sample
+//! `((t2 - t1) + (t3 - t4)) / 2` and a round-trip delay
+//! `(t4 - t1) - (t3 - t2)`. Samples with the smallest delay are the least
+//! distorted by queueing, so the estimate uses the best recent sample, and
+//! the drift of offsets over time gives the relative skew of the two clocks.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use time::Timespec;
+use envelope::{decode_u64, encode_u64};
+use {Error, SpreadClient, SpreadMessage};
+
+static PROBE_PREFIX: &'static [u8] = b"\x00spread-time-probe:";
+static REPLY_PREFIX: &'static [u8] = b"\x00spread-time-reply:";
+
+/// The estimated offset of a peer's clock from the local clock.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OffsetEstimate {
+    /// Milliseconds to add to a local time to get the peer's time.
+    pub offset_ms: i64,
+    /// Round-trip delay of the sample the offset was taken from; the offset
+    /// is accurate to within half of it.
+    pub round_trip_ms: i64,
+    /// Rate at which the peer's clock gains on the local one, in parts per
+    /// million, once enough time has passed between samples to tell.
+    pub skew_ppm: Option<f64>,
+    /// Number of samples the estimate is based on.
+    pub samples: usize
+}
+
+#[derive(Copy, Clone)]
+struct Sample {
+    local_ms: i64,
+    offset_ms: i64,
+    round_trip_ms: i64
+}
+
+/// Sends time probes to a group and collects offset samples from replies.
+pub struct OffsetEstimator {
+    group: String,
+    window: usize,
+    samples: HashMap<String, VecDeque<Sample>>
+}
+
+impl OffsetEstimator {
+    /// Probe `group`, keeping the last `window` samples per peer.
+    pub fn new(group: &str, window: usize) -> OffsetEstimator {
+        OffsetEstimator {
+            group: group.to_string(),
+            window: window,
+            samples: HashMap::new()
+        }
+    }
+
+    /// Multicast a probe stamped with `now` to the group.
+    pub fn probe(&self, client: &mut SpreadClient, now: Timespec) -> Result<(), Error> {
+        let mut probe = PROBE_PREFIX.to_vec();
+        probe.extend_from_slice(encode_u64(to_millis(now) as u64).as_slice());
+        client.multicast([self.group.as_str()].as_slice(), probe.as_slice())
+    }
+
+    /// Handle a received message: answer probes from other members and
+    /// record samples from replies to our own. Returns true if `message` was
+    /// a time probe or reply.
+    pub fn on_message(&mut self, client: &mut SpreadClient, now: Timespec,
+                      message: &SpreadMessage) -> Result<bool, Error> {
+        let data = message.data.as_slice();
+        let sender = message.sender.as_str().trim_end_matches('\0');
+        if data.starts_with(PROBE_PREFIX) {
+            if sender == client.private_name.as_str() {
+                return Ok(true);
+            }
+            if let Some(t1) = decode_u64(&data[PROBE_PREFIX.len()..]) {
+                let t2 = to_millis(now);
+                let mut reply = REPLY_PREFIX.to_vec();
+                reply.extend_from_slice(encode_u64(t1).as_slice());
+                reply.extend_from_slice(encode_u64(t2 as u64).as_slice());
+                reply.extend_from_slice(encode_u64(to_millis(client.clock.now()) as u64).as_slice());
+                client.multicast([sender].as_slice(), reply.as_slice())?;
+            }
+            return Ok(true);
+        }
+        if data.starts_with(REPLY_PREFIX) {
+            let body = &data[REPLY_PREFIX.len()..];
+            if body.len() == 24 {
+                let stamps = (decode_u64(&body[..8]), decode_u64(&body[8..16]), decode_u64(&body[16..]));
+                if let (Some(t1), Some(t2), Some(t3)) = stamps {
+                    self.record(sender, t1 as i64, t2 as i64, t3 as i64, to_millis(now));
+                }
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Record one round trip with `peer` from its four timestamps, in
+    /// milliseconds.
+    pub fn record(&mut self, peer: &str, t1: i64, t2: i64, t3: i64, t4: i64) {
+        let sample = Sample {
+            local_ms: t4,
+            offset_ms: ((t2 - t1) + (t3 - t4)) / 2,
+            round_trip_ms: (t4 - t1) - (t3 - t2)
+        };
+        if !self.samples.contains_key(peer) {
+            self.samples.insert(peer.to_string(), VecDeque::new());
+        }
+        let samples = self.samples.get_mut(peer).unwrap();
+        if samples.len() >= self.window {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// The current estimate for `peer`, if any replies have arrived from it.
+    pub fn estimate(&self, peer: &str) -> Option<OffsetEstimate> {
+        let samples = match self.samples.get(peer) {
+            Some(samples) if !samples.is_empty() => samples,
+            _ => return None
+        };
+        let best = samples.iter().min_by_key(|sample| sample.round_trip_ms).unwrap();
+        Some(OffsetEstimate {
+            offset_ms: best.offset_ms,
+            round_trip_ms: best.round_trip_ms,
+            skew_ppm: skew_ppm(samples),
+            samples: samples.len()
+        })
+    }
+
+    /// Estimates for every peer that has replied.
+    pub fn estimates(&self) -> BTreeMap<String, OffsetEstimate> {
+        self.samples.keys()
+            .filter_map(|peer| self.estimate(peer.as_str()).map(|e| (peer.clone(), e)))
+            .collect()
+    }
+
+    /// Forget `peer`, e.g. once it has left the group.
+    pub fn remove(&mut self, peer: &str) {
+        self.samples.remove(peer);
+    }
+}
+
+fn to_millis(t: Timespec) -> i64 {
+    t.sec * 1000 + t.nsec as i64 / 1_000_000
+}
+
+// Least-squares slope of offset over local time, in parts per million.
+fn skew_ppm(samples: &VecDeque<Sample>) -> Option<f64> {
+    let n = samples.len() as f64;
+    let mean_t = samples.iter().fold(0.0, |acc, s| acc + s.local_ms as f64) / n;
+    let mean_o = samples.iter().fold(0.0, |acc, s| acc + s.offset_ms as f64) / n;
+    let (covariance, variance) = samples.iter().fold((0.0, 0.0), |(c, v), s| {
+        let dt = s.local_ms as f64 - mean_t;
+        (c + dt * (s.offset_ms as f64 - mean_o), v + dt * dt)
+    });
+    if samples.len() < 2 || variance == 0.0 {
+        None
+    } else {
+        Some(covariance / variance * 1_000_000.0)
+    }
+}
@@ -0,0 +1,246 @@
+//! Reaching a daemon through a TLS terminator.
+//!
+//! Only available when the crate is built with the `tls` feature. Spread
+//! itself speaks plain TCP, so encrypting the link means putting a TLS
+//! terminator such as stunnel in front of the daemon; a `TlsTransport`
+//! speaks TLS to it, using rustls, and the Spread stream runs inside. Set a
+//! configuration with `ConnectOptions::via_tls`, and reconnects use it too.
+//!
+//! By default the terminator's certificate must chain to the Mozilla root
+//! program as bundled by `webpki-roots` and name the host connected to.
+//! `TlsConfig::with_roots` trusts a private CA instead, `with_server_name`
+//! verifies against a name other than the host dialled, and
+//! `with_client_certificate` presents a certificate for terminators that
+//! require one. Certificates are given DER-encoded.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+             StreamOwned};
+use transport::Transport;
+
+/// Which certificates a `TlsTransport` accepts from the terminator.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verification {
+    /// Certificates chaining to a root bundled by `webpki-roots`.
+    WebPkiRoots,
+    /// Certificates chaining to one of these DER-encoded CA certificates.
+    Roots(Vec<Vec<u8>>),
+    /// Any certificate at all. The link is still encrypted, but open to
+    /// interception by anyone on the path, so this is for testing only.
+    Disabled
+}
+
+/// How to verify a terminator and, optionally, identify to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsConfig {
+    pub verification: Verification,
+    /// The name to verify the certificate against and send in SNI, in
+    /// place of the host connected to.
+    pub server_name: Option<String>,
+    /// A DER-encoded certificate chain, leaf first, and the DER-encoded
+    /// private key to present if the terminator asks for a certificate.
+    pub client_certificate: Option<(Vec<Vec<u8>>, Vec<u8>)>
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig { verification: Verification::WebPkiRoots, server_name: None, client_certificate: None }
+    }
+
+    /// Trust only certificates issued by `ca_certificates`.
+    pub fn with_roots(mut self, ca_certificates: Vec<Vec<u8>>) -> TlsConfig {
+        self.verification = Verification::Roots(ca_certificates);
+        self
+    }
+
+    pub fn with_server_name(mut self, server_name: &str) -> TlsConfig {
+        self.server_name = Some(server_name.to_string());
+        self
+    }
+
+    pub fn with_client_certificate(mut self, chain: Vec<Vec<u8>>, private_key: Vec<u8>) -> TlsConfig {
+        self.client_certificate = Some((chain, private_key));
+        self
+    }
+
+    /// Accept whatever certificate the terminator presents. Never use this
+    /// outside of tests.
+    pub fn danger_accept_any_certificate(mut self) -> TlsConfig {
+        self.verification = Verification::Disabled;
+        self
+    }
+}
+
+/// A `Transport` carrying the Spread stream over TLS.
+pub struct TlsTransport {
+    stream: StreamOwned<ClientConnection, Box<dyn Transport>>
+}
+
+impl TlsTransport {
+    /// Connect to the terminator at `host:port` and complete the TLS
+    /// handshake, verifying its certificate against `host` unless `config`
+    /// names another server.
+    pub fn connect(host: &str, port: u16, config: &TlsConfig) -> io::Result<TlsTransport> {
+        let stream = TcpStream::connect((host, port))?;
+        TlsTransport::wrap(Box::new(stream), host, config)
+    }
+
+    /// Complete a TLS handshake over `inner`, such as a proxy tunnel, with
+    /// a terminator at `host`. Fails if the terminator's certificate isn't
+    /// accepted.
+    pub fn wrap(inner: Box<dyn Transport>, host: &str, config: &TlsConfig) -> io::Result<TlsTransport> {
+        let name = config.server_name.as_ref().map_or(host, |name| name.as_str());
+        let server_name = ServerName::try_from(name.to_string()).map_err(|_| {
+            io::Error::new(ErrorKind::InvalidInput, format!("Invalid TLS server name: {}", name))
+        })?;
+        let connection = ClientConnection::new(Arc::new(client_config(config)?), server_name)
+            .map_err(tls_error)?;
+        let mut stream = StreamOwned::new(connection, inner);
+        debug!("Starting TLS handshake with {}", name);
+        stream.conn.complete_io(&mut stream.sock)?;
+        Ok(TlsTransport { stream: stream })
+    }
+}
+
+impl Read for TlsTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TlsTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn peer_name(&mut self) -> Option<SocketAddr> {
+        self.stream.sock.peer_name()
+    }
+
+    fn socket_name(&mut self) -> Option<SocketAddr> {
+        self.stream.sock.socket_name()
+    }
+
+    // Tell the terminator the session is over before closing the
+    // connection, so it can tell a clean close from a truncation.
+    fn close(&mut self) -> io::Result<()> {
+        self.stream.conn.send_close_notify();
+        let _ = self.stream.flush();
+        self.stream.sock.close()
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.sock.set_write_timeout(timeout)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.stream.sock.set_nonblocking(nonblocking)
+    }
+}
+
+fn tls_error(error: rustls::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
+
+// The rustls configuration for `config`, using the ring provider
+// regardless of any process-wide default.
+fn client_config(config: &TlsConfig) -> io::Result<ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+    let builder = match config.verification {
+        Verification::WebPkiRoots => {
+            let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+            builder.with_root_certificates(roots)
+        },
+        Verification::Roots(ref ca_certificates) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in ca_certificates.iter() {
+                roots.add(CertificateDer::from(certificate.clone())).map_err(tls_error)?;
+            }
+            builder.with_root_certificates(roots)
+        },
+        Verification::Disabled => {
+            warn!("TLS certificate verification is disabled");
+            builder.dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate { provider: provider }))
+        }
+    };
+    match config.client_certificate {
+        Some((ref chain, ref private_key)) => {
+            let chain = chain.iter().map(|certificate| CertificateDer::from(certificate.clone())).collect();
+            let private_key = PrivateKeyDer::try_from(private_key.clone())
+                .map_err(|error| io::Error::new(ErrorKind::InvalidInput, error))?;
+            builder.with_client_auth_cert(chain, private_key).map_err(tls_error)
+        },
+        None => Ok(builder.with_no_client_auth())
+    }
+}
+
+// Accepts any certificate, while still checking the handshake is signed by
+// the key in whatever certificate was presented.
+struct AcceptAnyCertificate {
+    provider: Arc<CryptoProvider>
+}
+
+impl fmt::Debug for AcceptAnyCertificate {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("AcceptAnyCertificate")
+    }
+}
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, certificate, signature,
+                                       &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, certificate, signature,
+                                       &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,87 @@
+//! W3C trace context carried in message envelopes, for OpenTelemetry.
+//!
+//! A client with `SpreadClient::set_trace_propagation` on envelopes every
+//! multicast made while an OpenTelemetry span is current with that span's
+//! `traceparent` and `tracestate` headers, in the format of the W3C Trace
+//! Context recommendation. On the receiving side, `extract` turns them back
+//! into a `Context` whose remote parent is the sender's span, and `attach`
+//! makes that context current, so spans started while handling the message
+//! join the sender's trace. A `Dispatcher` attaches each message's context
+//! while its handlers run. Headers that fail to parse are ignored.
+
+use std::str::FromStr;
+use opentelemetry::{Context, ContextGuard};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use envelope::{Envelope, TAG_TRACE_CONTEXT};
+use SpreadMessage;
+
+// The only `traceparent` version defined so far.
+static VERSION: &'static str = "00";
+
+/// The `traceparent` header for `span_context`, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+pub fn traceparent(span_context: &SpanContext) -> String {
+    format!("{}-{:032x}-{:016x}-{:02x}", VERSION, span_context.trace_id(), span_context.span_id(),
+            span_context.trace_flags())
+}
+
+/// The remote span context given by `traceparent` and `tracestate`
+/// headers, or `None` if `traceparent` is malformed or names an invalid
+/// span. A malformed `tracestate` is treated as empty.
+pub fn parse_traceparent(traceparent: &str, tracestate: &str) -> Option<SpanContext> {
+    let parts: Vec<&str> = traceparent.trim().split('-').collect();
+    let (version, trace_id, span_id, flags) = match parts.as_slice() {
+        [version, trace_id, span_id, flags] => (*version, *trace_id, *span_id, *flags),
+        _ => return None
+    };
+    if version != VERSION || trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    let trace_state = TraceState::from_str(tracestate).unwrap_or_default();
+    let span_context = SpanContext::new(trace_id, span_id, TraceFlags::new(flags), true, trace_state);
+    if span_context.is_valid() { Some(span_context) } else { None }
+}
+
+/// Returns true if a span with a valid context is current in `context`.
+pub fn is_traced(context: &Context) -> bool {
+    context.span().span_context().is_valid()
+}
+
+/// Stamp `envelope` with the trace context of the span current in
+/// `context`, if it has a valid one. Returns true if it was stamped.
+pub fn inject(envelope: &mut Envelope, context: &Context) -> bool {
+    let span = context.span();
+    let span_context = span.span_context();
+    if !span_context.is_valid() {
+        return false;
+    }
+    let mut headers = traceparent(span_context);
+    let tracestate = span_context.trace_state().header();
+    if !tracestate.is_empty() {
+        headers.push('\n');
+        headers.push_str(tracestate.as_str());
+    }
+    envelope.set_field(TAG_TRACE_CONTEXT, headers.as_bytes());
+    true
+}
+
+/// The context the sender of `message` propagated, with the sender's span
+/// as its remote parent, or `None` if the message carries no trace context.
+pub fn extract(message: &SpreadMessage) -> Option<Context> {
+    let envelope = Envelope::decode(message.data.as_slice())?;
+    let headers = String::from_utf8_lossy(envelope.field(TAG_TRACE_CONTEXT)?).into_owned();
+    let mut lines = headers.splitn(2, '\n');
+    let traceparent = lines.next().unwrap_or("");
+    let tracestate = lines.next().unwrap_or("");
+    parse_traceparent(traceparent, tracestate)
+        .map(|span_context| Context::current().with_remote_span_context(span_context))
+}
+
+/// Make the context propagated with `message` current until the returned
+/// guard is dropped, or return `None` if it carries none.
+pub fn attach(message: &SpreadMessage) -> Option<ContextGuard> {
+    extract(message).map(|context| context.attach())
+}
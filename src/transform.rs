@@ -0,0 +1,116 @@
+//! Compressing and encrypting multicast payloads.
+//!
+//! A client given a compressor with `SpreadClient::set_compression` or a
+//! cipher with `set_encryption` envelopes every multicast, compresses and
+//! then encrypts its payload, and sets the envelope's `FLAG_COMPRESSED` or
+//! `FLAG_ENCRYPTED` accordingly, advertising `CAP_COMPRESSION` or
+//! `CAP_ENCRYPTION`. Receivers undo whichever transforms a message's flags
+//! say were applied, so one group can carry plain, compressed and encrypted
+//! traffic from differently configured senders. A message flagged with a
+//! transform the receiver lacks, or whose payload fails to decode, is
+//! delivered unchanged; `SpreadMessage::is_opaque` tells such messages
+//! apart. A transform that panics fails as if it had returned
+//! `Error::CallbackPanicked`.
+
+use capability::{CAP_COMPRESSION, CAP_ENCRYPTION};
+use envelope::{Envelope, FLAG_COMPRESSED, FLAG_ENCRYPTED};
+use error::catch_panic;
+use Error;
+
+/// A reversible transformation of payloads, such as a compressor or a
+/// cipher.
+pub trait PayloadTransform: Send {
+    /// Transform an outgoing payload.
+    fn encode(&mut self, payload: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Undo `encode` on a received payload.
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Returns true if `data` is an envelope whose payload is still compressed
+/// or encrypted.
+pub fn is_opaque(data: &[u8]) -> bool {
+    Envelope::is_enveloped(data) && Envelope::decode(data)
+        .is_some_and(|envelope| envelope.flags & (FLAG_COMPRESSED | FLAG_ENCRYPTED) != 0)
+}
+
+/// The compressor and cipher a client applies to outgoing payloads and
+/// reverses on incoming ones.
+pub struct PayloadTransforms {
+    compression: Option<Box<dyn PayloadTransform>>,
+    encryption: Option<Box<dyn PayloadTransform>>
+}
+
+impl PayloadTransforms {
+    pub fn new() -> PayloadTransforms {
+        PayloadTransforms { compression: None, encryption: None }
+    }
+
+    pub fn set_compression(&mut self, compression: Option<Box<dyn PayloadTransform>>) {
+        self.compression = compression;
+    }
+
+    pub fn set_encryption(&mut self, encryption: Option<Box<dyn PayloadTransform>>) {
+        self.encryption = encryption;
+    }
+
+    /// Returns true if neither a compressor nor a cipher is set.
+    pub fn is_empty(&self) -> bool {
+        self.compression.is_none() && self.encryption.is_none()
+    }
+
+    /// The capabilities to advertise for the transforms set.
+    pub fn capabilities(&self) -> u64 {
+        let compression = if self.compression.is_some() { CAP_COMPRESSION } else { 0 };
+        let encryption = if self.encryption.is_some() { CAP_ENCRYPTION } else { 0 };
+        compression | encryption
+    }
+
+    /// Compress and then encrypt the payload of `envelope`, flagging each
+    /// transform applied.
+    pub fn apply(&mut self, envelope: &mut Envelope) -> Result<(), Error> {
+        if let Some(ref mut compression) = self.compression {
+            let payload = envelope.payload.as_slice();
+            envelope.payload = catch_panic("Compression", || compression.encode(payload))??;
+            envelope.flags |= FLAG_COMPRESSED;
+        }
+        if let Some(ref mut encryption) = self.encryption {
+            let payload = envelope.payload.as_slice();
+            envelope.payload = catch_panic("Encryption", || encryption.encode(payload))??;
+            envelope.flags |= FLAG_ENCRYPTED;
+        }
+        Ok(())
+    }
+
+    /// Undo the transforms flagged on the envelope in `data`, returning the
+    /// envelope re-encoded with the plain payload and those flags cleared,
+    /// or `None` if `data` isn't a flagged envelope. Fails if a flagged
+    /// transform isn't set or can't decode the payload.
+    pub fn reverse(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let mut envelope = match Envelope::decode(data) {
+            Some(envelope) if envelope.flags & (FLAG_COMPRESSED | FLAG_ENCRYPTED) != 0 => envelope,
+            _ => return Ok(None)
+        };
+        if envelope.flags & FLAG_ENCRYPTED != 0 {
+            envelope.payload = match self.encryption {
+                Some(ref mut encryption) =>
+                    catch_panic("Encryption", || encryption.decode(envelope.payload.as_slice()))??,
+                None => {
+                    return Err(Error::EncodingError("Encrypted payload but no cipher set".to_string()));
+                }
+            };
+            envelope.flags &= !FLAG_ENCRYPTED;
+        }
+        if envelope.flags & FLAG_COMPRESSED != 0 {
+            envelope.payload = match self.compression {
+                Some(ref mut compression) =>
+                    catch_panic("Compression", || compression.decode(envelope.payload.as_slice()))??,
+                None => {
+                    return Err(Error::EncodingError("Compressed payload but no compressor set".to_string()));
+                }
+            };
+            envelope.flags &= !FLAG_COMPRESSED;
+        }
+        envelope.encode().map(Some)
+    }
+}
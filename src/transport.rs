@@ -0,0 +1,311 @@
+//! Byte-stream transports over which a client speaks the Spread protocol.
+
+use std::cmp;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use parser::{FrameHeader, HEADER_LENGTH};
+
+/// A bidirectional byte stream connected to a Spread daemon.
+///
+/// `TcpStream` is the usual transport, but anything that can read and write
+/// bytes (a TLS wrapper, a proxied connection, an in-memory pipe) can back a
+/// `SpreadClient` by implementing this trait.
+pub trait Transport: Read + Write + Send {
+    /// The address of the remote end, if the transport has one.
+    fn peer_name(&mut self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The address of the local end, if the transport has one.
+    fn socket_name(&mut self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Shut down both directions of the stream.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Fail reads that wait longer than `timeout`, or block indefinitely if
+    /// `None`. Transports without timeouts ignore this.
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Fail writes that wait longer than `timeout`, or block indefinitely
+    /// if `None`. Transports without timeouts ignore this.
+    fn set_write_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Make reads fail with `WouldBlock` instead of waiting for data.
+    /// Transports that can't ignore this, so polling them blocks until
+    /// data arrives.
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    fn peer_name(&mut self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+
+    fn socket_name(&mut self) -> Option<SocketAddr> {
+        self.local_addr().ok()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// For daemons listening on a Unix domain socket, as Spread does by default
+// alongside its TCP port.
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// A transport with a read buffer in front of it, so a client can poll for
+// whatever bytes are available without blocking and only start decoding a
+// frame once all of it has arrived.
+pub struct BufferedTransport {
+    inner: Box<dyn Transport>,
+    buffer: Vec<u8>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>
+}
+
+impl BufferedTransport {
+    pub fn new(inner: Box<dyn Transport>) -> BufferedTransport {
+        BufferedTransport { inner: inner, buffer: Vec::new(), read_timeout: None, write_timeout: None }
+    }
+
+    // Close the current connection and carry on over `inner`, with the
+    // same timeouts. Bytes buffered from the old connection are dropped.
+    pub fn replace(&mut self, inner: Box<dyn Transport>) -> io::Result<()> {
+        let _ = self.close();
+        self.inner = inner;
+        let (read_timeout, write_timeout) = (self.read_timeout, self.write_timeout);
+        self.set_read_timeout(read_timeout)?;
+        self.set_write_timeout(write_timeout)
+    }
+
+    // Append every byte that can be read without waiting to the buffer,
+    // stopping once it holds `max_bytes` and a whole frame, if a limit is
+    // given. Reaching the end of the stream is only an error once no whole
+    // frame is left to decode.
+    pub fn fill_available(&mut self, max_bytes: Option<usize>) -> io::Result<()> {
+        self.inner.set_nonblocking(true)?;
+        let mut chunk = [0u8; 4096];
+        let mut result = Ok(());
+        while max_bytes.is_none_or(|max| self.buffer.len() < max) || !self.has_frame() {
+            match self.inner.read(&mut chunk) {
+                Ok(0) => {
+                    if !self.has_frame() {
+                        result = Err(closed());
+                    }
+                    break;
+                },
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+        self.inner.set_nonblocking(false)?;
+        result
+    }
+
+    // If a read timeout is set, buffer a whole frame before it is decoded,
+    // so a timeout partway through leaves the stream aligned on a frame
+    // boundary for the next read. Nothing past that frame is read, so
+    // waiting never buffers more than it returns. Without a timeout,
+    // frames are read straight from the stream.
+    pub fn await_frame(&mut self) -> io::Result<()> {
+        if self.read_timeout.is_none() {
+            return Ok(());
+        }
+        let mut chunk = [0u8; 4096];
+        while !self.has_frame() {
+            let wanted = self.missing_bytes().min(chunk.len());
+            match self.inner.read(&mut chunk[..wanted]) {
+                Ok(0) => return Err(closed()),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                // Sockets report an expired read timeout as `WouldBlock` on
+                // some platforms and `TimedOut` on others.
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
+                                  error.kind() == ErrorKind::TimedOut =>
+                    return Err(io::Error::new(ErrorKind::TimedOut, "Read timed out")),
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(error) => return Err(error)
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    // Returns true if the buffer holds at least one whole frame. A header
+    // that fails to decode counts as whole, so the next read reports it.
+    pub fn has_frame(&self) -> bool {
+        if self.buffer.len() < HEADER_LENGTH {
+            return false;
+        }
+        match FrameHeader::body_lengths(&self.buffer[..HEADER_LENGTH]) {
+            Ok((groups_len, data_len)) => self.buffer.len() >= HEADER_LENGTH + groups_len + data_len,
+            Err(_) => true
+        }
+    }
+
+    // Bytes still to be read before the buffer holds the header of the
+    // next frame, or once it does, the whole frame.
+    fn missing_bytes(&self) -> usize {
+        if self.buffer.len() < HEADER_LENGTH {
+            return HEADER_LENGTH - self.buffer.len();
+        }
+        match FrameHeader::body_lengths(&self.buffer[..HEADER_LENGTH]) {
+            Ok((groups_len, data_len)) => {
+                (HEADER_LENGTH + groups_len + data_len).saturating_sub(self.buffer.len())
+            },
+            Err(_) => 0
+        }
+    }
+
+    // Bytes read from the transport and not yet taken from the buffer.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Number of whole frames in the buffer, counting a header that fails
+    // to decode as one last frame.
+    pub fn buffered_frames(&self) -> usize {
+        let mut frames = 0;
+        let mut offset = 0;
+        while self.buffer.len() >= offset + HEADER_LENGTH {
+            match FrameHeader::body_lengths(&self.buffer[offset..offset + HEADER_LENGTH]) {
+                Ok((groups_len, data_len)) => {
+                    offset += HEADER_LENGTH + groups_len + data_len;
+                    if offset > self.buffer.len() {
+                        break;
+                    }
+                    frames += 1;
+                },
+                Err(_) => return frames + 1
+            }
+        }
+        frames
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(ErrorKind::UnexpectedEof, "Connection closed by daemon")
+}
+
+// Writes block, so `WouldBlock` from one means its write timeout expired.
+fn write_error(error: io::Error) -> io::Error {
+    match error.kind() {
+        ErrorKind::WouldBlock => io::Error::new(ErrorKind::TimedOut, "Write timed out"),
+        _ => error
+    }
+}
+
+impl Read for BufferedTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            return self.inner.read(buf);
+        }
+        let n = cmp::min(buf.len(), self.buffer.len());
+        buf[..n].copy_from_slice(&self.buffer[..n]);
+        self.buffer.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for BufferedTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(write_error)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf).map_err(write_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for BufferedTransport {
+    fn peer_name(&mut self) -> Option<SocketAddr> {
+        self.inner.peer_name()
+    }
+
+    fn socket_name(&mut self) -> Option<SocketAddr> {
+        self.inner.socket_name()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.inner.close()
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)?;
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)?;
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
+// Describe the remote end of a transport for log messages.
+pub fn describe_peer(transport: &mut dyn Transport) -> String {
+    match transport.peer_name() {
+        Some(addr) => format!("{}", addr),
+        None => "<unknown peer>".to_string()
+    }
+}
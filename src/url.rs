@@ -0,0 +1,135 @@
+//! Connection settings in a single `spread://` URL.
+//!
+//! The form is
+//!
+//! ```text
+//! spread://<private name>@<host>[:<port>][?<option>=<value>&...]
+//! ```
+//!
+//! with options `membership` (`true` or `false`, default `false`),
+//! `priority` (likewise) and `groups` (a comma-separated list of groups to
+//! join once connected). The host may be a bracketed IPv6 literal, and
+//! `%XX` escapes are decoded in the private name and group names.
+
+use address::DaemonAddress;
+use options::ConnectOptions;
+use {Error, SpreadClient};
+
+static SCHEME: &'static str = "spread://";
+
+/// Connection settings parsed from a `spread://` URL.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpreadUrl {
+    pub address: DaemonAddress,
+    pub private_name: String,
+    pub receive_membership_messages: bool,
+    /// Requested connection priority. Accepted for compatibility with other
+    /// client libraries; current Spread daemons ignore it.
+    pub priority: bool,
+    pub groups: Vec<String>
+}
+
+impl SpreadUrl {
+    pub fn parse(url: &str) -> Result<SpreadUrl, Error> {
+        let url = url.trim();
+        if !url.starts_with(SCHEME) {
+            return Err(invalid_url(url, "expected a spread:// URL"));
+        }
+        let rest = &url[SCHEME.len()..];
+        let (authority, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, "")
+        };
+        let authority = authority.trim_end_matches('/');
+        let (name, host) = match authority.find('@') {
+            Some(i) => (&authority[..i], &authority[i + 1..]),
+            None => return Err(invalid_url(url, "missing private name"))
+        };
+        let private_name = percent_decode(name).ok_or(invalid_url(url, "bad escape in private name"))?;
+        if private_name.is_empty() {
+            return Err(invalid_url(url, "missing private name"));
+        }
+        if host.is_empty() {
+            return Err(invalid_url(url, "missing host"));
+        }
+
+        let mut parsed = SpreadUrl {
+            address: DaemonAddress::parse(host)?,
+            private_name: private_name,
+            receive_membership_messages: false,
+            priority: false,
+            groups: Vec::new()
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, "")
+            };
+            match key {
+                "membership" => parsed.receive_membership_messages = parse_bool(url, value)?,
+                "priority" => parsed.priority = parse_bool(url, value)?,
+                "groups" => for group in value.split(',').filter(|g| !g.is_empty()) {
+                    let group = percent_decode(group).ok_or(invalid_url(url, "bad escape in group"))?;
+                    parsed.groups.push(group);
+                },
+                _ => return Err(invalid_url(url, "unknown option"))
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Connect and join the URL's groups.
+    pub fn connect(&self) -> Result<SpreadClient, Error> {
+        self.options().connect(self.address.clone())
+    }
+
+    /// The URL's private name, membership setting and groups.
+    pub fn options(&self) -> ConnectOptions {
+        ConnectOptions {
+            private_name: self.private_name.clone(),
+            receive_membership_messages: self.receive_membership_messages,
+            groups: self.groups.clone(),
+            reconnect: None,
+            #[cfg(feature = "proxy")]
+            proxy: None,
+            #[cfg(feature = "tls")]
+            tls: None
+        }
+    }
+}
+
+fn parse_bool(url: &str, value: &str) -> Result<bool, Error> {
+    match value {
+        "true" | "1" | "" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(invalid_url(url, "expected true or false"))
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 3 > bytes.len() {
+                return None;
+            }
+            let high = (bytes[i + 1] as char).to_digit(16);
+            let low = (bytes[i + 2] as char).to_digit(16);
+            match (high, low) {
+                (Some(high), Some(low)) => out.push((high * 16 + low) as u8),
+                _ => return None
+            }
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn invalid_url(url: &str, reason: &str) -> Error {
+    Error::InvalidInput(format!("Malformed spread:// URL: {}: {}", reason, url))
+}
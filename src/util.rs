@@ -1,4 +1,6 @@
-use std::iter::range_step_inclusive;
+use std::io::{self, Read};
+use std::thread;
+use time::Duration;
 
 /// Used to determine endianness.
 static ENDIANTYPE: u32 = 0x80000080;
@@ -6,7 +8,7 @@ static ENDIANTYPE: u32 = 0x80000080;
 /// Convert an i32 to a 4-element byte vector.
 pub fn int_to_bytes(i: u32) -> Vec<u8> {
     let mut vec: Vec<u8> = Vec::new();
-    for p in range_step_inclusive(0u8, 24, 8) {
+    for p in (0u8..=24).step_by(8) {
         vec.push(((i >> p) & 0xFF) as u8);
     }
     vec.reverse();
@@ -20,7 +22,40 @@ pub fn bytes_to_int(bytes: &[u8]) -> u32 {
     let i2 = bytes[2] as u32 & 0xFF;
     let i3 = bytes[3] as u32 & 0xFF;
 
-    ((i0 << 24) | (i1 << 16) | (i2 << 8) | (i3))
+    (i0 << 24) | (i1 << 16) | (i2 << 8) | i3
+}
+
+/// Read exactly `len` bytes from `reader`.
+pub fn read_bytes<R: Read + ?Sized>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Read exactly `len` bytes from `reader`, appending them to `buf`.
+pub fn append_bytes<R: Read + ?Sized>(reader: &mut R, len: usize, buf: &mut Vec<u8>) -> io::Result<()> {
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    let result = reader.read_exact(&mut buf[start..]);
+    if result.is_err() {
+        buf.truncate(start);
+    }
+    result
+}
+
+/// Read a single byte from `reader`.
+pub fn read_byte<R: Read + ?Sized>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+/// Block the current thread for `duration`, or not at all if it is
+/// negative.
+pub fn sleep(duration: Duration) {
+    if let Ok(duration) = duration.to_std() {
+        thread::sleep(duration);
+    }
 }
 
 /// Returns true if the argument int has the same endianness
@@ -38,3 +73,261 @@ pub fn flip_endianness(i: u32) -> u32 {
 
     i0 | i1 | i2 | i3
 }
+
+/// Format `bytes` as a hex dump of sixteen bytes per line, each line
+/// prefixed with its offset and followed by a printable-ASCII rendering.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        out.push_str(format!("{:04x}  ", line * 16).as_str());
+        for i in 0..16 {
+            if i < chunk.len() {
+                out.push_str(format!("{:02x} ", chunk[i]).as_str());
+            } else {
+                out.push_str("   ");
+            }
+        }
+        out.push_str(" |");
+        for &b in chunk.iter() {
+            out.push(if (0x20..0x7f).contains(&b) { b as char } else { '.' });
+        }
+        out.push_str("|\n");
+    }
+    out
+}
+
+static BASE64_ALPHABET: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `bytes` as padded standard base64.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
+        let b2 = if chunk.len() > 2 { chunk[2] as u32 } else { 0 };
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+
+        out.push(BASE64_ALPHABET[((triple >> 18) & 0x3f) as usize] as char);
+        out.push(BASE64_ALPHABET[((triple >> 12) & 0x3f) as usize] as char);
+        if chunk.len() > 1 {
+            out.push(BASE64_ALPHABET[((triple >> 6) & 0x3f) as usize] as char);
+        } else {
+            out.push('=');
+        }
+        if chunk.len() > 2 {
+            out.push(BASE64_ALPHABET[(triple & 0x3f) as usize] as char);
+        } else {
+            out.push('=');
+        }
+    }
+    out
+}
+
+/// Decode padded standard base64, or `None` if `text` isn't valid.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for chunk in text.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut triple: u32 = 0;
+        for &c in chunk[..4 - padding].iter() {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c)?;
+            triple = (triple << 6) | value as u32;
+        }
+        triple <<= 6 * padding as u32;
+        out.push((triple >> 16) as u8);
+        if padding < 2 {
+            out.push((triple >> 8) as u8);
+        }
+        if padding < 1 {
+            out.push(triple as u8);
+        }
+    }
+    Some(out)
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Unlike the standard library's hashers,
+/// its output is fixed, so it can be used to agree on values across
+/// processes.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes.iter() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Returns true if `text` matches `pattern`, in which `*` matches any run of
+/// characters and `?` matches any single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen, and of the text when it was seen.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            p = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+/// `s` as a quoted JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A field value in the flat JSON objects this crate writes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    String(String),
+    Number(i64),
+    Strings(Vec<String>)
+}
+
+/// Parse a single-line JSON object whose values are strings, integers or
+/// arrays of strings, such as those written by `tap`, into its fields in
+/// order.
+pub fn parse_json_object(line: &str) -> Result<Vec<(String, JsonValue)>, String> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = Vec::new();
+    expect(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = parse_json_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            expect(&mut chars, ':')?;
+            skip_whitespace(&mut chars);
+            let value = match chars.peek() {
+                Some(&'"') => JsonValue::String(parse_json_string(&mut chars)?),
+                Some(&'[') => {
+                    chars.next();
+                    let mut strings = Vec::new();
+                    skip_whitespace(&mut chars);
+                    if chars.peek() == Some(&']') {
+                        chars.next();
+                    } else {
+                        loop {
+                            skip_whitespace(&mut chars);
+                            strings.push(parse_json_string(&mut chars)?);
+                            skip_whitespace(&mut chars);
+                            match chars.next() {
+                                Some(',') => {},
+                                Some(']') => break,
+                                other => return Err(format!("expected ',' or ']', found {:?}", other))
+                            }
+                        }
+                    }
+                    JsonValue::Strings(strings)
+                },
+                _ => {
+                    let mut number = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c != '-' && !c.is_ascii_digit() {
+                            break;
+                        }
+                        number.push(c);
+                        chars.next();
+                    }
+                    JsonValue::Number(number.parse().map_err(|_| format!("bad value for \"{}\"", key))?)
+                }
+            };
+            fields.push((key, value));
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => {},
+                Some('}') => break,
+                other => return Err(format!("expected ',' or '}}', found {:?}", other))
+            }
+        }
+    }
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(fields),
+        Some(c) => Err(format!("unexpected {:?} after object", c))
+    }
+}
+
+type JsonChars<'a> = ::std::iter::Peekable<::std::str::Chars<'a>>;
+
+fn expect(chars: &mut JsonChars, expected: char) -> Result<(), String> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        other => Err(format!("expected {:?}, found {:?}", expected, other))
+    }
+}
+
+fn skip_whitespace(chars: &mut JsonChars) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_json_string(chars: &mut JsonChars) -> Result<String, String> {
+    expect(chars, '"')?;
+    let mut out = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(out),
+            Some('\\') => match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('/') => out.push('/'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('b') => out.push('\u{8}'),
+                Some('f') => out.push('\u{c}'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(hex.as_str(), 16)
+                        .map_err(|_| format!("bad escape \\u{}", hex))?;
+                    out.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                },
+                other => return Err(format!("bad escape {:?}", other))
+            },
+            Some(c) => out.push(c),
+            None => return Err("unterminated string".to_string())
+        }
+    }
+}
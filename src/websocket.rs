@@ -0,0 +1,273 @@
+//! Tunnelling the Spread byte stream over a WebSocket connection.
+//!
+//! Only available when the crate is built with the `websocket` feature. The
+//! relay on the far side is expected to forward the payloads of binary
+//! frames to and from a daemon verbatim. The relay's handshake response
+//! must carry the `Sec-WebSocket-Accept` value for the key sent, and frames
+//! longer than the transport's maximum frame size are refused.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use time::precise_time_ns;
+use transport::Transport;
+use util::{base64_encode, read_byte, read_bytes};
+
+static OPCODE_CONTINUATION: u8 = 0x0;
+static OPCODE_BINARY: u8 = 0x2;
+static OPCODE_CLOSE: u8 = 0x8;
+static OPCODE_PING: u8 = 0x9;
+static OPCODE_PONG: u8 = 0xa;
+
+// Appended to the client's key to derive `Sec-WebSocket-Accept`.
+static ACCEPT_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest frame payload a transport accepts unless changed with
+/// `set_max_frame_size`, in bytes.
+pub static DEFAULT_MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// A `Transport` carrying the Spread stream in WebSocket binary frames.
+pub struct WebSocketTransport {
+    stream: TcpStream,
+    // Payload bytes received but not yet read.
+    pending: Vec<u8>,
+    rng_state: u64,
+    max_frame_size: usize
+}
+
+impl WebSocketTransport {
+    /// Open a WebSocket connection to the relay at `host:port`, requesting
+    /// `path`.
+    pub fn connect(host: &str, port: u16, path: &str) -> io::Result<WebSocketTransport> {
+        let stream = TcpStream::connect((host, port))?;
+        let mut transport = WebSocketTransport {
+            stream: stream,
+            pending: Vec::new(),
+            rng_state: precise_time_ns() | 1,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE
+        };
+        transport.handshake(host, port, path)?;
+        Ok(transport)
+    }
+
+    /// Refuse frames whose payload is longer than `max_frame_size` bytes.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    fn handshake(&mut self, host: &str, port: u16, path: &str) -> io::Result<()> {
+        let mut nonce = [0u8; 16];
+        for byte in nonce.iter_mut() {
+            *byte = self.next_random() as u8;
+        }
+        let key = base64_encode(&nonce);
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path, host, port, key
+        );
+        debug!("Opening WebSocket tunnel to {}:{}{}", host, port, path);
+        self.stream.write_all(request.as_bytes())?;
+
+        let mut response: Vec<u8> = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(read_byte(&mut self.stream)?);
+            if response.len() > 8192 {
+                return Err(io::Error::other(
+                    "WebSocket handshake response too long"
+                ));
+            }
+        }
+
+        let response = String::from_utf8_lossy(response.as_slice()).into_owned();
+        let status_line = response.as_str().lines().next().unwrap_or("");
+        if status_line.split(' ').nth(1) != Some("101") {
+            return Err(io::Error::other(
+                format!("WebSocket upgrade rejected: {}", status_line)
+            ));
+        }
+        let accept = response.as_str().lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|&(name, _)| name.trim().eq_ignore_ascii_case("Sec-WebSocket-Accept"))
+            .map(|(_, value)| value.trim());
+        if accept != Some(accept_key(key.as_str()).as_str()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("WebSocket upgrade has wrong Sec-WebSocket-Accept: {:?}", accept)
+            ));
+        }
+        Ok(())
+    }
+
+    // xorshift64; frame masks need not be cryptographically strong here.
+    fn next_random(&mut self) -> u64 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        self.rng_state
+    }
+
+    // Write a single masked frame, as required of clients.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame: Vec<u8> = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else if payload.len() <= 0xffff {
+            frame.push(0x80 | 126);
+            frame.push((payload.len() >> 8) as u8);
+            frame.push(payload.len() as u8);
+        } else {
+            frame.push(0x80 | 127);
+            for shift in (0..8).rev() {
+                frame.push(((payload.len() as u64) >> (shift * 8)) as u8);
+            }
+        }
+
+        let mask = self.next_random() as u32;
+        let mask_bytes = [(mask >> 24) as u8, (mask >> 16) as u8, (mask >> 8) as u8, mask as u8];
+        frame.extend_from_slice(&mask_bytes);
+        for (i, &b) in payload.iter().enumerate() {
+            frame.push(b ^ mask_bytes[i % 4]);
+        }
+        self.stream.write_all(frame.as_slice())
+    }
+
+    // Read frames until at least one payload byte is pending.
+    fn fill_pending(&mut self) -> io::Result<()> {
+        while self.pending.is_empty() {
+            let head = read_bytes(&mut self.stream, 2)?;
+            let opcode = head[0] & 0x0f;
+            let masked = head[1] & 0x80 != 0;
+            let len = match head[1] & 0x7f {
+                126 => read_be(&mut self.stream, 2)?,
+                127 => read_be(&mut self.stream, 8)?,
+                short => short as u64
+            };
+            if len > self.max_frame_size as u64 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("WebSocket frame too long: {} bytes, maximum {}", len, self.max_frame_size)
+                ));
+            }
+            let mask = if masked { Some(read_bytes(&mut self.stream, 4)?) } else { None };
+            let mut payload = read_bytes(&mut self.stream, len as usize)?;
+            if let Some(mask) = mask {
+                for i in 0..payload.len() {
+                    payload[i] ^= mask[i % 4];
+                }
+            }
+
+            match opcode {
+                op if op == OPCODE_BINARY || op == OPCODE_CONTINUATION =>
+                    self.pending.extend_from_slice(payload.as_slice()),
+                op if op == OPCODE_PING => self.write_frame(OPCODE_PONG, payload.as_slice())?,
+                op if op == OPCODE_PONG => (),
+                op if op == OPCODE_CLOSE => return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "WebSocket closed by relay"
+                )),
+                other => return Err(io::Error::other(
+                    format!("Unexpected WebSocket frame: opcode {}", other)
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value a server answers the handshake key
+/// `key` with.
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()))
+}
+
+// SHA-1, as the handshake requires.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6)
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+// Read a `len`-byte big-endian length field.
+fn read_be(stream: &mut TcpStream, len: usize) -> io::Result<u64> {
+    let bytes = read_bytes(stream, len)?;
+    Ok(bytes.iter().fold(0, |n, &b| (n << 8) | b as u64))
+}
+
+impl Read for WebSocketTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_pending()?;
+        let n = if buf.len() < self.pending.len() { buf.len() } else { self.pending.len() };
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for WebSocketTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_frame(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write_frame(OPCODE_BINARY, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn peer_name(&mut self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+
+    fn socket_name(&mut self) -> Option<SocketAddr> {
+        self.stream.local_addr().ok()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let _ = self.write_frame(OPCODE_CLOSE, &[]);
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
@@ -0,0 +1,144 @@
+//! Competing-consumer semantics on top of a Spread group.
+//!
+//! Every consumer joins the same group and so receives every message, but
+//! only one of them — chosen by rendezvous hashing of the message's sender
+//! and sequence number over the current membership view — processes it.
+//! When done, the owner multicasts a small completion notice. The other
+//! members hold each message they do not own until its completion notice
+//! arrives, so that if the owner disappears from the view its outstanding
+//! work is reassigned to the surviving members.
+//!
+//! Held messages are kept in arrival order, and a full queue forgets the
+//! one that arrived first. Unsequenced messages are identified by their
+//! payload, so identical ones from the same sender are held as separate
+//! copies, each released by one completion notice.
+
+use std::collections::VecDeque;
+use envelope::{decode_u64, encode_u64, Envelope};
+use util::fnv1a;
+use {Error, SpreadClient, SpreadMessage};
+
+static DONE_PREFIX: &'static [u8] = b"\x00spread-work-done:";
+
+// Identifies a unit of work: its sender and sequence number (or, for
+// unsequenced messages, a hash of the payload).
+type WorkId = (String, u64);
+
+/// A competing-consumer view of one group.
+pub struct WorkQueue {
+    group: String,
+    me: String,
+    members: Vec<String>,
+    held: VecDeque<(WorkId, SpreadMessage)>,
+    max_held: usize
+}
+
+impl WorkQueue {
+    /// Consume from `group` as `me` (the client's private group name),
+    /// holding at most `max_held` messages owned by other members.
+    pub fn new(group: &str, me: &str, max_held: usize) -> WorkQueue {
+        WorkQueue {
+            group: group.to_string(),
+            me: me.to_string(),
+            members: vec!(me.to_string()),
+            held: VecDeque::new(),
+            max_held: max_held
+        }
+    }
+
+    pub fn group(&self) -> &str {
+        self.group.as_str()
+    }
+
+    /// Feed a message received on the group. Returns it if this member
+    /// should process it; completion notices and work owned by others are
+    /// absorbed.
+    pub fn on_message(&mut self, message: SpreadMessage) -> Option<SpreadMessage> {
+        if let Some(id) = decode_done(message.data.as_slice()) {
+            if let Some(position) = self.held.iter().position(|(held, _)| *held == id) {
+                self.held.remove(position);
+            }
+            return None;
+        }
+
+        let id = work_id(&message);
+        if self.owner(&id) == Some(self.me.as_str()) {
+            return Some(message);
+        }
+        if self.held.len() >= self.max_held {
+            // Drop the earliest held item rather than grow without bound.
+            if let Some((oldest, _)) = self.held.pop_front() {
+                warn!("Work queue \"{}\" full; forgetting held work {:?}", self.group, oldest);
+            }
+        }
+        self.held.push_back((id, message));
+        None
+    }
+
+    /// Announce that `message`, returned earlier by `on_message`, has been
+    /// processed.
+    pub fn complete(&self, client: &mut SpreadClient, message: &SpreadMessage) -> Result<(), Error> {
+        let notice = encode_done(&work_id(message));
+        client.multicast([self.group.as_str()].as_slice(), notice.as_slice())
+    }
+
+    /// Apply a new membership view of the group. Returns held messages whose
+    /// owner left and which this member now owns, in arrival order.
+    pub fn on_view(&mut self, members: &[String]) -> Vec<SpreadMessage> {
+        self.members = members.to_vec();
+        if !self.members.contains(&self.me) {
+            self.members.push(self.me.clone());
+        }
+
+        let held: Vec<(WorkId, SpreadMessage)> = self.held.drain(..).collect();
+        let mut reassigned = Vec::new();
+        for (id, message) in held {
+            if self.owner(&id) == Some(self.me.as_str()) {
+                reassigned.push(message);
+            } else {
+                self.held.push_back((id, message));
+            }
+        }
+        reassigned
+    }
+
+    /// Number of messages held on behalf of other members.
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    fn owner(&self, id: &WorkId) -> Option<&str> {
+        self.members.iter()
+            .max_by_key(|member| fnv1a(format!("{}/{}/{}", member, id.0, id.1).as_bytes()))
+            .map(|member| member.as_str())
+    }
+}
+
+fn work_id(message: &SpreadMessage) -> WorkId {
+    let sender = message.sender.as_str().trim_end_matches('\0').to_string();
+    match Envelope::decode(message.data.as_slice()).and_then(|e| e.sequence()) {
+        Some(sequence) => (sender, sequence),
+        None => (sender, fnv1a(message.data.as_slice()))
+    }
+}
+
+fn encode_done(id: &WorkId) -> Vec<u8> {
+    let mut out = DONE_PREFIX.to_vec();
+    out.push(id.0.len() as u8);
+    out.extend_from_slice(id.0.as_bytes());
+    out.extend_from_slice(encode_u64(id.1).as_slice());
+    out
+}
+
+fn decode_done(data: &[u8]) -> Option<WorkId> {
+    if !data.starts_with(DONE_PREFIX) || data.len() < DONE_PREFIX.len() + 1 {
+        return None;
+    }
+    let sender_len = data[DONE_PREFIX.len()] as usize;
+    let start = DONE_PREFIX.len() + 1;
+    if data.len() != start + sender_len + 8 {
+        return None;
+    }
+    let sender = String::from_utf8_lossy(&data[start..start + sender_len]).into_owned();
+    decode_u64(&data[start + sender_len..]).map(|sequence| (sender, sequence))
+}